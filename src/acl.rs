@@ -0,0 +1,75 @@
+use std::ffi::CStr;
+
+use crate::spawn::User;
+
+/// What a user may do in a shared session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    ReadWrite,
+    /// Only watch: keys and commands that change the session are refused,
+    /// see `Command::needs_write_access`.
+    ReadOnly,
+}
+
+/// The users besides the session's own who may attach, from the
+/// allow-users option.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    grants: Vec<(libc::uid_t, Access)>,
+}
+
+impl Acl {
+    /// Parses names or uids separated by blanks or commas, each followed
+    /// by `:ro` to only watch or `:rw`, the default.
+    pub fn parse(text: &str) -> Result<Acl, String> {
+        let mut grants = vec![];
+        for entry in text.split(|c: char| c == ',' || c.is_whitespace()) {
+            if entry.is_empty() {
+                continue;
+            }
+            let (user, access) = match entry.rsplit_once(':') {
+                Some((user, "ro")) => (user, Access::ReadOnly),
+                Some((user, "rw")) => (user, Access::ReadWrite),
+                Some((_, access)) => return Err(format!("not ro or rw: {}", access)),
+                None => (entry, Access::ReadWrite),
+            };
+            let uid = match user.parse() {
+                Ok(uid) => uid,
+                Err(_) => User::lookup(user).map_err(|e| e.to_string())?.uid,
+            };
+            grants.push((uid, access));
+        }
+        Ok(Acl { grants })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.grants.is_empty()
+    }
+
+    /// What the user may do, None if it may not attach. The session's own
+    /// user and root, who can reach the socket anyway, may do anything.
+    pub fn access(&self, uid: libc::uid_t) -> Option<Access> {
+        if uid == unsafe { libc::getuid() } || uid == 0 {
+            return Some(Access::ReadWrite);
+        }
+        self.grants
+            .iter()
+            .find(|(granted, _)| *granted == uid)
+            .map(|(_, access)| *access)
+    }
+}
+
+/// The name of a user for list-clients, or the uid if it has none.
+pub fn user_name(uid: libc::uid_t) -> String {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let rc =
+        unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+    if rc != 0 || result.is_null() || passwd.pw_name.is_null() {
+        return uid.to_string();
+    }
+    unsafe { CStr::from_ptr(passwd.pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
@@ -0,0 +1,133 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::pin::Pin;
+use std::process::ExitStatus;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+
+use tokio::io::unix::AsyncFd;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::fd::FileDescriptor;
+use crate::protocol::{Message, HEADER_SIZE, MAX_PAYLOAD_SIZE};
+use crate::pty::{Pty, PtySize};
+
+/// A pty for a tokio runtime: reading and writing it never blocks a worker
+/// thread, so a task can wait on many panes and clients, with timeouts and
+/// cancellation, where the server needs a thread per blocking read.
+pub struct AsyncPty {
+    pty: Pty,
+    fd: AsyncFd<FileDescriptor>,
+}
+
+impl AsyncPty {
+    const WAIT_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Takes over a pty, it must not be read or written elsewhere since its
+    /// controller is made non-blocking. Must be called within a runtime.
+    pub fn new(pty: Pty) -> io::Result<Self> {
+        let fd = pty.try_clone_fd()?;
+        fd.set_nonblocking()?;
+        Ok(AsyncPty {
+            pty,
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// The pty, for what doesn't block like resizing or killing.
+    pub fn pty(&self) -> &Pty {
+        &self.pty
+    }
+
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        self.pty.resize(size)
+    }
+
+    /// Waits for the command to exit without blocking the runtime, it is
+    /// polled like `Pty::wait` does.
+    pub async fn wait(&self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.pty.try_wait()? {
+                return Ok(status);
+            }
+            tokio::time::sleep(Self::WAIT_INTERVAL).await;
+        }
+    }
+}
+
+impl AsyncRead for AsyncPty {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.fd.poll_read_ready_mut(cx))?;
+            let unfilled = buf.initialize_unfilled();
+            match guard.try_io(|fd| fd.get_mut().read(unfilled)) {
+                Ok(Ok(size)) => {
+                    buf.advance(size);
+                    return Poll::Ready(Ok(()));
+                }
+                Ok(Err(e)) => return Poll::Ready(Err(e)),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+impl AsyncWrite for AsyncPty {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        loop {
+            let mut guard = ready!(this.fd.poll_write_ready_mut(cx))?;
+            match guard.try_io(|fd| fd.get_mut().write(buf)) {
+                Ok(result) => return Poll::Ready(result),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads the next message like `Message::read_from`, None when the peer
+/// closed the stream cleanly between two messages.
+pub async fn read_message<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<Option<Message>> {
+    let mut header = [0u8; HEADER_SIZE];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_PAYLOAD_SIZE {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            "message payload too large",
+        ));
+    }
+    let mut frame = header.to_vec();
+    frame.resize(HEADER_SIZE + len, 0);
+    reader.read_exact(&mut frame[HEADER_SIZE..]).await?;
+    Message::take_from(&mut frame)
+}
+
+pub async fn write_message<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    message: &Message,
+) -> io::Result<()> {
+    writer.write_all(&message.encode()).await?;
+    writer.flush().await
+}
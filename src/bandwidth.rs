@@ -0,0 +1,129 @@
+use std::time::{Duration, Instant};
+
+/// Counts bytes, like those sent to a client, in total and per second.
+#[derive(Debug, Clone)]
+pub struct Meter {
+    total: u64,
+    window_start: Instant,
+    window: u64,
+    rate: u64,
+}
+
+impl Meter {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    pub fn new() -> Self {
+        Meter {
+            total: 0,
+            window_start: Instant::now(),
+            window: 0,
+            rate: 0,
+        }
+    }
+
+    pub fn add(&mut self, bytes: usize) {
+        self.roll();
+        self.total += bytes as u64;
+        self.window += bytes as u64;
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Bytes per second over the last whole second.
+    pub fn rate(&mut self) -> u64 {
+        self.roll();
+        self.rate
+    }
+
+    fn roll(&mut self) {
+        let elapsed = self.window_start.elapsed();
+        if elapsed < Self::WINDOW {
+            return;
+        }
+        // a quiet client sent nothing in the seconds since
+        self.rate = match elapsed < 2 * Self::WINDOW {
+            true => self.window * 1000 / elapsed.as_millis() as u64,
+            false => 0,
+        };
+        self.window = 0;
+        self.window_start = Instant::now();
+    }
+}
+
+impl Default for Meter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Caps the bytes per second sent to a client. Updates are held back while
+/// the client is over its budget, so the changes in the meantime go out
+/// together in one frame rather than one frame each.
+#[derive(Debug, Clone)]
+pub struct Limiter {
+    /// Bytes per second, 0 for no limit.
+    rate: u64,
+    /// The budget left, negative after a frame bigger than it.
+    tokens: f64,
+    last: Instant,
+}
+
+impl Limiter {
+    pub fn new(rate: u64) -> Self {
+        Limiter {
+            rate,
+            tokens: rate as f64,
+            last: Instant::now(),
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    pub fn set_rate(&mut self, rate: u64) {
+        if rate != self.rate {
+            *self = Self::new(rate);
+        }
+    }
+
+    /// Whether something may be sent now.
+    pub fn ready(&mut self) -> bool {
+        if self.rate == 0 {
+            return true;
+        }
+        // at most a second's worth is saved up for a burst
+        let now = Instant::now();
+        let earned = now.duration_since(self.last).as_secs_f64() * self.rate as f64;
+        self.tokens = (self.tokens + earned).min(self.rate as f64);
+        self.last = now;
+        self.tokens > 0.0
+    }
+
+    /// Takes what was sent out of the budget, it may go into debt.
+    pub fn spend(&mut self, bytes: usize) {
+        if self.rate > 0 {
+            self.tokens -= bytes as f64;
+        }
+    }
+}
+
+/// A byte count in the style of `ls -h`: 512B, 1.5K, 12M.
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    if bytes < 1024 {
+        return format!("{}B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    match value < 10.0 {
+        true => format!("{:.1}{}", value, UNITS[unit]),
+        false => format!("{:.0}{}", value, UNITS[unit]),
+    }
+}
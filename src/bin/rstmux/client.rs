@@ -0,0 +1,666 @@
+use std::{
+    env,
+    io::{self, stdin, stdout, Read, Write},
+    net::TcpStream,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use replicating_tmux::{
+    command::{Command, CommandResult},
+    compress::{self, MessageReader},
+    config,
+    features::{self, Features},
+    keys::{KeyAction, KeyBindings, KeyDispatcher},
+    mouse::{self, Input},
+    pipe::{PanePipe, PipeTarget},
+    poll::{poll, pollfd},
+    protocol::{Message, PaneExit, KEEPALIVE_INTERVAL, KEEPALIVE_TIMEOUT},
+    socket::{create_socket_dir, socket_dir, socket_path, Stream},
+    tls,
+};
+use rustls::ClientConfig;
+use termion::{clear, cursor, raw::IntoRawMode, terminal_size, terminal_size_pixels};
+
+/// How long the terminal has to say what it can do, see `probe_features`.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// How long a session listening on TCP has to accept the token.
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a client attaches, given to attach with `-f` as a comma separated list.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AttachFlags {
+    /// Watch the session without typing into it or resizing it.
+    pub read_only: bool,
+    /// Leave out colors and attributes, see `refresh-client -f text-only`.
+    pub text_only: bool,
+    /// Print lines of new text instead of drawing the screen, see
+    /// `refresh-client -f accessible`.
+    pub accessible: bool,
+    /// Let a program drive the session with commands and notifications
+    /// instead of drawing it, see `control::run`.
+    pub control: bool,
+}
+
+impl AttachFlags {
+    pub fn parse(list: &str) -> Result<Self, String> {
+        let mut flags = Self::default();
+        for flag in list.split(',').filter(|flag| !flag.is_empty()) {
+            match flag {
+                "read-only" => flags.read_only = true,
+                "text-only" => flags.text_only = true,
+                "accessible" => flags.accessible = true,
+                "control" => flags.control = true,
+                _ => return Err(format!("unknown client flag: {}", flag)),
+            }
+        }
+        Ok(flags)
+    }
+
+    /// What a client with these flags says to the server to attach: what
+    /// its terminal can do, its size and a request to be drawn.
+    pub fn attach_messages(&self, features: Features, size: Message) -> Vec<Message> {
+        let mut messages = vec![];
+        if self.read_only {
+            messages.push(Message::ReadOnly);
+        }
+        messages.push(Message::Features(features));
+        messages.push(Message::Attach);
+        let client_flags = [
+            (self.text_only, "text-only"),
+            (self.accessible, "accessible"),
+        ];
+        for (_, flag) in client_flags.iter().filter(|(on, _)| *on) {
+            let args = ["refresh-client", "-f", flag].map(str::to_string);
+            messages.push(Message::Command(args.to_vec()));
+        }
+        messages.push(size);
+        messages.push(Message::Refresh);
+        messages
+    }
+}
+
+/// Turns off whatever input modes the pane's application had set and
+/// leaves the alternate screen.
+pub const RESET_TERMINAL: &str = concat!(
+    "\x1b[?1l\x1b>\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1004l\x1b[?1006l\x1b[?2004l",
+    "\x1b[r\x1b[0m\x1b[?25h\x1b[?1049l",
+);
+
+/// Where a client keeps the token it was issued to resume with, one file
+/// per terminal so a client restarted in the same one finds it.
+#[derive(Clone)]
+struct ResumeFile {
+    path: String,
+    session: String,
+}
+
+impl ResumeFile {
+    /// None when stdin isn't a terminal.
+    fn for_terminal(session: &str) -> Option<Self> {
+        let mut buf = [0 as libc::c_char; 256];
+        if unsafe { libc::ttyname_r(0, buf.as_mut_ptr(), buf.len()) } != 0 {
+            return None;
+        }
+        let tty = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+        Some(ResumeFile {
+            path: format!("{}/resume{}", socket_dir(), tty.replace('/', "-")),
+            session: session.to_string(),
+        })
+    }
+
+    /// The token left by a client of the same session that crashed.
+    fn token(&self) -> Option<String> {
+        let text = std::fs::read_to_string(&self.path).ok()?;
+        let (session, token) = text.trim_end().split_once('\n')?;
+        (session == self.session).then(|| token.to_string())
+    }
+
+    fn save(&self, token: &str) -> io::Result<()> {
+        create_socket_dir()?;
+        std::fs::write(&self.path, format!("{}\n{}\n", self.session, token))
+    }
+
+    fn remove(&self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct Client {
+    flags: AttachFlags,
+    stop: Arc<AtomicBool>,
+    detached: Arc<AtomicBool>,
+    /// How the pane's command ended, if the server said so before hanging up.
+    exit: Arc<Mutex<Option<PaneExit>>>,
+    /// When anything last came from the server, see `KEEPALIVE_INTERVAL`.
+    heard: Arc<Mutex<Instant>>,
+    /// Set when the server pinged, the input thread answers since it is the
+    /// one writing to the server.
+    pong_due: Arc<AtomicBool>,
+    /// Set once the server stopped answering.
+    lost: Arc<AtomicBool>,
+    /// Where the session moved to, if the server said so before hanging up.
+    moved: Arc<Mutex<Option<String>>>,
+    /// What copied text goes to when the session leaves it to the client.
+    copy_pipe: Option<String>,
+}
+
+impl Client {
+    pub fn new(flags: AttachFlags) -> Self {
+        Self {
+            flags,
+            stop: Arc::new(AtomicBool::new(false)),
+            detached: Arc::new(AtomicBool::new(false)),
+            exit: Arc::new(Mutex::new(None)),
+            heard: Arc::new(Mutex::new(Instant::now())),
+            pong_due: Arc::new(AtomicBool::new(false)),
+            lost: Arc::new(AtomicBool::new(false)),
+            moved: Arc::new(Mutex::new(None)),
+            copy_pipe: load_copy_pipe(),
+        }
+    }
+
+    /// Attaches this terminal to the session until detached or the session
+    /// exits. A session that moved is reached through the relay that took
+    /// over its socket.
+    pub fn attach(&self, session_name: &str, idle_timeout: Option<Duration>) -> io::Result<()> {
+        let connect = |_: &str| UnixStream::connect(socket_path(session_name)).map(Stream::Unix);
+        let stream = connect(session_name)?;
+        self.attach_stream(stream, session_name, idle_timeout, connect)
+    }
+
+    /// Attaches to the session listening on TCP at `address`, once it
+    /// accepted `token`, see new-session -l. The connection is encrypted
+    /// with `tls` if it is given.
+    pub fn attach_tcp(
+        &self,
+        address: &str,
+        token: &str,
+        tls: Option<Arc<ClientConfig>>,
+        idle_timeout: Option<Duration>,
+    ) -> io::Result<()> {
+        let connect = |address: &str| connect_tcp(address, token, tls.clone());
+        let stream = connect(address)?;
+        self.attach_stream(stream, address, idle_timeout, connect)
+    }
+
+    /// Shows the session on this terminal over `stream`. When the session
+    /// moves, `connect` reaches it again at the address it moved to.
+    fn attach_stream(
+        &self,
+        mut stream: Stream,
+        session_name: &str,
+        idle_timeout: Option<Duration>,
+        connect: impl Fn(&str) -> io::Result<Stream>,
+    ) -> io::Result<()> {
+        let mut keys = KeyDispatcher::new(load_key_bindings());
+
+        // raw mode is restored when the guard drops, before reporting the exit.
+        // it turns off IXON, so C-s and C-q reach the pane unless asked not to
+        let mut raw = stdout().into_raw_mode()?;
+        if keys.bindings().flow_control() {
+            enable_flow_control(&stdout())?;
+        }
+
+        // the server draws the session on the alternate screen, so whatever
+        // was on the terminal before comes back afterwards
+        if !self.flags.accessible {
+            write!(raw, "\x1b[?1049h")?;
+            raw.flush()?;
+        }
+        let (features, mut typed) = probe_features(&mut raw)?;
+        let resume = ResumeFile::for_terminal(session_name);
+        let mut token = resume.as_ref().and_then(ResumeFile::token);
+        loop {
+            self.draw(&stream, session_name, resume.clone())?;
+            let (features, typed) = (features.clone(), std::mem::take(&mut typed));
+            let token = token.take();
+            self.process_input(&stream, &mut keys, idle_timeout, features, typed, token)?;
+
+            // the session goes on where it moved to, see migrate-session
+            let Some(address) = self.moved.lock().unwrap().take() else {
+                break;
+            };
+            match connect(&address) {
+                Ok(moved) => stream = moved,
+                Err(e) => {
+                    eprintln!("can't reach {}: {}", address, e);
+                    self.lost.store(true, Relaxed);
+                    break;
+                }
+            }
+            self.stop.store(false, Relaxed);
+            *self.heard.lock().unwrap() = Instant::now();
+        }
+        // only a crash leaves something to resume
+        if let Some(resume) = &resume {
+            resume.remove();
+        }
+
+        // leave whatever input modes the pane's application had set
+        if !self.flags.accessible {
+            write!(raw, "{}", RESET_TERMINAL)?;
+            raw.flush()?;
+        }
+        drop(raw);
+
+        if self.detached.load(Relaxed) {
+            println!("[detached (from session {})]", session_name);
+        } else if self.lost.load(Relaxed) {
+            println!("[lost (session {} stopped answering)]", session_name);
+        } else {
+            let exit = self.exit.lock().unwrap().unwrap_or(PaneExit::Unknown);
+            println!("[{}]", exit);
+        }
+
+        Ok(())
+    }
+
+    fn draw(
+        &self,
+        stream: &Stream,
+        session_name: &str,
+        resume: Option<ResumeFile>,
+    ) -> io::Result<()> {
+        let mut stdout = stdout();
+        let mut server_out = stream.try_clone()?;
+        let stop = self.stop.clone();
+        let detached = self.detached.clone();
+        let exit = self.exit.clone();
+        let moved = self.moved.clone();
+        let copy_pipe = self.copy_pipe.clone().map(PipeTarget::Command);
+        let session_name = session_name.to_string();
+        let heard = self.heard.clone();
+        let pong_due = self.pong_due.clone();
+        let accessible = self.flags.accessible;
+        let mut reader = MessageReader::new();
+
+        thread::spawn(move || {
+            // a terminal that went away fails the first update below as well
+            if !accessible {
+                let _ = write!(stdout, "{}{}", clear::All, cursor::Goto(1, 1));
+            }
+
+            loop {
+                if stop.load(Relaxed) {
+                    break;
+                }
+
+                let message = reader.read_from(&mut server_out);
+                if let Ok(Some(_)) = message {
+                    *heard.lock().unwrap() = Instant::now();
+                }
+                match message {
+                    Ok(Some(Message::Data(data))) => {
+                        if stdout.write_all(&data).is_err() {
+                            break;
+                        }
+                        if stdout.flush().is_err() {
+                            break;
+                        }
+                    }
+                    Ok(Some(Message::Text(line))) => {
+                        // the terminal is in raw mode, newlines don't return
+                        if write!(stdout, "{}\r\n", line).is_err() {
+                            break;
+                        }
+                        if stdout.flush().is_err() {
+                            break;
+                        }
+                    }
+                    Ok(Some(Message::Detach)) => {
+                        detached.store(true, Relaxed);
+                        break;
+                    }
+                    Ok(Some(Message::Moved(address))) => {
+                        *moved.lock().unwrap() = Some(address);
+                        break;
+                    }
+                    Ok(Some(Message::Yank(text))) => {
+                        let pipe = copy_pipe.as_ref().map(|c| PanePipe::open(c, &session_name));
+                        match pipe {
+                            Some(Ok(pipe)) => {
+                                pipe.write(text.as_bytes());
+                            }
+                            Some(Err(e)) => eprintln!("copy-pipe: {}", e),
+                            None => {}
+                        }
+                    }
+                    // the rest of the output may still be on its way
+                    Ok(Some(Message::Exited(status))) => *exit.lock().unwrap() = Some(status),
+                    Ok(Some(Message::Ping)) => pong_due.store(true, Relaxed),
+                    Ok(Some(Message::Resume(token))) => {
+                        if let Some(Err(e)) = resume.as_ref().map(|r| r.save(&token)) {
+                            eprintln!("can't keep the token to resume with: {}", e);
+                        }
+                    }
+                    Ok(Some(_)) => {} // not handled yet
+                    _ => break,       // EOF or failure
+                }
+            }
+
+            stop.store(true, Relaxed);
+        });
+
+        Ok(())
+    }
+
+    fn process_input(
+        &self,
+        stream: &Stream,
+        keys: &mut KeyDispatcher,
+        idle_timeout: Option<Duration>,
+        features: Features,
+        typed: Vec<u8>,
+        resume_token: Option<String>,
+    ) -> io::Result<()> {
+        let mut server_in = stream.try_clone()?;
+        let mut stdin = stdin().lock();
+        let stop = self.stop.clone();
+        let mut buf = [0u8; 128]; // at least one row at a time
+
+        // let the server size the pty to this terminal and redraw it
+        let mut size = terminal_resize()?;
+        // the server says whether it compresses by doing it
+        let codecs = compress::CODECS.iter().map(|c| c.to_string()).collect();
+        Message::Compress(codecs).write_to(&mut server_in)?;
+        // pick up where a crashed client in this terminal left off
+        if let Some(token) = resume_token {
+            Message::Resume(token).write_to(&mut server_in)?;
+        }
+        for message in self.flags.attach_messages(features, size.clone()) {
+            message.write_to(&mut server_in)?;
+        }
+        for message in input_messages(keys, &typed) {
+            message.write_to(&mut server_in)?;
+        }
+
+        // make stdin non-blocking
+        let fd = stdin.as_raw_fd();
+        let flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags | libc::O_NONBLOCK) };
+
+        let mut last_input = Instant::now();
+        // the first ping asks the server to keep the connection alive too
+        let mut pinged = Instant::now() - KEEPALIVE_INTERVAL;
+
+        loop {
+            if stop.load(Relaxed) {
+                break;
+            }
+
+            // forgotten attachments shouldn't linger with write access
+            if idle_timeout.is_some_and(|timeout| last_input.elapsed() >= timeout) {
+                self.detached.store(true, Relaxed);
+                break;
+            }
+
+            let pong_due = self.pong_due.swap(false, Relaxed);
+            if pong_due && Message::Pong.write_to(&mut server_in).is_err() {
+                break;
+            }
+            if pinged.elapsed() >= KEEPALIVE_INTERVAL {
+                if Message::Ping.write_to(&mut server_in).is_err() {
+                    break;
+                }
+                pinged = Instant::now();
+            }
+            if self.heard.lock().unwrap().elapsed() >= KEEPALIVE_TIMEOUT {
+                self.lost.store(true, Relaxed);
+                break;
+            }
+
+            // forward terminal size changes so the shell reflows
+            if let Ok(resize) = terminal_resize() {
+                if resize != size {
+                    size = resize;
+                    if size.write_to(&mut server_in).is_err() {
+                        break;
+                    }
+                    if Message::Refresh.write_to(&mut server_in).is_err() {
+                        break;
+                    }
+                }
+            }
+
+            match stdin.read(&mut buf) {
+                Ok(bytes_read) => {
+                    if bytes_read == 0 || stop.load(Relaxed) {
+                        break;
+                    }
+                    last_input = Instant::now();
+
+                    let messages = input_messages(keys, &buf[..bytes_read]);
+                    let sent = messages.iter().all(|m| m.write_to(&mut server_in).is_ok());
+                    if !sent {
+                        break;
+                    }
+                    if stop.load(Relaxed) {
+                        break;
+                    }
+                }
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(5));
+                }
+                _ => break,
+            }
+        }
+        stop.store(true, Relaxed);
+
+        // the tty is shared with the shell we return to, so restore blocking reads
+        unsafe { libc::fcntl(fd, libc::F_SETFL, flags) };
+
+        // let the server know this was intentional, it may already be gone
+        let _ = Message::Detach.write_to(&mut server_in);
+
+        Ok(())
+    }
+}
+
+/// Asks the terminal what it can do, see `features::PROBE`. A terminal
+/// that doesn't answer in time is taken to be what TERM says. Keys typed
+/// meanwhile are returned to be sent on.
+pub fn probe_features(terminal: &mut impl Write) -> io::Result<(Features, Vec<u8>)> {
+    let env = |name| env::var(name).unwrap_or_default();
+    let guess = Features::from_env(&env("TERM"), &env("COLORTERM"));
+    terminal.write_all(features::PROBE.as_bytes())?;
+    terminal.flush()?;
+
+    // read past std's buffering, so nothing waits in it unseen by poll
+    let fd = stdin().as_raw_fd();
+    let started = Instant::now();
+    let mut input = vec![];
+    while let Some(left) = PROBE_TIMEOUT.checked_sub(started.elapsed()) {
+        if poll(&mut [pollfd(fd, libc::POLLIN)], left)? == 0 {
+            break;
+        }
+        let mut buf = [0u8; 256];
+        let read = unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) };
+        if read <= 0 {
+            break;
+        }
+        input.extend_from_slice(&buf[..read as usize]);
+        if let Some(found) = Features::from_replies(&input, &guess) {
+            return Ok(found);
+        }
+    }
+    Ok((guess, input))
+}
+
+/// The messages for what was typed on the terminal. Bound commands run on
+/// the server, like detach-client, and it decides where the mouse goes.
+pub fn input_messages(keys: &mut KeyDispatcher, input: &[u8]) -> Vec<Message> {
+    let mut messages = vec![];
+    for input in mouse::split_input(input) {
+        match input {
+            Input::Keys(data) => {
+                let actions = keys.feed(&data).into_iter();
+                messages.extend(actions.map(|action| match action {
+                    KeyAction::Input(data) => Message::Data(data),
+                    KeyAction::Command(args) => Message::Command(args),
+                }));
+            }
+            Input::Mouse(event) => messages.push(Message::Mouse(event)),
+        }
+    }
+    messages
+}
+
+/// The terminal's size as a message for the server.
+pub fn terminal_resize() -> io::Result<Message> {
+    let (cols, rows) = terminal_size()?;
+    // not every terminal knows its size in pixels
+    let (pixel_width, pixel_height) = terminal_size_pixels().unwrap_or_default();
+    Ok(Message::Resize {
+        rows,
+        cols,
+        pixel_width,
+        pixel_height,
+    })
+}
+
+/// The default bindings with the configuration file applied, the same
+/// for every client.
+pub fn load_key_bindings() -> KeyBindings {
+    let mut bindings = KeyBindings::default();
+    let Some(path) = config::default_path() else {
+        return bindings;
+    };
+    // the server reports mistakes in %if blocks
+    let Ok(file) = config::read_lines(&path) else {
+        return bindings;
+    };
+
+    for (number, line) in file.lines {
+        if let Err(e) = bindings.apply_line(&line) {
+            eprintln!("{}:{}: {}", path.display(), number, e);
+        }
+    }
+    bindings
+}
+
+/// The copy-pipe command set in this machine's configuration file, which
+/// runs here when the session's copy-pipe-side option is client.
+pub fn load_copy_pipe() -> Option<String> {
+    let path = config::default_path()?;
+    let file = config::read_lines(&path).ok()?;
+    // the last one set counts, like for the server
+    let command = file
+        .lines
+        .iter()
+        .rev()
+        .find_map(|(_, line)| match Command::parse_line(line) {
+            Ok(Command::SetOption { name, value, .. }) if name == "copy-pipe" => Some(value),
+            _ => None,
+        });
+    command.flatten().filter(|command| !command.is_empty())
+}
+
+/// Has the terminal itself stop and resume output on C-s and C-q again.
+pub fn enable_flow_control(terminal: &impl AsRawFd) -> io::Result<()> {
+    let fd = terminal.as_raw_fd();
+    let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+    if unsafe { libc::tcgetattr(fd, &mut termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    termios.c_iflag |= libc::IXON;
+    if unsafe { libc::tcsetattr(fd, libc::TCSANOW, &termios) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Connects to the session listening on TCP at `address` and has it accept
+/// `token`, over TLS with `tls` if it is given. What follows is the same as
+/// on the session socket.
+pub fn connect_tcp(
+    address: &str,
+    token: &str,
+    tls: Option<Arc<ClientConfig>>,
+) -> io::Result<Stream> {
+    let tcp = TcpStream::connect(address)?;
+    tcp.set_nodelay(true)?;
+    // with TLS 1.3 a certificate the session doesn't take only shows
+    // once it hangs up
+    let refused = match tls {
+        Some(_) => "the session refused the token or the certificate",
+        None => "the session refused the token",
+    };
+    let mut stream = match tls {
+        Some(config) => {
+            // the certificate is for the host, like [::1] without brackets
+            let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            Stream::Unix(tls::connect(tcp, host, config)?)
+        }
+        None => Stream::Tcp(tcp),
+    };
+    Message::Token(token.to_string()).write_to(&mut stream)?;
+
+    // only a client that sent the token is answered
+    stream.set_read_timeout(Some(TOKEN_TIMEOUT))?;
+    Message::Ping.write_to(&mut stream)?;
+    match Message::read_from(&mut stream) {
+        Ok(Some(Message::Pong)) => {}
+        Ok(_) => return Err(io::Error::other(refused)),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+            return Err(io::Error::from(io::ErrorKind::TimedOut))
+        }
+        Err(e) => return Err(e),
+    }
+    stream.set_read_timeout(None)?;
+    Ok(stream)
+}
+
+/// Checks that the session's server is alive and answering, a server that
+/// doesn't answer within `timeout` is reported with `TimedOut`.
+pub fn ping(session_name: &str, timeout: Duration) -> io::Result<()> {
+    let mut server = UnixStream::connect(socket_path(session_name))?;
+    server.set_read_timeout(Some(timeout))?;
+    Message::Ping.write_to(&mut server)?;
+
+    loop {
+        match Message::read_from(&mut server) {
+            Ok(Some(Message::Pong)) => return Ok(()),
+            Ok(Some(_)) => {} // screen updates are not for us
+            Ok(None) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                return Err(io::Error::from(io::ErrorKind::TimedOut))
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Runs a single command in the session and returns its result.
+pub fn run_command(session_name: &str, args: &[String]) -> io::Result<CommandResult> {
+    let mut server = UnixStream::connect(socket_path(session_name))?;
+    run_command_on(&mut server, args)
+}
+
+/// Runs a single command over a connection to the session's server made
+/// earlier, like before a relay took over the socket.
+pub fn run_command_on(server: &mut UnixStream, args: &[String]) -> io::Result<CommandResult> {
+    Message::Command(args.to_vec()).write_to(server)?;
+
+    loop {
+        match Message::read_from(server)? {
+            Some(Message::CommandDone {
+                success: true,
+                output,
+            }) => return Ok(Ok(output)),
+            Some(Message::CommandDone {
+                success: false,
+                output,
+            }) => return Ok(Err(output)),
+            Some(_) => {} // screen updates are not for us
+            None => return Err(io::Error::other("server exited")),
+        }
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    collections::VecDeque,
+    io::{self, stdin, stdout, BufRead, Write},
+    os::unix::net::UnixStream,
+    sync::{
+        atomic::{AtomicBool, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use replicating_tmux::{
+    command::{split_line, CommandResult},
+    protocol::Message,
+    socket::socket_path,
+};
+
+/// A command read in control mode, answered in the order it was read.
+enum Pending {
+    /// Sent to the server, its result is on the way.
+    Sent(u64),
+    /// Handled here, waiting for those sent before it.
+    Done(u64, CommandResult),
+}
+
+/// The commands not answered yet, oldest first.
+type Queue = Arc<Mutex<VecDeque<Pending>>>;
+
+/// Drives a session from a program instead of showing it, like tmux -CC.
+///
+/// Each line read from stdin is a command, answered with its output between
+/// `%begin <time> <number> 0` and `%end` or `%error` with the same time and
+/// number, in the order they were read. `refresh-client -C <cols>x<rows>`
+/// sizes the pane like an attached terminal would, and an empty line
+/// detaches. Everything else printed is a notification:
+///
+/// - `%session-changed $0 <name>` first
+/// - `%output %0 <data>` for screen updates of the pane, with bytes below a
+///   space and backslashes escaped as `\ooo`
+/// - `%layout-change @0 <cols>x<rows>` when the pane is resized
+/// - `%window-renamed @0 <name>`
+/// - `%client-attached <id>` and `%client-detached <id>`
+/// - `%exit [reason]` last, without a reason after detaching
+pub fn run(session_name: &str) -> io::Result<()> {
+    let mut server = UnixStream::connect(socket_path(session_name))?;
+    Message::Control.write_to(&mut server)?;
+    Message::Subscribe { pane: 0 }.write_to(&mut server)?;
+
+    let queue = Queue::default();
+    let detached = Arc::new(AtomicBool::new(false));
+    let mut server_out = server.try_clone()?;
+    let (pending, detaching) = (queue.clone(), detached.clone());
+    thread::spawn(move || {
+        read_commands(&mut server_out, &pending);
+        detaching.store(true, Relaxed);
+        let _ = Message::Detach.write_to(&mut server_out);
+    });
+
+    let reason = loop {
+        let message = match Message::read_from(&mut server) {
+            Ok(Some(message)) => message,
+            Ok(None) if detached.load(Relaxed) => break String::new(),
+            Ok(None) => break "server exited".to_string(),
+            Err(e) => break e.to_string(),
+        };
+        match message {
+            Message::PaneData { pane, data } => {
+                print_line(&format!("%output %{} {}", pane, escape(&data)));
+            }
+            Message::Notify(line) => print_line(&line),
+            Message::CommandDone { success, output } => {
+                let result = if success { Ok(output) } else { Err(output) };
+                answer(&queue, result);
+            }
+            Message::Exited(exit) => break format!("pane {}", exit),
+            Message::Detach => break "detached".to_string(),
+            _ => {} // frames are for attached clients
+        }
+    };
+    print_line(format!("%exit {}", reason).trim_end());
+    Ok(())
+}
+
+/// Sends the commands read from stdin until an empty line or the end of
+/// the input.
+fn read_commands(server: &mut UnixStream, queue: &Queue) {
+    let mut number = 0;
+    for line in stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            break;
+        }
+        number += 1;
+
+        let args = match split_line(&line) {
+            Ok(args) => args,
+            Err(e) => {
+                finish(queue, number, Err(e));
+                continue;
+            }
+        };
+        match resize_message(&args) {
+            Some(Ok(resize)) => {
+                if resize.write_to(server).is_err() {
+                    return;
+                }
+                finish(queue, number, Ok(String::new()));
+            }
+            Some(Err(e)) => finish(queue, number, Err(e)),
+            None => {
+                queue.lock().unwrap().push_back(Pending::Sent(number));
+                if Message::Command(args).write_to(server).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// The size a control client asks for with `refresh-client -C`, as cols
+/// and rows separated by `x` or `,` like tmux takes them.
+fn resize_message(args: &[String]) -> Option<Result<Message, String>> {
+    let [name, flag, size] = args else {
+        return None;
+    };
+    if !matches!(name.as_str(), "refresh-client" | "refresh") || flag != "-C" {
+        return None;
+    }
+    let size = size
+        .split_once(['x', ','])
+        .and_then(|(cols, rows)| Some((cols.parse().ok()?, rows.parse().ok()?)));
+    match size {
+        Some((cols, rows)) if cols > 0 && rows > 0 => Some(Ok(Message::Resize {
+            rows,
+            cols,
+            pixel_width: 0,
+            pixel_height: 0,
+        })),
+        _ => Some(Err("usage: refresh-client -C <cols>x<rows>".to_string())),
+    }
+}
+
+/// Answers a command handled here, once those sent before it are answered.
+fn finish(queue: &Queue, number: u64, result: CommandResult) {
+    let mut queue = queue.lock().unwrap();
+    if queue.is_empty() {
+        print_result(number, &result);
+    } else {
+        queue.push_back(Pending::Done(number, result));
+    }
+}
+
+/// Answers the oldest command sent to the server, then those handled here
+/// that waited for it.
+fn answer(queue: &Queue, result: CommandResult) {
+    let mut queue = queue.lock().unwrap();
+    if let Some(Pending::Sent(number)) = queue.pop_front() {
+        print_result(number, &result);
+    }
+    while let Some(Pending::Done(..)) = queue.front() {
+        if let Some(Pending::Done(number, result)) = queue.pop_front() {
+            print_result(number, &result);
+        }
+    }
+}
+
+fn print_result(number: u64, result: &CommandResult) {
+    let time = SystemTime::now().duration_since(UNIX_EPOCH);
+    let time = time.unwrap_or_default().as_secs();
+    let (end, output) = match result {
+        Ok(output) => ("%end", output),
+        Err(error) => ("%error", error),
+    };
+    let mut out = stdout().lock();
+    let _ = writeln!(out, "%begin {} {} 0", time, number);
+    if !output.is_empty() {
+        let _ = writeln!(out, "{}", output);
+    }
+    let _ = writeln!(out, "{} {} {} 0", end, time, number);
+    let _ = out.flush();
+}
+
+fn print_line(line: &str) {
+    let mut out = stdout().lock();
+    let _ = writeln!(out, "{}", line);
+    let _ = out.flush();
+}
+
+/// Pane output on one line, like tmux escapes it.
+fn escape(data: &[u8]) -> String {
+    let mut escaped = Vec::with_capacity(data.len());
+    for &b in data {
+        if b < b' ' || b == b'\\' {
+            escaped.extend(format!("\\{:03o}", b).into_bytes());
+        } else {
+            escaped.push(b);
+        }
+    }
+    String::from_utf8_lossy(&escaped).into_owned()
+}
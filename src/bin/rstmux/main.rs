@@ -0,0 +1,905 @@
+mod client;
+mod control;
+mod migrate;
+mod relay;
+mod remote;
+mod server;
+
+use std::fs;
+use std::io::{self, Write};
+use std::net::TcpListener;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use client::{AttachFlags, Client};
+use relay::RemoteSession;
+use replicating_tmux::compat;
+use replicating_tmux::config;
+use replicating_tmux::daemon::daemonize;
+use replicating_tmux::mark::Mark;
+use replicating_tmux::predict::PredictMode;
+use replicating_tmux::socket::{log_path, relay_path, session_names, socket_path, TOKEN_ENV};
+use replicating_tmux::spawn::User;
+use replicating_tmux::sync::SyncKey;
+use replicating_tmux::tls;
+use replicating_tmux::workspace::{PaneSpec, SessionSpec};
+use rustls::{ClientConfig, ServerConfig};
+use server::TcpAccess;
+
+/// How long to wait for a server started in the background to listen.
+const START_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long list-sessions waits for a server to answer.
+const PING_TIMEOUT: Duration = Duration::from_secs(1);
+
+const USAGE: &str = "usage: rstmux <command> [flags] [args]
+
+commands:
+  new-session (new) [-d] [-c dir] [-e var=value] [-l address [-C cert -K key [-A ca]]] [-s name] [-u user] [command...]
+  attach-session (attach, a) [-r] [-f read-only,text-only,accessible,control] [-t name | -H host:port [-A ca [-C cert -K key]]] [-i minutes]
+  mirror-pane [-d] -s source [-t name]
+  list-sessions (ls)
+  export-session (export) [-t name]    > session.yaml
+  import-session (import) [--dry-run] [-d] [-s name] <file>
+  list-keys (lsk) [-N] [-T table]
+  check-config [-f file]
+  sync-server [-t name] [-p port]    prints RSTMUX CONNECT <port> <key>
+  sync-client [-r] [-f flags] [-p adaptive|always|never] <host> <port>    with RSTMUX_KEY=<key>
+  relay [-s name] [-A ca [-C cert -K key]] <host:port>    serves a session listening on TCP under a local name
+  migrate-session [-t name] [-A ca [-C cert -K key]] <host:port>    moves a session to receive-session there
+  receive-session [-s name] [-C cert -K key [-A ca]] -l address
+  kill-session [--dry-run] [-t name]
+  kill-server [--dry-run]
+  display-message (display) -a [-t name] message    shows every client of the session, or of every session
+  <command> [-t name[:pane]] [args...]    run a command in a session
+
+a target of '~' is the marked pane, see select-pane -m
+a target with a / is the socket of a session another user shares with set-option allow-users
+new-session -l, attach-session -H, relay, migrate-session and receive-session take a shared secret in RSTMUX_TOKEN
+attach, ls and commands, kill-session too, reach a relayed session like one on this machine
+attach -f control reads commands and prints their output and events in lines, like tmux -CC
+with RSTMUX_COMPAT=tmux, tmux spellings like setw, killp and capture-pane -p work too
+with -C and -K the connection is TLS, the other side's certificate must be signed by -A";
+
+/// Flags before the positional arguments of a subcommand, like `-t name`.
+struct Flags {
+    values: Vec<(char, String)>,
+    rest: Vec<String>,
+}
+
+impl Flags {
+    /// Splits `args` into the flags in `allowed` and everything from the
+    /// first non-flag argument or `--` on. Like getopt, a flag followed by
+    /// `:` in `allowed` takes a value.
+    fn parse(args: &[String], allowed: &str) -> Result<Self, String> {
+        let mut values = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.as_slice().first() {
+            if arg == "--" {
+                args.next();
+                break;
+            }
+            let Some(flag) = arg.strip_prefix('-').filter(|f| !f.is_empty()) else {
+                break;
+            };
+            args.next();
+
+            let mut chars = flag.chars();
+            while let Some(name) = chars.next() {
+                let Some(spec) = allowed.find(name).filter(|_| name != ':') else {
+                    return Err(format!("unknown flag -{}", name));
+                };
+                if !allowed[spec + 1..].starts_with(':') {
+                    // switches can be combined, like -dr
+                    values.push((name, String::new()));
+                    continue;
+                }
+
+                // both -tname and -t name
+                let value = match chars.as_str() {
+                    "" => args
+                        .next()
+                        .ok_or_else(|| format!("-{} expects a value", name))?
+                        .clone(),
+                    value => value.to_string(),
+                };
+                values.push((name, value));
+                break;
+            }
+        }
+
+        Ok(Self {
+            values,
+            rest: args.cloned().collect(),
+        })
+    }
+
+    fn get(&self, name: char) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Every value of a flag that can be given more than once, in order.
+    fn all(&self, name: char) -> impl Iterator<Item = &str> {
+        self.values
+            .iter()
+            .filter(move |(n, _)| *n == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn has(&self, name: char) -> bool {
+        self.get(name).is_some()
+    }
+
+    fn no_args(&self) -> Result<(), String> {
+        match self.rest.first() {
+            Some(arg) => Err(format!("unexpected argument {}", arg)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whether a server is listening on the session's socket, sockets of
+/// servers that didn't exit cleanly are left behind.
+fn is_running(session_name: &str) -> bool {
+    UnixStream::connect(socket_path(session_name)).is_ok()
+}
+
+fn running_sessions() -> io::Result<Vec<String>> {
+    Ok(session_names()?
+        .into_iter()
+        .filter(|name| is_running(name))
+        .collect())
+}
+
+/// The session named with -t, or the only one running.
+fn target_session(flags: &Flags) -> Result<String, String> {
+    if let Some(name) = flags.get('t') {
+        return resolve_target(name);
+    }
+
+    let sessions = running_sessions().map_err(|e| e.to_string())?;
+    match sessions.as_slice() {
+        [] => Err("no sessions".to_string()),
+        [name] => Ok(name.clone()),
+        _ => Err("more than one session, pick one with -t".to_string()),
+    }
+}
+
+/// Replaces the `~` target with the marked pane's session like tmux,
+/// each session has a single pane for now.
+fn resolve_target(target: &str) -> Result<String, String> {
+    if target != "~" {
+        return Ok(target.to_string());
+    }
+    Mark::load()
+        .map(|mark| mark.session)
+        .ok_or_else(|| "no marked target".to_string())
+}
+
+/// The first unused session number, unnamed sessions are numbered from 0 like in tmux.
+fn next_session_name() -> String {
+    (0..)
+        .map(|n: usize| n.to_string())
+        .find(|name| !is_running(name))
+        .unwrap()
+}
+
+/// Starts the server of a session in the background and waits until it
+/// accepts clients. Its output goes to a log file next to its socket. The
+/// window is named `window` and the pane starts out showing `history`, see
+/// `server::run`.
+fn start_server(
+    name: &str,
+    window: Option<&str>,
+    pane: PaneSpec,
+    tcp: Option<TcpAccess>,
+    history: &[u8],
+) -> Result<(), String> {
+    let log = log_path(name);
+    let daemon = daemonize(Path::new(&log)).map_err(|e| e.to_string())?;
+    if daemon {
+        let code = match server::run(name, window, pane, tcp, history) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("rstmux: {}", e);
+                1
+            }
+        };
+        exit(code);
+    }
+
+    wait_for_server(name)
+}
+
+/// Waits for a server started in the background to listen on the socket
+/// of session `name`.
+fn wait_for_server(name: &str) -> Result<(), String> {
+    wait_for(name, || is_running(name))
+}
+
+/// Waits until the server of session `name` started in the background is
+/// `ready`, its log tells what went wrong if it doesn't get there.
+fn wait_for(name: &str, ready: impl Fn() -> bool) -> Result<(), String> {
+    let started = Instant::now();
+    while !ready() {
+        if started.elapsed() >= START_TIMEOUT {
+            let log = log_path(name);
+            return Err(format!("server for {} didn't start, see {}", name, log));
+        }
+        sleep(Duration::from_millis(10));
+    }
+    Ok(())
+}
+
+fn new_session(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "A:c:C:de:K:l:s:u:")?;
+    let env: Vec<String> = flags.all('e').map(str::to_string).collect();
+    if let Some(var) = env.iter().find(|var| !var.contains('=')) {
+        return Err(format!("-e expects var=value, not {}", var));
+    }
+    if let Some(user) = flags.get('u') {
+        User::lookup(user).map_err(|e| e.to_string())?;
+    }
+    // bound here, so a taken port is reported rather than logged
+    let tcp = match flags.get('l') {
+        Some(address) => Some(TcpAccess {
+            listener: TcpListener::bind(address)
+                .map_err(|e| format!("can't listen on {}: {}", address, e))?,
+            token: token()?,
+            tls: tls_server_config(&flags)?,
+        }),
+        None if flags.has('C') || flags.has('A') => return Err("TLS needs -l".to_string()),
+        None => None,
+    };
+    let name = match flags.get('s') {
+        Some(name) if is_running(name) => return Err(format!("duplicate session: {}", name)),
+        Some(name) => name.to_string(),
+        None => next_session_name(),
+    };
+
+    let pane = PaneSpec {
+        cwd: flags.get('c').map(str::to_string),
+        env,
+        user: flags.get('u').map(str::to_string),
+        command: flags.rest.clone(),
+    };
+    start_server(&name, None, pane, tcp, &[])?;
+    if flags.has('d') {
+        return Ok(());
+    }
+    attach(&name, None)
+}
+
+fn attach_session(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "A:C:f:H:K:rt:i:")?;
+    flags.no_args()?;
+    let mut attach_flags = AttachFlags::parse(flags.get('f').unwrap_or_default())?;
+    attach_flags.read_only |= flags.has('r');
+    let idle_timeout = match flags.get('i') {
+        Some(minutes) => {
+            let minutes: u64 = minutes
+                .parse()
+                .map_err(|_| format!("bad idle timeout: {}", minutes))?;
+            Some(Duration::from_secs(minutes * 60))
+        }
+        None => None,
+    };
+
+    if attach_flags.control && flags.has('H') {
+        return Err("control mode needs a session on this machine".to_string());
+    }
+    if let Some(address) = flags.get('H') {
+        return Client::new(attach_flags)
+            .attach_tcp(address, &token()?, tls_client_config(&flags)?, idle_timeout)
+            .map_err(|e| format!("can't attach to {}: {}", address, e));
+    }
+
+    if flags.has('A') {
+        return Err("TLS needs -H".to_string());
+    }
+
+    // attaching to a session that doesn't exist yet creates it
+    let name = match flags.get('t') {
+        Some(name) => resolve_target(name)?,
+        None if running_sessions().is_ok_and(|s| s.is_empty()) => next_session_name(),
+        None => target_session(&flags)?,
+    };
+    if !is_running(&name) {
+        start_server(&name, None, PaneSpec::default(), None, &[])?;
+    }
+    if attach_flags.control {
+        return control::run(&name).map_err(|e| format!("can't attach to {}: {}", name, e));
+    }
+    Client::new(attach_flags)
+        .attach(&name, idle_timeout)
+        .map_err(|e| format!("can't attach to {}: {}", name, e))
+}
+
+/// Creates a session whose pane is a read-only view of another session's,
+/// to keep an eye on it from another workspace.
+fn mirror_pane(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "ds:t:")?;
+    flags.no_args()?;
+    let source = flags
+        .get('s')
+        .ok_or("mirror-pane expects a source session with -s")?;
+    let source = &resolve_target(source)?;
+    if !is_running(source) {
+        return Err(format!("can't find session: {}", source));
+    }
+    let name = match flags.get('t') {
+        Some(name) if is_running(name) => return Err(format!("duplicate session: {}", name)),
+        Some(name) => name.to_string(),
+        None => next_session_name(),
+    };
+
+    // the pane runs a read-only client of the source session
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    let command: Vec<String> = [&exe.to_string_lossy(), "attach", "-r", "-t", source]
+        .iter()
+        .map(|arg| arg.to_string())
+        .collect();
+    let pane = PaneSpec {
+        command,
+        ..PaneSpec::default()
+    };
+    start_server(&name, None, pane, None, &[])?;
+    if flags.has('d') {
+        return Ok(());
+    }
+    attach(&name, None)
+}
+
+/// Serves a session over UDP to a sync-client on another machine, which
+/// stays in sync on lossy or slow links like mosh. Prints the port and the
+/// key to connect with, usually read over ssh, then serves in the
+/// background.
+fn sync_server(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "p:t:")?;
+    flags.no_args()?;
+    let name = target_session(&flags)?;
+    if !is_running(&name) {
+        return Err(format!("can't find session: {}", name));
+    }
+    let port = match flags.get('p') {
+        Some(port) => Some(port.parse().map_err(|_| format!("bad port: {}", port))?),
+        None => None,
+    };
+    let socket = remote::bind(port).map_err(|e| e.to_string())?;
+    let port = socket.local_addr().map_err(|e| e.to_string())?.port();
+    let key = SyncKey::generate().map_err(|e| e.to_string())?;
+    println!("RSTMUX CONNECT {} {}", port, key);
+
+    let daemon = daemonize(Path::new(&log_path(&name))).map_err(|e| e.to_string())?;
+    if daemon {
+        let code = match remote::serve(&name, socket, &key) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("rstmux: sync-server: {}", e);
+                1
+            }
+        };
+        exit(code);
+    }
+    Ok(())
+}
+
+/// Shows a session served by sync-server, with the key it printed in
+/// RSTMUX_KEY so it isn't seen in the process list. Typing is echoed before
+/// the server confirms it while the connection is slow, or as -p says.
+fn sync_client(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "f:p:r")?;
+    let [host, port] = flags.rest.as_slice() else {
+        return Err("sync-client expects a host and a port".to_string());
+    };
+    let port = port.parse().map_err(|_| format!("bad port: {}", port))?;
+    let key = std::env::var("RSTMUX_KEY").map_err(|_| "RSTMUX_KEY is not set")?;
+    let key = SyncKey::parse(&key)?;
+    let mut attach_flags = AttachFlags::parse(flags.get('f').unwrap_or_default())?;
+    attach_flags.read_only |= flags.has('r');
+    if attach_flags.accessible {
+        return Err("accessible clients can't sync, they are sent text".to_string());
+    }
+
+    let predict = PredictMode::parse(flags.get('p').unwrap_or("adaptive"))?;
+
+    let why = remote::connect(host, port, &key, attach_flags, predict)
+        .map_err(|e| format!("can't connect to {}: {}", host, e))?;
+    println!("{}", why);
+    Ok(())
+}
+
+/// Serves a session listening on TCP on another machine under a local
+/// name, so it is attached to and listed like a session on this one. The
+/// relay runs in the background until the remote session is gone.
+fn relay(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "A:C:K:s:")?;
+    let [address] = flags.rest.as_slice() else {
+        return Err("relay expects a host:port".to_string());
+    };
+    let remote = RemoteSession {
+        address: address.clone(),
+        token: token()?,
+        tls: tls_client_config(&flags)?,
+    };
+    // a wrong address or token is reported here rather than in the log
+    remote
+        .connect()
+        .map_err(|e| format!("can't reach {}: {}", address, e))?;
+    let name = match flags.get('s') {
+        Some(name) => name.to_string(),
+        None => address
+            .rsplit_once(':')
+            .map_or(address.as_str(), |(host, _)| host)
+            .to_string(),
+    };
+    if is_running(&name) {
+        return Err(format!("duplicate session: {}", name));
+    }
+    start_relay(&name, remote)
+}
+
+/// Starts a relay to `remote` on the socket of session `name` in the
+/// background, taking the socket over from a server that has it, and waits
+/// until it serves.
+fn start_relay(name: &str, remote: RemoteSession) -> Result<(), String> {
+    let daemon = daemonize(Path::new(&log_path(name))).map_err(|e| e.to_string())?;
+    if daemon {
+        let code = match relay::serve(name, remote) {
+            Ok(()) => 0,
+            Err(e) => {
+                eprintln!("rstmux: relay: {}", e);
+                1
+            }
+        };
+        exit(code);
+    }
+    wait_for(name, || Path::new(&relay_path(name)).exists())
+}
+
+/// Moves a session to the machine where receive-session waits at
+/// `host:port`. The pane's command can't move, so it starts again there in
+/// the same directory with the same environment, below the history and the
+/// screen it had here. A relay takes over the session's name here, and the
+/// clients attached to it carry on there without detaching.
+fn migrate_session(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "A:C:K:t:")?;
+    let [address] = flags.rest.as_slice() else {
+        return Err("migrate-session expects a host:port".to_string());
+    };
+    let name = target_session(&flags)?;
+    if !is_running(&name) {
+        return Err(format!("can't find session: {}", name));
+    }
+    if Path::new(&relay_path(&name)).exists() {
+        return Err(format!("{} runs on another machine already", name));
+    }
+    let remote = RemoteSession {
+        address: address.clone(),
+        token: token()?,
+        tls: tls_client_config(&flags)?,
+    };
+
+    // the relay replaces the socket, the server is told to hand over on a
+    // connection made before
+    let mut server = UnixStream::connect(socket_path(&name)).map_err(|e| e.to_string())?;
+    let command = |args: &[&str]| {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match client::run_command(&name, &args) {
+            Ok(result) => result,
+            Err(e) => Err(e.to_string()),
+        }
+    };
+    let spec = command(&["export-session"])?;
+    let history = command(&["capture-pane", "-e"])?;
+    migrate::send(&remote, &spec, &history)
+        .map_err(|e| format!("can't migrate to {}: {}", address, e))?
+        .map_err(|e| format!("{}: {}", address, e))?;
+
+    // the session already runs there, the one here goes on as well
+    start_relay(&name, remote).map_err(|e| format!("{} runs on {} too: {}", name, address, e))?;
+    let hand_over = ["hand-over".to_string(), address.clone()];
+    client::run_command_on(&mut server, &hand_over)
+        .map_err(|e| e.to_string())?
+        .map_err(|e| format!("{}: {}", name, e))?;
+    println!("{} moved to {}", name, address);
+    Ok(())
+}
+
+/// Waits at `-l address` for migrate-session to move a session here from
+/// another machine with the token in RSTMUX_TOKEN, then runs it under its
+/// name or the one given with -s. It goes on listening there for the
+/// session's clients like new-session -l.
+fn receive_session(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "A:C:K:l:s:")?;
+    flags.no_args()?;
+    let address = flags
+        .get('l')
+        .ok_or("receive-session expects an address with -l")?;
+    let tcp = TcpAccess {
+        listener: TcpListener::bind(address)
+            .map_err(|e| format!("can't listen on {}: {}", address, e))?,
+        token: token()?,
+        tls: tls_server_config(&flags)?,
+    };
+
+    let migration = migrate::receive(&tcp).map_err(|e| e.to_string())?;
+    let start = || {
+        let spec = SessionSpec::parse_yaml(&migration.spec)?;
+        let name = flags.get('s').unwrap_or(&spec.name).to_string();
+        if is_running(&name) {
+            return Err(format!("duplicate session: {}", name));
+        }
+        // sessions have a single window with a single pane for now
+        let window = spec.windows.first();
+        let pane = window.and_then(|window| window.panes.first());
+        let mut pane = pane.cloned().unwrap_or_default();
+        // a directory of the other machine may not be on this one
+        pane.cwd = pane.cwd.filter(|dir| Path::new(dir).is_dir());
+        let tcp = TcpAccess {
+            listener: tcp.listener.try_clone().map_err(|e| e.to_string())?,
+            token: tcp.token.clone(),
+            tls: tcp.tls.clone(),
+        };
+        let window = window.map(|window| window.name.as_str());
+        start_server(&name, window, pane, Some(tcp), &migration.history)?;
+        Ok(name)
+    };
+    let result = start();
+    let answered = migration.answer(&result);
+    let name = result?;
+    answered.map_err(|e| format!("{} runs here, but the other end didn't hear: {}", name, e))?;
+    println!("received session {}", name);
+    Ok(())
+}
+
+/// Recreates a session written by export-session.
+fn import_session(args: &[String]) -> Result<(), String> {
+    let (dry_run, args) = take_dry_run(args);
+    let flags = Flags::parse(&args, "ds:")?;
+    let [path] = flags.rest.as_slice() else {
+        return Err("import-session expects a file".to_string());
+    };
+    let yaml = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+    let spec = SessionSpec::parse_yaml(&yaml).map_err(|e| format!("{}: {}", path, e))?;
+
+    let name = flags.get('s').unwrap_or(&spec.name);
+    if is_running(name) {
+        return Err(format!("duplicate session: {}", name));
+    }
+
+    // sessions have a single window with a single pane for now, named like
+    // the window the pane is in
+    let mut panes = spec
+        .windows
+        .iter()
+        .flat_map(|window| window.panes.iter().map(move |pane| (window, pane)));
+    let (window, pane) = match panes.next() {
+        Some((window, pane)) => (Some(window.name.as_str()), pane.clone()),
+        None => (
+            spec.windows.first().map(|w| w.name.as_str()),
+            PaneSpec::default(),
+        ),
+    };
+    if panes.next().is_some() {
+        eprintln!("rstmux: only the first pane of {} is imported", path);
+    }
+
+    if dry_run {
+        let command = match pane.command.is_empty() {
+            true => "the default shell".to_string(),
+            false => pane.command.join(" "),
+        };
+        println!("would create session {}", name);
+        if let Some(window) = window {
+            println!("  window {}", window);
+        }
+        println!("  command {}", command);
+        if let Some(cwd) = &pane.cwd {
+            println!("  cwd {}", cwd);
+        }
+        for var in &pane.env {
+            println!("  env {}", var);
+        }
+        if let Some(user) = &pane.user {
+            println!("  user {}", user);
+        }
+        println!("  socket {}", socket_path(name));
+        println!("  log {}", log_path(name));
+        return Ok(());
+    }
+
+    start_server(name, window, pane, None, &[])?;
+    if flags.has('d') {
+        return Ok(());
+    }
+    attach(name, None)
+}
+
+/// Lists the key bindings of attaching clients, which are read from the
+/// configuration file by each client rather than kept by the server.
+fn list_keys(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "NT:")?;
+    flags.no_args()?;
+    let bindings = client::load_key_bindings();
+    let lines = bindings.list(flags.get('T'), flags.has('N'));
+    if lines.is_empty() {
+        if let Some(table) = flags.get('T') {
+            return Err(format!("table {} doesn't exist", table));
+        }
+    }
+    let mut stdout = io::stdout();
+    for line in lines {
+        if writeln!(stdout, "{}", line).is_err() {
+            break; // e.g. piped into head
+        }
+    }
+    Ok(())
+}
+
+/// The shared secret of sessions listening on TCP, kept out of the process
+/// list in the environment.
+fn token() -> Result<String, String> {
+    match std::env::var(TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => Ok(token),
+        _ => Err(format!("{} is not set", TOKEN_ENV)),
+    }
+}
+
+/// The certificate a session listening on TCP presents, from -C and -K,
+/// and the CA its clients' certificates must be signed by from -A.
+fn tls_server_config(flags: &Flags) -> Result<Option<Arc<ServerConfig>>, String> {
+    let (cert, key) = match (flags.get('C'), flags.get('K')) {
+        (Some(cert), Some(key)) => (cert, key),
+        (None, None) if flags.has('A') => return Err("-A needs -C and -K".to_string()),
+        (None, None) => return Ok(None),
+        _ => return Err("-C and -K go together".to_string()),
+    };
+    let client_ca = flags.get('A').map(Path::new);
+    tls::server_config(Path::new(cert), Path::new(key), client_ca)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+/// TLS for attaching over TCP, when -A gives the CA the session's
+/// certificate must be signed by. -C and -K are the client's certificate,
+/// for sessions that ask for one.
+fn tls_client_config(flags: &Flags) -> Result<Option<Arc<ClientConfig>>, String> {
+    let identity = match (flags.get('C'), flags.get('K')) {
+        (Some(cert), Some(key)) => Some((Path::new(cert), Path::new(key))),
+        (None, None) => None,
+        _ => return Err("-C and -K go together".to_string()),
+    };
+    let Some(ca) = flags.get('A') else {
+        return match identity {
+            Some(_) => Err("-C and -K need -A".to_string()),
+            None => Ok(None),
+        };
+    };
+    tls::client_config(Path::new(ca), identity)
+        .map(Some)
+        .map_err(|e| e.to_string())
+}
+
+fn attach(name: &str, idle_timeout: Option<Duration>) -> Result<(), String> {
+    Client::new(AttachFlags::default())
+        .attach(name, idle_timeout)
+        .map_err(|e| format!("can't attach to {}: {}", name, e))
+}
+
+/// Whether an error talking to a server means it exited.
+fn is_gone(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::NotFound
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+/// Lists every session that answers, removing the sockets left behind by
+/// servers that are gone.
+fn list_sessions(args: &[String]) -> Result<(), String> {
+    Flags::parse(args, "")?.no_args()?;
+
+    let mut listed = 0;
+    for name in session_names().map_err(|e| e.to_string())? {
+        // a relayed session is listed under its local name
+        let relayed = fs::read_to_string(relay_path(&name)).ok();
+        let via = relayed.map_or(String::new(), |address| format!("{} via ", address));
+        match client::ping(&name, PING_TIMEOUT) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                // nothing listens on the socket anymore
+                let _ = fs::remove_file(socket_path(&name));
+                continue;
+            }
+            Err(e) if is_gone(&e) => continue, // exited in the meantime
+            Err(e) => {
+                println!("{}: {}not responding ({})", name, via, e);
+                listed += 1;
+                continue;
+            }
+        }
+
+        if let Ok(Ok(line)) = client::run_command(&name, &["list-sessions".to_string()]) {
+            match via.is_empty() {
+                true => println!("{}", line),
+                false => println!("{}: {}{}", name, via, line),
+            }
+            listed += 1;
+        }
+    }
+
+    if listed == 0 {
+        return Err("no sessions".to_string());
+    }
+    Ok(())
+}
+
+/// Reports the mistakes in a configuration file, `~/.rstmux.conf` by
+/// default, without starting a server with it.
+fn check_config(args: &[String]) -> Result<(), String> {
+    let flags = Flags::parse(args, "f:")?;
+    flags.no_args()?;
+    let path = match flags.get('f') {
+        Some(path) => PathBuf::from(path),
+        None => config::default_path().ok_or("HOME is not set")?,
+    };
+
+    let errors = config::check(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    for error in &errors {
+        eprintln!("{}:{}", path.display(), error);
+    }
+    match errors.len() {
+        0 => Ok(()),
+        1 => Err("1 error".to_string()),
+        n => Err(format!("{} errors", n)),
+    }
+}
+
+/// Takes `--dry-run` out of `args`, `Flags` only knows single letters.
+fn take_dry_run(args: &[String]) -> (bool, Vec<String>) {
+    let rest: Vec<String> = args
+        .iter()
+        .filter(|arg| *arg != "--dry-run")
+        .cloned()
+        .collect();
+    (rest.len() != args.len(), rest)
+}
+
+/// The kill-session sent to a server, which only says what it would end
+/// on a dry run.
+fn kill_command(dry_run: bool) -> Vec<String> {
+    let mut command = vec!["kill-session".to_string()];
+    if dry_run {
+        command.push("--dry-run".to_string());
+    }
+    command
+}
+
+fn kill_session(args: &[String]) -> Result<(), String> {
+    let (dry_run, args) = take_dry_run(args);
+    let flags = Flags::parse(&args, "t:")?;
+    flags.no_args()?;
+    let name = target_session(&flags)?;
+    send_command(&name, &kill_command(dry_run))
+}
+
+fn kill_server(args: &[String]) -> Result<(), String> {
+    let (dry_run, args) = take_dry_run(args);
+    Flags::parse(&args, "")?.no_args()?;
+
+    // every session has a server of its own
+    for name in running_sessions().map_err(|e| e.to_string())? {
+        send_command(&name, &kill_command(dry_run))?;
+    }
+    Ok(())
+}
+
+/// Shows a message to every client attached to any session, for
+/// display-message -a without a target.
+fn display_everywhere(args: &[String]) -> Result<(), String> {
+    let mut command = vec!["display-message".to_string()];
+    command.extend_from_slice(args);
+    for name in running_sessions().map_err(|e| e.to_string())? {
+        send_command(&name, &command)?;
+    }
+    Ok(())
+}
+
+/// Runs any other command in the target session and prints its output.
+fn send_command(name: &str, args: &[String]) -> Result<(), String> {
+    match client::run_command(name, args) {
+        Ok(Ok(output)) => {
+            // output piped into something like head may be cut short
+            if !output.is_empty() {
+                let _ = writeln!(io::stdout(), "{}", output);
+            }
+            Ok(())
+        }
+        Ok(Err(error)) => Err(error),
+        Err(e) => Err(format!("can't reach session {}: {}", name, e)),
+    }
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let translated;
+    let args = if compat::enabled() {
+        translated = compat::translate(args);
+        &translated
+    } else {
+        args
+    };
+    let Some((command, args)) = args.split_first() else {
+        return attach_session(&[]);
+    };
+
+    match command.as_str() {
+        "new-session" | "new" => new_session(args),
+        "attach-session" | "attach" | "a" => attach_session(args),
+        "list-sessions" | "ls" => list_sessions(args),
+        "mirror-pane" => mirror_pane(args),
+        "import-session" | "import" => import_session(args),
+        "list-keys" | "lsk" => list_keys(args),
+        "check-config" => check_config(args),
+        "sync-server" => sync_server(args),
+        "sync-client" => sync_client(args),
+        "relay" => relay(args),
+        "migrate-session" => migrate_session(args),
+        "receive-session" => receive_session(args),
+        "kill-session" => kill_session(args),
+        "kill-server" => kill_server(args),
+        "display-message" | "display"
+            if args.first().is_some_and(|arg| arg == "-a") && !args.contains(&"-t".to_string()) =>
+        {
+            display_everywhere(args)
+        }
+        "-h" | "--help" | "help" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => {
+            // the first -t is ours, the command's own flags are left to the
+            // server, a pane in the target is passed on like tmux's session:pane
+            let mut command = vec![command.clone()];
+            let mut rest = args.to_vec();
+            let mut flags = Flags::parse(&[], "")?;
+            if let Some(i) = rest.iter().position(|arg| arg == "-t") {
+                let target = rest.get(i + 1).ok_or("-t expects a value")?.clone();
+                rest.drain(i..i + 2);
+                let session = match target.split_once(':') {
+                    Some((session, pane)) => {
+                        command.extend(["-t".to_string(), pane.to_string()]);
+                        session.to_string()
+                    }
+                    None => target,
+                };
+                flags = Flags::parse(&["-t".to_string(), session], "t:")?;
+            }
+            let name = target_session(&flags)?;
+            command.extend(rest);
+            send_command(&name, &command)
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("rstmux: {}", e);
+        if e.starts_with("unknown flag") || e.starts_with("unexpected argument") {
+            eprintln!("{}", USAGE);
+        }
+        exit(1);
+    }
+}
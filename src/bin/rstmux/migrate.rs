@@ -0,0 +1,110 @@
+use std::io;
+use std::time::Duration;
+
+use replicating_tmux::command::CommandResult;
+use replicating_tmux::protocol::Message;
+use replicating_tmux::socket::Stream;
+use replicating_tmux::tls;
+
+use crate::relay::RemoteSession;
+use crate::server::{token_matches, TcpAccess};
+
+/// How long receive-session waits for each message of the handshake, so a
+/// connection that sends nothing doesn't hold up the session on its way.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long migrate-session waits for the other machine to start the session.
+const START_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A session on its way from another machine, see `Message::Migrate`.
+pub struct Migration {
+    /// Where to answer whether it runs here now.
+    pub stream: Stream,
+    pub spec: String,
+    pub history: Vec<u8>,
+}
+
+impl Migration {
+    /// Tells migrate-session under which name the session runs here, or
+    /// why it doesn't.
+    pub fn answer(mut self, result: &CommandResult) -> io::Result<()> {
+        let done = match result {
+            Ok(output) => Message::CommandDone {
+                success: true,
+                output: output.clone(),
+            },
+            Err(output) => Message::CommandDone {
+                success: false,
+                output: output.clone(),
+            },
+        };
+        done.write_to(&mut self.stream)
+    }
+}
+
+/// Sends a session to receive-session on `remote`: its spec as
+/// export-session writes it and its history as `capture-pane -e` prints
+/// it. Returns what the other end answered.
+pub fn send(remote: &RemoteSession, spec: &str, history: &str) -> io::Result<CommandResult> {
+    let mut stream = remote.connect()?;
+    let migrate = Message::Migrate {
+        spec: spec.to_string(),
+        history: history.as_bytes().to_vec(),
+    };
+    migrate.write_to(&mut stream)?;
+
+    stream.set_read_timeout(Some(START_TIMEOUT))?;
+    match Message::read_from(&mut stream)? {
+        Some(Message::CommandDone {
+            success: true,
+            output,
+        }) => Ok(Ok(output)),
+        Some(Message::CommandDone {
+            success: false,
+            output,
+        }) => Ok(Err(output)),
+        _ => Err(io::Error::other("the other end hung up")),
+    }
+}
+
+/// Waits for migrate-session to connect over `tcp` and send a session,
+/// like a client attaching it must send the token first. Connections that
+/// don't are hung up on, and waiting goes on.
+pub fn receive(tcp: &TcpAccess) -> io::Result<Migration> {
+    loop {
+        let (stream, address) = tcp.listener.accept()?;
+        let stream = match &tcp.tls {
+            Some(config) => tls::accept(stream, config.clone()).map(Stream::Unix),
+            None => Ok(Stream::Tcp(stream)),
+        };
+        match stream.and_then(|stream| handshake(stream, &tcp.token)) {
+            Ok(Some(migration)) => return Ok(migration),
+            Ok(None) => eprintln!("{} sent no valid token", address),
+            Err(e) => eprintln!("connection from {} failed: {}", address, e),
+        }
+    }
+}
+
+/// Checks the token, answers the ping that follows it like a session would
+/// and reads the migration. None when the token doesn't match.
+fn handshake(mut stream: Stream, token: &str) -> io::Result<Option<Migration>> {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+    match Message::read_from(&mut stream)? {
+        Some(Message::Token(sent)) if token_matches(&sent, token) => {}
+        _ => return Ok(None),
+    }
+    loop {
+        match Message::read_from(&mut stream)? {
+            Some(Message::Ping) => Message::Pong.write_to(&mut stream)?,
+            Some(Message::Migrate { spec, history }) => {
+                stream.set_read_timeout(None)?;
+                return Ok(Some(Migration {
+                    stream,
+                    spec,
+                    history,
+                }));
+            }
+            _ => return Err(io::Error::other("expected a session")),
+        }
+    }
+}
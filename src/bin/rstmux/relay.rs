@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::Shutdown;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use replicating_tmux::poll::{poll, pollfd};
+use replicating_tmux::socket::{bind_unix_socket, relay_path, socket_path, Stream};
+use rustls::ClientConfig;
+
+use crate::client;
+
+/// How often the relay looks whether the remote session went away while
+/// nobody connects.
+const ACCEPT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A session listening on TCP on another machine, see new-session -l.
+#[derive(Clone)]
+pub struct RemoteSession {
+    pub address: String,
+    pub token: String,
+    pub tls: Option<Arc<ClientConfig>>,
+}
+
+impl RemoteSession {
+    pub fn connect(&self) -> io::Result<Stream> {
+        client::connect_tcp(&self.address, &self.token, self.tls.clone())
+    }
+}
+
+/// Serves a remote session on the socket of the local session `name`:
+/// every connection to it, to attach, run a command or for list-sessions'
+/// ping, gets a connection of its own to the remote session, and what
+/// either end sends is passed on as it is. Ends once nothing listens on
+/// the remote address anymore.
+pub fn serve(name: &str, remote: RemoteSession) -> io::Result<()> {
+    let path = socket_path(name);
+    let listener = bind_unix_socket(&path)?;
+    let bound = fs::metadata(&path)?.ino();
+    fs::write(relay_path(name), &remote.address)?;
+    let result = accept(&listener, remote);
+
+    // a session may have taken the name over since, like a migrated one
+    if fs::metadata(&path).is_ok_and(|meta| meta.ino() == bound) {
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(relay_path(name));
+    }
+    result
+}
+
+fn accept(listener: &UnixListener, remote: RemoteSession) -> io::Result<()> {
+    let gone = Arc::new(AtomicBool::new(false));
+    while !gone.load(Relaxed) {
+        if poll(
+            &mut [pollfd(listener.as_raw_fd(), libc::POLLIN)],
+            ACCEPT_INTERVAL,
+        )? == 0
+        {
+            continue;
+        }
+        let (local, _) = match listener.accept() {
+            Ok(accepted) => accepted,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        let remote = remote.clone();
+        let gone = gone.clone();
+        thread::spawn(move || match remote.connect() {
+            Ok(stream) => relay(local, stream),
+            Err(e) if e.kind() == ErrorKind::ConnectionRefused => {
+                eprintln!("{} is gone", remote.address);
+                gone.store(true, Relaxed);
+            }
+            // the local end sees the connection close
+            Err(e) => eprintln!("can't reach {}: {}", remote.address, e),
+        });
+    }
+    Ok(())
+}
+
+/// Passes what either end sends on to the other until one hangs up.
+fn relay(local: UnixStream, remote: Stream) {
+    let (Ok(mut local_in), Ok(mut remote_out)) = (local.try_clone(), remote.try_clone()) else {
+        return;
+    };
+    let upstream = thread::spawn(move || {
+        let _ = io::copy(&mut local_in, &mut remote_out);
+        let _ = remote_out.shutdown(Shutdown::Write);
+    });
+
+    let (mut local_out, mut remote_in) = (local, remote);
+    let _ = io::copy(&mut remote_in, &mut local_out);
+    // the copy upstream may be waiting on a client that is still there
+    let _ = local_out.shutdown(Shutdown::Both);
+    let _ = remote_in.shutdown(Shutdown::Both);
+    let _ = upstream.join();
+}
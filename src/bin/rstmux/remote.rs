@@ -0,0 +1,554 @@
+use std::collections::VecDeque;
+use std::io::{self, stdin, stdout, ErrorKind, Read, Write};
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::ops::RangeInclusive;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use replicating_tmux::keys::KeyDispatcher;
+use replicating_tmux::predict::{PredictMode, Predictor};
+use replicating_tmux::protocol::{Message, PaneExit};
+use replicating_tmux::socket::socket_path;
+use replicating_tmux::sync::{
+    Assembler, Channel, Direction, InputPacket, StatePacket, SyncKey, MAX_FRAGMENT,
+};
+use replicating_tmux::terminal::{Frame, Terminal};
+use termion::raw::IntoRawMode;
+
+use crate::client::{self, AttachFlags, RESET_TERMINAL};
+
+/// The ports sync-server tries when none is given, the same as mosh's.
+const PORTS: RangeInclusive<u16> = 60001..=60999;
+
+/// The least time between two states, changes in between are sent together.
+const SEND_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How long a state or input goes unacknowledged before it is sent again.
+const RETRANSMIT: Duration = Duration::from_millis(250);
+
+/// How often each end says it is still there when it has nothing to send.
+const KEEPALIVE: Duration = Duration::from_secs(3);
+
+/// How long sync-server waits for a client before giving up.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long sync-server keeps telling a client the session is over.
+const END_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The most states kept for diffing against, a client further behind is
+/// sent a redraw.
+const MAX_STATES: usize = 64;
+
+/// C-^ followed by a dot quits sync-client when the server can't be
+/// reached to detach, like in mosh.
+const ESCAPE: u8 = 0x1e;
+
+/// The socket sync-server listens on, on `port` or the first free port.
+pub fn bind(port: Option<u16>) -> io::Result<UdpSocket> {
+    match port {
+        Some(port) => UdpSocket::bind(("0.0.0.0", port)),
+        None => PORTS
+            .clone()
+            .find_map(|port| UdpSocket::bind(("0.0.0.0", port)).ok())
+            .ok_or_else(|| io::Error::new(ErrorKind::AddrInUse, "no free port")),
+    }
+}
+
+/// The server end of a UDP connection. It attaches to the session like any
+/// client and keeps a model of what the client's terminal should show, the
+/// client is sent how to get there from the last state it acknowledged
+/// rather than every byte the session drew.
+struct Relay {
+    socket: UdpSocket,
+    channel: Channel,
+    /// Where the client was last heard from, it may move between addresses.
+    peer: Option<SocketAddr>,
+    server_in: UnixStream,
+    terminal: Arc<Mutex<Terminal>>,
+    changed: Arc<AtomicBool>,
+    /// Why the session is over for this client, once it is.
+    ended: Arc<Mutex<Option<String>>>,
+    /// How much of the client's input has arrived, and the start of a
+    /// message whose rest hasn't.
+    received: u64,
+    partial: Vec<u8>,
+    ack_due: bool,
+    /// The states the client may still acknowledge, oldest first.
+    states: VecDeque<(u64, Frame)>,
+    /// The last state the client showed, 0 before the first.
+    acked: u64,
+    /// The last redraw sent because the client's screen went astray, asks
+    /// from before it arrived are old news.
+    redrawn: u64,
+    sent_at: Instant,
+}
+
+/// Serves the session to a sync-client until the session ends for it.
+pub fn serve(session_name: &str, socket: UdpSocket, key: &SyncKey) -> io::Result<()> {
+    let stream = UnixStream::connect(socket_path(session_name))?;
+    socket.set_read_timeout(Some(Duration::from_millis(10)))?;
+    let relay = Relay {
+        socket,
+        channel: Channel::new(key, Direction::ToClient),
+        peer: None,
+        server_in: stream.try_clone()?,
+        terminal: Arc::new(Mutex::new(Terminal::default())),
+        changed: Arc::new(AtomicBool::new(false)),
+        ended: Arc::new(Mutex::new(None)),
+        received: 0,
+        partial: vec![],
+        ack_due: false,
+        states: VecDeque::new(),
+        acked: 0,
+        redrawn: 0,
+        sent_at: Instant::now(),
+    };
+    relay.read_server(stream, session_name);
+    relay.run()
+}
+
+impl Relay {
+    /// Feeds what the session draws into the model of the client's terminal.
+    fn read_server(&self, mut server_out: UnixStream, session_name: &str) {
+        let terminal = self.terminal.clone();
+        let changed = self.changed.clone();
+        let ended = self.ended.clone();
+        let session_name = session_name.to_string();
+
+        thread::spawn(move || {
+            let mut exit = None;
+            let why = loop {
+                match Message::read_from(&mut server_out) {
+                    Ok(Some(Message::Data(data))) => {
+                        terminal.lock().unwrap().process(&data);
+                        changed.store(true, Relaxed);
+                    }
+                    Ok(Some(Message::Detach)) => {
+                        break format!("[detached (from session {})]", session_name);
+                    }
+                    Ok(Some(Message::Exited(status))) => exit = Some(status),
+                    Ok(Some(_)) => {}
+                    _ => break format!("[{}]", exit.unwrap_or(PaneExit::Unknown)),
+                }
+            };
+            *ended.lock().unwrap() = Some(why);
+        });
+    }
+
+    fn run(mut self) -> io::Result<()> {
+        let started = Instant::now();
+        let mut end: Option<(u64, Instant)> = None;
+        let mut buf = vec![0u8; 65536];
+
+        loop {
+            match self.socket.recv_from(&mut buf) {
+                Ok((len, from)) => {
+                    let packet = self.channel.open(&buf[..len]);
+                    if let Some(packet) = packet.and_then(|p| InputPacket::decode(&p)) {
+                        self.peer = Some(from);
+                        self.receive(packet)?;
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => return Err(e),
+            }
+            if self.peer.is_none() {
+                if self.ended.lock().unwrap().is_some() {
+                    return Ok(());
+                }
+                if started.elapsed() >= CONNECT_TIMEOUT {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "no client connected"));
+                }
+                continue;
+            }
+
+            // the last state says why the session is over
+            let ended = self.ended.lock().unwrap().clone();
+            if let Some(why) = ended {
+                let num = self.states.back().map_or(self.acked, |(num, _)| *num);
+                let (num, since) = *end.get_or_insert((num + 1, Instant::now()));
+                if self.acked >= num || since.elapsed() >= END_TIMEOUT {
+                    return Ok(());
+                }
+                if self.sent_at.elapsed() >= RETRANSMIT {
+                    self.send(num, self.acked, true, why.as_bytes());
+                }
+                continue;
+            }
+            self.send_state();
+        }
+    }
+
+    /// Takes the client's acknowledgement and passes on its new input.
+    fn receive(&mut self, packet: InputPacket) -> io::Result<()> {
+        if packet.state_ack > self.acked {
+            self.acked = packet.state_ack;
+            while self
+                .states
+                .front()
+                .is_some_and(|(num, _)| *num < self.acked)
+            {
+                self.states.pop_front();
+            }
+        }
+
+        if packet.resync && packet.state_ack >= self.redrawn {
+            self.resync();
+        }
+
+        // input sent again may overlap what already arrived
+        self.ack_due |= !packet.data.is_empty();
+        let end = packet.offset + packet.data.len() as u64;
+        if packet.offset > self.received || end <= self.received {
+            return Ok(());
+        }
+        let new = &packet.data[(self.received - packet.offset) as usize..];
+        self.partial.extend_from_slice(new);
+        self.received = end;
+        while let Some(message) = Message::take_from(&mut self.partial)? {
+            if let Message::Resize { rows, cols, .. } = message {
+                self.terminal.lock().unwrap().resize(rows, cols);
+            }
+            message.write_to(&mut self.server_in)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the latest state again as a redraw from scratch, as a new
+    /// state so the client takes it.
+    fn resync(&mut self) {
+        let Some((num, frame)) = self.states.pop_back() else {
+            return;
+        };
+        self.states.clear();
+        self.states.push_back((num + 1, frame));
+        self.redrawn = num + 1;
+        self.send_latest();
+    }
+
+    /// The checksum of a state the client may show, 0 if it is no longer kept.
+    fn checksum(&self, num: u64) -> u64 {
+        self.states
+            .iter()
+            .find(|(kept, _)| *kept == num)
+            .map_or(0, |(_, frame)| frame.checksum())
+    }
+
+    /// Sends the latest state when the screen changed, again when the
+    /// client doesn't acknowledge it, or just an acknowledgement of input.
+    fn send_state(&mut self) {
+        let since = self.sent_at.elapsed();
+        if self.changed.load(Relaxed) && since >= SEND_INTERVAL {
+            self.changed.store(false, Relaxed);
+            let frame = {
+                let terminal = self.terminal.lock().unwrap();
+                let screen = terminal.screen();
+                terminal.frame(screen.rows() as u16, screen.cols() as u16, &[])
+            };
+            if self.states.back().is_none_or(|(_, last)| *last != frame) {
+                let num = self.states.back().map_or(self.acked, |(num, _)| *num) + 1;
+                self.states.push_back((num, frame));
+                if self.states.len() > MAX_STATES {
+                    self.states.pop_front();
+                }
+                return self.send_latest();
+            }
+        }
+        let latest = self.states.back().map_or(0, |(num, _)| *num);
+        if latest > self.acked && since >= RETRANSMIT {
+            return self.send_latest();
+        }
+        if self.ack_due || since >= KEEPALIVE {
+            let packet = StatePacket::ack(self.acked, self.received, self.checksum(self.acked));
+            self.send_packets(vec![packet]);
+        }
+    }
+
+    /// The latest state as a diff from the one the client shows, or as a
+    /// redraw if that one is no longer kept.
+    fn send_latest(&mut self) {
+        let Some((num, frame)) = self.states.back() else {
+            return;
+        };
+        let base = self.states.iter().find(|(num, _)| *num == self.acked);
+        let data = frame.render(base.map(|(_, frame)| frame));
+        let base = base.map_or(0, |(num, _)| *num);
+        self.send(*num, base, false, &data);
+    }
+
+    fn send(&mut self, num: u64, base: u64, ended: bool, data: &[u8]) {
+        let checksum = self.checksum(num);
+        let packets = StatePacket::fragments(num, base, self.received, checksum, ended, data);
+        self.send_packets(packets);
+    }
+
+    fn send_packets(&mut self, packets: Vec<StatePacket>) {
+        let Some(peer) = self.peer else {
+            return;
+        };
+        for packet in packets {
+            let packet = self.channel.seal(&packet.encode());
+            // lost packets are sent again, so are ones that failed to send
+            let _ = self.socket.send_to(&packet, peer);
+        }
+        self.sent_at = Instant::now();
+        self.ack_due = false;
+    }
+}
+
+/// The client end of a UDP connection. Everything it would write to the
+/// session socket goes into an input stream that is sent until the server
+/// acknowledges it, and it shows each state the server sends that follows
+/// on from the one it shows, with its guesses at what typing does on top.
+struct Remote {
+    socket: UdpSocket,
+    server: SocketAddr,
+    channel: Channel,
+    /// The input the server hasn't acknowledged, from `offset` on in the stream.
+    pending: Vec<u8>,
+    offset: u64,
+    /// Whether there is input that was never sent.
+    fresh: bool,
+    sent_at: Instant,
+    /// The state on the terminal, 0 before the first.
+    shown: u64,
+    /// Set while the terminal doesn't look like the server says the state
+    /// it shows should, until a redraw arrives.
+    resync: bool,
+    ack_due: bool,
+    assembler: Assembler,
+    predictor: Predictor,
+    /// The end of some input and when it was first sent, to time the round
+    /// trip with.
+    timing: Option<(u64, Instant)>,
+}
+
+/// Shows the session served by sync-server on `host` until it ends for
+/// this client, returning why.
+pub fn connect(
+    host: &str,
+    port: u16,
+    key: &SyncKey,
+    flags: AttachFlags,
+    predict: PredictMode,
+) -> io::Result<String> {
+    let server = (host, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "no address"))?;
+    let socket = match server {
+        SocketAddr::V4(_) => UdpSocket::bind(("0.0.0.0", 0))?,
+        SocketAddr::V6(_) => UdpSocket::bind(("::", 0))?,
+    };
+    socket.set_read_timeout(Some(Duration::from_millis(5)))?;
+    // the server ignores what read-only clients type
+    let predict = match flags.read_only {
+        true => PredictMode::Never,
+        false => predict,
+    };
+    let (cols, rows) = termion::terminal_size()?;
+    let mut remote = Remote {
+        socket,
+        server,
+        channel: Channel::new(key, Direction::ToServer),
+        pending: vec![],
+        offset: 0,
+        fresh: false,
+        sent_at: Instant::now(),
+        shown: 0,
+        resync: false,
+        ack_due: false,
+        assembler: Assembler::new(),
+        predictor: Predictor::new(rows, cols, predict),
+        timing: None,
+    };
+    let mut keys = KeyDispatcher::new(client::load_key_bindings());
+
+    let mut raw = stdout().into_raw_mode()?;
+    if keys.bindings().flow_control() {
+        client::enable_flow_control(&stdout())?;
+    }
+    write!(raw, "\x1b[?1049h")?;
+    raw.flush()?;
+
+    let (features, typed) = client::probe_features(&mut raw)?;
+    let mut size = client::terminal_resize()?;
+    for message in flags.attach_messages(features, size.clone()) {
+        remote.queue(&message);
+    }
+    for message in client::input_messages(&mut keys, &typed) {
+        remote.queue(&message);
+    }
+
+    // make stdin non-blocking
+    let mut stdin = stdin().lock();
+    let fd = stdin.as_raw_fd();
+    let stdin_flags = unsafe { libc::fcntl(fd, libc::F_GETFL, 0) };
+    unsafe { libc::fcntl(fd, libc::F_SETFL, stdin_flags | libc::O_NONBLOCK) };
+
+    let mut buf = vec![0u8; 65536];
+    let mut escaped = false;
+    let why = loop {
+        if let Ok(resize) = client::terminal_resize() {
+            if resize != size {
+                size = resize;
+                if let Message::Resize { rows, cols, .. } = size {
+                    remote.predictor.resize(rows, cols);
+                }
+                remote.queue(&size);
+                remote.queue(&Message::Refresh);
+            }
+        }
+
+        match stdin.read(&mut buf[..128]) {
+            Ok(0) => break "[lost the terminal]".to_string(),
+            Ok(len) => {
+                let input = &buf[..len];
+                if escaped && input[0] == b'.' {
+                    remote.queue(&Message::Detach);
+                    remote.send_input();
+                    break "[disconnected]".to_string();
+                }
+                escaped = input.last() == Some(&ESCAPE);
+                for message in client::input_messages(&mut keys, input) {
+                    remote.queue(&message);
+                    match &message {
+                        Message::Data(data) => remote.predictor.typed(data, remote.end()),
+                        _ => remote.predictor.interrupt(),
+                    }
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => break format!("[{}]", e),
+        }
+
+        match remote.socket.recv(&mut buf) {
+            Ok(len) => {
+                if let Some(why) = remote.receive(&buf[..len]) {
+                    break why;
+                }
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            // e.g. refused while the server is unreachable, it is tried again
+            Err(_) => thread::sleep(Duration::from_millis(5)),
+        }
+        remote.send_input();
+        remote.predictor.expire();
+        raw.write_all(&remote.predictor.render())?;
+        raw.flush()?;
+    };
+
+    // the tty is shared with the shell we return to, so restore blocking reads
+    unsafe { libc::fcntl(fd, libc::F_SETFL, stdin_flags) };
+    write!(raw, "{}", RESET_TERMINAL)?;
+    raw.flush()?;
+    Ok(why)
+}
+
+impl Remote {
+    fn queue(&mut self, message: &Message) {
+        self.pending.extend(message.encode());
+        self.fresh = true;
+    }
+
+    /// The end of the input stream.
+    fn end(&self) -> u64 {
+        self.offset + self.pending.len() as u64
+    }
+
+    /// Handles a packet from the server, returning why the session ended
+    /// once it has.
+    fn receive(&mut self, packet: &[u8]) -> Option<String> {
+        let packet = self.channel.open(packet);
+        let packet = packet.and_then(|p| StatePacket::decode(&p))?;
+        if let Some((end, sent_at)) = self.timing {
+            if packet.input_ack >= end {
+                self.predictor.observe_rtt(sent_at.elapsed());
+                self.timing = None;
+            }
+        }
+        self.predictor.ack(packet.input_ack);
+        if packet.input_ack > self.offset {
+            let acked = (packet.input_ack - self.offset) as usize;
+            self.pending.drain(..acked.min(self.pending.len()));
+            self.offset = packet.input_ack;
+            // the rest of a long input goes out without waiting
+            self.fresh |= !self.pending.is_empty();
+        }
+
+        // an acknowledgement says what the state shown should look like
+        let checksum = packet.checksum;
+        if packet.count == 0 && packet.num == self.shown {
+            self.verify(checksum);
+        }
+
+        let update = self.assembler.add(packet)?;
+        if update.num <= self.shown {
+            return None;
+        }
+        if update.ended {
+            // the server stops sending the end once it hears about it
+            self.shown = update.num;
+            for _ in 0..3 {
+                self.ack_due = true;
+                self.send_input();
+            }
+            return Some(String::from_utf8_lossy(&update.data).into_owned());
+        }
+        // a diff from a state this terminal doesn't show would garble it
+        if update.base != self.shown && update.base != 0 {
+            return None;
+        }
+        self.predictor.update(&update.data);
+        self.shown = update.num;
+        self.ack_due = true;
+        self.resync &= update.base != 0;
+        self.verify(checksum);
+        None
+    }
+
+    /// Asks for a redraw if the terminal doesn't show what the server says
+    /// the state it shows looks like, so it doesn't drift further with
+    /// every diff applied on top.
+    fn verify(&mut self, checksum: u64) {
+        // input the server hasn't taken yet, like a resize, can change it
+        if self.resync || self.shown == 0 || checksum == 0 || !self.pending.is_empty() {
+            return;
+        }
+        if self.predictor.checksum() != checksum {
+            self.resync = true;
+            self.ack_due = true;
+        }
+    }
+
+    /// Sends the input the server hasn't acknowledged and what this
+    /// terminal shows, when there is something new or it is time to.
+    fn send_input(&mut self) {
+        let since = self.sent_at.elapsed();
+        let retransmit = !self.pending.is_empty() && since >= RETRANSMIT;
+        if !self.fresh && !retransmit && !self.ack_due && since < KEEPALIVE {
+            return;
+        }
+        // timed from the first send, a lost packet makes the trip look slow
+        if self.fresh && self.timing.is_none() {
+            self.timing = Some((self.end(), Instant::now()));
+        }
+        let data = &self.pending[..self.pending.len().min(MAX_FRAGMENT)];
+        let packet = InputPacket {
+            state_ack: self.shown,
+            resync: self.resync,
+            offset: self.offset,
+            data: data.to_vec(),
+        };
+        let packet = self.channel.seal(&packet.encode());
+        // lost packets are sent again, so are ones that failed to send
+        let _ = self.socket.send_to(&packet, self.server);
+        self.sent_at = Instant::now();
+        self.fresh = false;
+        self.ack_due = false;
+    }
+}
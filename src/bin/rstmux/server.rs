@@ -0,0 +1,2722 @@
+use regex::Regex;
+use replicating_tmux::acl::{self, Access, Acl};
+use replicating_tmux::bandwidth::{self, Limiter, Meter};
+use replicating_tmux::command::{
+    split_line, ClientFlag, Command, CommandQueue, CommandResult, CommandSource, QueuedCommand,
+};
+use replicating_tmux::compress::{self, Deflater};
+use replicating_tmux::config::{self, ConfigLines};
+use replicating_tmux::copy::{CopyAction, CopyMode};
+use replicating_tmux::features::{ColorDepth, Features};
+use replicating_tmux::format::{self, Variables};
+use replicating_tmux::hooks::{Hook, Hooks, Value};
+use replicating_tmux::keys::{encode_keys, KeyBindings};
+use replicating_tmux::limits::{self, Cgroup, Limits};
+use replicating_tmux::mark::Mark;
+use replicating_tmux::mouse::MouseEvent;
+use replicating_tmux::options::{Options, Scope};
+use replicating_tmux::pipe::{self, PanePipe, PipeTarget};
+use replicating_tmux::poll::{poll, pollfd, Waker};
+use replicating_tmux::prompt::{Prompt, PromptAction};
+use replicating_tmux::protocol::{
+    Message, Outbox, PaneExit, KEEPALIVE_INTERVAL, KEEPALIVE_TIMEOUT,
+};
+use replicating_tmux::pty::{resolve_shell, Pty, PtyCommandBuilder, PtySize, ReadFailure};
+use replicating_tmux::retention::Retention;
+use replicating_tmux::segments::Segments;
+use replicating_tmux::socket::{self, bind_unix_socket, socket_path, Stream};
+use replicating_tmux::spawn::User;
+use replicating_tmux::status::{self, StatusLine, Window};
+use replicating_tmux::terminal::{
+    Attributes, Frame, MouseMode, Narrator, Row, Screen, Terminal, DEFAULT_COLS, DEFAULT_ROWS,
+};
+use replicating_tmux::text;
+use replicating_tmux::tls;
+use replicating_tmux::watchdog::{Watchdog, Worker};
+use replicating_tmux::workspace::{PaneSpec, SessionSpec, WindowSpec};
+use rustls::ServerConfig;
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener};
+use std::os::fd::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// The only pane, in the only window.
+const PANE: Scope = Scope::Pane { window: 0, pane: 0 };
+
+/// How often logs are checked against the retention options.
+const MAINTENANCE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+/// What list-windows shows of each window without -F.
+const LIST_WINDOWS_FORMAT: &str =
+    "#{window_index}: #{window_name}#{?window_active,*,} (#{window_panes} panes) [#{window_width}x#{window_height}]";
+
+/// What list-panes shows of each pane without -F.
+const LIST_PANES_FORMAT: &str =
+    "#{pane_index}: [#{pane_width}x#{pane_height}] #{pane_id}#{?pane_active, (active),}";
+
+/// How list-sessions shows when a session was created, same as tmux.
+const CREATED_FORMAT: &str = "%a %b %e %H:%M:%S %Y";
+
+/// How long input waits for more to arrive so it goes into the pty in one
+/// write, short enough that typing doesn't lag.
+const INPUT_LATENCY: Duration = Duration::from_millis(2);
+
+/// Input stops waiting for more once it has this much.
+const INPUT_BATCH: usize = 4096;
+
+/// How long a client connecting over TCP has to send the token.
+const TOKEN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The most a client connecting over TCP may send before the token has
+/// arrived in full.
+const MAX_TOKEN_INPUT: usize = 4096;
+
+/// Lets clients on other machines attach over TCP, see new-session -l.
+pub struct TcpAccess {
+    pub listener: TcpListener,
+    /// What they must send before anything else, see `Message::Token`.
+    pub token: String,
+    /// Encrypts the connections, see `tls::server_config`.
+    pub tls: Option<Arc<ServerConfig>>,
+}
+
+struct Client {
+    id: usize,
+    stream: Stream,
+    /// Who connected, the user on the session socket or the address of
+    /// one over TCP.
+    peer: String,
+    /// What the user may do, see the allow-users option.
+    access: Access,
+    outbox: Arc<Outbox>,
+    /// The size of the client's terminal, the pane fits the smallest.
+    size: Mutex<Option<PtySize>>,
+    /// Set once the client says it only watches, see `Message::ReadOnly`.
+    read_only: AtomicBool,
+    /// Set once the client shows the session, see `Message::Attach`.
+    attached: AtomicBool,
+    /// Set once the client drives the session in control mode, see
+    /// `Message::Control`.
+    control: AtomicBool,
+    /// Set while the mouse option is on, the client's terminal reports the
+    /// mouse for the server to scroll with.
+    mouse: AtomicBool,
+    /// Frames are sent without colors, see `Frame::strip_style`.
+    text_only: AtomicBool,
+    /// What the client's terminal can do, the colors it can't show are
+    /// sent as the nearest it can.
+    features: Mutex<Features>,
+    /// Set for accessible clients, which are sent lines of text instead of frames.
+    narrator: Mutex<Option<Narrator>>,
+    /// A multi-line paste waiting for the user to confirm it, shown in
+    /// place of the status line.
+    paste: Mutex<Option<Vec<u8>>>,
+    /// The attach-message, shown over the pane until the next key.
+    message: Mutex<Option<String>>,
+    /// A command-prompt being typed in place of the status line, with the
+    /// command it completes.
+    prompt: Mutex<Option<(Prompt, Vec<String>)>>,
+    /// Set while the client looks through the history, its keys move
+    /// around instead of going to the pane.
+    copy: Mutex<Option<CopyMode>>,
+    /// The last frame sent, updates only send what changed since.
+    frame: Mutex<Option<Frame>>,
+    /// The pane as it was when hold-pane held it back, drawn in its place
+    /// until it is let through again.
+    still: Mutex<Option<Frame>>,
+    /// Panes the client subscribed to by id, with the last frame of each
+    /// sent, see `Message::Subscribe`.
+    panes: Mutex<BTreeMap<u32, Option<Frame>>>,
+    /// What was written to the client.
+    meter: Mutex<Meter>,
+    /// Caps what is sent to the client, see the client-rate-limit option.
+    limiter: Mutex<Limiter>,
+    /// Set while an update is held back by the limiter.
+    held: AtomicBool,
+    /// Set once everything queued for the client has been written.
+    flushed: AtomicBool,
+    /// What the client can send to pick up where it was if it crashes,
+    /// issued when it attaches, see `Message::Resume`.
+    resume_token: Mutex<Option<String>>,
+    /// Set once the client said it is leaving, it has nothing to come back to.
+    left: AtomicBool,
+    /// Set if the client picked up where a crashed one left off.
+    resumed: AtomicBool,
+    stop: AtomicBool,
+}
+
+/// What a client that went away without detaching left behind, kept for
+/// resume-timeout in case it comes back.
+struct Resumable {
+    token: String,
+    /// Only the same user can pick it up.
+    peer: String,
+    /// The old client's id, where it stood among the clients. The first
+    /// attached is the owner of a frozen session.
+    id: usize,
+    until: Instant,
+    read_only: bool,
+    text_only: bool,
+    accessible: bool,
+    prompt: Option<(Prompt, Vec<String>)>,
+    copy: Option<CopyMode>,
+    panes: Vec<u32>,
+}
+
+impl Client {
+    pub fn new(id: usize, stream: Stream, peer: String, access: Access, waker: Arc<Waker>) -> Self {
+        Self {
+            id,
+            stream,
+            peer,
+            access,
+            outbox: Arc::new(Outbox::with_waker(waker)),
+            size: Mutex::new(None),
+            read_only: AtomicBool::new(access == Access::ReadOnly),
+            attached: AtomicBool::new(false),
+            control: AtomicBool::new(false),
+            mouse: AtomicBool::new(false),
+            text_only: AtomicBool::new(false),
+            features: Mutex::new(Features::default()),
+            narrator: Mutex::new(None),
+            paste: Mutex::new(None),
+            message: Mutex::new(None),
+            prompt: Mutex::new(None),
+            copy: Mutex::new(None),
+            frame: Mutex::new(None),
+            still: Mutex::new(None),
+            panes: Mutex::new(BTreeMap::new()),
+            meter: Mutex::new(Meter::new()),
+            limiter: Mutex::new(Limiter::new(0)),
+            held: AtomicBool::new(false),
+            flushed: AtomicBool::new(false),
+            resume_token: Mutex::new(None),
+            left: AtomicBool::new(false),
+            resumed: AtomicBool::new(false),
+            stop: AtomicBool::new(false),
+        }
+    }
+
+    pub fn send(&self, message: Message) {
+        self.outbox.push(message);
+    }
+
+    /// Redraws the whole screen, sized to this client if it reported a size.
+    pub fn refresh(&self, terminal: &Terminal, status: &StatusLine) {
+        if !self.is_attached() {
+            return;
+        }
+        if let Some(narrator) = self.narrator.lock().unwrap().as_mut() {
+            // there is nothing to redraw, what was told stays told, and
+            // what changed while held is told once it is let through
+            if !status.held {
+                self.narrate(narrator, terminal);
+            }
+            return;
+        }
+        let data = self.draw(terminal, status, true);
+        self.limiter.lock().unwrap().spend(data.len());
+
+        // queued updates are already part of the full frame
+        self.outbox.replace_data(Message::Data(data));
+    }
+
+    /// Sends whatever changed on the screen or status line since the last
+    /// frame. A client that fell too far behind had its queued updates
+    /// dropped, it is caught up with a full frame instead. A client over
+    /// its rate limit gets nothing until it is back under it.
+    pub fn update(&self, terminal: &Terminal, status: &StatusLine) {
+        // caught up with a redraw once the pane is let through again
+        if status.held {
+            return;
+        }
+        let behind = self.outbox.take_overflow();
+        if behind {
+            for last in self.panes.lock().unwrap().values_mut() {
+                *last = None;
+            }
+        }
+        let mut limiter = self.limiter.lock().unwrap();
+        let held = !behind && !limiter.ready();
+        self.held.store(held, Relaxed);
+        if held {
+            return;
+        }
+        limiter.spend(update_panes(&self.panes, &self.outbox, terminal));
+        drop(limiter);
+        if !self.is_attached() {
+            return;
+        }
+        if let Some(narrator) = self.narrator.lock().unwrap().as_mut() {
+            return self.narrate(narrator, terminal);
+        }
+        let data = self.draw(terminal, status, behind);
+        self.limiter.lock().unwrap().spend(data.len());
+        if behind {
+            self.outbox.replace_data(Message::Data(data));
+        } else if !data.is_empty() {
+            self.outbox.push(Message::Data(data));
+        }
+    }
+
+    fn narrate(&self, narrator: &mut Narrator, terminal: &Terminal) {
+        for line in narrator.narrate(terminal.screen()) {
+            self.outbox.push(Message::Text(line));
+        }
+    }
+
+    fn draw(&self, terminal: &Terminal, status: &StatusLine, full: bool) -> Vec<u8> {
+        let screen = terminal.screen();
+        let (rows, cols) = match *self.size.lock().unwrap() {
+            Some(size) => (size.rows, size.cols),
+            None => (screen.rows() as u16 + status.rows(), screen.cols() as u16),
+        };
+
+        // the prompts are shown even without a status line
+        let mut cursor = None;
+        let footer = match self.paste.lock().unwrap().as_ref() {
+            Some(paste) => vec![Row::from_text(
+                &paste_prompt(paste, cols as usize),
+                status.attrs,
+                cols as usize,
+            )],
+            None => match self.prompt.lock().unwrap().as_ref() {
+                Some((prompt, _)) => {
+                    let (row, col) = prompt.render(status.attrs, cols as usize);
+                    cursor = Some(col);
+                    vec![row]
+                }
+                None if status.visible => vec![status.render(cols as usize)],
+                None => vec![],
+            },
+        };
+        // taken the first time the pane is drawn held, even in copy mode
+        let mut still = self.still.lock().unwrap();
+        match status.held {
+            true if still.is_none() => {
+                let pane_rows = rows.saturating_sub(footer.len() as u16);
+                *still = Some(terminal.frame(pane_rows, cols, &[]));
+            }
+            true => {}
+            false => *still = None,
+        }
+        let mut frame = match (self.copy.lock().unwrap().as_ref(), still.as_ref()) {
+            (Some(copy), _) => copy.frame(screen, rows as usize, cols as usize, &footer),
+            (None, Some(still)) => still.refit(rows as usize, cols as usize, &footer),
+            (None, None) => terminal.frame(rows, cols, &footer),
+        };
+        drop(still);
+        if let Some(message) = self.message.lock().unwrap().as_ref() {
+            frame.overlay(&message_box(message, status.attrs, cols as usize));
+        }
+        if let Some(col) = cursor {
+            frame.show_cursor(rows as usize - 1, col);
+        }
+        if self.mouse.load(Relaxed) {
+            frame.report_mouse();
+        }
+        if self.text_only.load(Relaxed) {
+            frame.strip_style();
+        }
+        let colors = self.features.lock().unwrap().colors;
+        if colors < ColorDepth::TrueColor {
+            frame.limit_colors(colors);
+        }
+        let mut last = self.frame.lock().unwrap();
+        let previous = if full { None } else { last.as_ref() };
+        let data = frame.render(previous);
+        *last = Some(frame);
+        data
+    }
+
+    /// The rows of the client's terminal the pane is shown in.
+    fn pane_rows(&self, terminal: &Terminal, status: &StatusLine) -> usize {
+        match *self.size.lock().unwrap() {
+            Some(size) => size.rows.saturating_sub(status.rows()) as usize,
+            None => terminal.screen().rows(),
+        }
+    }
+
+    pub fn stop(&self) -> io::Result<()> {
+        self.outbox.close();
+        self.stream.shutdown(Shutdown::Both)?;
+        self.stop.store(true, Relaxed);
+        Ok(())
+    }
+
+    pub fn stopped(&self) -> bool {
+        self.stop.load(Relaxed)
+    }
+
+    /// Whether the client shows the session, as opposed to only running commands.
+    pub fn is_attached(&self) -> bool {
+        self.attached.load(Relaxed) && !self.stopped()
+    }
+
+    pub fn is_control(&self) -> bool {
+        self.control.load(Relaxed) && !self.stopped()
+    }
+
+    /// Handles a message from the client, false once the client is done.
+    fn handle(
+        &self,
+        message: Message,
+        server: &Server,
+        server_in: &Sender<Vec<u8>>,
+        commands: &CommandQueue,
+    ) -> bool {
+        let id = self.id;
+        match message {
+            Message::Data(_) if self.message.lock().unwrap().is_some() => {
+                // any key dismisses the message, read-only or not
+                *self.message.lock().unwrap() = None;
+                let refresh = Command::RefreshClient { flag: None };
+                commands.push(refresh, CommandSource::Client(id), |_| {});
+            }
+            Message::Data(data) if self.prompt.lock().unwrap().is_some() => {
+                let mut prompt = self.prompt.lock().unwrap();
+                let action = match prompt.as_mut() {
+                    Some((prompt, _)) => prompt.input(&data),
+                    None => PromptAction::Cancel,
+                };
+                if let PromptAction::Done(text) = &action {
+                    // replaced in each argument, so the text stays one argument
+                    let template = prompt.as_ref().map(|(_, t)| t.clone()).unwrap_or_default();
+                    let args: Vec<String> =
+                        template.iter().map(|arg| arg.replace("%%", text)).collect();
+                    self.queue_command(&args, commands);
+                }
+                if action != PromptAction::Continue {
+                    *prompt = None;
+                }
+                drop(prompt);
+                let refresh = Command::RefreshClient { flag: None };
+                commands.push(refresh, CommandSource::Client(id), |_| {});
+            }
+            Message::Data(data) if self.copy.lock().unwrap().is_some() => {
+                let terminal = server.terminal.lock().unwrap();
+                let clients = server.clients.lock().unwrap();
+                let sync = server.session_flag("copy-mode-sync");
+                let status = server.status.lock().unwrap();
+                let rows = self.pane_rows(&terminal, &status);
+                let mut copy = self.copy.lock().unwrap();
+                let action = match copy.as_mut() {
+                    Some(copy) => copy.input(&data, terminal.screen(), rows),
+                    None => CopyAction::Exit,
+                };
+                if action != CopyAction::Continue {
+                    *copy = None;
+                }
+                drop(copy);
+                self.refresh(&terminal, &status);
+                if sync {
+                    sync_copy(self, &clients, &terminal, &status);
+                }
+                drop((status, clients, terminal));
+                if let CopyAction::Copy(text) = action {
+                    server.yank(text, self);
+                }
+            }
+            Message::Data(_) if self.read_only.load(Relaxed) => {}
+            Message::Data(data) if self.paste.lock().unwrap().is_some() => {
+                // the first key answers the prompt
+                let confirmed = matches!(data.first(), Some(b'y' | b'Y'));
+                let pasted = self.paste.lock().unwrap().take().unwrap_or_default();
+                if confirmed && server_in.send(pasted).is_err() {
+                    return false;
+                }
+                let refresh = Command::RefreshClient { flag: None };
+                commands.push(refresh, CommandSource::Client(id), |_| {});
+            }
+            Message::Data(data)
+                if is_multiline_paste(&data) && server.session_flag("paste-confirm") =>
+            {
+                *self.paste.lock().unwrap() = Some(data);
+                let refresh = Command::RefreshClient { flag: None };
+                commands.push(refresh, CommandSource::Client(id), |_| {});
+            }
+            Message::Data(data) => return server_in.send(data).is_ok(),
+            Message::Resize {
+                rows,
+                cols,
+                pixel_width,
+                pixel_height,
+            } => {
+                *self.size.lock().unwrap() = Some(PtySize {
+                    rows,
+                    cols,
+                    pixel_width,
+                    pixel_height,
+                });
+                server.fit_pane();
+            }
+            Message::Refresh => {
+                let source = CommandSource::Client(id);
+                let refresh = Command::RefreshClient { flag: None };
+                commands.push(refresh, source, |_| {});
+            }
+            // answered right away, so it works even with the queue stuck
+            Message::Command(args) if Command::parse(&args) == Ok(Command::ServerInfo) => {
+                self.outbox.push(command_done(Ok(server.info())));
+            }
+            Message::Command(args) => self.queue_command(&args, commands),
+            Message::ReadOnly => self.read_only.store(true, Relaxed),
+            Message::Features(features) => *self.features.lock().unwrap() = features,
+            Message::Resume(token) if !self.is_attached() => {
+                match server.take_resumable(&token, &self.peer) {
+                    Some(resumable) => self.resume(resumable, server),
+                    None => println!("client {} can't resume, attaching afresh", id),
+                }
+            }
+            Message::Attach if !self.attached.swap(true, Relaxed) => {
+                let options = server.options.lock().unwrap();
+                let timeout = options.number("resume-timeout", Scope::Session);
+                let text = options.text("attach-message", Scope::Session);
+                drop(options);
+                if timeout > 0 {
+                    match resume_token() {
+                        Ok(token) => {
+                            *self.resume_token.lock().unwrap() = Some(token.clone());
+                            self.outbox.push(Message::Resume(token));
+                        }
+                        Err(e) => eprintln!("resume token: {}", e),
+                    }
+                }
+                // a resumed client has seen it
+                if !text.is_empty() && !self.resumed.load(Relaxed) {
+                    *self.message.lock().unwrap() = Some(text.replace("\\n", "\n"));
+                }
+                notify(
+                    &server.clients.lock().unwrap(),
+                    format!("%client-attached {}", id),
+                );
+                let id = Value::Number(id as i64);
+                server.hooks.fire(Hook::ClientAttached, &[("client", id)]);
+            }
+            Message::Control if !self.control.swap(true, Relaxed) => {
+                let terminal = server.terminal.lock().unwrap();
+                let status = server.status.lock().unwrap();
+                let size = server.pane_size(&terminal);
+                self.outbox.push(Message::Notify(format!(
+                    "%session-changed $0 {}",
+                    status.session
+                )));
+                self.outbox.push(Message::Notify(layout_change(size)));
+                drop(status);
+                drop(terminal);
+                server.fit_pane();
+            }
+            // the server has a single pane, others are never sent
+            Message::Subscribe { pane: 0 } => {
+                self.panes.lock().unwrap().insert(0, None);
+                let terminal = server.terminal.lock().unwrap();
+                update_panes(&self.panes, &self.outbox, &terminal);
+            }
+            Message::Unsubscribe { pane } => {
+                self.panes.lock().unwrap().remove(&pane);
+            }
+            Message::Mouse(event) => self.mouse_event(&event, server, server_in),
+            Message::Ping => self.outbox.push(Message::Pong),
+            Message::Detach => {
+                self.left.store(true, Relaxed);
+                return false;
+            }
+            _ => {} // not handled yet
+        }
+        true
+    }
+
+    /// Drops the client once it is done or gone, the pty lives on.
+    /// Copy mode takes the mouse first, then an application that asked for
+    /// it. Otherwise, with the mouse option on, the wheel scrolls up into
+    /// copy mode. Events on the status line are ignored.
+    fn mouse_event(&self, event: &MouseEvent, server: &Server, server_in: &Sender<Vec<u8>>) {
+        let terminal = server.terminal.lock().unwrap();
+        let clients = server.clients.lock().unwrap();
+        let sync = server.session_flag("copy-mode-sync");
+        let status = server.status.lock().unwrap();
+        let rows = self.pane_rows(&terminal, &status);
+        if event.row as usize >= rows {
+            return;
+        }
+        let screen = terminal.screen();
+        let mut copy = self.copy.lock().unwrap();
+        let action = match copy.as_mut() {
+            Some(mode) => mode.mouse(event, screen, rows),
+            None if screen.modes().mouse != MouseMode::Off => {
+                if self.read_only.load(Relaxed) {
+                    return;
+                }
+                let modes = screen.modes();
+                if let Some(data) = event.encode(modes, event.row, event.col) {
+                    let _ = server_in.send(data);
+                }
+                return;
+            }
+            // the alternate screen has no history to scroll through
+            None if event.is_wheel_up() && self.mouse.load(Relaxed) && !screen.is_alternate() => {
+                let mode = copy.insert(CopyMode::scrolled_in(screen));
+                mode.mouse(event, screen, rows)
+            }
+            None => return,
+        };
+        if action != CopyAction::Continue {
+            *copy = None;
+        }
+        drop(copy);
+        self.refresh(&terminal, &status);
+        if sync {
+            sync_copy(self, &clients, &terminal, &status);
+        }
+        drop((status, clients, terminal));
+        if let CopyAction::Copy(text) = action {
+            server.yank(text, self);
+        }
+    }
+
+    /// Queues a command the client sent, its result is sent back. An
+    /// attached client ran it from a key binding or a prompt, where nobody
+    /// would see an error unless it is shown on the screen.
+    fn queue_command(&self, args: &[String], commands: &CommandQueue) {
+        let id = self.id;
+        let attached = self.is_attached();
+        let show_error = move |commands: &CommandQueue, error: &str| {
+            println!("client {} command failed: {}", id, error);
+            if attached {
+                let message = Command::DisplayMessage {
+                    message: error.to_string(),
+                    all: false,
+                };
+                commands.push(message, CommandSource::Client(id), |_| {});
+            }
+        };
+        match Command::parse(args) {
+            Ok(command) => {
+                let outbox = self.outbox.clone();
+                let queue = commands.clone();
+                commands.push(command, CommandSource::Client(id), move |result| {
+                    if let Err(e) = &result {
+                        show_error(&queue, e);
+                    }
+                    outbox.push(command_done(result));
+                });
+            }
+            Err(e) => {
+                show_error(commands, &e);
+                self.outbox.push(command_done(Err(e)));
+            }
+        }
+    }
+
+    fn disconnect(&self, server: &Server) {
+        println!("client {} disconnected", self.id);
+        self.stop.store(true, Relaxed);
+        self.outbox.close();
+        self.flushed.store(true, Relaxed);
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if self.attached.load(Relaxed) {
+            if !self.left.load(Relaxed) && !server.stop.load(Relaxed) {
+                server.keep_resumable(self);
+            }
+            notify(
+                &server.clients.lock().unwrap(),
+                format!("%client-detached {}", self.id),
+            );
+            let id = Value::Number(self.id as i64);
+            server.hooks.fire(Hook::ClientDetached, &[("client", id)]);
+        }
+        if self.attached.load(Relaxed) || self.control.load(Relaxed) {
+            server.fit_pane();
+        }
+    }
+
+    /// Takes over what a crashed client left behind, see `Message::Resume`.
+    fn resume(&self, resumable: Resumable, server: &Server) {
+        println!("client {} resumed client {}", self.id, resumable.id);
+        self.resumed.store(true, Relaxed);
+        self.read_only.fetch_or(resumable.read_only, Relaxed);
+        self.text_only.store(resumable.text_only, Relaxed);
+        if resumable.accessible {
+            *self.narrator.lock().unwrap() = Some(Narrator::new());
+        }
+        *self.prompt.lock().unwrap() = resumable.prompt;
+        *self.copy.lock().unwrap() = resumable.copy;
+        let mut panes = self.panes.lock().unwrap();
+        panes.extend(resumable.panes.into_iter().map(|pane| (pane, None)));
+        drop(panes);
+
+        // back in the old client's place, before those that came after it
+        let mut clients = server.clients.lock().unwrap();
+        if let Some(i) = clients.iter().position(|c| c.id == self.id) {
+            let client = clients.remove(i);
+            let at = clients
+                .iter()
+                .position(|c| c.id > resumable.id)
+                .unwrap_or(clients.len());
+            clients.insert(at, client);
+        }
+    }
+}
+
+/// The client thread's side of a client: its socket and what was read from
+/// it or is still to be written to it.
+struct Connection {
+    client: Arc<Client>,
+    stream: Stream,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    /// The token a client connecting over TCP still has to send, it isn't
+    /// one of the server's clients until then.
+    token: Option<String>,
+    connected: Instant,
+    /// When anything last came from the client.
+    heard: Instant,
+    /// When the client was last pinged.
+    pinged: Instant,
+    /// Set once the client pinged, it answers pings too, see `KEEPALIVE_INTERVAL`.
+    keepalive: bool,
+    /// Whether the client is on another machine, see the compression option.
+    remote: bool,
+    /// Set once the client offered a codec the server picked.
+    deflater: Option<Deflater>,
+}
+
+impl Connection {
+    /// Output is taken from the outbox only as fast as it is written, so
+    /// a redraw can still replace what a slow client hasn't been sent.
+    const MAX_OUTPUT: usize = 64 * 1024;
+
+    fn new(
+        id: usize,
+        stream: Stream,
+        token: Option<String>,
+        peer: String,
+        access: Access,
+        waker: Arc<Waker>,
+    ) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        let client = Arc::new(Client::new(id, stream.try_clone()?, peer, access, waker));
+        Ok(Connection {
+            client,
+            stream,
+            input: vec![],
+            output: vec![],
+            remote: token.is_some(),
+            token,
+            connected: Instant::now(),
+            heard: Instant::now(),
+            pinged: Instant::now(),
+            keepalive: false,
+            deflater: None,
+        })
+    }
+
+    /// Whether the client connected over TCP and didn't send the token in
+    /// time.
+    fn timed_out(&self) -> bool {
+        self.token.is_some() && self.connected.elapsed() >= TOKEN_TIMEOUT
+    }
+
+    /// Pings a client that keeps the connection alive when it has been
+    /// quiet, false once it has been quiet for too long.
+    fn keep_alive(&mut self) -> bool {
+        if !self.keepalive {
+            return true;
+        }
+        if self.heard.elapsed() >= KEEPALIVE_TIMEOUT {
+            println!("client {} stopped answering", self.client.id);
+            return false;
+        }
+        if self.heard.elapsed() >= KEEPALIVE_INTERVAL && self.pinged.elapsed() >= KEEPALIVE_INTERVAL
+        {
+            self.client.send(Message::Ping);
+            self.pinged = Instant::now();
+        }
+        true
+    }
+
+    /// Reads what the client sent and handles every message that fully
+    /// arrived, false once the client is done or gone.
+    fn receive(
+        &mut self,
+        server: &Server,
+        server_in: &Sender<Vec<u8>>,
+        commands: &CommandQueue,
+    ) -> bool {
+        let mut buf = [0u8; 16 * 1024];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => return false,
+                Ok(size) => {
+                    self.input.extend_from_slice(&buf[..size]);
+                    self.heard = Instant::now();
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+
+        if self.token.is_some() && self.input.len() > MAX_TOKEN_INPUT {
+            return false;
+        }
+
+        loop {
+            match Message::take_from(&mut self.input) {
+                Ok(Some(message)) if self.token.is_some() => {
+                    if !self.authenticate(message, server) {
+                        println!("client {} sent no valid token", self.client.id);
+                        return false;
+                    }
+                }
+                Ok(Some(Message::Compress(codecs))) => self.negotiate(&codecs, server),
+                Ok(Some(message)) => {
+                    self.keepalive |= message == Message::Ping;
+                    if !self.client.handle(message, server, server_in, commands) {
+                        return false;
+                    }
+                }
+                Ok(None) => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    /// Checks the first message of a client connecting over TCP, which
+    /// becomes one of the server's clients if it is the token.
+    fn authenticate(&mut self, message: Message, server: &Server) -> bool {
+        let Message::Token(sent) = message else {
+            return false;
+        };
+        let token = self.token.take().unwrap_or_default();
+        if !token_matches(&sent, &token) {
+            return false;
+        }
+        let mut clients = server.clients.lock().unwrap();
+        clients.retain(|c| !c.stopped());
+        clients.push(self.client.clone());
+        true
+    }
+
+    /// Compresses what is sent from now on with the first of the client's
+    /// codecs the server knows, unless the compression option says not to.
+    fn negotiate(&mut self, codecs: &[String], server: &Server) {
+        let option = server
+            .options
+            .lock()
+            .unwrap()
+            .text("compression", Scope::Session);
+        let wanted = match option.as_str() {
+            "on" => true,
+            "off" => false,
+            "remote" => self.remote,
+            _ => {
+                eprintln!("compression: not on, off or remote: {}", option);
+                self.remote
+            }
+        };
+        let codec = codecs
+            .iter()
+            .find(|c| compress::CODECS.contains(&c.as_str()));
+        if let Some(codec) = codec.filter(|_| wanted) {
+            println!("client {} compressed with {}", self.client.id, codec);
+            self.deflater = Some(Deflater::new());
+        }
+    }
+
+    /// Writes as much of the outbox as the socket takes, false once the
+    /// outbox is closed and everything in it was written, or the client
+    /// is gone. Control messages are still popped ahead of queued output.
+    fn flush(&mut self) -> bool {
+        loop {
+            let mut encoded = vec![];
+            while self.output.len() + encoded.len() < Self::MAX_OUTPUT {
+                match self.client.outbox.try_pop() {
+                    Some(message) => encoded.extend_from_slice(&message.encode()),
+                    None => break,
+                }
+            }
+            match &mut self.deflater {
+                Some(deflater) if !encoded.is_empty() => match deflater.compress(&encoded) {
+                    Ok(chunk) => self
+                        .output
+                        .extend_from_slice(&Message::Compressed(chunk).encode()),
+                    Err(_) => return false,
+                },
+                _ => self.output.extend_from_slice(&encoded),
+            }
+            if self.output.is_empty() {
+                return !self.client.outbox.is_drained();
+            }
+
+            match self.stream.write(&self.output) {
+                Ok(0) => return false,
+                Ok(size) => {
+                    self.output.drain(..size);
+                    self.client.meter.lock().unwrap().add(size);
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return true,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => return false,
+            }
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Server {
+    /// None when the pane's command could not be started.
+    pty: Arc<Mutex<Option<Pty>>>,
+    terminal: Arc<Mutex<Terminal>>,
+    clients: Arc<Mutex<Vec<Arc<Client>>>>,
+    status: Arc<Mutex<StatusLine>>,
+    hooks: Hooks,
+    /// When the session was created, in seconds since the epoch.
+    created: libc::time_t,
+    /// How the pane was started, for export-session.
+    pane: PaneSpec,
+    /// Where pipe-pane copies the pane's output to.
+    pipe: Arc<Mutex<Option<PanePipe>>>,
+    /// Set once the pane's command has been reaped.
+    exited: Arc<AtomicBool>,
+    /// The pane's output, for the pane_output_rate format variable.
+    output: Arc<Mutex<Meter>>,
+    /// Changed at runtime with set-option.
+    options: Arc<Mutex<Options>>,
+    /// The text last copied in copy mode, for paste-buffer.
+    buffer: Arc<Mutex<Option<String>>>,
+    /// What the right of the status line shows, never locked while taking
+    /// another lock.
+    segments: Arc<Mutex<Segments>>,
+    /// What crashed clients left behind, see `Message::Resume`.
+    resumable: Arc<Mutex<Vec<Resumable>>>,
+    /// Keeps track of the threads below, see server-info.
+    watchdog: Watchdog,
+    stop: Arc<AtomicBool>,
+}
+
+impl Server {
+    pub fn new(pty: Option<Pty>, pane: PaneSpec, status: StatusLine, hooks: Hooks) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        Server {
+            pty: Arc::new(Mutex::new(pty)),
+            terminal: Arc::new(Mutex::new(Terminal::default())),
+            clients: Arc::new(Mutex::new(vec![])),
+            status: Arc::new(Mutex::new(status)),
+            hooks,
+            created: unsafe { libc::time(std::ptr::null_mut()) },
+            pane,
+            pipe: Arc::new(Mutex::new(None)),
+            exited: Arc::new(AtomicBool::new(false)),
+            output: Arc::new(Mutex::new(Meter::new())),
+            options: Arc::new(Mutex::new(Options::new())),
+            buffer: Arc::new(Mutex::new(None)),
+            segments: Arc::new(Mutex::new(Segments::new())),
+            resumable: Arc::new(Mutex::new(vec![])),
+            watchdog: Watchdog::new(stop.clone()),
+            stop,
+        }
+    }
+
+    pub fn run(&self, session_name: &str, tcp: Option<TcpAccess>) -> io::Result<()> {
+        let (tx, rx) = channel();
+        let (commands, queued) = CommandQueue::new();
+        self.process_output(tx.clone())?;
+        self.process_status()?;
+        self.process_maintenance()?;
+        self.process_commands(queued, tx.clone())?;
+        self.process_clients(session_name, tcp, tx, commands)?;
+        self.process_watchdog();
+        self.hooks.fire(Hook::SessionCreated, &[]);
+        let result = self.process_input(rx);
+        match self.archive() {
+            Ok(Some(path)) => println!("archived to {}", path.display()),
+            Ok(None) => {}
+            Err(e) => eprintln!("archive failed: {}", e),
+        }
+        self.disconnect_clients();
+        result
+    }
+
+    /// Puts the dead pane's error on the screen, with the status a shell
+    /// would report for the same failure.
+    fn show_spawn_error(&self, program: &str, error: &io::Error) {
+        let status = match error.kind() {
+            io::ErrorKind::NotFound => 127,
+            io::ErrorKind::PermissionDenied => 126,
+            _ => 1,
+        };
+        let message = format!(
+            "\x1b[1mrstmux: failed to start {}: {}\x1b[0m\r\n\r\nPane is dead (status {}), press Enter to close\r\n",
+            program, error, status
+        );
+        self.terminal.lock().unwrap().process(message.as_bytes());
+    }
+
+    fn process_commands(
+        &self,
+        queued: Receiver<QueuedCommand>,
+        server_in: Sender<Vec<u8>>,
+    ) -> io::Result<()> {
+        let server = self.clone();
+        let input_limiter = Mutex::new(Limiter::new(0));
+
+        // commands run one at a time, in the order they were queued
+        self.watchdog
+            .spawn_restarting("commands", move |worker| loop {
+                worker.wait();
+                let Ok(queued) = queued.recv() else {
+                    break;
+                };
+                worker.beat();
+                if let Err(e) = server.check_access(&queued.command, queued.source) {
+                    queued.finish(Err(e));
+                    continue;
+                }
+
+                // waiting must not hold up the commands queued after it
+                if let Command::WaitForOutput { .. } = queued.command {
+                    server.wait_for_output(queued);
+                    continue;
+                }
+
+                // the filter may take a while too
+                if let Command::FilterPane(_) = queued.command {
+                    server.filter_pane(queued);
+                    continue;
+                }
+
+                // keys go through the same channel as client input
+                if let Command::SendKeys { keys, literal } = &queued.command {
+                    let data = encode_keys(keys, *literal);
+                    queued.finish(server.type_input(&data, &server_in, &input_limiter, worker));
+                    continue;
+                }
+
+                if let Command::PasteBuffer = queued.command {
+                    let result = match server.paste_buffer() {
+                        Some(data) => server.type_input(&data, &server_in, &input_limiter, worker),
+                        None => Err("no buffers".to_string()),
+                    };
+                    queued.finish(result);
+                    continue;
+                }
+
+                let result = server.execute(&queued.command, queued.source);
+                queued.finish(result);
+            })
+    }
+
+    /// Types what send-keys or paste-buffer sends into the pane, within the
+    /// input-rate-limit budget. It goes a batch at a time so keys typed on
+    /// a client meanwhile get in between instead of waiting for all of it.
+    fn type_input(
+        &self,
+        data: &[u8],
+        server_in: &Sender<Vec<u8>>,
+        limiter: &Mutex<Limiter>,
+        worker: &Worker,
+    ) -> CommandResult {
+        let options = self.options.lock().unwrap();
+        let rate = options.number("input-rate-limit", PANE).max(0) as u64 * 1024;
+        drop(options);
+        let mut limiter = limiter.lock().unwrap();
+        limiter.set_rate(rate);
+        for batch in data.chunks(INPUT_BATCH) {
+            while !limiter.ready() {
+                worker.beat();
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            limiter.spend(batch.len());
+            if server_in.send(batch.to_vec()).is_err() {
+                return Err("pane exited".to_string());
+            }
+        }
+        Ok(String::new())
+    }
+
+    /// Keeps what a client that went away without detaching leaves behind,
+    /// for resume-timeout.
+    fn keep_resumable(&self, client: &Client) {
+        let Some(token) = client.resume_token.lock().unwrap().take() else {
+            return;
+        };
+        let timeout = self
+            .options
+            .lock()
+            .unwrap()
+            .number("resume-timeout", Scope::Session);
+        let panes = client.panes.lock().unwrap().keys().copied().collect();
+        let resumable = Resumable {
+            token,
+            peer: client.peer.clone(),
+            id: client.id,
+            until: Instant::now() + Duration::from_secs(timeout.max(0) as u64),
+            read_only: client.read_only.load(Relaxed),
+            text_only: client.text_only.load(Relaxed),
+            accessible: client.narrator.lock().unwrap().is_some(),
+            prompt: client.prompt.lock().unwrap().take(),
+            copy: client.copy.lock().unwrap().take(),
+            panes,
+        };
+        println!("client {} can resume for {}s", client.id, timeout);
+        let mut resumable_clients = self.resumable.lock().unwrap();
+        resumable_clients.retain(|r| r.until > Instant::now());
+        resumable_clients.push(resumable);
+    }
+
+    /// What the client issued `token` left behind, if it is still kept.
+    fn take_resumable(&self, token: &str, peer: &str) -> Option<Resumable> {
+        let mut resumable = self.resumable.lock().unwrap();
+        resumable.retain(|r| r.until > Instant::now());
+        let i = resumable
+            .iter()
+            .position(|r| r.token == token && r.peer == peer)?;
+        Some(resumable.remove(i))
+    }
+
+    /// Refuses what a client that may only watch can't do, see
+    /// `Command::needs_write_access`.
+    fn check_access(&self, command: &Command, source: CommandSource) -> Result<(), String> {
+        let CommandSource::Client(id) = source else {
+            return Ok(());
+        };
+        let clients = self.clients.lock().unwrap();
+        match clients.iter().find(|c| c.id == id) {
+            Some(client) if client.access == Access::ReadOnly && command.needs_write_access() => {
+                Err(format!(
+                    "{}: {} may only watch",
+                    command.name(),
+                    client.peer
+                ))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Keeps the text `client` copied in copy mode for paste-buffer and
+    /// writes it to the copy-pipe command, to put it on the system clipboard
+    /// say. The client runs the command if copy-pipe-side says so.
+    fn yank(&self, text: String, client: &Client) {
+        let (command, side) = {
+            let options = self.options.lock().unwrap();
+            let command = options.text("copy-pipe", Scope::Session);
+            (command, options.text("copy-pipe-side", Scope::Session))
+        };
+        if side == "client" {
+            client.send(Message::Yank(text.clone()));
+        } else if !command.is_empty() {
+            // the command runs on its own, a slow one never holds up the client
+            let session = self.status.lock().unwrap().session.clone();
+            match PanePipe::open(&PipeTarget::Command(command), &session) {
+                Ok(pipe) => {
+                    pipe.write(text.as_bytes());
+                }
+                Err(e) => eprintln!("copy-pipe: {}", e),
+            }
+        }
+        *self.buffer.lock().unwrap() = Some(text);
+    }
+
+    /// The copied text as typed into the pane, lines end with a carriage
+    /// return like pressing Enter. It is bracketed if the pane asked for
+    /// bracketed paste, so a shell doesn't run it right away.
+    fn paste_buffer(&self) -> Option<Vec<u8>> {
+        let text = self.buffer.lock().unwrap().clone()?;
+        let text = text.replace('\n', "\r");
+        let terminal = self.terminal.lock().unwrap();
+        Some(match terminal.screen().modes().bracketed_paste {
+            true => format!("\x1b[200~{}\x1b[201~", text).into_bytes(),
+            false => text.into_bytes(),
+        })
+    }
+
+    /// Runs the pane's lines through the filter on a thread of its own, one
+    /// line for each row. The lines it printed are shown to the client that
+    /// asked in copy mode, or printed numbered like search-panes matches
+    /// for one that isn't attached.
+    fn filter_pane(&self, queued: QueuedCommand) {
+        let Command::FilterPane(filter) = &queued.command else {
+            return;
+        };
+        let filter = filter.clone();
+        let session = self.status.lock().unwrap().session.clone();
+        let (first, lines) = {
+            let terminal = self.terminal.lock().unwrap();
+            let screen = terminal.screen();
+            let lines: Vec<String> = screen.lines().map(Row::text).collect();
+            (screen.first_line(), lines)
+        };
+        let server = self.clone();
+
+        std::thread::spawn(move || {
+            let kept = match pipe::filter(&filter, &lines, &session) {
+                Ok(kept) if kept.is_empty() => {
+                    return queued.finish(Err(format!("no lines kept by: {}", filter)))
+                }
+                Ok(kept) => kept,
+                Err(e) => return queued.finish(Err(format!("{}: {}", filter, e))),
+            };
+            let terminal = server.terminal.lock().unwrap();
+            let clients = server.clients.lock().unwrap();
+            let current = match queued.source {
+                CommandSource::Client(id) => clients.iter().find(|c| c.id == id && !c.stopped()),
+                CommandSource::Server => None,
+            };
+            let screen = terminal.screen();
+            let Some(client) = current.filter(|c| c.is_attached()) else {
+                let lines: Vec<String> = kept
+                    .iter()
+                    .filter_map(|&i| {
+                        let index = screen.line_index(first + i as u64)?;
+                        let text = text::ellipsize(lines[i].trim(), 80);
+                        Some(format!("%0:{}: {}", index, text))
+                    })
+                    .collect();
+                return queued.finish(Ok(lines.join("\n")));
+            };
+            let kept = kept.iter().map(|&i| first + i as u64).collect();
+            *client.copy.lock().unwrap() = Some(CopyMode::filtered(screen, &filter, kept));
+            client.refresh(&terminal, &server.status.lock().unwrap());
+            queued.finish(Ok(String::new()));
+        });
+    }
+
+    /// Finishes the command once the pane's content matches, content that
+    /// was already there counts so a script can't miss fast output.
+    fn wait_for_output(&self, queued: QueuedCommand) {
+        let Command::WaitForOutput {
+            pane,
+            pattern,
+            timeout,
+        } = &queued.command
+        else {
+            return;
+        };
+        if *pane != 0 {
+            // a single pane for now
+            let error = format!("can't find pane: %{}", pane);
+            return queued.finish(Err(error));
+        }
+        let pattern = match Regex::new(pattern) {
+            Ok(pattern) => pattern,
+            Err(e) => return queued.finish(Err(e.to_string())),
+        };
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let terminal = self.terminal.clone();
+        let stop = self.stop.clone();
+
+        std::thread::spawn(move || loop {
+            if let Some(found) = terminal.lock().unwrap().search(&pattern).pop() {
+                return queued.finish(Ok(found.text.trim_end().to_string()));
+            }
+            if stop.load(Relaxed) {
+                return queued.finish(Err("session exited".to_string()));
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return queued.finish(Err(format!("timed out waiting for: {}", pattern)));
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        });
+    }
+
+    fn execute(&self, command: &Command, source: CommandSource) -> CommandResult {
+        // lock in the same order as the output fan-out
+        let mut terminal = self.terminal.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
+        let current = match source {
+            CommandSource::Client(id) => clients.iter().find(|c| c.id == id && !c.stopped()),
+            CommandSource::Server => None,
+        };
+
+        // the owner is whoever attached first and is still there, the command
+        // line can kill the server anyway so only attached clients are held back
+        if command.is_locked_when_frozen() && self.session_flag("frozen") {
+            let owner = clients
+                .iter()
+                .find(|c| c.is_attached() && !c.read_only.load(Relaxed));
+            if let Some(client) = current.filter(|c| c.is_attached()) {
+                if owner.map(|o| o.id) != Some(client.id) {
+                    return Err(format!("{}: the session is frozen", command.name()));
+                }
+            }
+        }
+
+        match command {
+            Command::DetachClient => {
+                let client = current.ok_or("no current client")?;
+                client.send(Message::Detach);
+                Ok(String::new())
+            }
+            Command::RefreshClient { flag } => {
+                let client = current.ok_or("no current client")?;
+                match flag {
+                    Some((ClientFlag::TextOnly, on)) => client.text_only.store(*on, Relaxed),
+                    Some((ClientFlag::Accessible, on)) => {
+                        *client.narrator.lock().unwrap() = on.then(Narrator::new);
+                        *client.frame.lock().unwrap() = None;
+                    }
+                    None => {}
+                }
+                client.refresh(&terminal, &self.status.lock().unwrap());
+                Ok(String::new())
+            }
+            Command::ListClients => {
+                let lines: Vec<String> = clients
+                    .iter()
+                    .filter(|c| c.is_attached() || c.is_control())
+                    .map(|c| {
+                        let size = match *c.size.lock().unwrap() {
+                            Some(size) => format!("{}x{}", size.cols, size.rows),
+                            None => "unsized".to_string(),
+                        };
+                        let mut mode = String::new();
+                        if c.is_control() {
+                            mode.push_str(" (control)");
+                        }
+                        if c.read_only.load(Relaxed) {
+                            mode.push_str(" (read-only)");
+                        }
+                        if c.text_only.load(Relaxed) {
+                            mode.push_str(" (text-only)");
+                        }
+                        if c.narrator.lock().unwrap().is_some() {
+                            mode.push_str(" (accessible)");
+                        }
+                        let limit = c.limiter.lock().unwrap().rate();
+                        if limit > 0 {
+                            let limit = bandwidth::format_bytes(limit);
+                            mode.push_str(&format!(" (limited to {}/s)", limit));
+                        }
+                        let features = c.features.lock().unwrap();
+                        let mut meter = c.meter.lock().unwrap();
+                        let sent = bandwidth::format_bytes(meter.total());
+                        let rate = bandwidth::format_bytes(meter.rate());
+                        format!(
+                            "{}: {} {}{} [{}] sent {}, {}/s",
+                            c.id, c.peer, size, mode, features, sent, rate
+                        )
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            Command::ListSessions => {
+                // every session has a server of its own, so this is the only one
+                let status = self.status.lock().unwrap();
+                let attached = clients.iter().filter(|c| c.is_attached()).count();
+                Ok(format!(
+                    "{}: {} windows (created {}) ({} attached)",
+                    status.session,
+                    status.windows.len(),
+                    status::format_time(CREATED_FORMAT, self.created),
+                    attached
+                ))
+            }
+            Command::ListWindows { format } => {
+                let status = self.status.lock().unwrap();
+                let format = format.as_deref().unwrap_or(LIST_WINDOWS_FORMAT);
+                let lines: Vec<String> = status
+                    .windows
+                    .iter()
+                    .map(|window| {
+                        let variables = self.format_variables(&terminal, &status, &clients, window);
+                        format::expand(format, &variables)
+                    })
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            Command::ListPanes { format } => {
+                // a single pane for now, in the active window
+                let status = self.status.lock().unwrap();
+                let window = status.windows.iter().find(|w| w.active);
+                let window = window.ok_or("no current window")?;
+                let variables = self.format_variables(&terminal, &status, &clients, window);
+                let format = format.as_deref().unwrap_or(LIST_PANES_FORMAT);
+                Ok(format::expand(format, &variables))
+            }
+            Command::ExportSession => {
+                let spec = self.session_spec(&self.status.lock().unwrap());
+                Ok(spec.to_yaml().trim_end().to_string())
+            }
+            Command::PipePane { target, toggle } => {
+                // like tmux, an open pipe is always closed first
+                let mut pipe = self.pipe.lock().unwrap();
+                let was_open = pipe.take().is_some();
+                if let Some(target) = target.as_ref().filter(|_| !(*toggle && was_open)) {
+                    let session = &self.status.lock().unwrap().session;
+                    *pipe = Some(PanePipe::open(target, session).map_err(|e| e.to_string())?);
+                }
+                Ok(String::new())
+            }
+            Command::CapturePane { escaped: true, .. } => Ok(terminal.replay()),
+            Command::CapturePane { styled: true, .. } => {
+                Ok(terminal.capture().trim_end().to_string())
+            }
+            Command::CapturePane { styled: false, .. } => {
+                let screen = terminal.screen();
+                let lines: Vec<String> = (0..screen.rows())
+                    .map(|r| screen.grid().row(r).text())
+                    .collect();
+                Ok(lines.join("\n").trim_end().to_string())
+            }
+            Command::DisplayMessage { message, all: true } => {
+                let status = self.status.lock().unwrap();
+                for client in clients.iter().filter(|c| c.is_attached()) {
+                    *client.message.lock().unwrap() = Some(message.clone());
+                    client.refresh(&terminal, &status);
+                }
+                Ok(String::new())
+            }
+            Command::DisplayMessage { message, .. } => match current.filter(|c| c.is_attached()) {
+                Some(client) => {
+                    *client.message.lock().unwrap() = Some(message.clone());
+                    client.refresh(&terminal, &self.status.lock().unwrap());
+                    Ok(String::new())
+                }
+                None => Ok(message.clone()),
+            },
+            Command::CopyMode { line } => {
+                let client = current.filter(|c| c.is_attached());
+                let client = client.ok_or("no current client")?;
+                if line.is_some_and(|line| terminal.screen().line(line).is_none()) {
+                    return Err(format!("no line {}", line.unwrap_or_default()));
+                }
+                self.copy_mode(client, *line, &terminal, &clients);
+                Ok(String::new())
+            }
+            Command::JumpToTime(text) => {
+                let client = current.filter(|c| c.is_attached());
+                let client = client.ok_or("no current client")?;
+                let now = unsafe { libc::time(std::ptr::null_mut()) };
+                let time = status::parse_time(text, now);
+                let time = time.ok_or_else(|| format!("invalid time: {}", text))?;
+                let time = UNIX_EPOCH + Duration::from_secs(time.max(0) as u64);
+                let line = terminal.screen().find_time(time);
+                let line = line.ok_or_else(|| format!("no output since {}", text))?;
+                self.copy_mode(client, Some(line), &terminal, &clients);
+                Ok(String::new())
+            }
+            Command::CommandPrompt {
+                label,
+                initial,
+                template,
+            } => {
+                let client = current.filter(|c| c.is_attached());
+                let client = client.ok_or("no current client")?;
+                let status = self.status.lock().unwrap();
+                let window = status.windows.iter().find(|w| w.active);
+                let initial = initial
+                    .replace("#W", window.map_or("", |w| &w.name))
+                    .replace("#S", &status.session);
+                let label = match label {
+                    Some(label) => label.clone(),
+                    None => format!("({}) ", template[0]),
+                };
+                let prompt = Prompt::new(&label, &initial);
+                *client.prompt.lock().unwrap() = Some((prompt, template.clone()));
+                client.refresh(&terminal, &status);
+                Ok(String::new())
+            }
+            Command::RenameWindow(name) => {
+                let mut status = self.status.lock().unwrap();
+                for window in status.windows.iter_mut().filter(|w| w.active) {
+                    window.name = name.clone();
+                }
+                notify(&clients, format!("%window-renamed @0 {}", name));
+                for client in clients.iter() {
+                    client.update(&terminal, &status);
+                }
+                Ok(String::new())
+            }
+            Command::ServerInfo => Ok(self.info()),
+            Command::ShowBuffer => {
+                let buffer = self.buffer.lock().unwrap();
+                buffer.clone().ok_or_else(|| "no buffers".to_string())
+            }
+            Command::ShowTimeline => {
+                let lines = timeline(terminal.screen());
+                if lines.is_empty() {
+                    return Err("no commands, is shell integration set up?".to_string());
+                }
+                match current.filter(|c| c.is_attached()) {
+                    Some(client) => {
+                        *client.message.lock().unwrap() = Some(lines.join("\n"));
+                        client.refresh(&terminal, &self.status.lock().unwrap());
+                        Ok(String::new())
+                    }
+                    None => Ok(lines.join("\n")),
+                }
+            }
+            Command::SearchPanes(pattern) => {
+                let pattern = Regex::new(pattern).map_err(|e| e.to_string())?;
+                let matches = terminal.search(&pattern);
+                if matches.is_empty() {
+                    return Err(format!("no matches for: {}", pattern));
+                }
+
+                // picked from like the lines filter-pane kept, Enter jumps there
+                if let Some(client) = current.filter(|c| c.is_attached()) {
+                    let screen = terminal.screen();
+                    let lines = matches
+                        .iter()
+                        .map(|m| screen.first_line() + m.line as u64)
+                        .collect();
+                    let picker = CopyMode::matches(screen, pattern.as_str(), lines);
+                    *client.copy.lock().unwrap() = Some(picker);
+                    client.refresh(&terminal, &self.status.lock().unwrap());
+                    return Ok(String::new());
+                }
+
+                // a single pane for now, named like tmux pane ids
+                let lines: Vec<String> = matches
+                    .iter()
+                    .map(|m| format!("%0:{}: {}", m.line, text::ellipsize(m.text.trim(), 80)))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            Command::SetHook(hook, handler) => {
+                self.hooks.set(*hook, handler);
+                Ok(String::new())
+            }
+            Command::SetOption {
+                global,
+                window,
+                pane,
+                name,
+                value,
+            } => {
+                let scope = Scope::from_flags(*global, *window, *pane);
+                if let ("status-right", Some(value)) = (name.as_str(), value) {
+                    self.segments.lock().unwrap().check(value)?;
+                }
+                if let ("allow-users", Some(value)) = (name.as_str(), value) {
+                    Acl::parse(value)?;
+                }
+                let mut options = self.options.lock().unwrap();
+                match value {
+                    Some(value) => options.set(scope, name, value)?,
+                    None => options.unset(scope, name)?,
+                }
+                drop(options);
+                self.apply_options(&mut terminal, &clients);
+                Ok(String::new())
+            }
+            Command::ShowOptions {
+                global,
+                window,
+                pane,
+                inherited,
+            } => {
+                let scope = Scope::from_flags(*global, *window, *pane);
+                let options = self.options.lock().unwrap();
+                let lines = options.show(scope, *window || *pane, *inherited);
+                Ok(lines.join("\n"))
+            }
+            Command::HoldPane => {
+                let mut status = self.status.lock().unwrap();
+                status.held = !status.held;
+                for client in clients.iter() {
+                    client.refresh(&terminal, &status);
+                    client.update(&terminal, &status);
+                }
+                Ok(String::new())
+            }
+            Command::SelectPane { mark: None } => Ok(String::new()), // a single pane for now
+            Command::SelectPane { mark: Some(on) } => {
+                let mut status = self.status.lock().unwrap();
+                let this = Mark {
+                    session: status.session.clone(),
+                    pane: 0,
+                };
+
+                // like tmux, marking the marked pane again clears the mark
+                let marked = *on && Mark::load().as_ref() != Some(&this);
+                match marked {
+                    true => this.save(),
+                    false => Mark::clear(),
+                }
+                .map_err(|e| e.to_string())?;
+                update_marked(&mut status);
+                for client in clients.iter() {
+                    client.update(&terminal, &status);
+                }
+                Ok(String::new())
+            }
+            Command::WaitForOutput { .. }
+            | Command::SendKeys { .. }
+            | Command::PasteBuffer
+            | Command::FilterPane(_) => Err(format!("{} can't run here", command.name())),
+            Command::KillSession { dry_run: true } => {
+                let session = self.status.lock().unwrap().session.clone();
+                let mut lines = vec![
+                    format!("would kill session {}", session),
+                    format!("  server pid {}", std::process::id()),
+                    format!("  socket {}", socket_path(&session)),
+                ];
+                match self.pty.lock().unwrap().as_ref() {
+                    Some(pty) => {
+                        let processes = session_processes(pty.pid());
+                        lines.push(format!("  pane processes {}", processes.join(", ")));
+                    }
+                    None => lines.push("  no pane process".to_string()),
+                }
+                let attached = clients.iter().filter(|c| c.is_attached()).count();
+                lines.push(format!("  attached clients {}", attached));
+                let dir = self
+                    .options
+                    .lock()
+                    .unwrap()
+                    .text("archive-dir", Scope::Session);
+                if !dir.is_empty() {
+                    lines.push(format!("  history archived in {}", dir));
+                }
+                Ok(lines.join("\n"))
+            }
+            Command::KillSession { dry_run: false } => {
+                // the pane exiting shuts the server down, a dead pane has nothing to wait for
+                match self.pty.lock().unwrap().as_ref() {
+                    Some(pty) => pty.kill(libc::SIGHUP).map_err(|e| e.to_string())?,
+                    None => self.stop.store(true, Relaxed),
+                }
+                Ok(String::new())
+            }
+            Command::HandOver(address) => {
+                println!("session moved to {}", address);
+                for client in clients.iter().filter(|c| c.is_attached()) {
+                    client.send(Message::Moved(address.clone()));
+                }
+                // like kill-session, the pane's command runs on there now
+                match self.pty.lock().unwrap().as_ref() {
+                    Some(pty) => pty.kill(libc::SIGHUP).map_err(|e| e.to_string())?,
+                    None => self.stop.store(true, Relaxed),
+                }
+                Ok(String::new())
+            }
+        }
+    }
+
+    /// What list-windows and list-panes formats can show of a window and
+    /// its pane, named like tmux's.
+    fn format_variables(
+        &self,
+        terminal: &Terminal,
+        status: &StatusLine,
+        clients: &[Arc<Client>],
+        window: &Window,
+    ) -> Variables {
+        let screen = terminal.screen();
+        let (width, height) = (screen.cols().to_string(), screen.rows().to_string());
+        let mut variables = Variables::new();
+        variables.insert("session_name", status.session.clone());
+        variables.insert("window_index", window.index.to_string());
+        variables.insert("window_name", window.name.clone());
+        variables.insert("window_active", (window.active as u8).to_string());
+        variables.insert("window_panes", "1".to_string());
+        variables.insert("window_width", width.clone());
+        variables.insert("window_height", height.clone());
+        variables.insert("pane_index", "0".to_string());
+        variables.insert("pane_id", "%0".to_string());
+        variables.insert("pane_active", "1".to_string());
+        variables.insert("pane_width", width);
+        variables.insert("pane_height", height);
+        variables.insert("pane_title", window.title.clone());
+
+        // the command and the directory are those of the job in the
+        // foreground, the shell's while it waits for one
+        if let Some(pty) = self.pty.lock().unwrap().as_ref() {
+            let pid = pty.foreground_pid().unwrap_or(pty.pid());
+            let command = std::fs::read_to_string(format!("/proc/{}/comm", pid));
+            let cwd = std::fs::read_link(format!("/proc/{}/cwd", pid))
+                .map(|cwd| cwd.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            variables.insert("pane_pid", pty.pid().to_string());
+            let command = command.unwrap_or_default().trim().to_string();
+            variables.insert("pane_current_command", command);
+            variables.insert("pane_current_path", cwd);
+        }
+
+        // health of a shared session: how busy the pane is, who watches
+        // and how many bytes the furthest behind client has yet to get
+        let rate = self.output.lock().unwrap().rate();
+        variables.insert("pane_output_rate", rate.to_string());
+        let attached: Vec<&Arc<Client>> = clients.iter().filter(|c| c.is_attached()).collect();
+        variables.insert("session_clients", attached.len().to_string());
+        let lag = attached.iter().map(|c| c.outbox.backlog()).max();
+        variables.insert("session_lag", lag.unwrap_or(0).to_string());
+        variables
+    }
+
+    /// Shows `client` the history in copy mode, from the start of `line`, an
+    /// index in `Screen::lines`, if given.
+    fn copy_mode(
+        &self,
+        client: &Client,
+        line: Option<usize>,
+        terminal: &Terminal,
+        clients: &[Arc<Client>],
+    ) {
+        let status = self.status.lock().unwrap();
+        let screen = terminal.screen();
+        let rows = client.pane_rows(terminal, &status);
+        let mut copy = client.copy.lock().unwrap();
+        let mode = copy.get_or_insert_with(|| CopyMode::new(screen));
+        if let Some(line) = line {
+            mode.jump(screen, line, rows);
+        }
+        drop(copy);
+        client.refresh(terminal, &status);
+        if self.session_flag("copy-mode-sync") {
+            sync_copy(client, clients, terminal, &status);
+        }
+    }
+
+    /// The right of the status line, the formats among the segments are
+    /// expanded for the active window.
+    fn status_right(
+        &self,
+        terminal: &Terminal,
+        status: &StatusLine,
+        clients: &[Arc<Client>],
+    ) -> String {
+        let variables = match status.windows.iter().find(|w| w.active) {
+            Some(window) => self.format_variables(terminal, status, clients, window),
+            None => Variables::new(),
+        };
+        self.segments.lock().unwrap().text(&variables)
+    }
+
+    /// The session as export-session writes it.
+    fn session_spec(&self, status: &StatusLine) -> SessionSpec {
+        // the shell may have moved on from where the pane started
+        let mut pane = self.pane.clone();
+        if let Some(pty) = self.pty.lock().unwrap().as_ref() {
+            if let Ok(cwd) = std::fs::read_link(format!("/proc/{}/cwd", pty.pid())) {
+                pane.cwd = Some(cwd.to_string_lossy().into_owned());
+            }
+        }
+
+        SessionSpec {
+            name: status.session.clone(),
+            windows: status
+                .windows
+                .iter()
+                .map(|window| WindowSpec {
+                    name: window.name.clone(),
+                    panes: vec![pane.clone()],
+                })
+                .collect(),
+        }
+    }
+
+    /// Saves the pane's history and screen, the session as export-session
+    /// writes it and the commands of show-timeline to a directory of their
+    /// own in the archive-dir, if it is set. Returns the directory.
+    fn archive(&self) -> io::Result<Option<PathBuf>> {
+        let dir = self
+            .options
+            .lock()
+            .unwrap()
+            .text("archive-dir", Scope::Session);
+        if dir.is_empty() {
+            return Ok(None);
+        }
+        let terminal = self.terminal.lock().unwrap();
+        let status = self.status.lock().unwrap();
+
+        // sessions can be created again under the same name
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let ended = status::format_time("%Y%m%d-%H%M%S", now.as_secs() as libc::time_t);
+        let path = Path::new(&dir).join(format!("{}-{}", status.session, ended));
+        std::fs::create_dir_all(&path)?;
+
+        let screen = terminal.screen();
+        let mut lines: Vec<String> = screen.lines().map(|row| row.text()).collect();
+        while lines.last().is_some_and(String::is_empty) {
+            lines.pop();
+        }
+        std::fs::write(path.join("scrollback.txt"), lines.join("\n") + "\n")?;
+        let spec = self.session_spec(&status);
+        std::fs::write(path.join("session.yaml"), spec.to_yaml())?;
+        let timeline = timeline(screen);
+        if !timeline.is_empty() {
+            std::fs::write(path.join("timeline.txt"), timeline.join("\n") + "\n")?;
+        }
+        Ok(Some(path))
+    }
+
+    fn session_flag(&self, name: &str) -> bool {
+        self.options.lock().unwrap().flag(name, Scope::Session)
+    }
+
+    /// The client-rate-limit option in bytes per second.
+    fn client_rate_limit(&self) -> u64 {
+        let options = self.options.lock().unwrap();
+        options.number("client-rate-limit", Scope::Session) as u64 * 1024
+    }
+
+    /// The pane's size before any client is attached.
+    fn default_size(&self) -> PtySize {
+        let options = self.options.lock().unwrap();
+        let size = options.text("default-size", Scope::Session);
+        PtySize::parse(&size).unwrap_or_else(|e| {
+            eprintln!("default-size: {}", e);
+            PtySize::new(DEFAULT_ROWS, DEFAULT_COLS)
+        })
+    }
+
+    /// What the pane's processes may use, from when the pane is started.
+    fn pane_limits(&self) -> Limits {
+        let options = self.options.lock().unwrap();
+        let memory = options.text("pane-memory-max", Scope::Session);
+        let cpu = options.number("pane-cpu-max", Scope::Session) as u64;
+        Limits {
+            memory: limits::parse_size(&memory).unwrap_or_else(|e| {
+                eprintln!("pane-memory-max: {}", e);
+                None
+            }),
+            cpu: (cpu > 0).then_some(cpu),
+        }
+    }
+
+    /// Who besides this user may attach, none if allow-users no longer
+    /// makes sense, a user may have been removed since it was set.
+    fn acl(&self) -> Acl {
+        let allowed = self
+            .options
+            .lock()
+            .unwrap()
+            .text("allow-users", Scope::Session);
+        Acl::parse(&allowed).unwrap_or_else(|e| {
+            eprintln!("allow-users: {}", e);
+            Acl::default()
+        })
+    }
+
+    /// Opens the socket to other users while allow-users names any.
+    fn share_socket(&self, session_name: &str) {
+        let shared = !self.acl().is_empty();
+        if let Err(e) = socket::share_socket(&socket_path(session_name), shared) {
+            eprintln!("allow-users: {}", e);
+        }
+    }
+
+    /// Brings the pane and the clients in line with the options, after one
+    /// of them changed.
+    fn apply_options(&self, terminal: &mut Terminal, clients: &[Arc<Client>]) {
+        let limit = self.client_rate_limit();
+        for client in clients.iter() {
+            client.limiter.lock().unwrap().set_rate(limit);
+        }
+        let session = self.status.lock().unwrap().session.clone();
+        self.share_socket(&session);
+        let options = self.options.lock().unwrap();
+        terminal.set_history_limit(options.number("history-limit", Scope::Session) as usize);
+        terminal.set_monitor_bell(options.flag("monitor-bell", PANE));
+        terminal.set_timestamps(options.flag("history-timestamps", Scope::Session));
+
+        let mut status = self.status.lock().unwrap();
+        let visible = options.flag("status", Scope::Session);
+        if status.visible != visible {
+            // the pane takes over the status line's row or gives it back
+            let screen = terminal.screen();
+            let rows = (screen.rows() as u16 + status.rows()).saturating_sub(visible as u16);
+            let size = self.pane_size(terminal);
+            status.visible = visible;
+            self.resize_pane(terminal, size.with_rows(rows));
+            for client in clients.iter() {
+                client.refresh(terminal, &status);
+            }
+        }
+
+        let right = options.text("status-right", Scope::Session);
+        let mut segments = self.segments.lock().unwrap();
+        if let Err(e) = segments.set_format(&right) {
+            eprintln!("status-right: {}", e);
+        }
+        segments.refresh();
+        drop(segments);
+        status.right = self.status_right(terminal, &status, clients);
+
+        // the client's terminal starts or stops reporting the mouse
+        let mouse = options.flag("mouse", Scope::Session);
+        for client in clients.iter() {
+            if client.mouse.swap(mouse, Relaxed) != mouse {
+                client.update(terminal, &status);
+            }
+        }
+    }
+
+    /// The pane's size, from the pty when there is one since only it
+    /// knows the size in pixels.
+    fn pane_size(&self, terminal: &Terminal) -> PtySize {
+        let pty_size = self.pty.lock().unwrap().as_ref().map(Pty::get_size);
+        pty_size.and_then(Result::ok).unwrap_or_else(|| {
+            let screen = terminal.screen();
+            PtySize::new(screen.rows() as u16, screen.cols() as u16)
+        })
+    }
+
+    /// Resizes the pane, clients hold on to their own size for drawing.
+    fn resize_pane(&self, terminal: &mut Terminal, size: PtySize) {
+        let size = size.with_rows(size.rows.max(1));
+        if let Some(pty) = self.pty.lock().unwrap().as_ref() {
+            let _ = pty.resize(size); // ignore resize failures
+        }
+        terminal.resize(size.rows, size.cols);
+    }
+
+    /// Sizes the pane to the smallest attached client like tmux, bigger
+    /// clients see it padded. Read-only clients see whatever fits.
+    fn fit_pane(&self) {
+        let mut terminal = self.terminal.lock().unwrap();
+        let clients = self.clients.lock().unwrap();
+        let status = self.status.lock().unwrap();
+        // the pane gets what is left after the status line, a control
+        // client's size is the pane's as it has no status line
+        let size = clients
+            .iter()
+            .filter(|c| (c.is_attached() || c.is_control()) && !c.read_only.load(Relaxed))
+            .filter_map(|c| {
+                let size = (*c.size.lock().unwrap())?;
+                match c.is_attached() {
+                    true => Some(size.with_rows(size.rows.saturating_sub(status.rows()))),
+                    false => Some(size),
+                }
+            })
+            .reduce(PtySize::fit);
+        let Some(pane) = size else {
+            return;
+        };
+
+        if pane == self.pane_size(&terminal) {
+            return;
+        }
+        self.resize_pane(&mut terminal, pane);
+        for client in clients.iter() {
+            client.refresh(&terminal, &status);
+        }
+        notify(&clients, layout_change(self.pane_size(&terminal)));
+    }
+
+    fn process_output(&self, server_in: Sender<Vec<u8>>) -> io::Result<()> {
+        if self.pty.lock().unwrap().is_none() {
+            return Ok(()); // a dead pane has no output
+        }
+        let server = self.clone();
+        let pty = self.pty.clone();
+        let terminal = self.terminal.clone();
+        let clients = self.clients.clone();
+        let status = self.status.clone();
+        let pipe = self.pipe.clone();
+        let output = self.output.clone();
+        let stop = self.stop.clone();
+
+        // a single reader feeds the terminal model and every client, one
+        // started again reads on from where the last stopped
+        self.watchdog.spawn_restarting("output", move |worker| {
+            let reader = pty.lock().unwrap().as_ref().map(Pty::try_clone_reader);
+            let mut pty_out = match reader {
+                Some(Ok(pty_out)) => pty_out,
+                Some(Err(e)) => {
+                    eprintln!("can't read the pane: {}", e);
+                    return server.pane_exited();
+                }
+                None => return,
+            };
+            let mut outbuf = [0u8; 128 * 128];
+            loop {
+                if stop.load(Relaxed) {
+                    break;
+                }
+
+                worker.wait();
+                let read = pty_out.read(&mut outbuf);
+                worker.beat();
+                match read {
+                    Ok(bytes_read) => {
+                        if bytes_read == 0 {
+                            break; // EOF
+                        }
+
+                        // a pipe that went away closes itself
+                        let mut pipe = pipe.lock().unwrap();
+                        if pipe
+                            .as_ref()
+                            .is_some_and(|p| !p.write(&outbuf[..bytes_read]))
+                        {
+                            *pipe = None;
+                        }
+                        drop(pipe);
+                        output.lock().unwrap().add(bytes_read);
+
+                        // hold the terminal while fanning out so a frame
+                        // drawn for a refresh never misses or repeats output
+                        let mut terminal = terminal.lock().unwrap();
+                        terminal.process(&outbuf[..bytes_read]);
+
+                        // clients never see the queries, so the server answers them
+                        let replies = terminal.take_replies();
+                        if !replies.is_empty() && server_in.send(replies).is_err() {
+                            break;
+                        }
+
+                        let mut status = status.lock().unwrap();
+                        update_secure_input(&pty, &mut status);
+                        update_title(terminal.screen(), &mut status);
+                        let mut clients = clients.lock().unwrap();
+                        clients.retain(|c| !c.stopped());
+                        for client in clients.iter() {
+                            client.update(&terminal, &status);
+                        }
+                    }
+                    Err(e) => match ReadFailure::classify(&e) {
+                        // the pty is non-blocking for the input writer
+                        ReadFailure::Retry => {
+                            let mut fds = [pollfd(pty_out.as_raw_fd(), libc::POLLIN)];
+                            let _ = poll(&mut fds, Duration::from_millis(100));
+                        }
+                        ReadFailure::Closed => break,
+                    },
+                }
+            }
+            println!("should stop because of process output");
+            server.pane_exited();
+        })
+    }
+
+    /// Reaps the pane's command, fires pane-exited and tells attached
+    /// clients how the command ended, then shuts the session down. The
+    /// output ending and the reaper may both notice, only the first counts.
+    fn pane_exited(&self) {
+        if self.exited.swap(true, Relaxed) {
+            return;
+        }
+
+        // the pane stays locked only briefly, so it can still be killed
+        let status = loop {
+            match self.pty.lock().unwrap().as_ref().map(Pty::try_wait) {
+                Some(Ok(None)) => {}
+                status => break status,
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+
+        // the handler is started before the server begins shutting down
+        if let Some(Ok(Some(status))) = status {
+            let exit_code = status
+                .code()
+                .map_or(Value::Null, |c| Value::Number(c as i64));
+            let signal = status
+                .signal()
+                .map_or(Value::Null, |s| Value::Number(s as i64));
+            self.hooks.fire(
+                Hook::PaneExited,
+                &[("exit_code", exit_code), ("signal", signal)],
+            );
+
+            let exit = PaneExit::from_status(status);
+            let clients = self.clients.lock().unwrap();
+            for client in clients.iter().filter(|c| c.is_attached() || c.is_control()) {
+                client.send(Message::Exited(exit));
+            }
+        }
+        self.stop.store(true, Relaxed);
+    }
+
+    /// Hangs up on every client once what was queued for it is written,
+    /// clients that stopped reading are given a second.
+    fn disconnect_clients(&self) {
+        let clients = self.clients.lock().unwrap();
+        for client in clients.iter() {
+            client.outbox.close();
+        }
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while Instant::now() < deadline && clients.iter().any(|c| !c.flushed.load(Relaxed)) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        for client in clients.iter() {
+            let _ = client.stop();
+        }
+    }
+
+    /// Keeps the clock, the secure input indicator and the marked window
+    /// on the status line current, a pane can be marked from any session.
+    fn process_status(&self) -> io::Result<()> {
+        let server = self.clone();
+        let pty = self.pty.clone();
+        let terminal = self.terminal.clone();
+        let clients = self.clients.clone();
+        let status = self.status.clone();
+        let segments = self.segments.clone();
+        let stop = self.stop.clone();
+
+        self.watchdog.spawn_restarting("status", move |worker| {
+            while !stop.load(Relaxed) {
+                std::thread::sleep(Duration::from_secs(1));
+                worker.beat();
+                segments.lock().unwrap().refresh();
+
+                // nothing is sent to clients unless the line changed
+                let terminal = terminal.lock().unwrap();
+                let clients = clients.lock().unwrap();
+                let mut status = status.lock().unwrap();
+                status.right = server.status_right(&terminal, &status, &clients);
+                update_secure_input(&pty, &mut status);
+                update_marked(&mut status);
+                for client in clients.iter().filter(|c| !c.stopped()) {
+                    client.update(&terminal, &status);
+                }
+            }
+        })
+    }
+
+    /// Keeps the logs of this and earlier sessions within the retention
+    /// options, every server does its share.
+    fn process_maintenance(&self) -> io::Result<()> {
+        let options = self.options.clone();
+        let stop = self.stop.clone();
+
+        self.watchdog
+            .spawn_restarting("maintenance", move |worker| {
+                let mut last_run: Option<Instant> = None;
+                while !stop.load(Relaxed) {
+                    worker.beat();
+                    if last_run.is_none_or(|last| last.elapsed() >= MAINTENANCE_INTERVAL) {
+                        let options = options.lock().unwrap();
+                        let retention = Retention {
+                            max_age: Duration::from_secs(
+                                options.number("log-max-age", Scope::Session) as u64 * 3600,
+                            ),
+                            max_size: options.number("log-max-size", Scope::Session) as u64 * 1024,
+                        };
+                        drop(options);
+                        match retention.clean() {
+                            Ok(cleaned) => {
+                                for path in cleaned {
+                                    println!("cleaned up {}", path.display());
+                                }
+                            }
+                            Err(e) => eprintln!("log cleanup failed: {}", e),
+                        }
+                        last_run = Some(Instant::now());
+                    }
+                    std::thread::sleep(Duration::from_secs(1));
+                }
+            })
+    }
+
+    /// Accepts clients and reads from and writes to all of them on a single
+    /// thread, which sleeps in poll until a socket is ready or an outbox
+    /// has something to send. Once the server stops it only keeps going
+    /// until the clients are flushed and hung up on. Clients on other
+    /// machines connect over `tcp` if it is given.
+    fn process_clients(
+        &self,
+        session_name: &str,
+        tcp: Option<TcpAccess>,
+        server_in: Sender<Vec<u8>>,
+        commands: CommandQueue,
+    ) -> io::Result<()> {
+        let listener = bind_unix_socket(&socket_path(session_name))?;
+        listener.set_nonblocking(true)?;
+        // a relay that served the name before is gone
+        let _ = std::fs::remove_file(socket::relay_path(session_name));
+        self.share_socket(session_name);
+        if let Some(tcp) = &tcp {
+            tcp.listener.set_nonblocking(true)?;
+        }
+        let waker = Arc::new(Waker::new()?);
+        let server = self.clone();
+
+        self.watchdog.spawn("clients", move |worker| {
+            let mut connections: Vec<Connection> = vec![];
+            let mut next_id = 0;
+            loop {
+                worker.beat();
+                let stopping = server.stop.load(Relaxed);
+                connections.retain_mut(|connection| {
+                    let done = connection.client.stopped()
+                        || connection.timed_out()
+                        || !connection.keep_alive()
+                        || !connection.flush();
+                    if done {
+                        connection.client.disconnect(&server);
+                    }
+                    !done
+                });
+                if stopping && connections.is_empty() {
+                    break;
+                }
+
+                let accepting = if stopping { 0 } else { libc::POLLIN };
+                // poll skips a negative descriptor
+                let tcp_fd = tcp.as_ref().map_or(-1, |tcp| tcp.listener.as_raw_fd());
+                let mut fds = vec![
+                    pollfd(waker.as_raw_fd(), libc::POLLIN),
+                    pollfd(listener.as_raw_fd(), accepting),
+                    pollfd(tcp_fd, accepting),
+                ];
+                for connection in &connections {
+                    let mut events = libc::POLLIN;
+                    if !connection.output.is_empty() {
+                        events |= libc::POLLOUT;
+                    }
+                    fds.push(pollfd(connection.stream.as_raw_fd(), events));
+                }
+                // the timeout paces the reaper below
+                if poll(&mut fds, Duration::from_millis(100)).is_err() {
+                    break;
+                }
+
+                if fds[0].revents != 0 {
+                    waker.drain();
+                }
+                let mut accepted = vec![];
+                if fds[1].revents != 0 {
+                    while let Ok((stream, _)) = listener.accept() {
+                        // the socket may be open to others, see allow-users
+                        let uid = match socket::peer_uid(&stream) {
+                            Ok(uid) => uid,
+                            Err(e) => {
+                                eprintln!("refused a client: {}", e);
+                                continue;
+                            }
+                        };
+                        let user = acl::user_name(uid);
+                        match server.acl().access(uid) {
+                            Some(access) => {
+                                accepted.push((Stream::Unix(stream), None, user, access))
+                            }
+                            None => println!("refused a client of user {}", user),
+                        }
+                    }
+                }
+                if let Some(tcp) = tcp.as_ref().filter(|_| fds[2].revents != 0) {
+                    while let Ok((stream, address)) = tcp.listener.accept() {
+                        println!("connection from {}", address);
+                        let stream = match &tcp.tls {
+                            Some(config) => match tls::accept(stream, config.clone()) {
+                                Ok(stream) => Stream::Unix(stream),
+                                Err(e) => {
+                                    eprintln!("tls with {} failed: {}", address, e);
+                                    continue;
+                                }
+                            },
+                            None => Stream::Tcp(stream),
+                        };
+                        let peer = address.to_string();
+                        accepted.push((stream, Some(tcp.token.clone()), peer, Access::ReadWrite));
+                    }
+                }
+                for (stream, token, peer, access) in accepted {
+                    let waker = waker.clone();
+                    let Ok(connection) =
+                        Connection::new(next_id, stream, token, peer, access, waker)
+                    else {
+                        continue;
+                    };
+                    next_id += 1;
+                    let client = &connection.client;
+                    println!("client {} connected from {}", client.id, client.peer);
+                    let limit = server.client_rate_limit();
+                    connection.client.limiter.lock().unwrap().set_rate(limit);
+                    let mouse = server.session_flag("mouse");
+                    connection.client.mouse.store(mouse, Relaxed);
+
+                    // attaching clients ask for a refresh once they are sized
+                    if connection.token.is_none() {
+                        let mut clients = server.clients.lock().unwrap();
+                        clients.retain(|c| !c.stopped());
+                        clients.push(connection.client.clone());
+                    }
+                    connections.push(connection);
+                }
+                for (connection, fd) in connections.iter_mut().zip(&fds[3..]) {
+                    if fd.revents != 0 && !connection.receive(&server, &server_in, &commands) {
+                        connection.client.stop.store(true, Relaxed);
+                    }
+                }
+
+                // updates held back by a rate limit go out once it allows,
+                // as one frame with everything that changed in the meantime
+                for connection in &connections {
+                    let client = &connection.client;
+                    if client.held.load(Relaxed) && client.limiter.lock().unwrap().ready() {
+                        let terminal = server.terminal.lock().unwrap();
+                        let status = server.status.lock().unwrap();
+                        client.update(&terminal, &status);
+                    }
+                }
+
+                // the command can exit while something it started keeps the
+                // pty open, so the output never ends
+                let alive = server.pty.lock().unwrap().as_ref().map(Pty::is_alive);
+                if let Some(Ok(false)) = alive {
+                    server.pane_exited();
+                }
+            }
+
+            server.stop.store(true, Relaxed);
+            println!("process clients done");
+        })
+    }
+
+    /// Logs the threads that got stuck, server-info shows how they are.
+    fn process_watchdog(&self) {
+        let watchdog = self.watchdog.clone();
+        let stop = self.stop.clone();
+        std::thread::spawn(move || {
+            while !stop.load(Relaxed) {
+                std::thread::sleep(Duration::from_secs(1));
+                for (name, stuck) in watchdog.check() {
+                    eprintln!("{} stuck for {}s", name, stuck.as_secs());
+                }
+            }
+        });
+    }
+
+    /// The server's process and how its threads are, see `Watchdog`.
+    fn info(&self) -> String {
+        let now = unsafe { libc::time(std::ptr::null_mut()) };
+        let mut lines = vec![format!(
+            "pid {}, up {}s",
+            std::process::id(),
+            now - self.created
+        )];
+        lines.extend(self.watchdog.report());
+        lines.join("\n")
+    }
+
+    fn process_input(&self, aggregated_input: Receiver<Vec<u8>>) -> io::Result<()> {
+        let mut pty_in = match self.pty.lock().unwrap().as_ref() {
+            Some(pty) => Some(pty.take_writer()?),
+            None => None,
+        };
+        let stop = self.stop.clone();
+
+        loop {
+            if stop.load(Relaxed) {
+                break;
+            }
+
+            match aggregated_input.recv_timeout(Duration::from_millis(100)) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                Ok(mut buf) => {
+                    let disconnected = coalesce_input(&aggregated_input, &mut buf);
+                    // not even the length of a password is logged
+                    let secure = self.status.lock().unwrap().secure_input;
+                    if !secure || self.session_flag("log-secure-input") {
+                        println!("input received: {}", buf.len());
+                    }
+                    if let Some(pty_in) = pty_in.as_mut() {
+                        if pty_in.write_paced(&buf).is_err() {
+                            break;
+                        }
+                    } else if buf.contains(&b'\r') {
+                        break; // the dead pane goes away once it has been acknowledged
+                    }
+                    if disconnected {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        }
+        stop.store(true, Relaxed);
+
+        Ok(())
+    }
+}
+
+/// Adds input that arrives within `INPUT_LATENCY` of `buf` to it, keystrokes
+/// typed quickly or sent by a script become a single write. Returns whether
+/// the input channel was closed meanwhile.
+fn coalesce_input(input: &Receiver<Vec<u8>>, buf: &mut Vec<u8>) -> bool {
+    let deadline = Instant::now() + INPUT_LATENCY;
+    while buf.len() < INPUT_BATCH {
+        let wait = deadline.saturating_duration_since(Instant::now());
+        match input.recv_timeout(wait) {
+            Ok(more) => buf.extend_from_slice(&more),
+            Err(RecvTimeoutError::Timeout) => break,
+            Err(RecvTimeoutError::Disconnected) => return true,
+        }
+    }
+    false
+}
+
+/// Shows the secure input indicator while the pane reads a password, a
+/// prompt turns off echo before it is printed so checking on output is enough
+/// for most, the status timer catches the rest.
+fn update_secure_input(pty: &Mutex<Option<Pty>>, status: &mut StatusLine) {
+    if let Some(pty) = pty.lock().unwrap().as_ref() {
+        status.secure_input = pty.is_secure_input().unwrap_or(false);
+    }
+}
+
+/// The window shows the title its pane set last.
+fn update_title(screen: &Screen, status: &mut StatusLine) {
+    for window in status.windows.iter_mut().filter(|w| w.active) {
+        if window.title != screen.title() {
+            window.title = screen.title().to_string();
+        }
+    }
+}
+
+/// Flags the window holding the marked pane, if it is in this session.
+fn update_marked(status: &mut StatusLine) {
+    let mark = Mark::load().filter(|mark| mark.session == status.session);
+    for window in status.windows.iter_mut() {
+        // every pane is in the first window for now
+        window.marked = mark.is_some() && window.index == 0;
+    }
+}
+
+/// A token to resume with that can't be guessed.
+fn resume_token() -> io::Result<String> {
+    let mut bytes = [0u8; 16];
+    std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn command_done(result: CommandResult) -> Message {
+    match result {
+        Ok(output) => Message::CommandDone {
+            success: true,
+            output,
+        },
+        Err(output) => Message::CommandDone {
+            success: false,
+            output,
+        },
+    }
+}
+
+/// Applies the lines of the configuration file the server handles: hooks
+/// and options. Key bindings and key options are left to the client.
+fn load_config(server: &Server) {
+    let Some(path) = config::default_path() else {
+        return;
+    };
+    let Ok(ConfigLines { lines, errors }) = config::read_lines(&path) else {
+        return;
+    };
+    for error in errors {
+        eprintln!("{}:{}", path.display(), error);
+    }
+
+    for (number, line) in lines {
+        let applied = server.hooks.apply_line(&line).and_then(|applied| {
+            if applied {
+                return Ok(true);
+            }
+            let args = split_line(&line)?;
+            // the client reports its own mistakes
+            if KeyBindings::default()
+                .apply(&args)
+                .is_ok_and(|applied| applied)
+            {
+                return Ok(false);
+            }
+            match Command::parse(&args) {
+                Ok(command @ Command::SetOption { .. }) => server
+                    .execute(&command, CommandSource::Server)
+                    .map(|_| true),
+                _ => Ok(false),
+            }
+        });
+        if let Err(e) = applied {
+            eprintln!("{}:{}: {}", path.display(), number, e);
+        }
+    }
+}
+
+/// Whether client input looks like pasted text spanning several lines, a
+/// single line ending in a newline is left alone since a shell runs it
+/// either way.
+fn is_multiline_paste(data: &[u8]) -> bool {
+    let data = unbracket(data);
+    let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+    let data = data
+        .strip_suffix(b"\r")
+        .or_else(|| data.strip_suffix(b"\n"))
+        .unwrap_or(data);
+    data.iter().any(|&b| b == b'\r' || b == b'\n')
+}
+
+/// Pasted text without the markers of bracketed paste mode.
+fn unbracket(data: &[u8]) -> &[u8] {
+    let data = data.strip_prefix(b"\x1b[200~").unwrap_or(data);
+    data.strip_suffix(b"\x1b[201~").unwrap_or(data)
+}
+
+/// Moves the other clients in copy mode to the lines `leader` looks at and
+/// selects what it selected, for the copy-mode-sync option.
+fn sync_copy(leader: &Client, clients: &[Arc<Client>], terminal: &Terminal, status: &StatusLine) {
+    let Some(view) = leader.copy.lock().unwrap().clone() else {
+        return;
+    };
+    let followers = clients
+        .iter()
+        .filter(|c| c.id != leader.id && c.is_attached());
+    for client in followers {
+        let rows = client.pane_rows(terminal, status);
+        let mut copy = client.copy.lock().unwrap();
+        let Some(mode) = copy.as_mut() else {
+            continue;
+        };
+        mode.follow(&view, terminal.screen(), rows);
+        drop(copy);
+        client.refresh(terminal, status);
+    }
+}
+
+/// Tells control clients about an event, see `Message::Notify`.
+fn notify(clients: &[Arc<Client>], line: String) {
+    for client in clients.iter().filter(|c| c.is_control()) {
+        client.send(Message::Notify(line.clone()));
+    }
+}
+
+/// The notification of the pane's size, the session's only window and pane.
+fn layout_change(size: PtySize) -> String {
+    format!("%layout-change @0 {}x{}", size.cols, size.rows)
+}
+
+/// Sends subscribed panes what changed since their last frames, all of a
+/// pane the first time. Returns the bytes queued.
+fn update_panes(
+    panes: &Mutex<BTreeMap<u32, Option<Frame>>>,
+    outbox: &Outbox,
+    terminal: &Terminal,
+) -> usize {
+    let screen = terminal.screen();
+    let mut queued = 0;
+    for (&pane, last) in panes.lock().unwrap().iter_mut() {
+        let frame = terminal.frame(screen.rows() as u16, screen.cols() as u16, &[]);
+        let data = frame.render(last.as_ref());
+        *last = Some(frame);
+        if !data.is_empty() {
+            queued += data.len();
+            outbox.push(Message::PaneData { pane, data });
+        }
+    }
+    queued
+}
+
+/// The recent commands of the pane, newest first, with the line their
+/// output starts on for jumping to it, numbered like search-panes matches.
+fn timeline(screen: &Screen) -> Vec<String> {
+    let now = SystemTime::now();
+    screen
+        .commands()
+        .iter()
+        .rev()
+        .map(|record| {
+            let started = record.started.duration_since(UNIX_EPOCH);
+            let started = started.unwrap_or_default().as_secs() as libc::time_t;
+            let time = status::format_time("%H:%M:%S", started);
+            let finished = record.finished.unwrap_or(now);
+            let duration = finished.duration_since(record.started).unwrap_or_default();
+            let exit = match (record.finished, record.exit) {
+                (None, _) => "running".to_string(),
+                (Some(_), Some(code)) => format!("exit {}", code),
+                (Some(_), None) => "done".to_string(),
+            };
+            let line = match screen.line_index(record.output) {
+                Some(line) => format!("%0:{}", line),
+                None => "%0:-".to_string(),
+            };
+            let command = text::ellipsize(&record.command, 40);
+            let duration = format_duration(duration);
+            let columns = format!("{:>7} {:<8} {:<8}", duration, exit, line);
+            format!("{} {} {}", time, columns, command)
+        })
+        .collect()
+}
+
+/// A duration as short as `ls` sizes: 250ms, 12.5s, 3m05s, 2h10m.
+fn format_duration(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0 => format!("{}ms", duration.subsec_millis()),
+        1..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m{:02}s", secs / 60, secs % 60),
+        _ => format!("{}h{:02}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+/// A message in a box of its own, each line break starts a new line.
+fn message_box(message: &str, attrs: Attributes, cols: usize) -> Vec<Row> {
+    let mut lines: Vec<&str> = message.split('\n').collect();
+    lines.push("");
+    lines.push("(press any key to continue)");
+    let inner = lines
+        .iter()
+        .map(|line| text::width(line))
+        .max()
+        .unwrap_or(0);
+    let width = (inner + 4).min(cols);
+
+    let mut rows = vec![Row::from_text("", attrs, width)];
+    for line in lines {
+        let line = format!("  {}", text::ellipsize(line, width.saturating_sub(4)));
+        rows.push(Row::from_text(&line, attrs, width));
+    }
+    rows.push(Row::from_text("", attrs, width));
+    rows
+}
+
+/// The prompt shown in place of the status line while a paste waits to be confirmed.
+fn paste_prompt(paste: &[u8], cols: usize) -> String {
+    let text = String::from_utf8_lossy(unbracket(paste));
+    let lines: Vec<&str> = text
+        .split(['\r', '\n'])
+        .filter(|line| !line.trim().is_empty())
+        .collect();
+    let preview = lines.first().map_or("", |line| line.trim());
+    let question = format!(" (y/n) paste {} lines? ", lines.len());
+    let room = cols.saturating_sub(text::width(&question) + 2);
+    format!("{}\"{}\"", question, text::ellipsize(preview, room))
+}
+
+/// The processes in the session `sid`, the pane's command and whatever it
+/// started, as `pid name` from /proc. Killing the session hangs up on them.
+fn session_processes(sid: u32) -> Vec<String> {
+    let Ok(dir) = std::fs::read_dir("/proc") else {
+        return vec![];
+    };
+    let mut processes: Vec<(u32, String)> = dir
+        .filter_map(|entry| {
+            let pid: u32 = entry.ok()?.file_name().to_str()?.parse().ok()?;
+            let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+            // like `124 (sh) S 120 124 124 ...`, the name may have spaces
+            // and parentheses of its own
+            let (name, rest) = stat.split_once(" (")?.1.rsplit_once(") ")?;
+            let session: u32 = rest.split(' ').nth(3)?.parse().ok()?;
+            (session == sid).then(|| (pid, name.to_string()))
+        })
+        .collect();
+    processes.sort();
+    processes
+        .into_iter()
+        .map(|(pid, name)| format!("{} {}", pid, name))
+        .collect()
+}
+
+/// Whether a client sent the token of a session listening on TCP. It is
+/// compared in full, so the time taken doesn't tell how much matched.
+pub fn token_matches(sent: &str, token: &str) -> bool {
+    let diff = sent
+        .bytes()
+        .zip(token.bytes())
+        .fold(0, |d, (a, b)| d | (a ^ b));
+    sent.len() == token.len() && diff == 0
+}
+
+/// Runs the server of a session until its pane exits or it is killed.
+/// Its window is named `window_name`, after the pane's program if None.
+/// The pane runs its command in its cwd, the user's shell in the current
+/// directory by default, as its user if it has one. Clients attach over
+/// the session socket, and over TCP as well if `tcp` is given. The screen
+/// starts out showing `history`, like a migrated session's.
+pub fn run(
+    session_name: &str,
+    window_name: Option<&str>,
+    pane: PaneSpec,
+    tcp: Option<TcpAccess>,
+    history: &[u8],
+) -> io::Result<()> {
+    let hooks = Hooks::new(session_name);
+
+    // the configuration can pick the shell, so it is loaded first
+    let status = StatusLine::new(session_name, vec![]);
+    let server = Server::new(None, pane, status, hooks);
+    load_config(&server);
+    let shell = server
+        .options
+        .lock()
+        .unwrap()
+        .text("default-shell", Scope::Session);
+    let shell = (!shell.is_empty()).then_some(shell);
+
+    let mut builder = PtyCommandBuilder::new()
+        .shell(resolve_shell(shell.as_deref()))
+        .command(&server.pane.command);
+    if let Some(cwd) = &server.pane.cwd {
+        builder = builder.cwd(cwd);
+    }
+    for var in &server.pane.env {
+        // checked when the session was created
+        if let Some((key, value)) = var.split_once('=') {
+            builder = builder.env(key, value);
+        }
+    }
+    let user = server.pane.user.as_deref().map(User::lookup).transpose();
+    if let Ok(Some(user)) = &user {
+        builder = builder.user(user.clone());
+    }
+    let program = builder.program().to_string();
+    let name = window_name.unwrap_or(program.rsplit('/').next().unwrap_or(&program));
+    let window = Window {
+        index: 0,
+        name: name.to_string(),
+        active: true,
+        marked: false,
+        title: String::new(),
+    };
+    server.status.lock().unwrap().windows = vec![window];
+
+    // there may be no terminal to take the size from, like under cron or
+    // systemd, the pane fits the clients once they attach
+    let size = server.default_size();
+    let mut terminal = server.terminal.lock().unwrap();
+    terminal.resize(size.rows, size.cols);
+    terminal.process(history);
+    drop(terminal);
+
+    // the pane gets a cgroup of its own for the limits where it can, the
+    // memory is capped with setrlimit otherwise
+    let mut policy = builder.spawn_policy();
+    let limits = server.pane_limits();
+    let cgroup = match limits.is_empty() {
+        true => None,
+        false => Cgroup::create(&format!("rstmux-{}", std::process::id()), &limits)
+            .inspect(|cgroup| println!("pane cgroup {}", cgroup.path().display()))
+            .inspect_err(|e| {
+                eprintln!("no cgroup for the pane: {}", e);
+                if limits.cpu.is_some() {
+                    eprintln!("pane-cpu-max needs a cgroup, the pane's cpu is not limited");
+                }
+            })
+            .ok(),
+    };
+    match &cgroup {
+        Some(cgroup) => policy.cgroup_procs = Some(cgroup.procs_fd()),
+        None => policy.memory_limit = limits.memory,
+    }
+    let pty = user.and_then(|_| Pty::open_with_policy(builder.build(), &policy, size));
+    match pty {
+        Ok(pty) => *server.pty.lock().unwrap() = Some(pty),
+        // keep the session so whoever attaches can see what went wrong
+        Err(e) => server.show_spawn_error(&program, &e),
+    }
+    let result = server.run(session_name, tcp);
+    drop(cgroup);
+
+    // nobody is listening anymore, don't leave the socket behind unless a
+    // relay took it over, like after migrate-session
+    if !Path::new(&socket::relay_path(session_name)).exists() {
+        let _ = std::fs::remove_file(socket_path(session_name));
+    }
+    result
+}
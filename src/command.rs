@@ -0,0 +1,730 @@
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::Duration;
+
+use crate::compat;
+use crate::hooks::Hook;
+use crate::pipe::PipeTarget;
+
+/// A command understood by the server. The same commands can be issued from
+/// the command line, key bindings and hooks, they all go through the queue.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    DetachClient,
+    /// Redraws the current client, optionally turning one of its flags on or off.
+    RefreshClient {
+        flag: Option<(ClientFlag, bool)>,
+    },
+    ListClients,
+    ListSessions,
+    /// Lists the session's windows, or its panes, one line each expanded
+    /// from `format` or a default like tmux's, see `format::expand`.
+    ListWindows {
+        format: Option<String>,
+    },
+    ListPanes {
+        format: Option<String>,
+    },
+    /// Prints the server's process and how its threads are, see `watchdog::Watchdog`.
+    ServerInfo,
+    ExportSession,
+    /// Copies the pane's output to a file or command, or stops copying it
+    /// without a target. With `toggle`, an open pipe is only closed.
+    PipePane {
+        target: Option<PipeTarget>,
+        toggle: bool,
+    },
+    /// Types keys into the pane as if they came from a client, see `keys::encode_keys`.
+    SendKeys {
+        keys: Vec<String>,
+        literal: bool,
+    },
+    /// Prints the screen, with the attributes of each cell if `styled`.
+    /// With `escaped` it prints the history too, with escape sequences for
+    /// the attributes, for another terminal to show as it was.
+    CapturePane {
+        styled: bool,
+        escaped: bool,
+    },
+    /// Shows a message to the current client until a key is pressed, from
+    /// the command line it is printed. With `all` it is shown to every
+    /// attached client, like a notice to collaborators.
+    DisplayMessage {
+        message: String,
+        all: bool,
+    },
+    /// Shows the history to the current client to move around and copy
+    /// from, optionally from a line numbered like search-panes matches.
+    CopyMode {
+        line: Option<usize>,
+    },
+    /// Shows the current client the history in copy mode from the first
+    /// line the pane printed at or after a local time, see the
+    /// history-timestamps option.
+    JumpToTime(String),
+    /// Types the text last copied in copy mode into the pane.
+    PasteBuffer,
+    /// Prints the text last copied in copy mode.
+    ShowBuffer,
+    SetHook(Hook, String),
+    /// Asks the current client for a line of text and runs `template` with
+    /// every `%%` in it replaced by the text. `#W` and `#S` in `initial`
+    /// are the window and the session name.
+    CommandPrompt {
+        label: Option<String>,
+        initial: String,
+        template: Vec<String>,
+    },
+    RenameWindow(String),
+    /// Sets an option of the session, of the window with `window`, of the
+    /// pane with `pane` or the global default with `global`. Without a
+    /// value the option is unset.
+    SetOption {
+        global: bool,
+        window: bool,
+        pane: bool,
+        name: String,
+        value: Option<String>,
+    },
+    /// Lists the session's options, or the window's, the pane's or the
+    /// global ones. With `inherited` the values in effect are listed with
+    /// where they come from.
+    ShowOptions {
+        global: bool,
+        window: bool,
+        pane: bool,
+        inherited: bool,
+    },
+    /// Searches the panes' history for a regex. The current client is shown
+    /// the matching lines to pick from like filter-pane, others get them
+    /// printed.
+    SearchPanes(String),
+    /// Runs the pane's history through a shell command, like grep, and shows
+    /// the current client the lines it printed in copy mode, from where
+    /// Enter jumps to the line in the history.
+    FilterPane(String),
+    /// Lists the commands the pane's shell ran, from shell integration, in a
+    /// popup for an attached client.
+    ShowTimeline,
+    /// Holds the pane's output back from every client, or lets it through
+    /// again with a redraw. The output still goes into the history.
+    HoldPane,
+    /// Selects the pane, `mark` set turns the mark on or off, see `mark::Mark`.
+    SelectPane {
+        mark: Option<bool>,
+    },
+    /// Ends the session, with `dry_run` only tells what would be ended.
+    KillSession {
+        dry_run: bool,
+    },
+    /// Tells the attached clients that the session moved to the machine at
+    /// an address and ends the pane, see migrate-session which runs it once
+    /// a relay took over the socket.
+    HandOver(String),
+    /// Waits until the pane's content matches a pattern, for scripts that
+    /// need to know when a program is ready for the next command.
+    WaitForOutput {
+        pane: usize,
+        pattern: String,
+        timeout: Option<Duration>,
+    },
+}
+
+/// How the server draws for a client, changed with `refresh-client -f`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientFlag {
+    /// Leave out colors, for slow links and screen readers.
+    TextOnly,
+    /// Send lines of newly printed text instead of screen updates.
+    Accessible,
+}
+
+impl ClientFlag {
+    pub fn parse(name: &str) -> Result<ClientFlag, String> {
+        match name {
+            "text-only" => Ok(ClientFlag::TextOnly),
+            "accessible" => Ok(ClientFlag::Accessible),
+            _ => Err(format!("unknown client flag: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ClientFlag::TextOnly => "text-only",
+            ClientFlag::Accessible => "accessible",
+        }
+    }
+}
+
+pub type CommandResult = Result<String, String>;
+
+/// Who issued a command, and therefore who it applies to by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandSource {
+    Client(usize),
+    Server,
+}
+
+pub struct QueuedCommand {
+    pub command: Command,
+    pub source: CommandSource,
+    reply: Box<dyn FnOnce(CommandResult) + Send>,
+}
+
+/// Commands are executed one at a time in the order they were queued, the
+/// result of each is handed back to whoever queued it.
+#[derive(Clone)]
+pub struct CommandQueue {
+    sender: Sender<QueuedCommand>,
+}
+
+impl Command {
+    pub fn parse(args: &[String]) -> Result<Command, String> {
+        let translated;
+        let args = if compat::enabled() {
+            translated = compat::translate(args);
+            &translated
+        } else {
+            args
+        };
+        let (name, args) = args.split_first().ok_or("no command given")?;
+        let no_args = |command: Command| {
+            if args.is_empty() {
+                Ok(command)
+            } else {
+                Err(format!("{}: too many arguments", name))
+            }
+        };
+
+        match name.as_str() {
+            "detach-client" | "detach" => no_args(Command::DetachClient),
+            "refresh-client" | "refresh" => match args {
+                [] => Ok(Command::RefreshClient { flag: None }),
+                [flag, value] if flag == "-f" => {
+                    // like tmux, a leading ! turns the flag off
+                    let (name, on) = match value.strip_prefix('!') {
+                        Some(name) => (name, false),
+                        None => (value.as_str(), true),
+                    };
+                    let flag = Some((ClientFlag::parse(name)?, on));
+                    Ok(Command::RefreshClient { flag })
+                }
+                _ => Err("usage: refresh-client [-f [!]flag]".to_string()),
+            },
+            "list-clients" | "lsc" => no_args(Command::ListClients),
+            "list-sessions" | "ls" => no_args(Command::ListSessions),
+            "list-windows" | "lsw" => match args {
+                [] => Ok(Command::ListWindows { format: None }),
+                [flag, format] if flag == "-F" => Ok(Command::ListWindows {
+                    format: Some(format.clone()),
+                }),
+                _ => Err("usage: list-windows [-F format]".to_string()),
+            },
+            "list-panes" | "lsp" => match args {
+                [] => Ok(Command::ListPanes { format: None }),
+                [flag, format] if flag == "-F" => Ok(Command::ListPanes {
+                    format: Some(format.clone()),
+                }),
+                _ => Err("usage: list-panes [-F format]".to_string()),
+            },
+            "server-info" | "info" => no_args(Command::ServerInfo),
+            "export-session" | "export" => no_args(Command::ExportSession),
+            "send-keys" | "send" => match args.split_first() {
+                Some((flag, keys)) if flag == "-l" => Ok(Command::SendKeys {
+                    keys: keys.to_vec(),
+                    literal: true,
+                }),
+                _ => Ok(Command::SendKeys {
+                    keys: args.to_vec(),
+                    literal: false,
+                }),
+            },
+            "pipe-pane" | "pipep" => {
+                let (toggle, args) = match args.split_first() {
+                    Some((flag, rest)) if flag == "-o" => (true, rest),
+                    _ => (false, args),
+                };
+                let target = match args {
+                    [] => None,
+                    [flag, path] if flag == "-f" => Some(PipeTarget::File(path.clone())),
+                    [flag, ..] if flag == "-f" => {
+                        return Err("usage: pipe-pane [-o] [-f file | command]".to_string())
+                    }
+                    command => Some(PipeTarget::Command(command.join(" "))),
+                };
+                Ok(Command::PipePane { target, toggle })
+            }
+            "capture-pane" | "capturep" => match args {
+                [] => Ok(Command::CapturePane {
+                    styled: false,
+                    escaped: false,
+                }),
+                [flag] if flag == "-a" => Ok(Command::CapturePane {
+                    styled: true,
+                    escaped: false,
+                }),
+                [flag] if flag == "-e" => Ok(Command::CapturePane {
+                    styled: false,
+                    escaped: true,
+                }),
+                _ => Err("usage: capture-pane [-a | -e]".to_string()),
+            },
+            "kill-session" => match args {
+                [] => Ok(Command::KillSession { dry_run: false }),
+                [flag] if flag == "--dry-run" => Ok(Command::KillSession { dry_run: true }),
+                _ => Err("usage: kill-session [--dry-run]".to_string()),
+            },
+            "hand-over" => match args {
+                [address] => Ok(Command::HandOver(address.clone())),
+                _ => Err("usage: hand-over <host:port>".to_string()),
+            },
+            "select-pane" | "selectp" => match args {
+                [] => Ok(Command::SelectPane { mark: None }),
+                [flag] if flag == "-m" => Ok(Command::SelectPane { mark: Some(true) }),
+                [flag] if flag == "-M" => Ok(Command::SelectPane { mark: Some(false) }),
+                _ => Err("usage: select-pane [-m | -M]".to_string()),
+            },
+            "hold-pane" | "holdp" => no_args(Command::HoldPane),
+            "copy-mode" => match args {
+                [] => Ok(Command::CopyMode { line: None }),
+                [flag, line] if flag == "-l" => {
+                    let line = line
+                        .parse()
+                        .map_err(|_| format!("invalid line: {}", line))?;
+                    Ok(Command::CopyMode { line: Some(line) })
+                }
+                _ => Err("usage: copy-mode [-l line]".to_string()),
+            },
+            "jump-to-time" | "jumpt" => match args {
+                [] => Err("usage: jump-to-time [YYYY-MM-DD] HH:MM[:SS]".to_string()),
+                time => Ok(Command::JumpToTime(time.join(" "))),
+            },
+            "paste-buffer" | "pasteb" => no_args(Command::PasteBuffer),
+            "show-buffer" | "showb" => no_args(Command::ShowBuffer),
+            "display-message" | "display" => {
+                let (all, message) = match args.split_first() {
+                    Some((flag, rest)) if flag == "-a" => (true, rest),
+                    _ => (false, args),
+                };
+                Ok(Command::DisplayMessage {
+                    message: message.join(" "),
+                    all,
+                })
+            }
+            "search-panes" | "searchp" => match args {
+                [pattern] => Ok(Command::SearchPanes(pattern.clone())),
+                _ => Err("usage: search-panes <pattern>".to_string()),
+            },
+            "filter-pane" | "filterp" => match args {
+                [] => Err("usage: filter-pane <command>".to_string()),
+                command => Ok(Command::FilterPane(command.join(" "))),
+            },
+            "show-timeline" | "timeline" => no_args(Command::ShowTimeline),
+            "wait-for-output" | "waitfo" => parse_wait_for_output(args),
+            "set-option" | "set" => {
+                const USAGE: &str = "usage: set-option [-gpuw] <option> [value]";
+                let (flags, args) = option_flags(args, "gpuw").ok_or(USAGE)?;
+                let unset = flags.contains('u');
+                let (name, value) = match args {
+                    [name] if unset => (name, None),
+                    [name, value] if !unset => (name, Some(value.clone())),
+                    _ => return Err(USAGE.to_string()),
+                };
+                Ok(Command::SetOption {
+                    global: flags.contains('g'),
+                    window: flags.contains('w'),
+                    pane: flags.contains('p'),
+                    name: name.clone(),
+                    value,
+                })
+            }
+            "show-options" | "show" => match option_flags(args, "Agpw") {
+                Some((flags, [])) => Ok(Command::ShowOptions {
+                    global: flags.contains('g'),
+                    window: flags.contains('w'),
+                    pane: flags.contains('p'),
+                    inherited: flags.contains('A'),
+                }),
+                _ => Err("usage: show-options [-Agpw]".to_string()),
+            },
+            "command-prompt" => {
+                let mut label = None;
+                let mut initial = String::new();
+                let mut args = args;
+                loop {
+                    match args {
+                        [flag, value, ..] if flag == "-p" => label = Some(value.clone()),
+                        [flag, value, ..] if flag == "-I" => initial = value.clone(),
+                        _ => break,
+                    }
+                    args = &args[2..];
+                }
+                if args.is_empty() {
+                    return Err(
+                        "usage: command-prompt [-I initial] [-p label] <command>".to_string()
+                    );
+                }
+                Ok(Command::CommandPrompt {
+                    label,
+                    initial,
+                    template: args.to_vec(),
+                })
+            }
+            "rename-window" | "renamew" => match args {
+                [name] => Ok(Command::RenameWindow(name.clone())),
+                _ => Err("usage: rename-window <name>".to_string()),
+            },
+            "set-hook" => match args.split_first() {
+                Some((hook, command)) => {
+                    Ok(Command::SetHook(Hook::parse(hook)?, command.join(" ")))
+                }
+                None => Err("usage: set-hook <hook> [command]".to_string()),
+            },
+            _ => Err(format!("unknown command: {}", name)),
+        }
+    }
+
+    /// Parses a command written as a single line, with shell-like quoting.
+    pub fn parse_line(line: &str) -> Result<Command, String> {
+        Command::parse(&split_line(line)?)
+    }
+
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![self.name().to_string()];
+        match self {
+            Command::DisplayMessage { message, all } => {
+                if *all {
+                    args.push("-a".to_string());
+                }
+                args.push(message.clone());
+            }
+            Command::SearchPanes(message)
+            | Command::FilterPane(message)
+            | Command::JumpToTime(message)
+            | Command::HandOver(message)
+            | Command::RenameWindow(message) => args.push(message.clone()),
+            Command::CommandPrompt {
+                label,
+                initial,
+                template,
+            } => {
+                if !initial.is_empty() {
+                    args.extend(["-I".to_string(), initial.clone()]);
+                }
+                if let Some(label) = label {
+                    args.extend(["-p".to_string(), label.clone()]);
+                }
+                args.extend(template.iter().cloned());
+            }
+            Command::SetHook(hook, command) => {
+                args.push(hook.name().to_string());
+                args.push(command.clone());
+            }
+            Command::RefreshClient {
+                flag: Some((flag, on)),
+            } => {
+                let not = if *on { "" } else { "!" };
+                args.extend(["-f".to_string(), format!("{}{}", not, flag.name())]);
+            }
+            Command::ListWindows {
+                format: Some(format),
+            }
+            | Command::ListPanes {
+                format: Some(format),
+            } => args.extend(["-F".to_string(), format.clone()]),
+            Command::CapturePane { styled: true, .. } => args.push("-a".to_string()),
+            Command::CapturePane { escaped: true, .. } => args.push("-e".to_string()),
+            Command::KillSession { dry_run: true } => args.push("--dry-run".to_string()),
+            Command::CopyMode { line: Some(line) } => {
+                args.extend(["-l".to_string(), line.to_string()])
+            }
+            Command::SelectPane { mark: Some(on) } => {
+                args.push(if *on { "-m" } else { "-M" }.to_string())
+            }
+            Command::SetOption {
+                global,
+                window,
+                pane,
+                name,
+                value,
+            } => {
+                let flags: String = [
+                    (*global, 'g'),
+                    (*pane, 'p'),
+                    (value.is_none(), 'u'),
+                    (*window, 'w'),
+                ]
+                .into_iter()
+                .filter_map(|(set, flag)| set.then_some(flag))
+                .collect();
+                if !flags.is_empty() {
+                    args.push(format!("-{}", flags));
+                }
+                args.push(name.clone());
+                args.extend(value.clone());
+            }
+            Command::ShowOptions {
+                global,
+                window,
+                pane,
+                inherited,
+            } => {
+                let flags: String = [
+                    (*inherited, 'A'),
+                    (*global, 'g'),
+                    (*pane, 'p'),
+                    (*window, 'w'),
+                ]
+                .into_iter()
+                .filter_map(|(set, flag)| set.then_some(flag))
+                .collect();
+                if !flags.is_empty() {
+                    args.push(format!("-{}", flags));
+                }
+            }
+            Command::PipePane { target, toggle } => {
+                if *toggle {
+                    args.push("-o".to_string());
+                }
+                match target {
+                    Some(PipeTarget::File(path)) => args.extend(["-f".to_string(), path.clone()]),
+                    Some(PipeTarget::Command(command)) => args.push(command.clone()),
+                    None => {}
+                }
+            }
+            Command::SendKeys { keys, literal } => {
+                if *literal {
+                    args.push("-l".to_string());
+                }
+                args.extend(keys.iter().cloned());
+            }
+            Command::WaitForOutput {
+                pane,
+                pattern,
+                timeout,
+            } => {
+                args.extend(["-t".to_string(), format!("%{}", pane)]);
+                args.extend(["--pattern".to_string(), pattern.clone()]);
+                if let Some(timeout) = timeout {
+                    args.extend(["--timeout".to_string(), timeout.as_secs_f64().to_string()]);
+                }
+            }
+            _ => {}
+        }
+        args
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::DetachClient => "detach-client",
+            Command::RefreshClient { .. } => "refresh-client",
+            Command::ListClients => "list-clients",
+            Command::ListSessions => "list-sessions",
+            Command::ListWindows { .. } => "list-windows",
+            Command::ListPanes { .. } => "list-panes",
+            Command::ServerInfo => "server-info",
+            Command::ExportSession => "export-session",
+            Command::CapturePane { .. } => "capture-pane",
+            Command::SendKeys { .. } => "send-keys",
+            Command::PipePane { .. } => "pipe-pane",
+            Command::DisplayMessage { .. } => "display-message",
+            Command::CopyMode { .. } => "copy-mode",
+            Command::JumpToTime(_) => "jump-to-time",
+            Command::PasteBuffer => "paste-buffer",
+            Command::ShowBuffer => "show-buffer",
+            Command::SetHook(..) => "set-hook",
+            Command::CommandPrompt { .. } => "command-prompt",
+            Command::RenameWindow(_) => "rename-window",
+            Command::SetOption { .. } => "set-option",
+            Command::ShowOptions { .. } => "show-options",
+            Command::SearchPanes(_) => "search-panes",
+            Command::FilterPane(_) => "filter-pane",
+            Command::ShowTimeline => "show-timeline",
+            Command::HoldPane => "hold-pane",
+            Command::SelectPane { .. } => "select-pane",
+            Command::KillSession { .. } => "kill-session",
+            Command::HandOver(_) => "hand-over",
+            Command::WaitForOutput { .. } => "wait-for-output",
+        }
+    }
+
+    /// Whether the command ends the session, takes a client off it or
+    /// changes how it is set up, which a frozen session leaves to its owner.
+    pub fn is_locked_when_frozen(&self) -> bool {
+        matches!(
+            self,
+            Command::KillSession { dry_run: false }
+                | Command::HandOver(_)
+                | Command::DetachClient
+                | Command::PipePane { .. }
+                | Command::RenameWindow(_)
+                | Command::SetHook(..)
+                | Command::SetOption { .. }
+        )
+    }
+
+    /// Whether the command types into the pane or changes the session,
+    /// which a client that may only watch can't do. It can still detach.
+    pub fn needs_write_access(&self) -> bool {
+        match self {
+            Command::DetachClient => false,
+            // the other clients stop seeing the output too
+            Command::HoldPane => true,
+            // the filter is a shell command run as the server's user
+            Command::SendKeys { .. } | Command::PasteBuffer | Command::FilterPane(_) => true,
+            command => command.is_locked_when_frozen(),
+        }
+    }
+}
+
+fn parse_wait_for_output(args: &[String]) -> Result<Command, String> {
+    const USAGE: &str = "usage: wait-for-output [-t pane] --pattern <regex> [--timeout <seconds>]";
+    let mut pane = 0;
+    let mut pattern = None;
+    let mut timeout = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let value = args.next().ok_or(USAGE)?;
+        match arg.as_str() {
+            "-t" => pane = parse_pane(value)?,
+            "--pattern" => pattern = Some(value.clone()),
+            "--timeout" => {
+                let seconds: f64 = value.parse().map_err(|_| USAGE)?;
+                timeout = Some(Duration::try_from_secs_f64(seconds).map_err(|_| USAGE)?);
+            }
+            _ => return Err(USAGE.to_string()),
+        }
+    }
+
+    Ok(Command::WaitForOutput {
+        pane,
+        pattern: pattern.ok_or(USAGE)?,
+        timeout,
+    })
+}
+
+/// Splits leading switches like `-g -w` or `-gw` off the arguments, None if
+/// one of them is not in `allowed`.
+fn option_flags<'a>(args: &'a [String], allowed: &str) -> Option<(String, &'a [String])> {
+    let mut flags = String::new();
+    let mut rest = args;
+    while let Some((arg, remaining)) = rest.split_first() {
+        let Some(switches) = arg.strip_prefix('-').filter(|s| !s.is_empty()) else {
+            break;
+        };
+        if !switches.chars().all(|c| allowed.contains(c)) {
+            return None;
+        }
+        flags.push_str(switches);
+        rest = remaining;
+    }
+    Some((flags, rest))
+}
+
+/// Parses a pane id, written like `%1` or just `1`.
+pub fn parse_pane(id: &str) -> Result<usize, String> {
+    id.strip_prefix('%')
+        .unwrap_or(id)
+        .parse()
+        .map_err(|_| format!("bad pane: {}", id))
+}
+
+impl QueuedCommand {
+    /// Hands the result back to the issuer.
+    pub fn finish(self, result: CommandResult) {
+        (self.reply)(result);
+    }
+}
+
+impl CommandQueue {
+    pub fn new() -> (Self, Receiver<QueuedCommand>) {
+        let (sender, receiver) = channel();
+        (Self { sender }, receiver)
+    }
+
+    /// Queues a command, returns false if nothing is executing commands anymore.
+    pub fn push(
+        &self,
+        command: Command,
+        source: CommandSource,
+        reply: impl FnOnce(CommandResult) + Send + 'static,
+    ) -> bool {
+        let queued = QueuedCommand {
+            command,
+            source,
+            reply: Box::new(reply),
+        };
+        self.sender.send(queued).is_ok()
+    }
+}
+
+/// Splits a line into arguments on whitespace, honoring single quotes,
+/// double quotes and backslash escapes.
+pub fn split_line(line: &str) -> Result<Vec<String>, String> {
+    match split_line_columns(line) {
+        Ok(args) => Ok(args.into_iter().map(|(_, arg)| arg).collect()),
+        Err((_, e)) => Err(e),
+    }
+}
+
+/// Like `split_line`, along with the column each argument starts at,
+/// counted in characters from 1. An error comes with the column at fault.
+pub fn split_line_columns(line: &str) -> Result<Vec<(usize, String)>, (usize, String)> {
+    let mut args = vec![];
+    let mut current: Option<(usize, String)> = None;
+    let mut chars = line.chars().zip(1..);
+    let mut quote: Option<(char, usize)> = None;
+
+    while let Some((c, column)) = chars.next() {
+        match (quote, c) {
+            (Some((q, _)), c) if c == q => quote = None,
+            (Some(('"', _)) | None, '\\') => {
+                let (escaped, _) = chars
+                    .next()
+                    .ok_or((column, "trailing backslash".to_string()))?;
+                current
+                    .get_or_insert((column, String::new()))
+                    .1
+                    .push(escaped);
+            }
+            (Some(_), c) => current.get_or_insert((column, String::new())).1.push(c),
+            (None, '\'' | '"') => {
+                quote = Some((c, column));
+                current.get_or_insert((column, String::new()));
+            }
+            (None, c) if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            (None, c) => current.get_or_insert((column, String::new())).1.push(c),
+        }
+    }
+
+    if let Some((_, column)) = quote {
+        return Err((column, "missing closing quote".to_string()));
+    }
+    if let Some(arg) = current {
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+/// Joins arguments into a line that `split_line` splits back into them.
+pub fn join_line(args: &[String]) -> String {
+    let args: Vec<String> = args
+        .iter()
+        .map(|arg| {
+            let plain = !arg.is_empty()
+                && !arg
+                    .chars()
+                    .any(|c| c.is_whitespace() || matches!(c, '\'' | '"' | '\\'));
+            if plain {
+                return arg.clone();
+            }
+            let escaped = arg.replace('\\', "\\\\").replace('"', "\\\"");
+            format!("\"{}\"", escaped)
+        })
+        .collect();
+    args.join(" ")
+}
@@ -0,0 +1,113 @@
+/// Set to `tmux` to have the commands that tmux spells differently
+/// translated, for scripts and habits carried over from tmux.
+pub const COMPAT_ENV: &str = "RSTMUX_COMPAT";
+
+/// How a tmux command is spelled here.
+struct Alias {
+    /// The tmux command and its short names.
+    names: &'static [&'static str],
+    /// The command here, with any flags it needs to do the same.
+    command: &'static [&'static str],
+    /// tmux flags that are spelled differently, None for those that are
+    /// always the case here and are dropped.
+    flags: &'static [(char, Option<char>)],
+}
+
+const ALIASES: &[Alias] = &[
+    // the pane is printed anyway, -e is for its attributes
+    Alias {
+        names: &["capture-pane", "capturep"],
+        command: &["capture-pane"],
+        flags: &[('p', None), ('e', Some('a'))],
+    },
+    // a session has a single pane in a single window, closing it ends the
+    // session like the last pane in tmux
+    Alias {
+        names: &["kill-pane", "killp", "kill-window", "killw"],
+        command: &["kill-session"],
+        flags: &[],
+    },
+    // each session has a server of its own, its options are the global ones
+    Alias {
+        names: &["set-option", "set"],
+        command: &["set-option"],
+        flags: &[('s', Some('g')), ('q', None)],
+    },
+    Alias {
+        names: &["set-window-option", "setw"],
+        command: &["set-option", "-w"],
+        flags: &[('q', None)],
+    },
+    Alias {
+        names: &["show-options", "show"],
+        command: &["show-options"],
+        flags: &[('s', Some('g'))],
+    },
+    Alias {
+        names: &["show-window-options", "showw"],
+        command: &["show-options", "-w"],
+        flags: &[],
+    },
+];
+
+/// Whether tmux spellings are translated, see `COMPAT_ENV`.
+pub fn enabled() -> bool {
+    std::env::var(COMPAT_ENV).is_ok_and(|value| value == "tmux")
+}
+
+/// The command in `args` as it is spelled here. Commands spelled the same
+/// way are left alone, so translating twice changes nothing.
+pub fn translate(args: &[String]) -> Vec<String> {
+    let Some((name, rest)) = args.split_first() else {
+        return vec![];
+    };
+    let alias = ALIASES
+        .iter()
+        .find(|alias| alias.names.contains(&name.as_str()));
+    let mut translated: Vec<String> = match alias {
+        Some(alias) => alias.command.iter().map(|arg| arg.to_string()).collect(),
+        None => vec![name.clone()],
+    };
+    let flags = alias.map_or(&[][..], |alias| alias.flags);
+
+    let mut rest = rest.iter();
+    while let Some(arg) = rest.next() {
+        if arg == "--" || !arg.starts_with('-') || arg.len() < 2 {
+            translated.push(arg.clone());
+            translated.extend(rest.cloned());
+            break;
+        }
+        if arg == "-t" {
+            translated.push(arg.clone());
+            translated.extend(rest.next().map(|target| translate_target(target)));
+            continue;
+        }
+        let mut switches = String::new();
+        for c in arg[1..].chars() {
+            match flags.iter().find(|(flag, _)| *flag == c) {
+                Some((_, Some(flag))) => switches.push(*flag),
+                Some((_, None)) => {}
+                None => switches.push(c),
+            }
+        }
+        if !switches.is_empty() {
+            translated.push(format!("-{}", switches));
+        }
+    }
+    translated
+}
+
+/// A tmux target as `session:pane`. The window is dropped since a session
+/// has just the one, and so is the `=` tmux takes for an exact match since
+/// names always match exactly here. Pane ids like `%1` mean the same.
+fn translate_target(target: &str) -> String {
+    let target = target.strip_prefix('=').unwrap_or(target);
+    match target.split_once(':') {
+        Some((_, pane)) if pane.starts_with('%') => target.to_string(),
+        Some((session, window)) => match window.split_once('.') {
+            Some((_, pane)) if !pane.is_empty() => format!("{}:{}", session, pane),
+            _ => session.to_string(),
+        },
+        None => target.to_string(),
+    }
+}
@@ -0,0 +1,124 @@
+use std::io::{self, Read};
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress};
+
+use crate::protocol::{Message, MAX_PAYLOAD_SIZE};
+
+/// The ways the server can compress what it sends that this build knows,
+/// most preferred first. A client offers them with `Message::Compress`.
+pub const CODECS: &[&str] = &["deflate"];
+
+/// The server's side of a compressed stream. It is a single deflate stream
+/// for the whole connection, so a redraw is compressed against what was
+/// sent before, flushed after every chunk so the client can draw it at once.
+pub struct Deflater(Compress);
+
+impl Deflater {
+    pub fn new() -> Self {
+        // output is mostly small diffs sent as it happens, speed matters more
+        Deflater(Compress::new(Compression::fast(), false))
+    }
+
+    /// The next chunk of the stream, with everything in `data`.
+    pub fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() / 2 + 64);
+        let start = self.0.total_in();
+        loop {
+            let consumed = (self.0.total_in() - start) as usize;
+            if out.len() == out.capacity() {
+                out.reserve(out.capacity());
+            }
+            self.0
+                .compress_vec(&data[consumed..], &mut out, FlushCompress::Sync)
+                .map_err(io::Error::other)?;
+            // the flush is done once there is room left over
+            let consumed = (self.0.total_in() - start) as usize;
+            if consumed == data.len() && out.len() < out.capacity() {
+                return Ok(out);
+            }
+        }
+    }
+}
+
+impl Default for Deflater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The client's side of a compressed stream, see `Deflater`.
+pub struct Inflater(Decompress);
+
+impl Inflater {
+    pub fn new() -> Self {
+        Inflater(Decompress::new(false))
+    }
+
+    /// What the next chunk of the stream holds.
+    pub fn decompress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = Vec::with_capacity(data.len() * 4 + 64);
+        let start = self.0.total_in();
+        loop {
+            let consumed = (self.0.total_in() - start) as usize;
+            if out.len() == out.capacity() {
+                // a chunk is smaller than the largest message and the
+                // server's output buffer together
+                if out.len() > MAX_PAYLOAD_SIZE * 2 {
+                    return Err(invalid_data("compressed message too large"));
+                }
+                out.reserve(out.capacity());
+            }
+            self.0
+                .decompress_vec(&data[consumed..], &mut out, FlushDecompress::Sync)
+                .map_err(|e| invalid_data(&e.to_string()))?;
+            let consumed = (self.0.total_in() - start) as usize;
+            if consumed == data.len() && out.len() < out.capacity() {
+                return Ok(out);
+            }
+        }
+    }
+}
+
+impl Default for Inflater {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Reads the messages from the server, unpacking the ones that came
+/// compressed.
+#[derive(Default)]
+pub struct MessageReader {
+    inflater: Option<Inflater>,
+    /// Unpacked messages not yet returned.
+    pending: Vec<u8>,
+}
+
+impl MessageReader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Like `Message::read_from`.
+    pub fn read_from<R: Read + ?Sized>(&mut self, reader: &mut R) -> io::Result<Option<Message>> {
+        loop {
+            if let Some(message) = Message::take_from(&mut self.pending)? {
+                return Ok(Some(message));
+            }
+            match Message::read_from(reader)? {
+                Some(Message::Compressed(chunk)) => {
+                    let inflater = self.inflater.get_or_insert_with(Inflater::new);
+                    let data = inflater.decompress(&chunk)?;
+                    self.pending.extend_from_slice(&data);
+                }
+                // a message can't be cut off by the end of the stream
+                None if !self.pending.is_empty() => return Err(io::ErrorKind::UnexpectedEof.into()),
+                message => return Ok(message),
+            }
+        }
+    }
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
@@ -0,0 +1,285 @@
+use std::{
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::command::{split_line_columns, Command};
+use crate::hooks::Hooks;
+use crate::keys::{Key, KeyBindings};
+use crate::options::{Options, Scope};
+
+/// A mistake in a configuration file, found by `check`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConfigError {
+    pub line: usize,
+    /// Where the argument at fault starts, counted in characters from 1.
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// The configuration file, `~/.rstmux.conf`.
+pub fn default_path() -> Option<PathBuf> {
+    env::var_os("HOME").map(|home| Path::new(&home).join(".rstmux.conf"))
+}
+
+/// The command lines of a configuration file, see `preprocess`.
+#[derive(Debug, Clone, Default)]
+pub struct ConfigLines {
+    /// Each line with its line number.
+    pub lines: Vec<(usize, String)>,
+    /// Mistakes in `%if` blocks.
+    pub errors: Vec<ConfigError>,
+}
+
+/// Reads the command lines of a configuration file along with their line
+/// numbers, skipping blank lines and `#` comments.
+pub fn read_lines(path: &Path) -> io::Result<ConfigLines> {
+    Ok(preprocess(&fs::read_to_string(path)?))
+}
+
+/// Checks a configuration file without applying it. Every line has to be a
+/// hook, key binding or option the server or the client understands, and
+/// bound keys have to run commands that exist.
+pub fn check(path: &Path) -> io::Result<Vec<ConfigError>> {
+    let contents = fs::read_to_string(path)?;
+    let raw: Vec<&str> = contents.lines().collect();
+    let ConfigLines { lines, mut errors } = preprocess(&contents);
+    for (number, line) in lines {
+        let indent = raw[number - 1].chars().take_while(|c| c.is_whitespace());
+        if let Err((column, message)) = check_line(&line) {
+            errors.push(ConfigError {
+                line: number,
+                column: indent.count() + column,
+                message,
+            });
+        }
+    }
+    errors.sort_by_key(|e| (e.line, e.column));
+    Ok(errors)
+}
+
+/// An open `%if` block.
+struct Block {
+    line: usize,
+    /// Whether the lines of the current branch are used.
+    taken: bool,
+    /// Whether any branch so far was taken, or the block is inside one that wasn't.
+    done: bool,
+}
+
+/// Expands `${VAR}` and `${VAR:-default}` from the environment in every
+/// line and keeps only the lines of the branches taken in blocks like
+///
+/// ```text
+/// %if host == work-*
+/// set -g status off
+/// %elif os != linux
+/// ...
+/// %else
+/// ...
+/// %endif
+/// ```
+///
+/// A condition compares `host`, `os` or `term` with a pattern where `*`
+/// matches anything, or is a single value that holds unless it is empty or 0.
+pub fn preprocess(contents: &str) -> ConfigLines {
+    let mut lines = vec![];
+    let mut errors = vec![];
+    let mut blocks: Vec<Block> = vec![];
+    for (i, line) in contents.lines().enumerate() {
+        let number = i + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = interpolate(line);
+        let active = blocks.iter().all(|b| b.taken);
+        let (directive, rest) = line.split_once(char::is_whitespace).unwrap_or((&line, ""));
+        let mut holds = || match condition(rest.trim()) {
+            Ok(holds) => holds,
+            Err(message) => {
+                errors.push(ConfigError {
+                    line: number,
+                    column: 1,
+                    message,
+                });
+                false
+            }
+        };
+        let error = match (directive, blocks.last_mut()) {
+            ("%if", _) => {
+                let taken = holds() && active;
+                blocks.push(Block {
+                    line: number,
+                    taken,
+                    done: taken || !active,
+                });
+                None
+            }
+            ("%elif", Some(block)) => {
+                let holds = holds();
+                block.taken = !block.done && holds;
+                block.done |= block.taken;
+                None
+            }
+            ("%else", Some(block)) => {
+                block.taken = !block.done;
+                block.done = true;
+                None
+            }
+            ("%endif", Some(_)) => {
+                blocks.pop();
+                None
+            }
+            ("%elif" | "%else" | "%endif", None) => Some(format!("{} without %if", directive)),
+            _ if directive.starts_with('%') => Some(format!("unknown directive: {}", directive)),
+            _ => {
+                if active {
+                    lines.push((number, line.clone()));
+                }
+                None
+            }
+        };
+        if let Some(message) = error {
+            errors.push(ConfigError {
+                line: number,
+                column: 1,
+                message,
+            });
+        }
+    }
+
+    for block in blocks {
+        errors.push(ConfigError {
+            line: block.line,
+            column: 1,
+            message: "%if without %endif".to_string(),
+        });
+    }
+    ConfigLines { lines, errors }
+}
+
+/// Replaces `${VAR}` with the variable's value, or with `default` in
+/// `${VAR:-default}` when it is unset or empty. Anything else is left alone.
+fn interpolate(line: &str) -> String {
+    let mut out = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("${") {
+        let Some(end) = rest[start..].find('}') else {
+            break;
+        };
+        out.push_str(&rest[..start]);
+        let expression = &rest[start + 2..start + end];
+        let (name, default) = match expression.split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (expression, None),
+        };
+        let value = env::var(name).ok().filter(|v| !v.is_empty());
+        out.push_str(&value.or(default.map(str::to_string)).unwrap_or_default());
+        rest = &rest[start + end + 1..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Evaluates the condition of `%if` or `%elif`.
+fn condition(condition: &str) -> Result<bool, String> {
+    let (name, pattern, equal) = match (condition.split_once("=="), condition.split_once("!=")) {
+        (Some((name, pattern)), _) => (name, pattern, true),
+        (None, Some((name, pattern))) => (name, pattern, false),
+        (None, None) => return Ok(!condition.is_empty() && condition != "0"),
+    };
+    let value = match name.trim() {
+        "host" => hostname(),
+        "os" => env::consts::OS.to_string(),
+        "term" => env::var("TERM").unwrap_or_default(),
+        name => return Err(format!("unknown condition: {}", name)),
+    };
+    Ok(matches(pattern.trim(), &value) == equal)
+}
+
+/// Whether `value` matches `pattern`, where `*` matches any run of characters.
+fn matches(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            let Some(value) = value.strip_prefix(prefix) else {
+                return false;
+            };
+            // try every place the rest of the pattern could start
+            value
+                .char_indices()
+                .map(|(i, _)| i)
+                .chain([value.len()])
+                .any(|i| matches(rest, &value[i..]))
+        }
+    }
+}
+
+pub(crate) fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) } != 0 {
+        return String::new();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+fn check_line(line: &str) -> Result<(), (usize, String)> {
+    let args = split_line_columns(line)?;
+    let words: Vec<String> = args.iter().map(|(_, arg)| arg.clone()).collect();
+
+    // errors end with the argument at fault, like `unknown key: F13`
+    let at = |message: String| {
+        let column = args
+            .iter()
+            .rev()
+            .find(|(_, arg)| !arg.is_empty() && message.ends_with(arg.as_str()))
+            .map_or(1, |(column, _)| *column);
+        (column, message)
+    };
+
+    if Hooks::new("").apply_line(line).map_err(at)? {
+        return Ok(());
+    }
+
+    let mut bindings = KeyBindings::new(Key::parse("C-b").map_err(at)?);
+    if bindings.apply(&words).map_err(at)? {
+        for (_, _, command) in bindings.bindings() {
+            let name = command.first().map(String::as_str);
+            if name != Some(KeyBindings::SEND_PREFIX) && name != Some(KeyBindings::SWITCH_TABLE) {
+                Command::parse(command).map_err(at)?;
+            }
+        }
+        return Ok(());
+    }
+
+    match Command::parse(&words).map_err(at)? {
+        Command::SetOption {
+            global,
+            window,
+            pane,
+            name,
+            value,
+        } => {
+            let scope = Scope::from_flags(global, window, pane);
+            let mut options = Options::new();
+            match value {
+                Some(value) => options.set(scope, &name, &value),
+                None => options.unset(scope, &name),
+            }
+            .map_err(at)
+        }
+        command => Err((
+            1,
+            format!("{} can't be used in the configuration", command.name()),
+        )),
+    }
+}
@@ -0,0 +1,629 @@
+use std::time::UNIX_EPOCH;
+
+use regex::Regex;
+
+use crate::keys::key_len;
+use crate::mouse::MouseEvent;
+use crate::terminal::{Attributes, Frame, Row, Screen};
+
+/// How far a turn of the mouse wheel scrolls.
+const WHEEL_LINES: u64 = 3;
+
+/// How many of the pane's last lines search-panes shows below its matches.
+const PREVIEW_LINES: usize = 5;
+
+/// What a key did in copy mode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyAction {
+    /// Still in copy mode, the view may have changed.
+    Continue,
+    Exit,
+    /// The selected text was copied, which leaves copy mode.
+    Copy(String),
+}
+
+/// A client's view of the history in copy mode, moved around with vi keys.
+/// Lines are numbered like `Screen::scrolled`, so the view stays put while
+/// the pane prints more.
+#[derive(Debug, Clone)]
+pub struct CopyMode {
+    /// The cursor, a line and a column.
+    line: u64,
+    col: usize,
+    /// The line at the top of the view.
+    top: u64,
+    /// Where the selection started, it ends at the cursor.
+    anchor: Option<(u64, usize)>,
+    /// The search being typed after / or ?.
+    prompt: Option<Prompt>,
+    /// The last search, repeated with n and N.
+    search: Option<Search>,
+    /// Set when the wheel started copy mode, scrolling back down to the
+    /// bottom leaves it.
+    leave_at_bottom: bool,
+    /// The lines a filter kept, shown instead of the history until one is
+    /// picked, see filter-pane.
+    filter: Option<Filter>,
+}
+
+/// The lines of the history a filter command printed, only moved around in:
+/// Enter jumps to the cursor's line in the history, in copy mode as usual.
+#[derive(Debug, Clone)]
+struct Filter {
+    command: String,
+    /// The lines kept, numbered like `Screen::scrolled`.
+    lines: Vec<u64>,
+    /// The cursor and the line at the top of the view, indexes in `lines`.
+    cursor: usize,
+    top: usize,
+    /// How many of the pane's last lines are shown live below the list, to
+    /// tell the target by what it shows.
+    preview: usize,
+}
+
+/// A search as it is typed, the cursor follows the first match.
+#[derive(Debug, Clone)]
+struct Prompt {
+    text: String,
+    backward: bool,
+    /// Where the cursor and the view were, to go back to if it is cancelled.
+    origin: (u64, usize, u64),
+    /// The text as a pattern, none while it is not a valid one.
+    pattern: Option<Regex>,
+}
+
+#[derive(Debug, Clone)]
+struct Search {
+    pattern: Regex,
+    backward: bool,
+}
+
+impl CopyMode {
+    /// Starts out showing the screen, with the cursor where the pane's is.
+    pub fn new(screen: &Screen) -> Self {
+        let cursor = screen.cursor();
+        CopyMode {
+            line: screen.scrolled() + cursor.row as u64,
+            col: cursor.col,
+            top: screen.scrolled(),
+            anchor: None,
+            prompt: None,
+            search: None,
+            leave_at_bottom: false,
+            filter: None,
+        }
+    }
+
+    /// Starts out like `new`, for scrolling up with the mouse wheel.
+    pub fn scrolled_in(screen: &Screen) -> Self {
+        CopyMode {
+            leave_at_bottom: true,
+            ..CopyMode::new(screen)
+        }
+    }
+
+    /// Starts out showing only `lines`, numbered like `Screen::scrolled`,
+    /// which `command` kept of the history.
+    pub fn filtered(screen: &Screen, command: &str, lines: Vec<u64>) -> Self {
+        CopyMode {
+            filter: Some(Filter {
+                command: command.to_string(),
+                lines,
+                cursor: 0,
+                top: 0,
+                preview: 0,
+            }),
+            ..CopyMode::new(screen)
+        }
+    }
+
+    /// Shows the lines search-panes matched like `filtered` does, with the
+    /// end of the pane they are in below them.
+    pub fn matches(screen: &Screen, pattern: &str, lines: Vec<u64>) -> Self {
+        let mut mode = Self::filtered(screen, pattern, lines);
+        if let Some(filter) = mode.filter.as_mut() {
+            filter.preview = PREVIEW_LINES;
+        }
+        mode
+    }
+
+    /// Moves the cursor to the start of a line, an index in `Screen::lines`
+    /// like search-panes and show-timeline print, and shows it at the top.
+    pub fn jump(&mut self, screen: &Screen, index: usize, rows: usize) {
+        self.line = screen.first_line() + index as u64;
+        self.col = 0;
+        self.top = self.line;
+        self.clamp(screen, rows);
+    }
+
+    /// Looks where another client's view is and selects what it selected,
+    /// see the copy-mode-sync option. Searches stay each client's own.
+    pub fn follow(&mut self, leader: &CopyMode, screen: &Screen, rows: usize) {
+        self.line = leader.line;
+        self.col = leader.col;
+        self.top = leader.top;
+        self.anchor = leader.anchor;
+        self.clamp(screen, rows);
+    }
+
+    /// Handles the keys in `input`, stopping at the first that leaves.
+    pub fn input(&mut self, input: &[u8], screen: &Screen, rows: usize) -> CopyAction {
+        let mut i = 0;
+        while i < input.len() {
+            let len = key_len(&input[i..]).max(1);
+            let action = self.key(&input[i..i + len], screen, rows);
+            if action != CopyAction::Continue {
+                return action;
+            }
+            i += len;
+        }
+        CopyAction::Continue
+    }
+
+    fn key(&mut self, key: &[u8], screen: &Screen, rows: usize) -> CopyAction {
+        if self.filter.is_some() {
+            return self.filter_key(key, screen, rows);
+        }
+        if self.prompt.is_some() {
+            self.prompt_key(key, screen, rows);
+            return CopyAction::Continue;
+        }
+        let page = rows.max(1) as u64;
+        let last = screen.first_line() + (screen.history().len() + screen.rows()) as u64 - 1;
+        match key {
+            b"q" | b"\x1b" | b"\x03" => return CopyAction::Exit,
+            b"h" | b"\x1b[D" | b"\x1bOD" | b"\x7f" => self.col = self.col.saturating_sub(1),
+            b"l" | b"\x1b[C" | b"\x1bOC" => self.col += 1,
+            b"k" | b"\x1b[A" | b"\x1bOA" => self.line = self.line.saturating_sub(1),
+            b"j" | b"\x1b[B" | b"\x1bOB" => self.line += 1,
+            b"0" | b"\x1b[H" | b"\x1bOH" => self.col = 0,
+            b"$" | b"\x1b[F" | b"\x1bOF" => {
+                let text = self
+                    .row(screen, self.line)
+                    .map(Row::text)
+                    .unwrap_or_default();
+                self.col = text.chars().count().saturating_sub(1);
+            }
+            b"w" => self.next_word(screen),
+            b"b" => self.previous_word(screen),
+            b"g" => self.line = 0,
+            b"G" => self.line = last,
+            b"H" => self.line = self.top,
+            b"L" => self.line = self.top + page - 1,
+            b"\x15" => self.scroll_up(page / 2),
+            b"\x04" => self.scroll_down(page / 2),
+            b"\x02" | b"\x1b[5~" => self.scroll_up(page),
+            b"\x06" | b"\x1b[6~" => self.scroll_down(page),
+            b"/" | b"?" => {
+                self.prompt = Some(Prompt {
+                    text: String::new(),
+                    backward: key == b"?",
+                    origin: (self.line, self.col, self.top),
+                    pattern: None,
+                })
+            }
+            b"n" | b"N" => {
+                if let Some(search) = self.search.clone() {
+                    let backward = search.backward != (key == b"N");
+                    self.find(screen, &search.pattern, backward);
+                }
+            }
+            b" " | b"v" => {
+                self.anchor = match self.anchor {
+                    Some(_) => None,
+                    None => Some((self.line, self.col)),
+                }
+            }
+            b"y" | b"\r" => {
+                return match self.selection(screen) {
+                    Some(text) => CopyAction::Copy(text),
+                    None if key == b"\r" => CopyAction::Exit,
+                    None => CopyAction::Continue,
+                };
+            }
+            _ => {}
+        }
+        self.clamp(screen, rows);
+        CopyAction::Continue
+    }
+
+    /// Handles a mouse event on the pane: the wheel scrolls, a click moves
+    /// the cursor and dragging selects. The selection is copied once the
+    /// button is let go.
+    pub fn mouse(&mut self, event: &MouseEvent, screen: &Screen, rows: usize) -> CopyAction {
+        if let Some(filter) = self.filter.as_mut() {
+            // the view is read-only, a click only moves the cursor
+            if event.is_wheel() {
+                match event.is_wheel_up() {
+                    true => filter.scroll_up(WHEEL_LINES as usize),
+                    false => filter.scroll_down(WHEEL_LINES as usize),
+                }
+            } else if event.button() == 0 && !event.is_motion() && !event.release {
+                // a click on the preview is not on the list
+                if (event.row as usize) < filter.list_rows(rows) {
+                    filter.cursor = filter.top + event.row as usize;
+                }
+            }
+            filter.clamp(rows);
+            return CopyAction::Continue;
+        }
+        if event.is_wheel() {
+            match event.is_wheel_up() {
+                true => self.scroll_up(WHEEL_LINES),
+                false => self.scroll_down(WHEEL_LINES),
+            }
+            self.clamp(screen, rows);
+            if self.leave_at_bottom && self.top >= screen.scrolled() {
+                return CopyAction::Exit;
+            }
+            return CopyAction::Continue;
+        }
+        if event.button() != 0 {
+            return CopyAction::Continue;
+        }
+        let position = (self.top + event.row as u64, event.col as usize);
+        match (event.is_motion(), event.release) {
+            (false, false) => {
+                (self.line, self.col) = position;
+                self.anchor = None;
+            }
+            (true, _) => {
+                self.anchor.get_or_insert((self.line, self.col));
+                (self.line, self.col) = position;
+            }
+            (false, true) => {
+                if let Some(text) = self.selection(screen) {
+                    return CopyAction::Copy(text);
+                }
+            }
+        }
+        self.clamp(screen, rows);
+        CopyAction::Continue
+    }
+
+    /// Moves around the lines a filter kept, Enter leaves them for the
+    /// cursor's line in the history.
+    fn filter_key(&mut self, key: &[u8], screen: &Screen, rows: usize) -> CopyAction {
+        let Some(filter) = self.filter.as_mut() else {
+            return CopyAction::Continue;
+        };
+        let page = rows.max(1);
+        match key {
+            b"q" | b"\x1b" | b"\x03" => return CopyAction::Exit,
+            b"k" | b"\x1b[A" | b"\x1bOA" => filter.cursor = filter.cursor.saturating_sub(1),
+            b"j" | b"\x1b[B" | b"\x1bOB" => filter.cursor += 1,
+            b"g" => filter.cursor = 0,
+            b"G" => filter.cursor = filter.lines.len(),
+            b"\x15" => filter.scroll_up(page / 2),
+            b"\x04" => filter.scroll_down(page / 2),
+            b"\x02" | b"\x1b[5~" => filter.scroll_up(page),
+            b"\x06" | b"\x1b[6~" => filter.scroll_down(page),
+            b"\r" => {
+                if let Some(&line) = filter.lines.get(filter.cursor) {
+                    self.line = line;
+                    self.col = 0;
+                    self.top = line;
+                }
+                self.filter = None;
+                self.clamp(screen, rows);
+                return CopyAction::Continue;
+            }
+            _ => {}
+        }
+        filter.clamp(rows);
+        CopyAction::Continue
+    }
+
+    /// Edits the search being typed, moving to its first match as it
+    /// changes. Enter keeps it for n and N.
+    fn prompt_key(&mut self, key: &[u8], screen: &Screen, rows: usize) {
+        let Some(prompt) = self.prompt.as_mut() else {
+            return;
+        };
+        match key {
+            b"\r" => {
+                let prompt = self.prompt.take().unwrap();
+                if let Some(pattern) = prompt.pattern {
+                    let backward = prompt.backward;
+                    self.search = Some(Search { pattern, backward });
+                }
+                return;
+            }
+            b"\x1b" | b"\x03" => {
+                let prompt = self.prompt.take().unwrap();
+                (self.line, self.col, self.top) = prompt.origin;
+                return;
+            }
+            b"\x7f" | b"\x08" => {
+                prompt.text.pop();
+            }
+            _ => match std::str::from_utf8(key) {
+                Ok(text) if !text.chars().any(char::is_control) => prompt.text.push_str(text),
+                _ => return,
+            },
+        }
+
+        // every change searches again from where the search started
+        prompt.pattern = match prompt.text.is_empty() {
+            true => None,
+            false => Regex::new(&prompt.text).ok(),
+        };
+        (self.line, self.col, self.top) = prompt.origin;
+        if let Some(pattern) = prompt.pattern.clone() {
+            let backward = prompt.backward;
+            self.find(screen, &pattern, backward);
+        }
+        self.clamp(screen, rows);
+    }
+
+    /// Moves the cursor to the next match of `pattern`, or the previous one.
+    fn find(&mut self, screen: &Screen, pattern: &Regex, backward: bool) {
+        let Some(index) = screen.line_index(self.line) else {
+            return;
+        };
+        if let Some((index, columns)) = screen.find(pattern, index, self.col, backward) {
+            self.line = screen.first_line() + index as u64;
+            self.col = columns.start;
+        }
+    }
+
+    /// The view for a client, the pane's part of it `rows` x `cols` with
+    /// `footer` below. The top right shows how far back the view is, and
+    /// when the cursor's line was printed if the history records it.
+    pub fn frame(&self, screen: &Screen, rows: usize, cols: usize, footer: &[Row]) -> Frame {
+        if let Some(filter) = &self.filter {
+            return filter.frame(screen, rows, cols, footer);
+        }
+        // the pane may have printed enough since that the top fell out of
+        // the history
+        let top = self.top.max(screen.first_line());
+        let index = screen.line_index(top).unwrap_or(0);
+        let pane_rows = rows.saturating_sub(footer.len());
+        let mut frame = Frame::compose_history(screen, index, rows, cols, footer);
+
+        if let Some(((start_line, start_col), (end_line, end_col))) = self.range() {
+            for line in start_line.max(top)..=end_line {
+                let r = (line - top) as usize;
+                if r >= pane_rows {
+                    break;
+                }
+                let start = if line == start_line { start_col } else { 0 };
+                let end = if line == end_line { end_col + 1 } else { cols };
+                frame.highlight(r, start, end);
+            }
+        }
+
+        // every match in view stands out, the one typed so far while typing
+        let pattern = match &self.prompt {
+            Some(prompt) => prompt.pattern.as_ref(),
+            None => self.search.as_ref().map(|search| &search.pattern),
+        };
+        if let Some(pattern) = pattern {
+            for r in 0..pane_rows {
+                let Some(row) = screen.line(index + r) else {
+                    break;
+                };
+                for columns in row.find(pattern) {
+                    frame.highlight(r, columns.start, columns.end);
+                }
+            }
+        }
+
+        let back = screen.scrolled().saturating_sub(top);
+        let mut position = format!("{}/{}", back, screen.history().len());
+        if let Some(time) = self.row(screen, self.line).and_then(|row| row.time) {
+            let time = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            let time = crate::status::format_time("%H:%M:%S", time as libc::time_t);
+            position = format!("{} {}", time, position);
+        }
+        let position = label(&format!("[{}]", position));
+        frame.place(0, cols.saturating_sub(position.cells.len()), &position);
+        match &self.prompt {
+            // on the status line, or the last row without one
+            Some(prompt) => {
+                let kind = if prompt.backward { '?' } else { '/' };
+                let text = format!("{}{}", kind, prompt.text);
+                let r = rows.saturating_sub(1);
+                frame.place(r, 0, &Row::from_text(&text, Attributes::default(), cols));
+                frame.show_cursor(r, crate::text::width(&text));
+            }
+            None => frame.show_cursor(self.line.saturating_sub(top) as usize, self.col),
+        }
+        frame
+    }
+
+    /// The selection from its start to its end, both inclusive.
+    fn range(&self) -> Option<((u64, usize), (u64, usize))> {
+        let anchor = self.anchor?;
+        let cursor = (self.line, self.col);
+        Some((anchor.min(cursor), anchor.max(cursor)))
+    }
+
+    /// The selected text, rows that were wrapped by the terminal are joined
+    /// back into one line.
+    fn selection(&self, screen: &Screen) -> Option<String> {
+        let ((start_line, start_col), (end_line, end_col)) = self.range()?;
+        let mut text = String::new();
+        for line in start_line..=end_line {
+            let Some(row) = self.row(screen, line) else {
+                continue;
+            };
+            let start = if line == start_line { start_col } else { 0 };
+            let end = if line == end_line {
+                end_col + 1
+            } else {
+                row.cells.len()
+            };
+            let cells = row.cells.iter().take(end).skip(start);
+            let part: String = cells.filter(|c| c.width > 0).map(|c| c.c).collect();
+            if row.wrapped && line != end_line {
+                text.push_str(&part);
+            } else {
+                text.push_str(part.trim_end());
+                if line != end_line {
+                    text.push('\n');
+                }
+            }
+        }
+        Some(text)
+    }
+
+    fn row<'a>(&self, screen: &'a Screen, line: u64) -> Option<&'a Row> {
+        screen.line_index(line).and_then(|i| screen.line(i))
+    }
+
+    /// Moves to the start of the next word, on this line or the next.
+    fn next_word(&mut self, screen: &Screen) {
+        let Some(row) = self.row(screen, self.line) else {
+            return;
+        };
+        let chars: Vec<char> = row.cells.iter().map(|c| c.c).collect();
+        let mut col = self.col;
+        while col < chars.len() && !chars[col].is_whitespace() {
+            col += 1;
+        }
+        while col < chars.len() && chars[col].is_whitespace() {
+            col += 1;
+        }
+        if col < chars.len() {
+            self.col = col;
+        } else {
+            self.line += 1;
+            self.col = 0;
+        }
+    }
+
+    /// Moves to the start of this word, or of the previous one.
+    fn previous_word(&mut self, screen: &Screen) {
+        let Some(row) = self.row(screen, self.line) else {
+            return;
+        };
+        let chars: Vec<char> = row.cells.iter().map(|c| c.c).collect();
+        let mut col = self.col.min(chars.len());
+        while col > 0 && chars[col - 1].is_whitespace() {
+            col -= 1;
+        }
+        while col > 0 && !chars[col - 1].is_whitespace() {
+            col -= 1;
+        }
+        self.col = col;
+    }
+
+    fn scroll_up(&mut self, lines: u64) {
+        self.top = self.top.saturating_sub(lines);
+        self.line = self.line.saturating_sub(lines);
+    }
+
+    fn scroll_down(&mut self, lines: u64) {
+        self.top += lines;
+        self.line += lines;
+    }
+
+    /// Keeps the cursor on a line that exists and in the view, and the view
+    /// within the lines.
+    fn clamp(&mut self, screen: &Screen, rows: usize) {
+        let rows = rows.max(1) as u64;
+        let first = screen.first_line();
+        let last = first + (screen.history().len() + screen.rows()) as u64 - 1;
+        self.line = self.line.clamp(first, last);
+        self.col = self.col.min(screen.cols() - 1);
+        if self.line < self.top {
+            self.top = self.line;
+        }
+        if self.line >= self.top + rows {
+            self.top = self.line + 1 - rows;
+        }
+        self.top = self
+            .top
+            .clamp(first, (last + 1).saturating_sub(rows).max(first));
+        if let Some((line, col)) = self.anchor.as_mut() {
+            *line = (*line).max(first);
+            *col = (*col).min(screen.cols() - 1);
+        }
+    }
+}
+
+impl Filter {
+    /// The view of the lines kept, the cursor's stands out. The top right
+    /// shows the command and where the cursor is among them.
+    fn frame(&self, screen: &Screen, rows: usize, cols: usize, footer: &[Row]) -> Frame {
+        // lines that fell out of the history since are left blank
+        let gone = Row::new(cols);
+        let pane_rows = rows.saturating_sub(footer.len());
+        let shown = self.list_rows(pane_rows);
+        let mut lines: Vec<&Row> = self
+            .lines
+            .iter()
+            .skip(self.top)
+            .take(shown)
+            .map(|&line| {
+                let index = screen.line_index(line);
+                index.and_then(|i| screen.line(i)).unwrap_or(&gone)
+            })
+            .collect();
+
+        // the preview ends at the cursor's row, where the pane is printing,
+        // below a rule naming the pane
+        let rule = Row::from_text(
+            &format!("─ %0 {}", "─".repeat(cols)),
+            Attributes::default(),
+            cols,
+        );
+        if shown < pane_rows {
+            lines.resize(shown, &gone);
+            lines.push(&rule);
+            let end = screen.cursor().row + 1;
+            let start = end.saturating_sub(pane_rows - shown - 1);
+            lines.extend((start..end).map(|r| screen.grid().row(r)));
+        }
+        let mut frame = Frame::compose_rows(screen, lines.into_iter(), rows, cols, footer);
+        frame.highlight(self.cursor.saturating_sub(self.top), 0, cols);
+
+        let command = crate::text::ellipsize(&self.command, cols / 2);
+        let position = format!("[{}: {}/{}]", command, self.cursor + 1, self.lines.len());
+        let position = label(&position);
+        frame.place(0, cols.saturating_sub(position.cells.len()), &position);
+        frame
+    }
+
+    fn scroll_up(&mut self, lines: usize) {
+        self.top = self.top.saturating_sub(lines);
+        self.cursor = self.cursor.saturating_sub(lines);
+    }
+
+    fn scroll_down(&mut self, lines: usize) {
+        self.top += lines;
+        self.cursor += lines;
+    }
+
+    /// The rows of the view the list takes, those left by the preview. A
+    /// view too small for both shows only the list.
+    fn list_rows(&self, rows: usize) -> usize {
+        match self.preview > 0 && rows > self.preview + 2 {
+            true => rows - self.preview - 1,
+            false => rows,
+        }
+    }
+
+    /// Keeps the cursor on a line that was kept and in the view.
+    fn clamp(&mut self, rows: usize) {
+        let rows = self.list_rows(rows).max(1);
+        self.cursor = self.cursor.min(self.lines.len().saturating_sub(1));
+        self.top = self.top.min(self.lines.len().saturating_sub(rows));
+        if self.cursor < self.top {
+            self.top = self.cursor;
+        }
+        if self.cursor >= self.top + rows {
+            self.top = self.cursor + 1 - rows;
+        }
+    }
+}
+
+/// Text shown over the top right of the view, in reverse.
+fn label(text: &str) -> Row {
+    let attrs = Attributes {
+        reverse: true,
+        ..Attributes::default()
+    };
+    Row::from_text(text, attrs, crate::text::width(text))
+}
@@ -0,0 +1,66 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::{fd::AsRawFd, unix::fs::DirBuilderExt},
+    path::Path,
+};
+
+/// Forks a daemon that is detached from the controlling terminal, with stdin
+/// on /dev/null and stdout and stderr appended to `log_path`.
+///
+/// Returns true in the daemon and false in the calling process once the
+/// daemon is running. Must be called before any threads are started, only
+/// the calling thread survives a fork.
+pub fn daemonize(log_path: &Path) -> io::Result<bool> {
+    if let Some(parent) = log_path.parent() {
+        // the log may hold what was typed, see `log-secure-input`
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)?;
+    }
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log_path)?;
+    let null = File::open("/dev/null")?;
+
+    unsafe {
+        match libc::fork() {
+            -1 => return Err(io::Error::last_os_error()),
+            0 => {}
+            pid => {
+                // the intermediate child exits as soon as the daemon is forked
+                let mut status = 0;
+                if libc::waitpid(pid, &mut status, 0) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                if !libc::WIFEXITED(status) || libc::WEXITSTATUS(status) != 0 {
+                    return Err(io::Error::other("failed to fork the daemon"));
+                }
+                return Ok(false);
+            }
+        }
+
+        // a new session without a controlling terminal, so closing the
+        // terminal we were started from doesn't hang us up
+        if libc::setsid() == -1 {
+            libc::_exit(1);
+        }
+
+        // forking again means we are no session leader and can never
+        // acquire a controlling terminal by accident
+        match libc::fork() {
+            -1 => libc::_exit(1),
+            0 => {}
+            _ => libc::_exit(0),
+        }
+
+        // the working directory is kept, panes start where the session was created
+        libc::dup2(null.as_raw_fd(), 0);
+        libc::dup2(log.as_raw_fd(), 1);
+        libc::dup2(log.as_raw_fd(), 2);
+    }
+
+    Ok(true)
+}
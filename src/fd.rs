@@ -30,6 +30,19 @@ impl FileDescriptor {
         }
         Ok(FileDescriptor::new(duped))
     }
+
+    /// Makes reads and writes fail with `WouldBlock` instead of waiting,
+    /// for every duplicate of the fd as they share the flag.
+    pub fn set_nonblocking(&self) -> io::Result<()> {
+        let flags = unsafe { libc::fcntl(self.fd, libc::F_GETFL) };
+        if flags < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::fcntl(self.fd, libc::F_SETFL, flags | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
 }
 
 impl AsRawFd for FileDescriptor {
@@ -61,20 +74,24 @@ impl Drop for FileDescriptor {
 
 impl Read for FileDescriptor {
     fn read(&mut self, buf: &mut [u8]) -> Result<usize, io::Error> {
-        let size = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
-        if size == -1 {
+        loop {
+            let size = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut _, buf.len()) };
+            if size != -1 {
+                return Ok(size as usize);
+            }
+
             let e = std::io::Error::last_os_error();
-            if e.raw_os_error() == Some(libc::EIO) {
+            match e.raw_os_error() {
+                // EINTR means a signal arrived before any data was read,
+                // nothing was consumed so the read can simply be retried.
+                Some(libc::EINTR) => continue,
                 // EIO indicates that the worker pty has been closed.
                 // Treat this as EOF so that std::io::Read::read_to_string
                 // and similar functions gracefully terminate when they
                 // encounter this condition.
-                Ok(0)
-            } else {
-                Err(e)
+                Some(libc::EIO) => return Ok(0),
+                _ => return Err(e),
             }
-        } else {
-            Ok(size as usize)
         }
     }
 }
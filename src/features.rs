@@ -0,0 +1,221 @@
+use std::fmt;
+
+use crate::terminal::Color;
+
+/// Asks the client's terminal for its name and version (XTVERSION), sets a
+/// true color and reads it back (DECRQSS) to see whether it was kept, then
+/// asks for the primary device attributes (DA1). Every terminal answers the
+/// last, so its answer marks the end of the others.
+pub const PROBE: &str = "\x1b[>0q\x1b[38;2;1;2;3m\x1bP$qm\x1b\\\x1b[0m\x1b[c";
+
+/// How many colors a terminal shows, colors it can't are sent as the
+/// nearest it can.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum ColorDepth {
+    /// The 16 colors every color terminal has.
+    Basic,
+    Indexed,
+    #[default]
+    TrueColor,
+}
+
+impl fmt::Display for ColorDepth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorDepth::Basic => write!(f, "16 colors"),
+            ColorDepth::Indexed => write!(f, "256 colors"),
+            ColorDepth::TrueColor => write!(f, "true color"),
+        }
+    }
+}
+
+/// What a client's terminal can do. It is asked when the client attaches,
+/// TERM and COLORTERM are only a guess for terminals that don't answer.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Features {
+    /// The name and version the terminal reported, like `XTerm(388)`.
+    pub version: Option<String>,
+    /// The terminal's primary device attributes, 22 for color for example.
+    pub attributes: Vec<u16>,
+    pub colors: ColorDepth,
+    /// Whether the terminal answered the probe.
+    pub probed: bool,
+}
+
+impl Features {
+    /// A guess from the client's TERM and COLORTERM.
+    pub fn from_env(term: &str, colorterm: &str) -> Self {
+        let colors = if matches!(colorterm, "truecolor" | "24bit") {
+            ColorDepth::TrueColor
+        } else if term.contains("256color") {
+            ColorDepth::Indexed
+        } else {
+            ColorDepth::Basic
+        };
+        Features {
+            colors,
+            ..Features::default()
+        }
+    }
+
+    /// Reads the terminal's answers to `PROBE` from what it sent, None
+    /// until the device attributes arrived. `guess` gives the colors if
+    /// the terminal can't read back what it was set to. Also returns
+    /// whatever else was in `input`, like keys typed in the meantime.
+    pub fn from_replies(input: &[u8], guess: &Features) -> Option<(Features, Vec<u8>)> {
+        let mut features = Features {
+            probed: true,
+            ..guess.clone()
+        };
+        let mut rest = vec![];
+        let mut i = 0;
+        let mut done = false;
+        while i < input.len() {
+            let tail = &input[i..];
+            if let Some(body) = tail.strip_prefix(b"\x1bP") {
+                // a device control string, wait for its string terminator
+                let end = body.windows(2).position(|w| w == b"\x1b\\")?;
+                let body = String::from_utf8_lossy(&body[..end]);
+                if let Some(version) = body.strip_prefix(">|") {
+                    features.version = Some(version.to_string());
+                } else if let Some(sgr) = body.strip_prefix("1$r") {
+                    // like 38:2::1:2:3 from xterm or 38;2;1;2;3
+                    features.colors = match sgr.contains(":1:2:3") || sgr.contains(";1;2;3") {
+                        true => ColorDepth::TrueColor,
+                        false => guess.colors.min(ColorDepth::Indexed),
+                    };
+                }
+                i += 2 + end + 2;
+                continue;
+            }
+            if let Some(body) = tail.strip_prefix(b"\x1b[?") {
+                let end = body.iter().position(|b| !matches!(b, b'0'..=b'9' | b';'));
+                if let Some(end) = end.filter(|&end| body[end] == b'c') {
+                    let params = String::from_utf8_lossy(&body[..end]);
+                    features.attributes =
+                        params.split(';').filter_map(|p| p.parse().ok()).collect();
+                    done = true;
+                    i += 3 + end + 1;
+                    continue;
+                }
+            }
+            rest.push(input[i]);
+            i += 1;
+        }
+        done.then_some((features, rest))
+    }
+
+    /// The features as `name=value` lines, for the protocol.
+    pub fn encode(&self) -> String {
+        let attributes: Vec<String> = self.attributes.iter().map(u16::to_string).collect();
+        let colors = match self.colors {
+            ColorDepth::Basic => "16",
+            ColorDepth::Indexed => "256",
+            ColorDepth::TrueColor => "rgb",
+        };
+        let mut lines = vec![
+            format!("colors={}", colors),
+            format!("attributes={}", attributes.join(";")),
+            format!("probed={}", self.probed as u8),
+        ];
+        if let Some(version) = &self.version {
+            lines.push(format!("version={}", version));
+        }
+        lines.join("\n")
+    }
+
+    pub fn decode(text: &str) -> Result<Self, String> {
+        let mut features = Features::default();
+        for line in text.lines() {
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("invalid feature: {}", line))?;
+            match name {
+                "colors" => {
+                    features.colors = match value {
+                        "16" => ColorDepth::Basic,
+                        "256" => ColorDepth::Indexed,
+                        _ => ColorDepth::TrueColor,
+                    }
+                }
+                "attributes" => {
+                    features.attributes = value.split(';').filter_map(|p| p.parse().ok()).collect()
+                }
+                "probed" => features.probed = value == "1",
+                "version" => features.version = Some(value.to_string()),
+                // from newer clients
+                _ => {}
+            }
+        }
+        Ok(features)
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (&self.version, self.probed) {
+            (Some(version), _) => write!(f, "{}, {}", version, self.colors),
+            (None, true) => write!(f, "{}", self.colors),
+            (None, false) => write!(f, "{} from TERM", self.colors),
+        }
+    }
+}
+
+/// The 16 basic colors as xterm shows them.
+const BASIC: [(u8, u8, u8); 16] = [
+    (0, 0, 0),
+    (205, 0, 0),
+    (0, 205, 0),
+    (205, 205, 0),
+    (0, 0, 238),
+    (205, 0, 205),
+    (0, 205, 205),
+    (229, 229, 229),
+    (127, 127, 127),
+    (255, 0, 0),
+    (0, 255, 0),
+    (255, 255, 0),
+    (92, 92, 255),
+    (255, 0, 255),
+    (0, 255, 255),
+    (255, 255, 255),
+];
+
+/// The levels of each component in the 6x6x6 cube of the 256 colors.
+const CUBE: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+/// The nearest color a terminal with `depth` shows.
+pub fn limit_color(color: Color, depth: ColorDepth) -> Color {
+    let rgb = match (color, depth) {
+        (Color::Rgb(r, g, b), ColorDepth::Basic | ColorDepth::Indexed) => (r, g, b),
+        (Color::Indexed(i), ColorDepth::Basic) if i >= 16 => indexed_rgb(i),
+        _ => return color,
+    };
+    // the basic colors are left out of the 256, terminals often change them
+    let candidates = match depth {
+        ColorDepth::Basic => 0..=15,
+        _ => 16..=255,
+    };
+    let nearest = candidates.min_by_key(|&i| distance(rgb, indexed_rgb(i)));
+    Color::Indexed(nearest.unwrap_or(0))
+}
+
+fn indexed_rgb(i: u8) -> (u8, u8, u8) {
+    match i {
+        0..=15 => BASIC[i as usize],
+        16..=231 => {
+            let i = i - 16;
+            let (r, g, b) = (i / 36, i / 6 % 6, i % 6);
+            (CUBE[r as usize], CUBE[g as usize], CUBE[b as usize])
+        }
+        _ => {
+            let grey = 8 + (i - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
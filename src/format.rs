@@ -0,0 +1,43 @@
+use std::collections::BTreeMap;
+
+/// The values of the variables a format names, by name.
+pub type Variables = BTreeMap<&'static str, String>;
+
+/// Expands a format like tmux does for list-panes -F: `#{name}` is the value
+/// of a variable, empty if there is none, `#{?name,yes,no}` is `yes` if the
+/// variable is set to something other than empty or 0 and `no` otherwise,
+/// and `##` is a `#`.
+pub fn expand(format: &str, variables: &Variables) -> String {
+    let mut expanded = String::with_capacity(format.len());
+    let mut rest = format;
+    while let Some(start) = rest.find('#') {
+        expanded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("##") {
+            expanded.push('#');
+            rest = after;
+            continue;
+        }
+        let Some(end) = rest.find('}').filter(|_| rest.starts_with("#{")) else {
+            expanded.push('#');
+            rest = &rest[1..];
+            continue;
+        };
+        let name = &rest[2..end];
+        rest = &rest[end + 1..];
+        match name.strip_prefix('?') {
+            Some(condition) => {
+                let mut parts = condition.splitn(3, ',');
+                let name = parts.next().unwrap_or_default();
+                let (yes, no) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+                let set = variables
+                    .get(name)
+                    .is_some_and(|value| !value.is_empty() && value != "0");
+                expanded.push_str(if set { yes } else { no });
+            }
+            None => expanded.push_str(variables.get(name).map_or("", String::as_str)),
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
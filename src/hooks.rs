@@ -0,0 +1,188 @@
+use std::{
+    fmt::Write as _,
+    io::{self, Write},
+    process::{Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::command::split_line;
+
+/// Server events that run a handler command when they happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    SessionCreated,
+    ClientAttached,
+    ClientDetached,
+    PaneExited,
+}
+
+/// A value in the payload of an event.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Null,
+    Number(i64),
+    String(String),
+}
+
+/// Handlers for hooks, shared by everything in the server that fires them.
+///
+/// A handler is run with `sh -c` and gets the event twice: as `RSTMUX_HOOK`,
+/// `RSTMUX_SESSION` and an `RSTMUX_<FIELD>` variable for each field, and as a
+/// single line JSON object on stdin, e.g.
+/// `{"hook":"pane-exited","session":"work","time":1700000000,"exit_code":1,"signal":null}`
+#[derive(Clone)]
+pub struct Hooks {
+    session: String,
+    handlers: Arc<Mutex<Vec<(Hook, String)>>>,
+}
+
+impl Hook {
+    pub fn parse(name: &str) -> Result<Hook, String> {
+        match name {
+            "session-created" => Ok(Hook::SessionCreated),
+            "client-attached" => Ok(Hook::ClientAttached),
+            "client-detached" => Ok(Hook::ClientDetached),
+            "pane-exited" => Ok(Hook::PaneExited),
+            _ => Err(format!("unknown hook: {}", name)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Hook::SessionCreated => "session-created",
+            Hook::ClientAttached => "client-attached",
+            Hook::ClientDetached => "client-detached",
+            Hook::PaneExited => "pane-exited",
+        }
+    }
+}
+
+impl Value {
+    fn write_json(&self, out: &mut String) {
+        match self {
+            Value::Null => out.push_str("null"),
+            Value::Number(n) => {
+                let _ = write!(out, "{}", n);
+            }
+            Value::String(s) => write_json_string(s, out),
+        }
+    }
+
+    fn to_env(&self) -> String {
+        match self {
+            Value::Null => String::new(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => s.clone(),
+        }
+    }
+}
+
+impl Hooks {
+    pub fn new(session: &str) -> Self {
+        Self {
+            session: session.to_string(),
+            handlers: Arc::new(Mutex::new(vec![])),
+        }
+    }
+
+    /// Sets the handler for a hook, an empty command removes it.
+    pub fn set(&self, hook: Hook, command: &str) {
+        let mut handlers = self.handlers.lock().unwrap();
+        handlers.retain(|(h, _)| *h != hook);
+        if !command.is_empty() {
+            handlers.push((hook, command.to_string()));
+        }
+    }
+
+    /// Applies a `set-hook <hook> <command>` configuration line. Returns
+    /// false for any other command.
+    pub fn apply_line(&self, line: &str) -> Result<bool, String> {
+        let args = split_line(line)?;
+        match args.split_first() {
+            Some((name, [hook, command @ ..])) if name == "set-hook" => {
+                self.set(Hook::parse(hook)?, &command.join(" "));
+                Ok(true)
+            }
+            Some((name, _)) if name == "set-hook" => {
+                Err("usage: set-hook <hook> [command]".to_string())
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Runs the handler for the hook, if one is set, without waiting for it.
+    pub fn fire(&self, hook: Hook, fields: &[(&str, Value)]) {
+        let handler = self
+            .handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(h, _)| *h == hook)
+            .map(|(_, command)| command.clone());
+        let Some(handler) = handler else {
+            return;
+        };
+
+        if let Err(e) = self.run(hook, &handler, fields) {
+            eprintln!("{} hook failed: {}", hook.name(), e);
+        }
+    }
+
+    fn run(&self, hook: Hook, handler: &str, fields: &[(&str, Value)]) -> io::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let mut fields = fields.to_vec();
+        fields.insert(0, ("time", Value::Number(time)));
+
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c")
+            .arg(handler)
+            .env("RSTMUX_HOOK", hook.name())
+            .env("RSTMUX_SESSION", &self.session)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null());
+        for (name, value) in &fields {
+            cmd.env(format!("RSTMUX_{}", name.to_uppercase()), value.to_env());
+        }
+        let mut child = cmd.spawn()?;
+
+        // the payload is small enough for the pipe buffer, the handler may
+        // also exit without reading it
+        let mut payload = String::new();
+        let _ = write!(payload, "{{\"hook\":\"{}\",\"session\":", hook.name());
+        write_json_string(&self.session, &mut payload);
+        for (name, value) in &fields {
+            let _ = write!(payload, ",\"{}\":", name);
+            value.write_json(&mut payload);
+        }
+        payload.push_str("}\n");
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(payload.as_bytes());
+        }
+
+        // reap the handler whenever it finishes
+        thread::spawn(move || child.wait());
+        Ok(())
+    }
+}
+
+fn write_json_string(s: &str, out: &mut String) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
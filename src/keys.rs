@@ -0,0 +1,473 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use crate::command::{join_line, split_line};
+
+/// A key as the bytes a terminal sends for it.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Key(Vec<u8>);
+
+/// What the client does with a chunk of keyboard input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyAction {
+    /// Bytes to forward to the pane.
+    Input(Vec<u8>),
+    /// A command to run on the server.
+    Command(Vec<String>),
+}
+
+/// The prefix key and the key tables holding the commands bound to the keys
+/// that may follow it. Keys after the prefix are looked up in the prefix
+/// table, a binding may switch to another table for the key after that.
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    prefix: Key,
+    tables: BTreeMap<String, BTreeMap<Key, Vec<String>>>,
+    /// What some bindings do in a few words, by table and key, see `list`.
+    notes: BTreeMap<(String, Key), String>,
+    chord_timeout: Duration,
+    /// Whether C-s and C-q stop and resume output on the client's own
+    /// terminal rather than reaching the pane.
+    flow_control: bool,
+}
+
+/// Splits keyboard input into keys to forward and bound commands to run.
+/// The bytes typed by a list of keys, like send-keys takes them: an
+/// argument that names a key is sent as that key, anything else as text.
+/// With `literal` every argument is text.
+pub fn encode_keys(keys: &[String], literal: bool) -> Vec<u8> {
+    let mut bytes = vec![];
+    for key in keys {
+        match Key::parse(key) {
+            Ok(key) if !literal => bytes.extend(key.bytes()),
+            _ => bytes.extend(key.as_bytes()),
+        }
+    }
+    bytes
+}
+
+pub struct KeyDispatcher {
+    bindings: KeyBindings,
+    /// The table the next key is looked up in, and since when.
+    pending: Option<(String, Instant)>,
+}
+
+const NAMED_KEYS: &[(&str, &[u8])] = &[
+    ("Enter", b"\r"),
+    ("Tab", b"\t"),
+    ("Space", b" "),
+    ("Escape", b"\x1b"),
+    ("BSpace", b"\x7f"),
+    ("Up", b"\x1b[A"),
+    ("Down", b"\x1b[B"),
+    ("Right", b"\x1b[C"),
+    ("Left", b"\x1b[D"),
+    ("Home", b"\x1b[H"),
+    ("End", b"\x1b[F"),
+    ("PageUp", b"\x1b[5~"),
+    ("PageDown", b"\x1b[6~"),
+    ("Delete", b"\x1b[3~"),
+];
+
+impl Key {
+    /// Parses a key name like `d`, `C-b`, `M-x`, `Enter` or `Up`.
+    pub fn parse(name: &str) -> Result<Key, String> {
+        if let Some((_, bytes)) = NAMED_KEYS
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        {
+            return Ok(Key(bytes.to_vec()));
+        }
+
+        if let Some(rest) = name.strip_prefix("M-") {
+            let mut bytes = vec![0x1b];
+            bytes.extend(Key::parse(rest)?.0);
+            return Ok(Key(bytes));
+        }
+
+        if let Some(rest) = name.strip_prefix("C-") {
+            let mut chars = rest.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(c), None) if c.is_ascii_alphabetic() => {
+                    Ok(Key(vec![c.to_ascii_lowercase() as u8 - b'a' + 1]))
+                }
+                (Some('@'), None) => Ok(Key(vec![0])),
+                (Some(c @ '['..='_'), None) => Ok(Key(vec![c as u8 - b'@'])),
+                _ if rest.eq_ignore_ascii_case("Space") => Ok(Key(vec![0])),
+                _ => Err(format!("unknown key: {}", name)),
+            };
+        }
+
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Ok(Key(c.to_string().into_bytes())),
+            _ => Err(format!("unknown key: {}", name)),
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// The name of the key, in the form accepted by `parse`.
+    pub fn name(&self) -> String {
+        if let Some((name, _)) = NAMED_KEYS.iter().find(|(_, b)| *b == self.0.as_slice()) {
+            return name.to_string();
+        }
+
+        match self.0.as_slice() {
+            [0] => "C-Space".to_string(),
+            [b @ 1..=26] => format!("C-{}", (b'a' + b - 1) as char),
+            [b @ 27..=31] => format!("C-{}", (b'@' + b) as char),
+            [0x1b, rest @ ..] if !rest.is_empty() => {
+                format!("M-{}", Key(rest.to_vec()).name())
+            }
+            bytes => String::from_utf8_lossy(bytes).into_owned(),
+        }
+    }
+}
+
+impl KeyBindings {
+    /// The table looked up for the key that follows the prefix key.
+    pub const PREFIX_TABLE: &'static str = "prefix";
+    /// Sends the prefix key to the pane, handled by the client itself.
+    pub const SEND_PREFIX: &'static str = "send-prefix";
+    /// Looks the next key up in another table, handled by the client itself.
+    pub const SWITCH_TABLE: &'static str = "switch-table";
+    pub const DEFAULT_CHORD_TIMEOUT: Duration = Duration::from_millis(1000);
+
+    pub fn new(prefix: Key) -> Self {
+        Self {
+            prefix,
+            tables: BTreeMap::new(),
+            notes: BTreeMap::new(),
+            chord_timeout: Self::DEFAULT_CHORD_TIMEOUT,
+            flow_control: false,
+        }
+    }
+
+    pub fn prefix(&self) -> &Key {
+        &self.prefix
+    }
+
+    pub fn set_prefix(&mut self, prefix: Key) {
+        self.prefix = prefix;
+    }
+
+    /// How long a chained table waits for its key before input goes back to
+    /// being passed through.
+    pub fn chord_timeout(&self) -> Duration {
+        self.chord_timeout
+    }
+
+    pub fn set_chord_timeout(&mut self, timeout: Duration) {
+        self.chord_timeout = timeout;
+    }
+
+    pub fn flow_control(&self) -> bool {
+        self.flow_control
+    }
+
+    pub fn set_flow_control(&mut self, on: bool) {
+        self.flow_control = on;
+    }
+
+    pub fn bind(&mut self, table: &str, key: Key, command: Vec<String>) {
+        self.notes.remove(&(table.to_string(), key.clone()));
+        self.tables
+            .entry(table.to_string())
+            .or_default()
+            .insert(key, command);
+    }
+
+    /// Describes what a binding does, for `list` with notes.
+    pub fn set_note(&mut self, table: &str, key: &Key, note: &str) {
+        self.notes
+            .insert((table.to_string(), key.clone()), note.to_string());
+    }
+
+    pub fn unbind(&mut self, table: &str, key: &Key) {
+        self.notes.remove(&(table.to_string(), key.clone()));
+        if let Some(bindings) = self.tables.get_mut(table) {
+            bindings.remove(key);
+        }
+    }
+
+    /// Every binding as (table, key, command), ordered by table then key.
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &Key, &Vec<String>)> {
+        self.tables.iter().flat_map(|(table, bindings)| {
+            bindings
+                .iter()
+                .map(move |(key, command)| (table.as_str(), key, command))
+        })
+    }
+
+    /// The bindings of every table, or only of `table`, as bind-key commands
+    /// that recreate them. With `notes`, only bindings with a note are
+    /// listed as the keys to press and what they do.
+    pub fn list(&self, table: Option<&str>, notes: bool) -> Vec<String> {
+        let bindings = self
+            .bindings()
+            .filter(|(t, _, _)| table.is_none_or(|table| table == *t));
+        if !notes {
+            return bindings
+                .map(|(table, key, command)| {
+                    let key = join_line(&[key.name()]);
+                    format!("bind-key -T {} {} {}", table, key, join_line(command))
+                })
+                .collect();
+        }
+
+        let noted: Vec<(String, &String)> = bindings
+            .filter_map(|(table, key, _)| {
+                let note = self.notes.get(&(table.to_string(), key.clone()))?;
+                let keys = match table {
+                    Self::PREFIX_TABLE => format!("{} {}", self.prefix.name(), key.name()),
+                    table => format!("{}:{}", table, key.name()),
+                };
+                Some((keys, note))
+            })
+            .collect();
+        let width = noted.iter().map(|(keys, _)| keys.len()).max().unwrap_or(0);
+        noted
+            .into_iter()
+            .map(|(keys, note)| format!("{:width$}  {}", keys, note))
+            .collect()
+    }
+
+    /// Applies a key related configuration command: `bind-key [-T table]`,
+    /// `unbind-key [-T table]`, `set-option prefix`, `set-option
+    /// chord-timeout <ms>` or `set-option flow-control on|off`. Returns
+    /// false for any other command.
+    ///
+    /// Chords are built by binding a key to `switch-table <table>`, e.g.
+    /// `bind g switch-table git` and `bind -T git b display-message hi`.
+    pub fn apply(&mut self, args: &[String]) -> Result<bool, String> {
+        let Some((name, args)) = args.split_first() else {
+            return Ok(false);
+        };
+
+        match name.as_str() {
+            "bind-key" | "bind" => {
+                let (note, args) = match args {
+                    [flag, note, rest @ ..] if flag == "-N" => (Some(note), rest),
+                    _ => (None, args),
+                };
+                let (table, args) = table_arg(args)?;
+                match args {
+                    [key, command @ ..] if !command.is_empty() => {
+                        let key = Key::parse(key)?;
+                        self.bind(table, key.clone(), command.to_vec());
+                        if let Some(note) = note {
+                            self.set_note(table, &key, note);
+                        }
+                        Ok(true)
+                    }
+                    _ => Err(format!(
+                        "usage: {} [-N note] [-T table] <key> <command>",
+                        name
+                    )),
+                }
+            }
+            "unbind-key" | "unbind" => {
+                let (table, args) = table_arg(args)?;
+                match args {
+                    [key] => {
+                        self.unbind(table, &Key::parse(key)?);
+                        Ok(true)
+                    }
+                    _ => Err(format!("usage: {} [-T table] <key>", name)),
+                }
+            }
+            "set-option" | "set" => {
+                let args = match args {
+                    [flag, rest @ ..] if flag == "-g" => rest,
+                    _ => args,
+                };
+                match args {
+                    [option, key] if option == "prefix" => {
+                        self.set_prefix(Key::parse(key)?);
+                        Ok(true)
+                    }
+                    [option, ms] if option == "chord-timeout" => {
+                        let ms = ms
+                            .parse()
+                            .map_err(|_| format!("invalid chord-timeout: {}", ms))?;
+                        self.set_chord_timeout(Duration::from_millis(ms));
+                        Ok(true)
+                    }
+                    [option, value] if option == "flow-control" => {
+                        let on = match value.as_str() {
+                            "on" => true,
+                            "off" => false,
+                            _ => return Err(format!("invalid flow-control: {}", value)),
+                        };
+                        self.set_flow_control(on);
+                        Ok(true)
+                    }
+                    _ => Ok(false),
+                }
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Applies a configuration line, see `apply`.
+    pub fn apply_line(&mut self, line: &str) -> Result<bool, String> {
+        self.apply(&split_line(line)?)
+    }
+
+    /// Finds the longest key bound in `table` at the start of `input`.
+    /// Returns the number of bytes that make up the key and its command, if
+    /// bound.
+    fn lookup(&self, table: &str, input: &[u8]) -> (usize, Option<&Vec<String>>) {
+        let bound = self.tables.get(table).and_then(|bindings| {
+            bindings
+                .iter()
+                .filter(|(key, _)| input.starts_with(&key.0))
+                .max_by_key(|(key, _)| key.0.len())
+        });
+        match bound {
+            Some((key, command)) => (key.0.len(), Some(command)),
+            None => (key_len(input), None),
+        }
+    }
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        let mut bindings = Self::new(Key(vec![0x02]));
+        let defaults: &[(&[u8], &str, &str)] = &[
+            (b"\x02", Self::SEND_PREFIX, "Send the prefix key"),
+            (b"d", "detach-client", "Detach the current client"),
+            (b"r", "refresh-client", "Redraw the current client"),
+            (b"m", "select-pane -m", "Toggle the marked pane"),
+            (b"h", "show-timeline", "Show the commands run in the pane"),
+            (b"H", "hold-pane", "Hold or release the pane's output"),
+            (b"[", "copy-mode", "Enter copy mode"),
+            (b"]", "paste-buffer", "Paste the most recently copied text"),
+            (
+                b"f",
+                "command-prompt filter-pane %%",
+                "Show the lines of the history a command keeps",
+            ),
+            (
+                b"T",
+                "command-prompt -p time jump-to-time %%",
+                "Show the history from a time in copy mode",
+            ),
+            (
+                b",",
+                "command-prompt -I #W rename-window %%",
+                "Rename the window",
+            ),
+        ];
+        for (key, command, note) in defaults {
+            let key = Key(key.to_vec());
+            bindings.bind(
+                Self::PREFIX_TABLE,
+                key.clone(),
+                command.split(' ').map(str::to_string).collect(),
+            );
+            bindings.set_note(Self::PREFIX_TABLE, &key, note);
+        }
+        bindings
+    }
+}
+
+impl KeyDispatcher {
+    pub fn new(bindings: KeyBindings) -> Self {
+        Self {
+            bindings,
+            pending: None,
+        }
+    }
+
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.bindings
+    }
+
+    /// Splits input into bytes for the pane and commands. Keys are passed
+    /// through until the prefix key, the key after it is looked up in the
+    /// prefix table, and so on for chained tables until a command is found.
+    pub fn feed(&mut self, input: &[u8]) -> Vec<KeyAction> {
+        let mut actions = vec![];
+        let mut passthrough = vec![];
+        let mut i = 0;
+
+        // an abandoned chord doesn't swallow the next key typed much later
+        if let Some((table, since)) = &self.pending {
+            if table != KeyBindings::PREFIX_TABLE && since.elapsed() > self.bindings.chord_timeout {
+                self.pending = None;
+            }
+        }
+
+        while i < input.len() {
+            let rest = &input[i..];
+            if let Some((table, _)) = self.pending.take() {
+                let (len, command) = self.bindings.lookup(&table, rest);
+                match command.map(Vec::as_slice) {
+                    Some([name]) if name == KeyBindings::SEND_PREFIX => {
+                        passthrough.extend_from_slice(self.bindings.prefix.bytes());
+                    }
+                    Some([name, table]) if name == KeyBindings::SWITCH_TABLE => {
+                        self.pending = Some((table.clone(), Instant::now()));
+                    }
+                    Some(command) => {
+                        if !passthrough.is_empty() {
+                            actions.push(KeyAction::Input(std::mem::take(&mut passthrough)));
+                        }
+                        actions.push(KeyAction::Command(command.to_vec()));
+                    }
+                    None => {} // unbound keys are dropped
+                }
+                i += len;
+            } else if rest.starts_with(self.bindings.prefix.bytes()) {
+                self.pending = Some((KeyBindings::PREFIX_TABLE.to_string(), Instant::now()));
+                i += self.bindings.prefix.bytes().len();
+            } else {
+                passthrough.push(input[i]);
+                i += 1;
+            }
+        }
+
+        if !passthrough.is_empty() {
+            actions.push(KeyAction::Input(passthrough));
+        }
+        actions
+    }
+}
+
+/// Splits an optional leading `-T table` off a binding command.
+fn table_arg(args: &[String]) -> Result<(&str, &[String]), String> {
+    match args {
+        [flag, table, rest @ ..] if flag == "-T" => Ok((table.as_str(), rest)),
+        [flag] if flag == "-T" => Err("-T: missing table name".to_string()),
+        _ => Ok((KeyBindings::PREFIX_TABLE, args)),
+    }
+}
+
+/// The length of the first key in `input`: an escape sequence, or a single
+/// possibly multibyte character.
+pub(crate) fn key_len(input: &[u8]) -> usize {
+    match input {
+        [] => 0,
+        [0x1b, b'[' | b'O', rest @ ..] => {
+            // CSI and SS3 sequences end with a byte in 0x40..=0x7e
+            let end = rest.iter().position(|b| (0x40..=0x7e).contains(b));
+            end.map_or(input.len(), |end| end + 3)
+        }
+        [0x1b, rest @ ..] if !rest.is_empty() => 1 + key_len(rest),
+        [first, ..] => {
+            let len = match first {
+                0xC0..=0xDF => 2,
+                0xE0..=0xEF => 3,
+                0xF0..=0xF7 => 4,
+                _ => 1,
+            };
+            len.min(input.len())
+        }
+    }
+}
@@ -1,3 +1,36 @@
+pub mod acl;
+#[cfg(feature = "tokio")]
+pub mod asyncio;
+pub mod bandwidth;
+pub mod command;
+pub mod compat;
+pub mod compress;
+pub mod config;
+pub mod copy;
+pub mod daemon;
 pub mod fd;
+pub mod features;
+pub mod format;
+pub mod hooks;
+pub mod keys;
+pub mod limits;
+pub mod mark;
+pub mod mouse;
+pub mod options;
+pub mod pipe;
+pub mod poll;
+pub mod predict;
+pub mod prompt;
+pub mod protocol;
 pub mod pty;
+pub mod retention;
+pub mod segments;
 pub mod socket;
+pub mod spawn;
+pub mod status;
+pub mod sync;
+pub mod terminal;
+pub mod text;
+pub mod tls;
+pub mod watchdog;
+pub mod workspace;
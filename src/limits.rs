@@ -0,0 +1,176 @@
+use std::{
+    fs::{self, File},
+    io,
+    os::fd::{AsRawFd, RawFd},
+    path::{Path, PathBuf},
+};
+
+/// The period cpu.max quotas are given in, in microseconds.
+const CPU_PERIOD: u64 = 100_000;
+
+/// What a pane's processes may use, so a runaway build in one pane can't
+/// take down the machine or the server.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// In bytes.
+    pub memory: Option<u64>,
+    /// In percent of a CPU, 200 for two.
+    pub cpu: Option<u64>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.cpu.is_none()
+    }
+}
+
+/// Parses a size in bytes like `512M` or `2G`, None when it is empty.
+pub fn parse_size(text: &str) -> Result<Option<u64>, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let invalid = || format!("invalid size: {}", text);
+    let (digits, unit) = match text.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => text.split_at(i),
+        None => (text, ""),
+    };
+    let shift = match unit.to_ascii_uppercase().as_str() {
+        "" | "B" => 0,
+        "K" | "KB" => 10,
+        "M" | "MB" => 20,
+        "G" | "GB" => 30,
+        "T" | "TB" => 40,
+        _ => return Err(invalid()),
+    };
+    let size: u64 = digits.parse().map_err(|_| invalid())?;
+    size.checked_mul(1 << shift)
+        .filter(|&size| size > 0)
+        .map(Some)
+        .ok_or_else(invalid)
+}
+
+/// A cgroup of the v2 hierarchy for a pane's processes, made under the
+/// server's own and removed when dropped. The child joins it before exec
+/// by writing to `procs_fd`.
+pub struct Cgroup {
+    path: PathBuf,
+    procs: File,
+}
+
+impl Cgroup {
+    /// Makes the cgroup `name` with the limits. The memory and cpu
+    /// controllers must be delegated to the server's cgroup, like with
+    /// systemd's Delegate=yes, or be enabled for it already.
+    pub fn create(name: &str, limits: &Limits) -> io::Result<Cgroup> {
+        let base = own_cgroup()?;
+        let mut controllers = vec![];
+        if limits.memory.is_some() {
+            controllers.push("memory");
+        }
+        if limits.cpu.is_some() {
+            controllers.push("cpu");
+        }
+        enable_controllers(&base, &controllers)?;
+
+        let path = base.join(name);
+        match fs::create_dir(&path) {
+            Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+            _ => {}
+        }
+        let procs = File::options().write(true).open(path.join("cgroup.procs"));
+        let cgroup = Cgroup {
+            path,
+            procs: procs?,
+        };
+        if let Some(memory) = limits.memory {
+            fs::write(cgroup.path.join("memory.max"), memory.to_string())?;
+        }
+        if let Some(cpu) = limits.cpu {
+            let quota = format!("{} {}", cpu * CPU_PERIOD / 100, CPU_PERIOD);
+            fs::write(cgroup.path.join("cpu.max"), quota)?;
+        }
+        Ok(cgroup)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The cgroup's cgroup.procs, open for writing.
+    pub fn procs_fd(&self) -> RawFd {
+        self.procs.as_raw_fd()
+    }
+}
+
+impl Drop for Cgroup {
+    fn drop(&mut self) {
+        // fails while something the pane started is still running
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// Where the server's cgroup is, from where cgroup2 is mounted and the
+/// server's place in it.
+fn own_cgroup() -> io::Result<PathBuf> {
+    let unsupported = |what: &str| io::Error::new(io::ErrorKind::Unsupported, what.to_string());
+    // like `36 25 0:30 / /sys/fs/cgroup rw,nosuid - cgroup2 cgroup2 rw`
+    let mountinfo = fs::read_to_string("/proc/self/mountinfo")?;
+    let mount = mountinfo
+        .lines()
+        .find_map(|line| {
+            let (mount, fs) = line.split_once(" - ")?;
+            let point = mount.split(' ').nth(4)?;
+            fs.starts_with("cgroup2 ").then(|| point.to_string())
+        })
+        .ok_or_else(|| unsupported("cgroup2 is not mounted"))?;
+    // like `0::/user.slice/user-1000.slice/session-2.scope`
+    let cgroup = fs::read_to_string("/proc/self/cgroup")?;
+    let own = cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))
+        .ok_or_else(|| unsupported("the server is not in a cgroup2 hierarchy"))?;
+    Ok(Path::new(&mount).join(own.trim_start_matches('/')))
+}
+
+/// Lets the cgroups under `base` use `controllers`. Only a cgroup without
+/// processes of its own can, so a server alone in its cgroup moves to a
+/// leaf first.
+fn enable_controllers(base: &Path, controllers: &[&str]) -> io::Result<()> {
+    let enabled = fs::read_to_string(base.join("cgroup.subtree_control"))?;
+    let missing: Vec<&str> = controllers
+        .iter()
+        .copied()
+        .filter(|c| !enabled.split_whitespace().any(|e| e == *c))
+        .collect();
+    if missing.is_empty() {
+        return Ok(());
+    }
+    let available = fs::read_to_string(base.join("cgroup.controllers"))?;
+    if let Some(c) = missing
+        .iter()
+        .find(|c| !available.split_whitespace().any(|a| a == **c))
+    {
+        let message = format!(
+            "the {} controller is not delegated to {}",
+            c,
+            base.display()
+        );
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+    }
+
+    let pid = std::process::id().to_string();
+    let procs = fs::read_to_string(base.join("cgroup.procs"))?;
+    if procs.lines().any(|p| p != pid) {
+        let message = format!("the server shares {} with other processes", base.display());
+        return Err(io::Error::new(io::ErrorKind::Unsupported, message));
+    }
+    // the root cgroup is the exception that may have processes
+    if !procs.is_empty() && base.join("cgroup.type").exists() {
+        let leaf = base.join(format!("rstmux-server-{}", pid));
+        fs::create_dir_all(&leaf)?;
+        fs::write(leaf.join("cgroup.procs"), &pid)?;
+    }
+    let change: Vec<String> = missing.iter().map(|c| format!("+{}", c)).collect();
+    fs::write(base.join("cgroup.subtree_control"), change.join(" "))
+}
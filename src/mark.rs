@@ -0,0 +1,49 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::socket::{create_socket_dir, socket_dir, socket_path};
+
+/// The marked pane, one for all sessions like in tmux. Every session has a
+/// server of its own, so the mark is kept in a file next to the sockets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mark {
+    pub session: String,
+    pub pane: usize,
+}
+
+impl Mark {
+    fn path() -> String {
+        format!("{}/marked", socket_dir())
+    }
+
+    /// The marked pane, None if nothing is marked or its session is gone.
+    pub fn load() -> Option<Mark> {
+        let target = fs::read_to_string(Self::path()).ok()?;
+        let (session, pane) = target.trim_end().rsplit_once(":%")?;
+        let mark = Mark {
+            session: session.to_string(),
+            pane: pane.parse().ok()?,
+        };
+        Path::new(&socket_path(&mark.session))
+            .exists()
+            .then_some(mark)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        create_socket_dir()?;
+        fs::write(Self::path(), self.target() + "\n")
+    }
+
+    pub fn clear() -> io::Result<()> {
+        match fs::remove_file(Self::path()) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// The mark as a `session:%pane` target.
+    pub fn target(&self) -> String {
+        format!("{}:%{}", self.session, self.pane)
+    }
+}
@@ -0,0 +1,131 @@
+use crate::terminal::{Modes, MouseMode};
+
+/// A mouse event as the client's terminal reported it, in SGR encoding.
+/// Rows and columns start at 0 in the top left of the client's terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MouseEvent {
+    /// The xterm button code: the button in the low bits, 4, 8 and 16 for
+    /// shift, meta and control, 32 for motion and 64 for the wheel.
+    pub code: u16,
+    pub row: u16,
+    pub col: u16,
+    pub release: bool,
+}
+
+/// Input from the client's terminal, mouse events split from the rest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Keys(Vec<u8>),
+    Mouse(MouseEvent),
+}
+
+impl MouseEvent {
+    /// Parses an SGR mouse report, `ESC [ < code ; col ; row M` for a press
+    /// or `m` for a release.
+    pub fn parse_sgr(report: &[u8]) -> Option<MouseEvent> {
+        let body = report.strip_prefix(b"\x1b[<")?;
+        let (&last, body) = body.split_last()?;
+        let release = match last {
+            b'M' => false,
+            b'm' => true,
+            _ => return None,
+        };
+        let body = std::str::from_utf8(body).ok()?;
+        let mut fields = body.split(';').map(|f| f.parse::<u16>().ok());
+        let (code, col, row) = (fields.next()??, fields.next()??, fields.next()??);
+        if fields.next().is_some() || col == 0 || row == 0 {
+            return None;
+        }
+        Some(MouseEvent {
+            code,
+            row: row - 1,
+            col: col - 1,
+            release,
+        })
+    }
+
+    pub fn is_wheel(&self) -> bool {
+        self.code & 64 != 0
+    }
+
+    pub fn is_wheel_up(&self) -> bool {
+        self.is_wheel() && self.code & 3 == 0
+    }
+
+    pub fn is_motion(&self) -> bool {
+        self.code & 32 != 0
+    }
+
+    /// The button without modifiers, 0 to 2 for left, middle and right.
+    pub fn button(&self) -> u16 {
+        self.code & 3
+    }
+
+    /// The event as an application with `modes` asked to be sent it, none
+    /// if it didn't ask for this kind of event. `row` and `col` are
+    /// relative to the pane.
+    pub fn encode(&self, modes: &Modes, row: u16, col: u16) -> Option<Vec<u8>> {
+        let wanted = match modes.mouse {
+            MouseMode::Off => false,
+            MouseMode::Press => !self.is_motion(),
+            // motion without a button held is reported as button 3
+            MouseMode::ButtonMotion => !self.is_motion() || self.button() != 3,
+            MouseMode::AnyMotion => true,
+        };
+        if !wanted {
+            return None;
+        }
+        if modes.mouse_sgr {
+            let end = if self.release { 'm' } else { 'M' };
+            let report = format!("\x1b[<{};{};{}{}", self.code, col + 1, row + 1, end);
+            return Some(report.into_bytes());
+        }
+
+        // the legacy encoding can't tell which button was released and
+        // can't go past column 223
+        let code = if self.release && !self.is_wheel() {
+            3 | (self.code & !3)
+        } else {
+            self.code
+        };
+        let byte = |n: u16| u8::try_from(n + 32).ok();
+        Some(vec![
+            0x1b,
+            b'[',
+            b'M',
+            byte(code)?,
+            byte(col + 1)?,
+            byte(row + 1)?,
+        ])
+    }
+}
+
+/// Splits SGR mouse reports out of what the client's terminal sent,
+/// keeping the order of everything.
+pub fn split_input(data: &[u8]) -> Vec<Input> {
+    let mut inputs = vec![];
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < data.len() {
+        if data[i..].starts_with(b"\x1b[<") {
+            let end = data[i + 3..]
+                .iter()
+                .position(|b| matches!(b, b'M' | b'm'))
+                .map(|end| i + 3 + end + 1);
+            if let Some(event) = end.and_then(|end| MouseEvent::parse_sgr(&data[i..end])) {
+                if !keys.is_empty() {
+                    inputs.push(Input::Keys(std::mem::take(&mut keys)));
+                }
+                inputs.push(Input::Mouse(event));
+                i = end.unwrap_or(data.len());
+                continue;
+            }
+        }
+        keys.push(data[i]);
+        i += 1;
+    }
+    if !keys.is_empty() {
+        inputs.push(Input::Keys(keys));
+    }
+    inputs
+}
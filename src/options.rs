@@ -0,0 +1,395 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Where an option is set. Lookups fall back from a pane to its window to
+/// the session to the global value, and finally to the option's default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Global,
+    Session,
+    Window(usize),
+    Pane { window: usize, pane: usize },
+}
+
+impl Scope {
+    /// Where set-option and show-options look, a window or pane means the
+    /// current one, the first since a session has a single pane for now.
+    pub fn from_flags(global: bool, window: bool, pane: bool) -> Scope {
+        match (global, window, pane) {
+            (true, ..) => Scope::Global,
+            (false, _, true) => Scope::Pane { window: 0, pane: 0 },
+            (false, true, false) => Scope::Window(0),
+            (false, false, false) => Scope::Session,
+        }
+    }
+
+    /// This scope and the ones it inherits from, most specific first.
+    fn chain(self) -> Vec<Scope> {
+        match self {
+            Scope::Global => vec![Scope::Global],
+            Scope::Session => vec![Scope::Session, Scope::Global],
+            Scope::Window(window) => vec![Scope::Window(window), Scope::Session, Scope::Global],
+            Scope::Pane { window, .. } => {
+                let mut chain = Scope::Window(window).chain();
+                chain.insert(0, self);
+                chain
+            }
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Scope::Global => "global",
+            Scope::Session => "session",
+            Scope::Window(_) => "window",
+            Scope::Pane { .. } => "pane",
+        }
+    }
+}
+
+/// What kind of value an option holds, values are checked when they are set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OptionValue {
+    Flag(bool),
+    Number(i64),
+    Text(String),
+}
+
+impl fmt::Display for OptionValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OptionValue::Flag(true) => write!(f, "on"),
+            OptionValue::Flag(false) => write!(f, "off"),
+            OptionValue::Number(n) => write!(f, "{}", n),
+            OptionValue::Text(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+impl OptionValue {
+    /// Parses `value` as the same kind of value as `self`.
+    fn parse_like(&self, value: &str) -> Result<OptionValue, String> {
+        match self {
+            OptionValue::Flag(_) => match value {
+                "on" | "yes" | "true" | "1" => Ok(OptionValue::Flag(true)),
+                "off" | "no" | "false" | "0" => Ok(OptionValue::Flag(false)),
+                _ => Err(format!("not on or off: {}", value)),
+            },
+            OptionValue::Number(_) => value
+                .parse()
+                .ok()
+                .filter(|n| *n >= 0)
+                .map(OptionValue::Number)
+                .ok_or_else(|| format!("not a number: {}", value)),
+            OptionValue::Text(_) => Ok(OptionValue::Text(value.to_string())),
+        }
+    }
+}
+
+struct Definition {
+    name: &'static str,
+    /// Window options can be set down to a single pane, session options
+    /// only for the whole session.
+    window: bool,
+    default: fn() -> OptionValue,
+}
+
+/// Every option the server knows, in the order show-options lists them.
+const DEFINITIONS: &[Definition] = &[
+    // other users who may attach, as names or uids separated by blanks or
+    // commas with :ro for those who may only watch, see `acl::Acl`
+    Definition {
+        name: "allow-users",
+        window: false,
+        default: || OptionValue::Text(String::new()),
+    },
+    // where the history and the layout are saved when the session ends,
+    // empty to not save them
+    Definition {
+        name: "archive-dir",
+        window: false,
+        default: || OptionValue::Text(String::new()),
+    },
+    // shown to clients when they attach until they press a key, with \n
+    // between lines
+    Definition {
+        name: "attach-message",
+        window: false,
+        default: || OptionValue::Text(String::new()),
+    },
+    // the most sent to each client, in kilobytes per second, 0 for no limit
+    Definition {
+        name: "client-rate-limit",
+        window: false,
+        default: || OptionValue::Number(0),
+    },
+    // compress what is sent to clients that can take it: on, off, or remote
+    // for only those attached over TCP, local sockets are fast enough
+    Definition {
+        name: "compression",
+        window: false,
+        default: || OptionValue::Text("remote".to_string()),
+    },
+    // clients in copy mode look at the same lines and share a selection,
+    // off for each to scroll on its own
+    Definition {
+        name: "copy-mode-sync",
+        window: false,
+        default: || OptionValue::Flag(false),
+    },
+    // a shell command the text copied in copy mode is written to, like
+    // xclip -selection clipboard, empty to only keep it for paste-buffer
+    Definition {
+        name: "copy-pipe",
+        window: false,
+        default: || OptionValue::Text(String::new()),
+    },
+    // where copy-pipe runs: server, or client to run the copy-pipe command
+    // in the configuration file of the machine of the client that copied,
+    // whose clipboard a client attached from elsewhere wants it on
+    Definition {
+        name: "copy-pipe-side",
+        window: false,
+        default: || OptionValue::Text("server".to_string()),
+    },
+    // empty to use $SHELL or the user's login shell, see `pty::resolve_shell`
+    Definition {
+        name: "default-shell",
+        window: false,
+        default: || OptionValue::Text(String::new()),
+    },
+    // the pane's size as colsxrows until a client attaches, see `pty::PtySize::parse`
+    Definition {
+        name: "default-size",
+        window: false,
+        default: || {
+            let (rows, cols) = (crate::terminal::DEFAULT_ROWS, crate::terminal::DEFAULT_COLS);
+            OptionValue::Text(format!("{}x{}", cols, rows))
+        },
+    },
+    // for demos and kiosks, of the attached clients only the first can kill,
+    // detach or reconfigure, see `Command::is_locked_when_frozen`
+    Definition {
+        name: "frozen",
+        window: false,
+        default: || OptionValue::Flag(false),
+    },
+    Definition {
+        name: "history-limit",
+        window: false,
+        default: || OptionValue::Number(crate::terminal::DEFAULT_HISTORY_LIMIT as i64),
+    },
+    // lines record when output went to them, shown in copy mode and
+    // searched by jump-to-time, see `Screen::set_timestamps`
+    Definition {
+        name: "history-timestamps",
+        window: false,
+        default: || OptionValue::Flag(false),
+    },
+    // in hours, see `retention::Retention`
+    Definition {
+        name: "log-max-age",
+        window: false,
+        default: || OptionValue::Number(7 * 24),
+    },
+    // in kilobytes
+    Definition {
+        name: "log-max-size",
+        window: false,
+        default: || OptionValue::Number(1024),
+    },
+    Definition {
+        name: "log-secure-input",
+        window: false,
+        default: || OptionValue::Flag(false),
+    },
+    // the wheel scrolls through the history, see `mouse::MouseEvent`
+    Definition {
+        name: "mouse",
+        window: false,
+        default: || OptionValue::Flag(false),
+    },
+    // the most a pane's processes may use together in percent of a CPU, 0
+    // for no limit, see `limits::Limits`
+    Definition {
+        name: "pane-cpu-max",
+        window: false,
+        default: || OptionValue::Number(0),
+    },
+    // like 512M or 2G, empty for no limit
+    Definition {
+        name: "pane-memory-max",
+        window: false,
+        default: || OptionValue::Text(String::new()),
+    },
+    Definition {
+        name: "paste-confirm",
+        window: false,
+        default: || OptionValue::Flag(false),
+    },
+    // in seconds, how long a client that crashed can come back to pick up
+    // where it was, 0 to always attach afresh, see `Message::Resume`
+    Definition {
+        name: "resume-timeout",
+        window: false,
+        default: || OptionValue::Number(60),
+    },
+    Definition {
+        name: "status",
+        window: false,
+        default: || OptionValue::Flag(true),
+    },
+    // segment names separated by blanks, #(command) for the first line a
+    // command prints and words with #{variable} formats, like
+    // #{pane_output_rate}B/s, see `segments::Segments`
+    Definition {
+        name: "status-right",
+        window: false,
+        default: || OptionValue::Text("clock".to_string()),
+    },
+    // the most send-keys and paste-buffer type into the pane, in kilobytes
+    // per second with a second's worth at once, 0 for no limit. Keys typed
+    // on a client go in between and are never held back
+    Definition {
+        name: "input-rate-limit",
+        window: true,
+        default: || OptionValue::Number(256),
+    },
+    Definition {
+        name: "monitor-bell",
+        window: true,
+        default: || OptionValue::Flag(true),
+    },
+];
+
+fn definition(name: &str) -> Result<&'static Definition, String> {
+    DEFINITIONS
+        .iter()
+        .find(|d| d.name == name)
+        .ok_or_else(|| format!("invalid option: {}", name))
+}
+
+/// The options of a session, changed at runtime with `set-option` and
+/// listed with `show-options`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    global: BTreeMap<&'static str, OptionValue>,
+    session: BTreeMap<&'static str, OptionValue>,
+    windows: BTreeMap<usize, BTreeMap<&'static str, OptionValue>>,
+    /// By pane id, which is unique across windows.
+    panes: BTreeMap<usize, BTreeMap<&'static str, OptionValue>>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets an option, the value must be of the option's kind and a session
+    /// option can't be set on a window or pane.
+    pub fn set(&mut self, scope: Scope, name: &str, value: &str) -> Result<(), String> {
+        let definition = Self::check(scope, name)?;
+        let value = (definition.default)().parse_like(value)?;
+        self.values_mut(scope).insert(definition.name, value);
+        Ok(())
+    }
+
+    /// Removes an option from a scope, so it is inherited again.
+    pub fn unset(&mut self, scope: Scope, name: &str) -> Result<(), String> {
+        let definition = Self::check(scope, name)?;
+        self.values_mut(scope).remove(definition.name);
+        Ok(())
+    }
+
+    /// The value in effect at a scope.
+    pub fn get(&self, name: &str, at: Scope) -> OptionValue {
+        match definition(name) {
+            Ok(definition) => self.resolve(definition, at).0,
+            Err(_) => OptionValue::Text(String::new()),
+        }
+    }
+
+    pub fn flag(&self, name: &str, at: Scope) -> bool {
+        self.get(name, at) == OptionValue::Flag(true)
+    }
+
+    pub fn number(&self, name: &str, at: Scope) -> i64 {
+        match self.get(name, at) {
+            OptionValue::Number(n) => n,
+            _ => 0,
+        }
+    }
+
+    pub fn text(&self, name: &str, at: Scope) -> String {
+        match self.get(name, at) {
+            OptionValue::Text(s) => s,
+            _ => String::new(),
+        }
+    }
+
+    /// `name value` lines of session options, or window options with
+    /// `window_options`. Only what is set in the scope is listed, except
+    /// globally where defaults are listed too. With `inherited`, every
+    /// option is listed with its value in effect and where it comes from.
+    pub fn show(&self, scope: Scope, window_options: bool, inherited: bool) -> Vec<String> {
+        DEFINITIONS
+            .iter()
+            .filter(|d| d.window == window_options)
+            .filter_map(|d| {
+                if inherited {
+                    let (value, origin) = self.resolve(d, scope);
+                    let origin = origin.map_or("default", Scope::name);
+                    return Some(format!("{} {} ({})", d.name, value, origin));
+                }
+                let value = match scope {
+                    Scope::Global => Some(self.resolve(d, scope).0),
+                    _ => self
+                        .values(scope)
+                        .and_then(|values| values.get(d.name))
+                        .cloned(),
+                };
+                value.map(|value| format!("{} {}", d.name, value))
+            })
+            .collect()
+    }
+
+    /// The value in effect at a scope and the scope it is set in, None
+    /// for the default.
+    fn resolve(&self, definition: &Definition, at: Scope) -> (OptionValue, Option<Scope>) {
+        at.chain()
+            .into_iter()
+            .find_map(|scope| {
+                let value = self.values(scope)?.get(definition.name)?;
+                Some((value.clone(), Some(scope)))
+            })
+            .unwrap_or_else(|| ((definition.default)(), None))
+    }
+
+    fn check(scope: Scope, name: &str) -> Result<&'static Definition, String> {
+        let definition = definition(name)?;
+        match (scope, definition.window) {
+            (Scope::Window(_) | Scope::Pane { .. }, false) => {
+                Err(format!("not a window option: {}", name))
+            }
+            _ => Ok(definition),
+        }
+    }
+
+    fn values(&self, scope: Scope) -> Option<&BTreeMap<&'static str, OptionValue>> {
+        match scope {
+            Scope::Global => Some(&self.global),
+            Scope::Session => Some(&self.session),
+            Scope::Window(index) => self.windows.get(&index),
+            Scope::Pane { pane, .. } => self.panes.get(&pane),
+        }
+    }
+
+    fn values_mut(&mut self, scope: Scope) -> &mut BTreeMap<&'static str, OptionValue> {
+        match scope {
+            Scope::Global => &mut self.global,
+            Scope::Session => &mut self.session,
+            Scope::Window(index) => self.windows.entry(index).or_default(),
+            Scope::Pane { pane, .. } => self.panes.entry(pane).or_default(),
+        }
+    }
+}
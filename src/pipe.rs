@@ -0,0 +1,127 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::Path,
+    process::{Child, Command, Stdio},
+    sync::mpsc::{sync_channel, SyncSender, TrySendError},
+    thread,
+};
+
+/// How many reads of output a pipe can fall behind by before it is closed,
+/// so a command that stopped reading can't grow the server without bound.
+const PIPE_BACKLOG: usize = 1024;
+
+/// Where pipe-pane sends a copy of a pane's output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PipeTarget {
+    /// Appended to a file.
+    File(String),
+    /// Written to the stdin of a shell command.
+    Command(String),
+}
+
+/// A copy of a pane's output on its way to a file or a command. The writes
+/// happen on a thread of their own, so a slow command never holds up the pane.
+pub struct PanePipe {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl PanePipe {
+    pub fn open(target: &PipeTarget, session: &str) -> io::Result<Self> {
+        match target {
+            PipeTarget::File(path) => {
+                let file = OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(Path::new(path))?;
+                Ok(Self::start(Box::new(file), None))
+            }
+            PipeTarget::Command(command) => {
+                let mut child = Command::new("sh")
+                    .arg("-c")
+                    .arg(command)
+                    .env("RSTMUX_SESSION", session)
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .spawn()?;
+                let stdin = child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?;
+                Ok(Self::start(Box::new(stdin), Some(child)))
+            }
+        }
+    }
+
+    fn start(mut writer: Box<dyn Write + Send>, child: Option<Child>) -> Self {
+        let (sender, receiver) = sync_channel::<Vec<u8>>(PIPE_BACKLOG);
+        thread::spawn(move || {
+            for data in receiver {
+                if writer.write_all(&data).is_err() {
+                    break;
+                }
+            }
+
+            // closing stdin lets the command finish, then reap it
+            drop(writer);
+            if let Some(mut child) = child {
+                let _ = child.wait();
+            }
+        });
+        Self { sender }
+    }
+
+    /// Queues a copy of some output, false once the file or command is gone
+    /// or fell too far behind, either way the pipe should be closed.
+    pub fn write(&self, data: &[u8]) -> bool {
+        match self.sender.try_send(data.to_vec()) {
+            Ok(()) => true,
+            Err(TrySendError::Full(_)) => {
+                eprintln!("pipe fell {} writes behind, closing it", PIPE_BACKLOG);
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// Runs `lines` through a shell command, like grep, for filter-pane. The
+/// indexes of the lines it printed are matched up in order, so a line the
+/// history has twice is found where the command saw it. A command that
+/// printed nothing and failed is an error with what it printed to stderr.
+pub fn filter(command: &str, lines: &[String], session: &str) -> io::Result<Vec<usize>> {
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("RSTMUX_SESSION", session)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // written on a thread of its own, the command may print before it has
+    // read everything
+    let mut stdin = child.stdin.take().ok_or(io::ErrorKind::BrokenPipe)?;
+    let input: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    let writer = thread::spawn(move || stdin.write_all(input.as_bytes()));
+    let output = child.wait_with_output()?;
+    let _ = writer.join();
+
+    let mut kept = vec![];
+    let mut next = 0;
+    for printed in String::from_utf8_lossy(&output.stdout).lines() {
+        let printed = printed.trim_end();
+        let found = match lines[next..].iter().position(|line| line == printed) {
+            Some(i) => Some(next + i),
+            None => lines.iter().position(|line| line == printed),
+        };
+        if let Some(i) = found {
+            kept.push(i);
+            next = i + 1;
+        }
+    }
+    let error = String::from_utf8_lossy(&output.stderr);
+    match error.lines().next() {
+        Some(error) if kept.is_empty() && !output.status.success() => {
+            Err(io::Error::other(error.to_string()))
+        }
+        _ => Ok(kept),
+    }
+}
@@ -0,0 +1,69 @@
+use std::io;
+use std::os::fd::{AsRawFd, RawFd};
+use std::time::Duration;
+
+use crate::fd::FileDescriptor;
+
+/// Wakes a thread blocked in `poll` from other threads, by writing to a
+/// pipe the thread polls along with everything else.
+pub struct Waker {
+    reader: FileDescriptor,
+    writer: FileDescriptor,
+}
+
+impl Waker {
+    pub fn new() -> io::Result<Self> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Waker {
+            reader: FileDescriptor::new(fds[0]),
+            writer: FileDescriptor::new(fds[1]),
+        })
+    }
+
+    pub fn wake(&self) {
+        // a full pipe wakes the poller just the same
+        let byte = [1u8];
+        let _ = unsafe { libc::write(self.writer.as_raw_fd(), byte.as_ptr() as *const _, 1) };
+    }
+
+    /// Empties the pipe, once the poller is awake.
+    pub fn drain(&self) {
+        let mut buf = [0u8; 64];
+        let fd = self.reader.as_raw_fd();
+        while unsafe { libc::read(fd, buf.as_mut_ptr() as *mut _, buf.len()) } > 0 {}
+    }
+}
+
+impl AsRawFd for Waker {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
+
+/// A `pollfd` waiting for `events` on `fd`.
+pub fn pollfd(fd: RawFd, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    }
+}
+
+/// Waits until any of `fds` is ready or `timeout` passes, returns how many
+/// are ready.
+pub fn poll(fds: &mut [libc::pollfd], timeout: Duration) -> io::Result<usize> {
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    loop {
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ready >= 0 {
+            return Ok(ready as usize);
+        }
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
@@ -0,0 +1,323 @@
+use std::time::{Duration, Instant};
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::terminal::{Cell, Frame, Row, Terminal};
+
+/// How long a prediction may go unconfirmed once the server has its input
+/// before it is taken as wrong.
+const PREDICTION_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Adaptive prediction shows once the round trip is slower than this and
+/// stops once it is faster than `FAST_RTT`, the same as mosh.
+const SLOW_RTT: Duration = Duration::from_millis(30);
+const FAST_RTT: Duration = Duration::from_millis(20);
+
+/// When typing is echoed before the server confirms it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PredictMode {
+    /// Only while the connection is slow.
+    #[default]
+    Adaptive,
+    Always,
+    Never,
+}
+
+impl PredictMode {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "adaptive" => Ok(PredictMode::Adaptive),
+            "always" => Ok(PredictMode::Always),
+            "never" => Ok(PredictMode::Never),
+            _ => Err(format!("unknown prediction mode: {}", text)),
+        }
+    }
+}
+
+/// A guess at how a key changes the screen.
+#[derive(Debug, Clone)]
+struct Prediction {
+    row: usize,
+    col: usize,
+    /// The cell written, None when only the cursor moves.
+    cell: Option<Cell>,
+    /// Where the cursor is after the key.
+    cursor: (usize, usize),
+    epoch: u64,
+    /// The end of the key in the input stream and when the server said it
+    /// had arrived.
+    offset: u64,
+    acked: Option<Instant>,
+}
+
+/// Echoes typing on a slow connection before the server does, like mosh.
+/// It keeps the screen as the server last sent it and draws predictions of
+/// typed characters, backspaces and cursor movement over it, underlined
+/// until the server shows the same. A prediction the server doesn't
+/// confirm in time undoes them all.
+///
+/// Predictions start out tentative after anything it can't predict, like
+/// enter, and are only shown once one of them was confirmed, so a password
+/// prompt that doesn't echo never shows what was typed.
+pub struct Predictor {
+    model: Terminal,
+    rows: u16,
+    cols: u16,
+    mode: PredictMode,
+    rtt: Option<Duration>,
+    slow: bool,
+    predictions: Vec<Prediction>,
+    epoch: u64,
+    /// The newest epoch with a confirmed prediction.
+    confirmed: u64,
+    shown: Option<Frame>,
+    dirty: bool,
+}
+
+impl Predictor {
+    pub fn new(rows: u16, cols: u16, mode: PredictMode) -> Self {
+        Predictor {
+            model: Terminal::new(rows, cols),
+            rows,
+            cols,
+            mode,
+            rtt: None,
+            slow: false,
+            predictions: vec![],
+            epoch: 1,
+            confirmed: 0,
+            shown: None,
+            dirty: true,
+        }
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.model.resize(rows, cols);
+        (self.rows, self.cols) = (rows, cols);
+        self.reset();
+    }
+
+    /// Applies what the server sent to the screen.
+    pub fn update(&mut self, data: &[u8]) {
+        self.model.process(data);
+        self.dirty = true;
+        self.reconcile();
+    }
+
+    /// The `Frame::checksum` of the screen as the server sent it, without
+    /// the predictions.
+    pub fn checksum(&self) -> u64 {
+        self.model.frame(self.rows, self.cols, &[]).checksum()
+    }
+
+    /// Notes that the server has the input up to `offset`.
+    pub fn ack(&mut self, offset: u64) {
+        let now = Instant::now();
+        for prediction in &mut self.predictions {
+            if prediction.offset <= offset && prediction.acked.is_none() {
+                prediction.acked = Some(now);
+            }
+        }
+    }
+
+    /// Takes a round trip time into the estimate adaptive prediction uses.
+    pub fn observe_rtt(&mut self, sample: Duration) {
+        let rtt = self.rtt.map_or(sample, |rtt| (rtt * 7 + sample) / 8);
+        self.rtt = Some(rtt);
+        if rtt > SLOW_RTT {
+            self.slow = true;
+        } else if rtt < FAST_RTT {
+            self.slow = false;
+        }
+        self.dirty = true;
+    }
+
+    /// Predicts what pane input typed on the terminal does, `offset` is
+    /// where it ends in the input stream.
+    pub fn typed(&mut self, data: &[u8], offset: u64) {
+        if self.mode == PredictMode::Never {
+            return;
+        }
+        let Ok(text) = std::str::from_utf8(data) else {
+            return self.interrupt();
+        };
+        let mut rest = text;
+        while let Some(c) = rest.chars().next() {
+            let (row, col) = self.cursor();
+            let cursor = self.model.screen().cursor();
+            if cursor.pending_wrap || !self.model.screen().modes().cursor_visible {
+                return self.interrupt();
+            }
+            let cols = self.cols as usize;
+            // only typing at the end of a line, a shell inserts in the middle
+            let at_end = (col..cols).all(|c| self.cell_at(row, c).is_blank());
+
+            let arrow = ["\x1b[C", "\x1b[D", "\x1bOC", "\x1bOD"]
+                .into_iter()
+                .find(|arrow| rest.starts_with(arrow));
+            if let Some(arrow) = arrow {
+                rest = &rest[arrow.len()..];
+                let col = match arrow.ends_with('C') {
+                    true if !self.cell_at(row, col).is_blank() => col + 1,
+                    false if col > 0 => col - 1,
+                    _ => return self.interrupt(),
+                };
+                self.predict(row, col, None, (row, col), offset);
+                continue;
+            }
+
+            rest = &rest[c.len_utf8()..];
+            match c {
+                '\x7f' | '\x08' if at_end && col > 0 => {
+                    let blank = Cell::blank(cursor.attrs);
+                    self.predict(row, col - 1, Some(blank), (row, col - 1), offset);
+                }
+                c if at_end && col + 1 < cols && !c.is_control() && c.width() == Some(1) => {
+                    let cell = Cell {
+                        c,
+                        width: 1,
+                        attrs: cursor.attrs,
+                    };
+                    self.predict(row, col, Some(cell), (row, col + 1), offset);
+                }
+                _ => return self.interrupt(),
+            }
+        }
+    }
+
+    /// Something that can't be predicted was sent, predictions after it
+    /// are tentative.
+    pub fn interrupt(&mut self) {
+        self.epoch += 1;
+    }
+
+    /// Drops the predictions the server didn't confirm in time.
+    pub fn expire(&mut self) {
+        let expired = self.predictions.iter().any(|prediction| {
+            prediction
+                .acked
+                .is_some_and(|acked| acked.elapsed() >= PREDICTION_TIMEOUT)
+        });
+        if expired {
+            self.reset();
+        }
+    }
+
+    /// The escape sequences that bring the terminal up to date, empty if it
+    /// already is.
+    pub fn render(&mut self) -> Vec<u8> {
+        if !self.dirty {
+            return vec![];
+        }
+        self.dirty = false;
+        let mut frame = self.model.frame(self.rows, self.cols, &[]);
+        let showing = match self.mode {
+            PredictMode::Adaptive => self.slow,
+            PredictMode::Always => true,
+            PredictMode::Never => false,
+        };
+        if showing {
+            let mut cursor = None;
+            let shown = self
+                .predictions
+                .iter()
+                .filter(|p| p.epoch <= self.confirmed);
+            for prediction in shown {
+                if let Some(mut cell) = prediction.cell {
+                    cell.attrs.underline |= !cell.is_blank();
+                    let row = Row {
+                        cells: vec![cell],
+                        wrapped: false,
+                        time: None,
+                    };
+                    frame.place(prediction.row, prediction.col, &row);
+                }
+                cursor = Some(prediction.cursor);
+            }
+            if let Some((row, col)) = cursor {
+                frame.show_cursor(row, col);
+            }
+        }
+        let out = frame.render(self.shown.as_ref());
+        self.shown = Some(frame);
+        out
+    }
+
+    fn predict(
+        &mut self,
+        row: usize,
+        col: usize,
+        cell: Option<Cell>,
+        cursor: (usize, usize),
+        offset: u64,
+    ) {
+        // a newer guess at a cell replaces the older one
+        if cell.is_some() {
+            self.predictions
+                .retain(|p| p.cell.is_none() || (p.row, p.col) != (row, col));
+        }
+        self.predictions.push(Prediction {
+            row,
+            col,
+            cell,
+            cursor,
+            epoch: self.epoch,
+            offset,
+            acked: None,
+        });
+        self.dirty = true;
+        self.reconcile();
+    }
+
+    /// Drops the predictions the screen now shows, in the order they were
+    /// made.
+    fn reconcile(&mut self) {
+        let screen = self.model.screen();
+        let cursor = (screen.cursor().row, screen.cursor().col);
+        let confirmed = self
+            .predictions
+            .iter()
+            .take_while(|p| match p.cell {
+                Some(cell) => screen.grid().row(p.row).cells.get(p.col) == Some(&cell),
+                None => p.cursor == cursor,
+            })
+            .count();
+        for prediction in self.predictions.drain(..confirmed) {
+            // blanks and cursor movement are too easily right by chance
+            if prediction.cell.is_some_and(|cell| !cell.is_blank()) {
+                self.confirmed = self.confirmed.max(prediction.epoch);
+            }
+            self.dirty = true;
+        }
+    }
+
+    fn reset(&mut self) {
+        self.predictions.clear();
+        self.interrupt();
+        self.dirty = true;
+    }
+
+    /// Where the cursor is once the predictions are right.
+    fn cursor(&self) -> (usize, usize) {
+        match self.predictions.last() {
+            Some(prediction) => prediction.cursor,
+            None => {
+                let cursor = self.model.screen().cursor();
+                (cursor.row, cursor.col)
+            }
+        }
+    }
+
+    /// The cell as it is once the predictions are right.
+    fn cell_at(&self, row: usize, col: usize) -> Cell {
+        let predicted = self
+            .predictions
+            .iter()
+            .rev()
+            .find_map(|p| p.cell.filter(|_| (p.row, p.col) == (row, col)));
+        predicted.unwrap_or_else(|| {
+            let cells = &self.model.screen().grid().row(row).cells;
+            cells.get(col).copied().unwrap_or_default()
+        })
+    }
+}
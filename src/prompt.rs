@@ -0,0 +1,113 @@
+use crate::keys::key_len;
+use crate::terminal::{Attributes, Row};
+use crate::text;
+
+/// What a key did to a prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PromptAction {
+    Continue,
+    /// Enter was pressed, with the text as it was.
+    Done(String),
+    Cancel,
+}
+
+/// A line of text typed on a client in place of its status line, edited
+/// with the usual emacs keys.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    label: String,
+    text: Vec<char>,
+    /// Where typed text goes, an index into `text`.
+    cursor: usize,
+}
+
+impl Prompt {
+    /// A prompt showing `label` with `initial` typed, the cursor at its end.
+    pub fn new(label: &str, initial: &str) -> Self {
+        let text: Vec<char> = initial.chars().collect();
+        Prompt {
+            label: label.to_string(),
+            cursor: text.len(),
+            text,
+        }
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    /// Handles the keys in `input`, stopping at the first that ends the prompt.
+    pub fn input(&mut self, input: &[u8]) -> PromptAction {
+        let mut i = 0;
+        while i < input.len() {
+            let len = key_len(&input[i..]).max(1);
+            let action = self.key(&input[i..i + len]);
+            if action != PromptAction::Continue {
+                return action;
+            }
+            i += len;
+        }
+        PromptAction::Continue
+    }
+
+    fn key(&mut self, key: &[u8]) -> PromptAction {
+        match key {
+            b"\r" | b"\n" => return PromptAction::Done(self.text()),
+            b"\x1b" | b"\x03" | b"\x07" => return PromptAction::Cancel,
+            b"\x7f" | b"\x08" => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+            }
+            b"\x04" | b"\x1b[3~" => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            b"\x02" | b"\x1b[D" | b"\x1bOD" => self.cursor = self.cursor.saturating_sub(1),
+            b"\x06" | b"\x1b[C" | b"\x1bOC" => self.cursor = (self.cursor + 1).min(self.text.len()),
+            b"\x01" | b"\x1b[H" | b"\x1bOH" => self.cursor = 0,
+            b"\x05" | b"\x1b[F" | b"\x1bOF" => self.cursor = self.text.len(),
+            b"\x15" => {
+                self.text.drain(..self.cursor);
+                self.cursor = 0;
+            }
+            b"\x0b" => self.text.truncate(self.cursor),
+            b"\x17" => {
+                // the word before the cursor and the blanks after it
+                let mut start = self.cursor;
+                while start > 0 && self.text[start - 1] == ' ' {
+                    start -= 1;
+                }
+                while start > 0 && self.text[start - 1] != ' ' {
+                    start -= 1;
+                }
+                self.text.drain(start..self.cursor);
+                self.cursor = start;
+            }
+            _ => {
+                if let Ok(typed) = std::str::from_utf8(key) {
+                    for c in typed.chars().filter(|c| !c.is_control()) {
+                        self.text.insert(self.cursor, c);
+                        self.cursor += 1;
+                    }
+                }
+            }
+        }
+        PromptAction::Continue
+    }
+
+    /// The prompt as a row `cols` wide, with the column of the cursor. Long
+    /// text scrolls so the cursor stays in view.
+    pub fn render(&self, attrs: Attributes, cols: usize) -> (Row, usize) {
+        let label = text::truncate(&self.label, cols.saturating_sub(1));
+        let room = cols.saturating_sub(text::width(&label) + 1);
+        let before: String = self.text[..self.cursor].iter().collect();
+        let after: String = self.text[self.cursor..].iter().collect();
+        let before = text::ellipsize_start(&before, room);
+        let cursor = text::width(&label) + text::width(&before);
+        let line = format!("{}{}{}", label, before, after);
+        (Row::from_text(&line, attrs, cols), cursor)
+    }
+}
@@ -0,0 +1,654 @@
+use std::{
+    collections::VecDeque,
+    fmt,
+    io::{self, ErrorKind, Read, Write},
+    os::unix::process::ExitStatusExt,
+    process::ExitStatus,
+    sync::{Arc, Condvar, Mutex},
+    time::Duration,
+};
+
+use crate::features::Features;
+use crate::mouse::MouseEvent;
+use crate::poll::Waker;
+
+/// How often each end of an attached connection pings the other once the
+/// client started to, so one that went away without hanging up, like a
+/// laptop that was suspended, is noticed before anything is written to it.
+pub const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long an end that pings waits to hear anything before it takes the
+/// other to be gone.
+pub const KEEPALIVE_TIMEOUT: Duration = Duration::from_secs(45);
+
+/// Messages exchanged between a client and the server over the session socket.
+///
+/// Every message is framed as a one byte tag, followed by a big endian u32
+/// payload length and the payload itself, so control messages can never be
+/// confused with terminal data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Keystrokes from the client or pty output from the server.
+    Data(Vec<u8>),
+    /// A line of newly printed text, sent instead of screen updates to
+    /// accessible clients.
+    Text(String),
+    /// The client shows the session on its terminal. Connections that only
+    /// run commands never send it, so they are never drawn to.
+    Attach,
+    /// The client terminal has been resized. The pixel size is zero when
+    /// the terminal doesn't report it.
+    Resize {
+        rows: u16,
+        cols: u16,
+        pixel_width: u16,
+        pixel_height: u16,
+    },
+    /// The client is detaching, or the server is dropping the client.
+    Detach,
+    /// The client wants the whole screen redrawn.
+    Refresh,
+    /// A command for the server's command queue, as its arguments.
+    Command(Vec<String>),
+    /// The result of a command, sent back to the client that issued it.
+    CommandDone {
+        success: bool,
+        output: String,
+    },
+    /// The client only watches: its input is ignored and its size doesn't
+    /// change the pane's, it is sent as much of the pane as fits.
+    ReadOnly,
+    /// The pane's command exited and the session is going away.
+    Exited(PaneExit),
+    /// The client wants to be sent a pane as `PaneData`, whether or not it
+    /// is attached. A connection can subscribe to several panes at once.
+    Subscribe {
+        pane: u32,
+    },
+    Unsubscribe {
+        pane: u32,
+    },
+    /// Screen updates of a subscribed pane on its own, without the status
+    /// line: a full frame after subscribing, then only what changed.
+    PaneData {
+        pane: u32,
+        data: Vec<u8>,
+    },
+    /// A mouse event on the client's terminal.
+    Mouse(MouseEvent),
+    /// What the client's terminal answered when asked what it can do, sent
+    /// before attaching.
+    Features(Features),
+    /// The shared secret a client connecting over TCP sends first, nothing
+    /// else it sends is handled until it matched.
+    Token(String),
+    /// The ways the client can take compressed messages, most preferred
+    /// first, see `compress::CODECS`.
+    Compress(Vec<String>),
+    /// Messages from the server packed with the codec it picked from the
+    /// client's offer, the next chunk of a stream that runs for the whole
+    /// connection.
+    Compressed(Vec<u8>),
+    /// From the server once a client attached, what it can send in place of
+    /// a fresh attach if it crashes and comes back in time. From a client
+    /// before `Attach`, to pick up where the one it was issued to left off.
+    Resume(String),
+    /// The client is a program driving the session in control mode, see
+    /// `attach -f control`. It isn't drawn to but its size counts like an
+    /// attached client's, and it is told what happens as `Notify` lines.
+    Control,
+    /// An event for a control client, a line like `%layout-change @0 80x24`.
+    Notify(String),
+    Ping,
+    Pong,
+    /// A session moving here from another machine, see migrate-session:
+    /// its spec as export-session writes it and the history and screen to
+    /// show in its pane, see `Terminal::replay`.
+    Migrate {
+        spec: String,
+        history: Vec<u8>,
+    },
+    /// The session moved to the machine at the address. An attached client
+    /// attaches again, through the relay that took over the session socket
+    /// or over TCP to the address.
+    Moved(String),
+    /// Text the client copied in copy mode, for it to write to the
+    /// copy-pipe command of its own machine, see the copy-pipe-side option.
+    Yank(String),
+}
+
+/// How the pane's command ended, told to attached clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PaneExit {
+    Code(i32),
+    Signal(i32),
+    /// The command was gone before it could be waited for.
+    Unknown,
+}
+
+impl PaneExit {
+    pub fn from_status(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => PaneExit::Code(code),
+            (None, Some(signal)) => PaneExit::Signal(signal),
+            (None, None) => PaneExit::Unknown,
+        }
+    }
+}
+
+impl fmt::Display for PaneExit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaneExit::Code(0) | PaneExit::Unknown => write!(f, "exited"),
+            PaneExit::Code(code) => write!(f, "exited with status {}", code),
+            PaneExit::Signal(signal) => write!(f, "killed by signal {}", signal),
+        }
+    }
+}
+
+const TAG_DATA: u8 = 1;
+const TAG_RESIZE: u8 = 2;
+const TAG_DETACH: u8 = 3;
+const TAG_PING: u8 = 4;
+const TAG_PONG: u8 = 5;
+const TAG_REFRESH: u8 = 6;
+const TAG_COMMAND: u8 = 7;
+const TAG_COMMAND_DONE: u8 = 8;
+const TAG_READ_ONLY: u8 = 9;
+const TAG_ATTACH: u8 = 10;
+const TAG_TEXT: u8 = 11;
+const TAG_EXITED: u8 = 12;
+const TAG_SUBSCRIBE: u8 = 13;
+const TAG_UNSUBSCRIBE: u8 = 14;
+const TAG_PANE_DATA: u8 = 15;
+const TAG_MOUSE: u8 = 16;
+const TAG_FEATURES: u8 = 17;
+const TAG_TOKEN: u8 = 18;
+const TAG_COMPRESS: u8 = 19;
+const TAG_COMPRESSED: u8 = 20;
+const TAG_RESUME: u8 = 21;
+const TAG_CONTROL: u8 = 22;
+const TAG_NOTIFY: u8 = 23;
+const TAG_MIGRATE: u8 = 24;
+const TAG_MOVED: u8 = 25;
+const TAG_YANK: u8 = 26;
+
+pub(crate) const HEADER_SIZE: usize = 5;
+pub(crate) const MAX_PAYLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Bulk data is queued in frames of at most this size so that control
+/// messages never wait behind more than one frame of output.
+const MAX_QUEUED_DATA_SIZE: usize = 16 * 1024;
+
+/// How much data may wait for a slow peer. Past it the queued data is
+/// dropped and the sender is told to catch the peer up with a full redraw,
+/// so a stalled client costs memory up to this and never slows the others.
+const MAX_BACKLOG_SIZE: usize = 256 * 1024;
+
+/// Outgoing messages for a single peer.
+///
+/// Control messages always jump ahead of any queued data, so a burst of
+/// output can't delay a resize or a pong.
+pub struct Outbox {
+    queues: Mutex<OutboxQueues>,
+    ready: Condvar,
+    /// Woken on every change, for a writer polling instead of blocking in `pop`.
+    waker: Option<Arc<Waker>>,
+}
+
+#[derive(Default)]
+struct OutboxQueues {
+    control: VecDeque<Message>,
+    /// A redraw that replaced the queued data, see `Outbox::replace_data`.
+    /// It goes out before data queued after it and doesn't count against
+    /// the backlog, so a frame larger than that can still catch the peer up.
+    redraw: VecDeque<Message>,
+    data: VecDeque<Message>,
+    /// The bytes of data queued.
+    backlog: usize,
+    /// Set when data was dropped, see `take_overflow`.
+    overflowed: bool,
+    closed: bool,
+}
+
+impl OutboxQueues {
+    fn push_data(&mut self, message: Message) {
+        self.backlog += message.data_len();
+        self.data.push_back(message);
+        if self.backlog > MAX_BACKLOG_SIZE {
+            self.data.clear();
+            self.backlog = 0;
+            self.overflowed = true;
+        }
+    }
+
+    fn pop(&mut self) -> Option<Message> {
+        if let Some(message) = self.control.pop_front() {
+            return Some(message);
+        }
+        if let Some(message) = self.redraw.pop_front() {
+            return Some(message);
+        }
+        let message = self.data.pop_front()?;
+        self.backlog -= message.data_len();
+        Some(message)
+    }
+}
+
+impl Message {
+    pub fn is_control(&self) -> bool {
+        !matches!(self, Message::Data(_) | Message::PaneData { .. })
+    }
+
+    fn data_len(&self) -> usize {
+        match self {
+            Message::Data(data) | Message::PaneData { data, .. } => data.len(),
+            _ => 0,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let (tag, payload) = match self {
+            Message::Data(data) => (TAG_DATA, data.clone()),
+            Message::Resize {
+                rows,
+                cols,
+                pixel_width,
+                pixel_height,
+            } => {
+                let mut payload = Vec::with_capacity(8);
+                for n in [rows, cols, pixel_width, pixel_height] {
+                    payload.extend_from_slice(&n.to_be_bytes());
+                }
+                (TAG_RESIZE, payload)
+            }
+            Message::Detach => (TAG_DETACH, vec![]),
+            Message::Ping => (TAG_PING, vec![]),
+            Message::Pong => (TAG_PONG, vec![]),
+            Message::Refresh => (TAG_REFRESH, vec![]),
+            Message::ReadOnly => (TAG_READ_ONLY, vec![]),
+            Message::Attach => (TAG_ATTACH, vec![]),
+            Message::Text(text) => (TAG_TEXT, text.clone().into_bytes()),
+            Message::Exited(exit) => {
+                let (kind, value) = match exit {
+                    PaneExit::Code(code) => (0, *code),
+                    PaneExit::Signal(signal) => (1, *signal),
+                    PaneExit::Unknown => (2, 0),
+                };
+                let mut payload = vec![kind];
+                payload.extend_from_slice(&value.to_be_bytes());
+                (TAG_EXITED, payload)
+            }
+            Message::Subscribe { pane } => (TAG_SUBSCRIBE, pane.to_be_bytes().to_vec()),
+            Message::Unsubscribe { pane } => (TAG_UNSUBSCRIBE, pane.to_be_bytes().to_vec()),
+            Message::PaneData { pane, data } => {
+                let mut payload = Vec::with_capacity(4 + data.len());
+                payload.extend_from_slice(&pane.to_be_bytes());
+                payload.extend_from_slice(data);
+                (TAG_PANE_DATA, payload)
+            }
+            Message::Mouse(event) => {
+                let mut payload = Vec::with_capacity(7);
+                for n in [event.code, event.row, event.col] {
+                    payload.extend_from_slice(&n.to_be_bytes());
+                }
+                payload.push(event.release as u8);
+                (TAG_MOUSE, payload)
+            }
+            Message::Features(features) => (TAG_FEATURES, features.encode().into_bytes()),
+            Message::Token(token) => (TAG_TOKEN, token.clone().into_bytes()),
+            Message::Compress(codecs) => (TAG_COMPRESS, codecs.join(",").into_bytes()),
+            Message::Compressed(chunk) => (TAG_COMPRESSED, chunk.clone()),
+            Message::Resume(token) => (TAG_RESUME, token.clone().into_bytes()),
+            Message::Control => (TAG_CONTROL, vec![]),
+            Message::Notify(line) => (TAG_NOTIFY, line.clone().into_bytes()),
+            Message::Migrate { spec, history } => {
+                let mut payload = Vec::with_capacity(4 + spec.len() + history.len());
+                payload.extend_from_slice(&(spec.len() as u32).to_be_bytes());
+                payload.extend_from_slice(spec.as_bytes());
+                payload.extend_from_slice(history);
+                (TAG_MIGRATE, payload)
+            }
+            Message::Moved(address) => (TAG_MOVED, address.clone().into_bytes()),
+            Message::Yank(text) => (TAG_YANK, text.clone().into_bytes()),
+            Message::Command(args) => (TAG_COMMAND, args.join("\0").into_bytes()),
+            Message::CommandDone { success, output } => {
+                let mut payload = Vec::with_capacity(1 + output.len());
+                payload.push(*success as u8);
+                payload.extend_from_slice(output.as_bytes());
+                (TAG_COMMAND_DONE, payload)
+            }
+        };
+
+        let mut frame = Vec::with_capacity(HEADER_SIZE + payload.len());
+        frame.push(tag);
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    pub fn decode(tag: u8, payload: Vec<u8>) -> io::Result<Message> {
+        match tag {
+            TAG_DATA => Ok(Message::Data(payload)),
+            TAG_RESIZE => {
+                // older clients only send rows and columns
+                if payload.len() != 4 && payload.len() != 8 {
+                    return Err(invalid_data("malformed resize message"));
+                }
+                let n = |i: usize| {
+                    payload
+                        .get(i * 2..i * 2 + 2)
+                        .map_or(0, |b| u16::from_be_bytes([b[0], b[1]]))
+                };
+                Ok(Message::Resize {
+                    rows: n(0),
+                    cols: n(1),
+                    pixel_width: n(2),
+                    pixel_height: n(3),
+                })
+            }
+            TAG_DETACH => Ok(Message::Detach),
+            TAG_PING => Ok(Message::Ping),
+            TAG_PONG => Ok(Message::Pong),
+            TAG_REFRESH => Ok(Message::Refresh),
+            TAG_READ_ONLY => Ok(Message::ReadOnly),
+            TAG_ATTACH => Ok(Message::Attach),
+            TAG_TEXT => Ok(Message::Text(decode_string(payload)?)),
+            TAG_EXITED => {
+                let [kind, a, b, c, d] = payload[..] else {
+                    return Err(invalid_data("malformed exit message"));
+                };
+                let value = i32::from_be_bytes([a, b, c, d]);
+                match kind {
+                    0 => Ok(Message::Exited(PaneExit::Code(value))),
+                    1 => Ok(Message::Exited(PaneExit::Signal(value))),
+                    _ => Ok(Message::Exited(PaneExit::Unknown)),
+                }
+            }
+            TAG_SUBSCRIBE | TAG_UNSUBSCRIBE => {
+                let [a, b, c, d] = payload[..] else {
+                    return Err(invalid_data("malformed subscription message"));
+                };
+                let pane = u32::from_be_bytes([a, b, c, d]);
+                match tag {
+                    TAG_SUBSCRIBE => Ok(Message::Subscribe { pane }),
+                    _ => Ok(Message::Unsubscribe { pane }),
+                }
+            }
+            TAG_PANE_DATA => {
+                if payload.len() < 4 {
+                    return Err(invalid_data("malformed pane data message"));
+                }
+                let data = payload[4..].to_vec();
+                let pane = u32::from_be_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                Ok(Message::PaneData { pane, data })
+            }
+            TAG_MOUSE => {
+                let [a, b, c, d, e, f, release] = payload[..] else {
+                    return Err(invalid_data("malformed mouse message"));
+                };
+                Ok(Message::Mouse(MouseEvent {
+                    code: u16::from_be_bytes([a, b]),
+                    row: u16::from_be_bytes([c, d]),
+                    col: u16::from_be_bytes([e, f]),
+                    release: release != 0,
+                }))
+            }
+            TAG_FEATURES => {
+                let features = Features::decode(&decode_string(payload)?);
+                Ok(Message::Features(features.map_err(|e| invalid_data(&e))?))
+            }
+            TAG_TOKEN => Ok(Message::Token(decode_string(payload)?)),
+            TAG_COMPRESS => {
+                let codecs = decode_string(payload)?;
+                let codecs = codecs.split(',').filter(|c| !c.is_empty());
+                Ok(Message::Compress(codecs.map(str::to_string).collect()))
+            }
+            TAG_COMPRESSED => Ok(Message::Compressed(payload)),
+            TAG_RESUME => Ok(Message::Resume(decode_string(payload)?)),
+            TAG_CONTROL => Ok(Message::Control),
+            TAG_NOTIFY => Ok(Message::Notify(decode_string(payload)?)),
+            TAG_MIGRATE => {
+                let malformed = || invalid_data("malformed migration");
+                let (len, rest) = payload.split_first_chunk::<4>().ok_or_else(malformed)?;
+                let len = u32::from_be_bytes(*len) as usize;
+                if rest.len() < len {
+                    return Err(malformed());
+                }
+                Ok(Message::Migrate {
+                    spec: decode_string(rest[..len].to_vec())?,
+                    history: rest[len..].to_vec(),
+                })
+            }
+            TAG_MOVED => Ok(Message::Moved(decode_string(payload)?)),
+            TAG_YANK => Ok(Message::Yank(decode_string(payload)?)),
+            TAG_COMMAND => {
+                let args = decode_string(payload)?;
+                Ok(Message::Command(
+                    args.split('\0').map(str::to_string).collect(),
+                ))
+            }
+            TAG_COMMAND_DONE => {
+                let (&success, output) = payload
+                    .split_first()
+                    .ok_or_else(|| invalid_data("malformed command result"))?;
+                Ok(Message::CommandDone {
+                    success: success != 0,
+                    output: decode_string(output.to_vec())?,
+                })
+            }
+            _ => Err(invalid_data(&format!("unknown message tag {}", tag))),
+        }
+    }
+
+    pub fn write_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.encode())?;
+        writer.flush()
+    }
+
+    /// Takes the first message off the front of `buf`, None until all of
+    /// it has arrived. For reading from non-blocking streams.
+    pub fn take_from(buf: &mut Vec<u8>) -> io::Result<Option<Message>> {
+        let Some(header) = buf.first_chunk::<HEADER_SIZE>() else {
+            return Ok(None);
+        };
+        let tag = header[0];
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > MAX_PAYLOAD_SIZE {
+            return Err(invalid_data("message payload too large"));
+        }
+        if buf.len() < HEADER_SIZE + len {
+            return Ok(None);
+        }
+
+        let payload = buf[HEADER_SIZE..HEADER_SIZE + len].to_vec();
+        buf.drain(..HEADER_SIZE + len);
+        Message::decode(tag, payload).map(Some)
+    }
+
+    /// Reads the next message, returning None when the peer closed the stream
+    /// cleanly between two messages.
+    pub fn read_from<R: Read + ?Sized>(reader: &mut R) -> io::Result<Option<Message>> {
+        let mut header = [0u8; HEADER_SIZE];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let tag = header[0];
+        let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+        if len > MAX_PAYLOAD_SIZE {
+            return Err(invalid_data("message payload too large"));
+        }
+
+        let mut payload = vec![0u8; len];
+        reader.read_exact(&mut payload)?;
+        Message::decode(tag, payload).map(Some)
+    }
+}
+
+impl Outbox {
+    pub fn new() -> Self {
+        Self {
+            queues: Mutex::new(OutboxQueues::default()),
+            ready: Condvar::new(),
+            waker: None,
+        }
+    }
+
+    /// An outbox that wakes `waker` whenever there is something to write.
+    pub fn with_waker(waker: Arc<Waker>) -> Self {
+        Self {
+            waker: Some(waker),
+            ..Self::new()
+        }
+    }
+
+    /// Queues a message, messages pushed after close are dropped.
+    pub fn push(&self, message: Message) {
+        let mut queues = self.queues.lock().unwrap();
+        if queues.closed {
+            return;
+        }
+
+        match message {
+            Message::Data(data) if data.len() > MAX_QUEUED_DATA_SIZE => {
+                for chunk in data.chunks(MAX_QUEUED_DATA_SIZE) {
+                    queues.push_data(Message::Data(chunk.to_vec()));
+                }
+            }
+            _ if message.is_control() => queues.control.push_back(message),
+            _ => queues.push_data(message),
+        }
+        self.notify();
+    }
+
+    /// Blocks until a message is available, returns None once closed and drained.
+    pub fn pop(&self) -> Option<Message> {
+        let mut queues = self.queues.lock().unwrap();
+        loop {
+            if let Some(message) = queues.pop() {
+                return Some(message);
+            }
+            if queues.closed {
+                return None;
+            }
+            queues = self.ready.wait(queues).unwrap();
+        }
+    }
+
+    /// The next message if one is queued, without waiting.
+    pub fn try_pop(&self) -> Option<Message> {
+        self.queues.lock().unwrap().pop()
+    }
+
+    /// The bytes of data waiting for the peer, how far behind it is.
+    pub fn backlog(&self) -> usize {
+        let queues = self.queues.lock().unwrap();
+        let redraw: usize = queues.redraw.iter().map(Message::data_len).sum();
+        queues.backlog + redraw
+    }
+
+    /// Whether queued data was dropped because the peer fell too far
+    /// behind since the last call. The peer then needs a full redraw.
+    pub fn take_overflow(&self) -> bool {
+        std::mem::take(&mut self.queues.lock().unwrap().overflowed)
+    }
+
+    /// Whether the outbox is closed and everything in it was popped.
+    pub fn is_drained(&self) -> bool {
+        let queues = self.queues.lock().unwrap();
+        queues.closed
+            && queues.control.is_empty()
+            && queues.redraw.is_empty()
+            && queues.data.is_empty()
+    }
+
+    /// Replaces any queued data with `message`, used when a redraw makes
+    /// the queued output redundant. Updates of subscribed panes are kept,
+    /// they are drawn from frames of their own. The redraw is queued in
+    /// frames like pushed data, but never dropped for the backlog.
+    pub fn replace_data(&self, message: Message) {
+        let mut queues = self.queues.lock().unwrap();
+        if queues.closed {
+            return;
+        }
+
+        queues
+            .data
+            .retain(|m| matches!(m, Message::PaneData { .. }));
+        queues.backlog = queues.data.iter().map(Message::data_len).sum();
+        queues.redraw.clear();
+        match message {
+            Message::Data(data) => {
+                let chunks = data.chunks(MAX_QUEUED_DATA_SIZE);
+                let frames = chunks.map(|chunk| Message::Data(chunk.to_vec()));
+                queues.redraw.extend(frames);
+            }
+            message => queues.redraw.push_back(message),
+        }
+        self.notify();
+    }
+
+    pub fn close(&self) {
+        self.queues.lock().unwrap().closed = true;
+        self.ready.notify_all();
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+    }
+
+    fn notify(&self) {
+        self.ready.notify_one();
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+    }
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn decode_string(payload: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(payload).map_err(|_| invalid_data("message is not valid utf-8"))
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redraw_over_the_backlog_cap_is_not_dropped() {
+        let outbox = Outbox::new();
+        let redraw = vec![b'x'; MAX_BACKLOG_SIZE * 2];
+        outbox.replace_data(Message::Data(redraw));
+        // output arriving meanwhile overflows on its own, not with the redraw
+        outbox.push(Message::Data(vec![b'y'; MAX_BACKLOG_SIZE + 1]));
+        assert!(outbox.take_overflow());
+
+        let mut sent = 0;
+        while let Some(Message::Data(data)) = outbox.try_pop() {
+            assert!(data.len() <= MAX_QUEUED_DATA_SIZE);
+            assert!(data.iter().all(|&b| b == b'x'));
+            sent += data.len();
+        }
+        assert_eq!(sent, MAX_BACKLOG_SIZE * 2);
+    }
+
+    #[test]
+    fn control_messages_jump_ahead_of_a_redraw() {
+        let outbox = Outbox::new();
+        outbox.replace_data(Message::Data(vec![0; MAX_QUEUED_DATA_SIZE * 3]));
+        outbox.try_pop();
+        outbox.push(Message::Pong);
+        assert_eq!(outbox.try_pop(), Some(Message::Pong));
+    }
+}
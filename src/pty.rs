@@ -1,13 +1,21 @@
-use libc::{self, ioctl, winsize, TIOCSWINSZ};
+use libc::{self, ioctl, winsize, TIOCGWINSZ, TIOCSWINSZ};
 use std::{
     cell::Cell,
-    io::{ErrorKind, Read, Write},
+    io::{ErrorKind, Write},
     os::fd::AsRawFd,
+    path::{Path, PathBuf},
+    process::ExitStatus,
     sync::{Arc, Mutex},
+    thread,
+    time::Duration,
 };
 use std::{io, os::unix::process::CommandExt};
 
-use crate::fd::FileDescriptor;
+use crate::{
+    fd::FileDescriptor,
+    poll::{poll, pollfd},
+    spawn::{SpawnPolicy, User},
+};
 
 pub struct Pty {
     controller: PtyController,
@@ -23,15 +31,116 @@ struct PtyWorker {
     fd: FileDescriptor,
 }
 
-struct PtySize {
-    rows: u16,
-    cols: u16,
-    pixel_width: u16,
-    pixel_height: u16,
+/// Builds the command a pane runs: its program and arguments, where it
+/// starts, its environment and who it runs as. Without a command the
+/// user's shell is started, as a login shell like terminal emulators do.
+#[derive(Debug, Clone)]
+pub struct PtyCommandBuilder {
+    argv: Vec<String>,
+    shell: String,
+    cwd: Option<PathBuf>,
+    env: Vec<(String, String)>,
+    login_shell: bool,
+    user: Option<User>,
+}
+
+/// What a reader of the controller side of the pty should do after a failed read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadFailure {
+    /// The failure is transient (EAGAIN, EINTR), the read should be retried.
+    Retry,
+    /// The worker side is gone or the fd is unusable, the pane has exited.
+    Closed,
+}
+
+impl ReadFailure {
+    pub fn classify(e: &io::Error) -> Self {
+        match e.kind() {
+            ErrorKind::WouldBlock | ErrorKind::Interrupted => ReadFailure::Retry,
+            _ => ReadFailure::Closed,
+        }
+    }
+}
+
+/// Writes input into the pty in bounded chunks. The pty is non-blocking, so
+/// when its input queue is full the writer waits for the program to read
+/// some before going on, instead of dropping input or stalling in write(2).
+pub struct PacedWriter {
+    fd: FileDescriptor,
+    chunk_size: usize,
+}
+
+/// The window size of a pty. The pixel size is zero when unknown, programs
+/// that draw images divide it by the cells to find how big a cell is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PtySize {
+    pub rows: u16,
+    pub cols: u16,
+    pub pixel_width: u16,
+    pub pixel_height: u16,
+}
+
+impl PtySize {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        PtySize {
+            rows,
+            cols,
+            ..Default::default()
+        }
+    }
+
+    /// Parses a size written like tmux's default-size, `80x24` for 80
+    /// columns and 24 rows.
+    pub fn parse(size: &str) -> Result<Self, String> {
+        let invalid = || format!("invalid size: {}", size);
+        let (cols, rows) = size.split_once('x').ok_or_else(invalid)?;
+        let cols = cols.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?;
+        let rows = rows.parse().ok().filter(|&n| n > 0).ok_or_else(invalid)?;
+        Ok(PtySize::new(rows, cols))
+    }
+
+    /// The same size with a different number of rows, cells keep their
+    /// height in pixels.
+    pub fn with_rows(self, rows: u16) -> Self {
+        let pixel_height = match self.rows {
+            0 => 0,
+            old => (self.pixel_height as u32 * rows as u32 / old as u32) as u16,
+        };
+        PtySize {
+            rows,
+            pixel_height,
+            ..self
+        }
+    }
+
+    /// The biggest size that fits in both, cells keep their size in pixels.
+    pub fn fit(self, other: PtySize) -> Self {
+        let narrow = if self.cols <= other.cols { self } else { other };
+        let short = if self.rows <= other.rows { self } else { other };
+        PtySize {
+            rows: short.rows,
+            cols: narrow.cols,
+            pixel_width: narrow.pixel_width,
+            pixel_height: short.pixel_height,
+        }
+    }
 }
 
 impl Pty {
-    pub fn open(cmd: std::process::Command) -> io::Result<Pty> {
+    const WAIT_INTERVAL: Duration = Duration::from_millis(20);
+
+    /// Spawns `cmd` on a new pty of `size`. The size is set before the
+    /// program starts, so it never sees a 0x0 terminal when the server
+    /// was started without one and no client is attached yet.
+    pub fn open(cmd: std::process::Command, size: PtySize) -> io::Result<Pty> {
+        Self::open_with_policy(cmd, &SpawnPolicy::default(), size)
+    }
+
+    pub fn open_with_policy(
+        cmd: std::process::Command,
+        policy: &SpawnPolicy,
+        size: PtySize,
+    ) -> io::Result<Pty> {
         const FLAGS: i32 = libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC;
 
         // open the master PTY with O_CLOEXEC
@@ -62,40 +171,110 @@ impl Pty {
             return Err(io::Error::last_os_error());
         }
 
+        let controller = PtyController::new(FileDescriptor::new(controller_fd));
+        controller.resize(size)?;
+
         // spawn the command, it will cleanup the worker fd when it goes out of scope
         // since it is only needed when spawning the command
         let worker = PtyWorker::new(FileDescriptor::new(worker_fd));
-        let child = worker.spawn_command(cmd)?;
+        let child = worker.spawn_command(cmd, policy)?;
 
         Ok(Pty {
-            controller: PtyController::new(FileDescriptor::new(controller_fd)),
+            controller,
             child: Arc::new(Mutex::new(child)),
         })
     }
 
-    pub fn try_clone_reader(&self) -> io::Result<Box<dyn Read + Send>> {
+    /// A duplicate of the controller fd to read the output from. It is
+    /// non-blocking once the writer was taken, see `take_writer`.
+    pub fn try_clone_reader(&self) -> io::Result<FileDescriptor> {
         self.controller.try_clone_reader()
     }
 
-    pub fn take_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+    /// A duplicate of the controller fd, for `asyncio::AsyncPty`.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn try_clone_fd(&self) -> io::Result<FileDescriptor> {
+        self.controller.fd.duplicate()
+    }
+
+    /// The writer for the pane's input, there is only one. It makes the
+    /// controller fd non-blocking, which readers share.
+    pub fn take_writer(&self) -> io::Result<PacedWriter> {
         self.controller.take_writer()
     }
 
-    pub fn resize(&self, rows: u16, cols: u16) -> io::Result<()> {
-        self.controller.resize(PtySize {
-            rows,
-            cols,
-            pixel_width: 0,
-            pixel_height: 0,
-        })
+    pub fn resize(&self, size: PtySize) -> io::Result<()> {
+        self.controller.resize(size)
     }
 
-    pub fn stopped(&self) -> io::Result<bool> {
-        self.child
-            .lock()
-            .unwrap()
-            .try_wait()
-            .map(|opt| opt.is_some())
+    pub fn get_size(&self) -> io::Result<PtySize> {
+        self.controller.get_size()
+    }
+
+    /// Whether the pane's program reads a line without echoing it, the way
+    /// password prompts do. Raw mode programs like editors turn off echo
+    /// as well, but they read keys rather than lines.
+    pub fn is_secure_input(&self) -> io::Result<bool> {
+        let lflag = self.controller.local_modes()?;
+        Ok(lflag & libc::ECHO == 0 && lflag & libc::ICANON != 0)
+    }
+
+    /// Whether the child is still running, reaps it if it exited.
+    pub fn is_alive(&self) -> io::Result<bool> {
+        self.try_wait().map(|status| status.is_none())
+    }
+
+    /// The process id of the child.
+    pub fn pid(&self) -> u32 {
+        self.child.lock().unwrap().id()
+    }
+
+    /// The process group in the foreground of the pty, the shell or the
+    /// job it runs, as the pid of its leader.
+    pub fn foreground_pid(&self) -> io::Result<u32> {
+        let pgrp = unsafe { libc::tcgetpgrp(self.controller.fd.as_raw_fd()) };
+        if pgrp < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(pgrp as u32)
+    }
+
+    /// Sends a signal to the child's process group, e.g. SIGHUP to hang
+    /// up like closing a terminal would, or SIGTERM and SIGKILL. Signaling
+    /// a child that already exited is not an error.
+    pub fn kill(&self, signal: libc::c_int) -> io::Result<()> {
+        // a reaped child's pid may belong to someone else by now
+        let mut child = self.child.lock().unwrap();
+        if child.try_wait()?.is_some() {
+            return Ok(());
+        }
+
+        // the child is a session leader, so its process group has its pid
+        let pid = child.id() as libc::pid_t;
+        if unsafe { libc::kill(-pid, signal) } == -1 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(libc::ESRCH) {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// How the child exited, None while it is still running. The child is
+    /// reaped the first time, later calls return the same status.
+    pub fn try_wait(&self) -> io::Result<Option<ExitStatus>> {
+        self.child.lock().unwrap().try_wait()
+    }
+
+    /// Blocks until the child exits and returns how it exited. The child
+    /// is polled so the pty can still be signaled while waiting.
+    pub fn wait(&self) -> io::Result<ExitStatus> {
+        loop {
+            if let Some(status) = self.try_wait()? {
+                return Ok(status);
+            }
+            thread::sleep(Self::WAIT_INTERVAL);
+        }
     }
 }
 
@@ -106,6 +285,226 @@ impl Drop for Pty {
     }
 }
 
+impl PtyCommandBuilder {
+    pub fn new() -> Self {
+        Self {
+            argv: vec![],
+            shell: resolve_shell(None),
+            cwd: None,
+            // what the terminal model understands, programs can rely on it
+            env: vec![
+                ("TERM".to_string(), "xterm-256color".to_string()),
+                ("COLORTERM".to_string(), "truecolor".to_string()),
+            ],
+            login_shell: true,
+            user: None,
+        }
+    }
+
+    /// The program and its arguments, the default shell if empty.
+    pub fn command(mut self, argv: &[String]) -> Self {
+        self.argv = argv.to_vec();
+        self
+    }
+
+    /// The shell to run without a command, see `resolve_shell`.
+    pub fn shell(mut self, shell: impl Into<String>) -> Self {
+        self.shell = shell.into();
+        self
+    }
+
+    /// Where the command starts, the server's directory by default.
+    pub fn cwd(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.cwd = Some(dir.into());
+        self
+    }
+
+    /// Sets an environment variable, TERM and COLORTERM included.
+    pub fn env(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let key = key.into();
+        self.env.retain(|(k, _)| *k != key);
+        self.env.push((key, value.into()));
+        self
+    }
+
+    /// Whether the shell gets a dash in front of its argv0, which
+    /// makes it a login shell. A command is always run as given.
+    pub fn login_shell(mut self, login_shell: bool) -> Self {
+        self.login_shell = login_shell;
+        self
+    }
+
+    /// Runs the command as `user`, with its shell and in its home unless
+    /// told otherwise. A server running as root switches to the user
+    /// itself, any other asks sudo or doas to, which may ask for a password
+    /// in the pane.
+    pub fn user(mut self, user: User) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// The program that runs, for naming the window after it.
+    pub fn program(&self) -> &str {
+        let shell = match &self.user {
+            Some(user) if is_valid_shell(&user.shell) => &user.shell,
+            _ => &self.shell,
+        };
+        self.argv.first().map_or(shell.as_str(), String::as_str)
+    }
+
+    pub fn build(&self) -> std::process::Command {
+        if let Some(user) = self.user.as_ref().filter(|_| !is_privileged()) {
+            return self.build_elevated(user);
+        }
+        let mut cmd = std::process::Command::new(self.program());
+        cmd.args(self.argv.iter().skip(1));
+        if self.argv.is_empty() && self.login_shell {
+            let name = Path::new(self.program()).file_name().unwrap_or_default();
+            cmd.arg0(format!("-{}", name.to_string_lossy()));
+        }
+        if let Some(user) = &self.user {
+            cmd.current_dir(&user.home)
+                .env("HOME", &user.home)
+                .env("USER", &user.name)
+                .env("LOGNAME", &user.name)
+                .env("SHELL", self.program());
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.envs(self.env.iter().map(|(key, value)| (key, value)));
+        cmd
+    }
+
+    /// What the child is spawned with, switching to the user when the
+    /// server may.
+    pub fn spawn_policy(&self) -> SpawnPolicy {
+        SpawnPolicy {
+            user: self.user.clone().filter(|_| is_privileged()),
+            ..SpawnPolicy::default()
+        }
+    }
+
+    /// The command wrapped in sudo, or doas where there is no sudo. Either
+    /// sets up the user's environment, TERM is kept.
+    fn build_elevated(&self, user: &User) -> std::process::Command {
+        let sudo = ["sudo", "doas"]
+            .into_iter()
+            .find(|tool| find_in_path(tool))
+            .unwrap_or("sudo");
+        let mut cmd = std::process::Command::new(sudo);
+        cmd.args(["-u", &user.name]);
+        match self.argv.is_empty() {
+            // sudo -i starts the user's login shell in its home, doas has
+            // no such flag
+            true if sudo == "sudo" => cmd.arg("-i"),
+            true => cmd.args([self.program(), "-l"]),
+            false => cmd.arg("--").args(&self.argv),
+        };
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+        cmd.envs(self.env.iter().map(|(key, value)| (key, value)));
+        cmd
+    }
+}
+
+/// Whether the server can switch users itself.
+fn is_privileged() -> bool {
+    unsafe { libc::geteuid() == 0 }
+}
+
+fn find_in_path(program: &str) -> bool {
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path).any(|dir| dir.join(program).is_file())
+}
+
+impl Default for PtyCommandBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The shell panes run: `configured` (the default-shell option), else
+/// $SHELL, else the user's login shell from the passwd database, else
+/// /bin/sh. Like tmux, only an absolute path to an executable counts.
+pub fn resolve_shell(configured: Option<&str>) -> String {
+    let from_env = std::env::var("SHELL").ok();
+    [configured.map(str::to_string), from_env, passwd_shell()]
+        .into_iter()
+        .flatten()
+        .find(|shell| is_valid_shell(shell))
+        .unwrap_or_else(|| "/bin/sh".to_string())
+}
+
+fn is_valid_shell(shell: &str) -> bool {
+    let Ok(path) = std::ffi::CString::new(shell) else {
+        return false;
+    };
+    Path::new(shell).is_absolute() && unsafe { libc::access(path.as_ptr(), libc::X_OK) } == 0
+}
+
+/// The login shell of the user the server runs as.
+fn passwd_shell() -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 4096];
+    let rc = unsafe {
+        libc::getpwuid_r(
+            libc::getuid(),
+            &mut passwd,
+            buf.as_mut_ptr(),
+            buf.len(),
+            &mut result,
+        )
+    };
+    if rc != 0 || result.is_null() || passwd.pw_shell.is_null() {
+        return None;
+    }
+    let shell = unsafe { std::ffi::CStr::from_ptr(passwd.pw_shell) };
+    shell.to_str().ok().map(str::to_string)
+}
+
+impl PacedWriter {
+    const DEFAULT_CHUNK_SIZE: usize = 512;
+
+    fn new(fd: FileDescriptor) -> Self {
+        PacedWriter {
+            fd,
+            chunk_size: Self::DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    pub fn write_paced(&mut self, data: &[u8]) -> io::Result<()> {
+        for chunk in data.chunks(self.chunk_size) {
+            let mut written = 0;
+            while written < chunk.len() {
+                match self.fd.write(&chunk[written..]) {
+                    Ok(0) => {
+                        return Err(io::Error::new(
+                            ErrorKind::WriteZero,
+                            "pty stopped accepting input",
+                        ))
+                    }
+                    Ok(size) => written += size,
+                    Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                    // the pty input queue is full, wait for the child to drain it
+                    Err(e) if e.kind() == ErrorKind::WouldBlock => self.wait_writable()?,
+                    Err(e) => return Err(e),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits until the pty has room for more input, or is hung up which
+    /// the next write reports.
+    fn wait_writable(&self) -> io::Result<()> {
+        let mut fds = [pollfd(self.fd.as_raw_fd(), libc::POLLOUT)];
+        poll(&mut fds, Duration::MAX).map(|_| ())
+    }
+}
+
 impl PtyController {
     pub fn new(fd: FileDescriptor) -> Self {
         PtyController {
@@ -130,18 +529,42 @@ impl PtyController {
         Ok(())
     }
 
-    pub fn try_clone_reader(&self) -> io::Result<Box<dyn Read + Send>> {
-        let fd = self.fd.duplicate()?;
-        Ok(Box::new(fd))
+    pub fn get_size(&self) -> io::Result<PtySize> {
+        let mut size: winsize = unsafe { std::mem::zeroed() };
+        let ret = unsafe { ioctl(self.fd.as_raw_fd(), TIOCGWINSZ, &mut size) };
+        if ret < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        Ok(PtySize {
+            rows: size.ws_row,
+            cols: size.ws_col,
+            pixel_width: size.ws_xpixel,
+            pixel_height: size.ws_ypixel,
+        })
     }
 
-    pub fn take_writer(&self) -> io::Result<Box<dyn Write + Send>> {
+    /// The local modes of the worker's termios, the controller shares them.
+    pub fn local_modes(&self) -> io::Result<libc::tcflag_t> {
+        let mut termios: libc::termios = unsafe { std::mem::zeroed() };
+        if unsafe { libc::tcgetattr(self.fd.as_raw_fd(), &mut termios) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(termios.c_lflag)
+    }
+
+    pub fn try_clone_reader(&self) -> io::Result<FileDescriptor> {
+        self.fd.duplicate()
+    }
+
+    pub fn take_writer(&self) -> io::Result<PacedWriter> {
         if self.writer_taken.get() {
-            Err(io::Error::new(ErrorKind::Other, "writer already taken"))
+            Err(io::Error::other("writer already taken"))
         } else {
             let fd = self.fd.duplicate()?;
+            fd.set_nonblocking()?;
             self.writer_taken.set(true);
-            Ok(Box::new(fd))
+            Ok(PacedWriter::new(fd))
         }
     }
 }
@@ -151,13 +574,20 @@ impl PtyWorker {
         PtyWorker { fd }
     }
 
-    pub fn spawn_command(&self, mut cmd: std::process::Command) -> io::Result<std::process::Child> {
+    pub fn spawn_command(
+        &self,
+        mut cmd: std::process::Command,
+        policy: &SpawnPolicy,
+    ) -> io::Result<std::process::Child> {
         // prepare command for spawning
+        let fds = policy.plan_passed_fds()?;
+        policy.apply_env(&mut cmd);
+        let policy = policy.clone();
         unsafe {
             cmd.stdin(self.fd.as_stdio()?)
                 .stdout(self.fd.as_stdio()?)
                 .stderr(self.fd.as_stdio()?)
-                .pre_exec(Self::prepare_for_spawn)
+                .pre_exec(move || policy.prepare_for_spawn(&fds))
         };
 
         // spawn the command
@@ -170,57 +600,4 @@ impl PtyWorker {
 
         Ok(child)
     }
-
-    fn prepare_for_spawn() -> io::Result<()> {
-        unsafe {
-            // reset all signal handlers to default behavior
-            for signo in &[
-                libc::SIGCHLD,
-                libc::SIGHUP,
-                libc::SIGINT,
-                libc::SIGQUIT,
-                libc::SIGTERM,
-                libc::SIGALRM,
-            ] {
-                libc::signal(*signo, libc::SIG_DFL);
-            }
-
-            // unmask all signals, unblocking them
-            let empty_set: libc::sigset_t = std::mem::zeroed();
-            libc::sigprocmask(libc::SIG_SETMASK, &empty_set, std::ptr::null_mut());
-
-            // establish ourselves as a session leader.
-            if libc::setsid() == -1 {
-                return Err(io::Error::last_os_error());
-            }
-
-            // set the pty as the controlling terminal
-            // #[allow(clippy::cast_lossless)]
-            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
-                return Err(io::Error::last_os_error());
-            }
-
-            // closing all descriptors except for the stdio streams
-            if let Ok(dir) = std::fs::read_dir("/dev/fd") {
-                let mut fds = vec![];
-                for entry in dir {
-                    if let Some(num) = entry
-                        .ok()
-                        .map(|e| e.file_name())
-                        .and_then(|s| s.into_string().ok())
-                        .and_then(|n| n.parse::<libc::c_int>().ok())
-                    {
-                        if num > 2 {
-                            fds.push(num);
-                        }
-                    }
-                }
-                for fd in fds {
-                    libc::close(fd);
-                }
-            }
-        }
-
-        Ok(())
-    }
 }
@@ -0,0 +1,77 @@
+use std::cmp::Reverse;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use crate::socket::socket_dir;
+
+/// Limits on the logs servers leave behind in `socket_dir`, so sessions
+/// that come and go don't slowly fill the disk. A limit of zero is no limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Retention {
+    /// Logs of sessions that are gone are removed once this old.
+    pub max_age: Duration,
+    /// A running session's log is emptied when it grows past this, and
+    /// the logs of sessions that are gone are removed oldest first until
+    /// together they fit in it.
+    pub max_size: u64,
+}
+
+impl Retention {
+    /// Removes or empties every log over the limits, returns their paths.
+    pub fn clean(&self) -> io::Result<Vec<PathBuf>> {
+        let entries = match fs::read_dir(socket_dir()) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e),
+        };
+
+        let mut cleaned = vec![];
+        let mut finished = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_none_or(|ext| ext != "log") {
+                continue;
+            }
+            let Ok(metadata) = path.metadata() else {
+                continue; // another server got to it first
+            };
+
+            if path.with_extension("sock").exists() {
+                // the log is appended to, writes carry on at the new end
+                if self.max_size > 0 && metadata.len() > self.max_size {
+                    fs::OpenOptions::new().write(true).open(&path)?.set_len(0)?;
+                    cleaned.push(path);
+                }
+            } else {
+                let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+                finished.push((modified, metadata.len(), path));
+            }
+        }
+
+        // newest first, so the oldest are the ones over the size limit
+        finished.sort_by_key(|(modified, ..)| Reverse(*modified));
+        let now = SystemTime::now();
+        let mut total = 0;
+        for (modified, len, path) in finished {
+            total += len;
+            let too_old = !self.max_age.is_zero()
+                && now.duration_since(modified).unwrap_or_default() > self.max_age;
+            let too_big = self.max_size > 0 && total > self.max_size;
+            if (too_old || too_big) && remove(&path)? {
+                cleaned.push(path);
+            }
+        }
+        Ok(cleaned)
+    }
+}
+
+/// Removes a file, false if it was already gone.
+fn remove(path: &Path) -> io::Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
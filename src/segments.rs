@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use std::process::{Command, Stdio};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+use crate::format::{self, Variables};
+use crate::status::{clock, CLOCK_FORMAT};
+
+/// A part of the right side of the status line, worked out again once its
+/// value is older than its interval rather than on every draw.
+pub trait Segment: Send {
+    fn interval(&self) -> Duration;
+
+    /// Works out the value, None while it isn't known yet, like while a
+    /// command is still running. It is asked again on the next tick.
+    fn update(&mut self) -> Option<String>;
+}
+
+pub struct Clock;
+
+impl Segment for Clock {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(1)
+    }
+
+    fn update(&mut self) -> Option<String> {
+        Some(clock(CLOCK_FORMAT))
+    }
+}
+
+pub struct Hostname;
+
+impl Segment for Hostname {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(60)
+    }
+
+    fn update(&mut self) -> Option<String> {
+        Some(crate::config::hostname())
+    }
+}
+
+/// The load average over the last minute.
+pub struct Load;
+
+impl Segment for Load {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(5)
+    }
+
+    fn update(&mut self) -> Option<String> {
+        let mut load = [0f64; 1];
+        match unsafe { libc::getloadavg(load.as_mut_ptr(), 1) } {
+            1 => Some(format!("{:.2}", load[0])),
+            _ => Some(String::new()),
+        }
+    }
+}
+
+/// The first line a shell command prints, the command runs on a thread of
+/// its own so a slow one never holds up the status line.
+pub struct Shell {
+    command: String,
+    running: Option<Receiver<String>>,
+}
+
+impl Shell {
+    pub fn new(command: &str) -> Self {
+        Shell {
+            command: command.to_string(),
+            running: None,
+        }
+    }
+}
+
+impl Segment for Shell {
+    fn interval(&self) -> Duration {
+        Duration::from_secs(15)
+    }
+
+    fn update(&mut self) -> Option<String> {
+        let Some(running) = &self.running else {
+            let (tx, rx) = channel();
+            let command = self.command.clone();
+            std::thread::spawn(move || {
+                let output = Command::new("sh")
+                    .args(["-c", &command])
+                    .stdin(Stdio::null())
+                    .stderr(Stdio::null())
+                    .output();
+                let output = output.map(|o| o.stdout).unwrap_or_default();
+                let output = String::from_utf8_lossy(&output);
+                let _ = tx.send(output.lines().next().unwrap_or("").to_string());
+            });
+            self.running = Some(rx);
+            return None;
+        };
+        let value = match running.try_recv() {
+            Ok(value) => value,
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Disconnected) => String::new(),
+        };
+        self.running = None;
+        Some(value)
+    }
+}
+
+/// A word with `#{variable}` formats in it, expanded when the line is drawn
+/// rather than worked out on a tick.
+pub struct Format(String);
+
+impl Segment for Format {
+    fn interval(&self) -> Duration {
+        // the word never changes, its variables do
+        Duration::from_secs(24 * 60 * 60)
+    }
+
+    fn update(&mut self) -> Option<String> {
+        Some(self.0.clone())
+    }
+}
+
+struct Shown {
+    segment: Box<dyn Segment>,
+    /// Set for a `Format`, the value is expanded with the variables.
+    expand: bool,
+    value: String,
+    /// When the value is due to be worked out again.
+    due: Instant,
+}
+
+/// The segments shown on the right of the status line, set from the
+/// status-right option: names separated by blanks, with `#(command)` for
+/// the output of a shell command and words with `#{variable}` in them for
+/// formats, see `format::expand`. More kinds of segment can be registered
+/// under names of their own.
+pub struct Segments {
+    kinds: BTreeMap<String, fn() -> Box<dyn Segment>>,
+    /// The status-right value the segments were made from.
+    format: String,
+    shown: Vec<Shown>,
+}
+
+impl Segments {
+    /// Knows the clock, the hostname and the load, and shows the clock.
+    pub fn new() -> Self {
+        let mut segments = Segments {
+            kinds: BTreeMap::new(),
+            format: "clock".to_string(),
+            shown: vec![],
+        };
+        segments.register("clock", || Box::new(Clock));
+        segments.register("hostname", || Box::new(Hostname));
+        segments.register("load", || Box::new(Load));
+        segments.shown = vec![Self::show(Box::new(Clock), false)];
+        segments
+    }
+
+    pub fn register(&mut self, name: &str, make: fn() -> Box<dyn Segment>) {
+        self.kinds.insert(name.to_string(), make);
+    }
+
+    /// Shows the segments of a status-right value, nothing changes if it
+    /// names a segment that isn't registered. The values are kept when the
+    /// value is the same as before.
+    pub fn set_format(&mut self, format: &str) -> Result<(), String> {
+        if format != self.format {
+            self.shown = self.parse(format)?;
+            self.format = format.to_string();
+        }
+        Ok(())
+    }
+
+    /// Whether `format` only names registered segments.
+    pub fn check(&self, format: &str) -> Result<(), String> {
+        self.parse(format).map(|_| ())
+    }
+
+    fn parse(&self, format: &str) -> Result<Vec<Shown>, String> {
+        let mut shown = vec![];
+        let mut rest = format.trim_start();
+        while !rest.is_empty() {
+            let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+            let segment: Box<dyn Segment> = match rest.strip_prefix("#(") {
+                Some(command) => {
+                    let end = command
+                        .find(')')
+                        .ok_or_else(|| format!("unterminated #(: {}", rest))?;
+                    rest = &command[end + 1..];
+                    Box::new(Shell::new(&command[..end]))
+                }
+                None if rest[..end].contains("#{") => {
+                    let end = Self::format_end(rest)?;
+                    shown.push(Self::show(Box::new(Format(rest[..end].to_string())), true));
+                    rest = rest[end..].trim_start();
+                    continue;
+                }
+                None => {
+                    let name = &rest[..end];
+                    rest = &rest[end..];
+                    let make = self
+                        .kinds
+                        .get(name)
+                        .ok_or_else(|| format!("unknown segment: {}", name))?;
+                    make()
+                }
+            };
+            shown.push(Self::show(segment, false));
+            rest = rest.trim_start();
+        }
+        Ok(shown)
+    }
+
+    /// Where the format word at the start of `rest` ends, blanks inside a
+    /// `#{...}` like those of a `#{?name,yes,no}` don't end it.
+    fn format_end(rest: &str) -> Result<usize, String> {
+        let mut end = 0;
+        while end < rest.len() {
+            let word = &rest[end..];
+            if word.starts_with("#{") {
+                let close = word
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated #{{: {}", rest))?;
+                end += close + 1;
+            } else if word.starts_with(char::is_whitespace) {
+                break;
+            } else {
+                end += word.chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        Ok(end)
+    }
+
+    fn show(segment: Box<dyn Segment>, expand: bool) -> Shown {
+        Shown {
+            segment,
+            expand,
+            value: String::new(),
+            due: Instant::now(),
+        }
+    }
+
+    /// Works out the values that are due.
+    pub fn refresh(&mut self) {
+        let now = Instant::now();
+        for shown in self.shown.iter_mut().filter(|s| s.due <= now) {
+            if let Some(value) = shown.segment.update() {
+                shown.value = value;
+                shown.due = now + shown.segment.interval();
+            }
+        }
+    }
+
+    /// The values of the segments with the formats expanded with
+    /// `variables`, empty ones left out.
+    pub fn text(&self, variables: &Variables) -> String {
+        let values: Vec<String> = self
+            .shown
+            .iter()
+            .map(|s| match s.expand {
+                true => format::expand(&s.value, variables),
+                false => s.value.clone(),
+            })
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect();
+        values.join(" ")
+    }
+}
+
+impl Default for Segments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
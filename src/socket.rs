@@ -1,18 +1,212 @@
-use std::{fs, io};
-use std::os::unix::net::UnixListener;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::{AsRawFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
+use std::time::Duration;
+use std::{fs, io};
 
-pub fn bind_unix_socket(socket_path: &str) -> io::Result<UnixListener> {
-    // create directories if missing
-    if let Some(parent) = Path::new(socket_path).parent() {
-        fs::create_dir_all(parent)?;
+/// Where the socket of every session of this user lives, a directory per
+/// user like tmux so it can be kept private.
+pub fn socket_dir() -> String {
+    format!("/tmp/rstmux-{}", unsafe { libc::getuid() })
+}
+
+/// The socket of a session, or the path itself for the socket of a session
+/// another user shares, see `share_socket`.
+pub fn socket_path(session_name: &str) -> String {
+    if session_name.contains('/') {
+        return session_name.to_string();
+    }
+    format!("{}/{}.sock", socket_dir(), session_name)
+}
+
+/// Where the server of a session started in the background logs to.
+pub fn log_path(session_name: &str) -> String {
+    format!("{}/{}.log", socket_dir(), session_name)
+}
+
+/// Where a relay notes the address of the remote session it serves under
+/// a local name, for list-sessions.
+pub fn relay_path(session_name: &str) -> String {
+    format!("{}/{}.relay", socket_dir(), session_name)
+}
+
+/// Creates the socket directory readable only by this user, and refuses one
+/// that someone else owns or could write to.
+pub fn create_socket_dir() -> io::Result<String> {
+    let dir = socket_dir();
+    match fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Err(e) if e.kind() != io::ErrorKind::AlreadyExists => return Err(e),
+        _ => {}
+    }
+    let meta = fs::symlink_metadata(&dir)?;
+    if !meta.is_dir() || meta.uid() != unsafe { libc::getuid() } || meta.mode() & 0o022 != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} is not private to this user", dir),
+        ));
     }
+    Ok(dir)
+}
+
+/// The names of the sessions with a socket, sorted. A socket may be stale if
+/// its server didn't exit cleanly.
+pub fn session_names() -> io::Result<Vec<String>> {
+    let entries = match fs::read_dir(socket_dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+
+    let mut names = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == "sock") {
+            if let Some(name) = path.file_stem() {
+                names.push(name.to_string_lossy().into_owned());
+            }
+        }
+    }
+    names.sort();
+    Ok(names)
+}
+
+pub fn bind_unix_socket(socket_path: &str) -> io::Result<UnixListener> {
+    // create the directory if missing
+    create_socket_dir()?;
 
     // cleanup existing socket files
     if Path::new(socket_path).exists() {
         fs::remove_file(socket_path)?;
     }
 
-    // bind the socket
-    UnixListener::bind(socket_path)
+    // bind the socket, nobody else can reach it in the directory meanwhile
+    let listener = UnixListener::bind(socket_path)?;
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(0o600))?;
+    Ok(listener)
+}
+
+/// Lets other users reach a session's socket or takes that back. The
+/// directory can then be searched but not listed, and stays that way since
+/// other sessions may be shared too. The server checks who connects with
+/// `peer_uid`.
+pub fn share_socket(socket_path: &str, shared: bool) -> io::Result<()> {
+    if shared {
+        fs::set_permissions(socket_dir(), fs::Permissions::from_mode(0o711))?;
+    }
+    let mode = if shared { 0o666 } else { 0o600 };
+    fs::set_permissions(socket_path, fs::Permissions::from_mode(mode))
+}
+
+/// The user on the other end of a session socket.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+pub fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let mut cred: libc::ucred = unsafe { std::mem::zeroed() };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let rc = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if rc == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(cred.uid)
+}
+
+/// The user on the other end of a session socket.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+pub fn peer_uid(stream: &UnixStream) -> io::Result<libc::uid_t> {
+    let (mut uid, mut gid) = (0, 0);
+    if unsafe { libc::getpeereid(stream.as_raw_fd(), &mut uid, &mut gid) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(uid)
+}
+
+/// Where a client finds the shared secret for attaching over TCP, set for
+/// the server as well when it listens.
+pub const TOKEN_ENV: &str = "RSTMUX_TOKEN";
+
+/// A connection between a client and the server, over the session socket
+/// or over TCP from another machine. A TLS connection is relayed over a
+/// socket pair, see `tls::accept`.
+#[derive(Debug)]
+pub enum Stream {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Stream {
+    pub fn try_clone(&self) -> io::Result<Stream> {
+        match self {
+            Stream::Unix(stream) => stream.try_clone().map(Stream::Unix),
+            Stream::Tcp(stream) => stream.try_clone().map(Stream::Tcp),
+        }
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.set_nonblocking(nonblocking),
+            Stream::Tcp(stream) => stream.set_nonblocking(nonblocking),
+        }
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            Stream::Tcp(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+
+    pub fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.shutdown(how),
+            Stream::Tcp(stream) => stream.shutdown(how),
+        }
+    }
+
+    pub fn is_tcp(&self) -> bool {
+        matches!(self, Stream::Tcp(_))
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.read(buf),
+            Stream::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Unix(stream) => stream.write(buf),
+            Stream::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Unix(stream) => stream.flush(),
+            Stream::Tcp(stream) => stream.flush(),
+        }
+    }
+}
+
+impl AsRawFd for Stream {
+    fn as_raw_fd(&self) -> RawFd {
+        match self {
+            Stream::Unix(stream) => stream.as_raw_fd(),
+            Stream::Tcp(stream) => stream.as_raw_fd(),
+        }
+    }
 }
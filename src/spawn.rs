@@ -0,0 +1,438 @@
+use std::{
+    ffi::{CStr, CString, OsStr},
+    io,
+    os::{fd::RawFd, unix::ffi::OsStrExt},
+    process::Command,
+};
+
+/// Prefix shared by every environment variable rstmux uses internally.
+pub const INTERNAL_ENV_PREFIX: &str = "RSTMUX_";
+
+/// How many descriptors can be passed to a child, the child can't allocate.
+pub const MAX_PASSED_FDS: usize = 16;
+
+/// Describes the environment a pane's child process is started in.
+///
+/// Whatever the policy, a spawned child is always a session leader with the
+/// pty as its controlling terminal and stdin, stdout and stderr connected to
+/// it. On top of that the policy guarantees that the child:
+///
+/// - has every other file descriptor closed, except for `passed_fds`
+/// - has an empty signal mask and the default disposition for `reset_signals`
+/// - has its file mode creation mask set to `umask`
+/// - sees no environment variable starting with one of `strip_env_prefixes`
+/// - runs as `user` with its groups if set, which needs a privileged server
+/// - joins the cgroup `cgroup_procs` belongs to and has its address space
+///   capped at `memory_limit` bytes, if set
+#[derive(Debug, Clone)]
+pub struct SpawnPolicy {
+    pub umask: libc::mode_t,
+    pub reset_signals: Vec<libc::c_int>,
+    pub strip_env_prefixes: Vec<String>,
+    /// Descriptors intentionally inherited by the child, as (source, target)
+    /// pairs. The sources must stay open until the child has been spawned.
+    pub passed_fds: Vec<(RawFd, RawFd)>,
+    pub user: Option<User>,
+    /// cgroup.procs of the child's cgroup, opened by the server.
+    pub cgroup_procs: Option<RawFd>,
+    pub memory_limit: Option<u64>,
+}
+
+impl Default for SpawnPolicy {
+    fn default() -> Self {
+        Self {
+            umask: 0o022,
+            reset_signals: vec![
+                libc::SIGCHLD,
+                libc::SIGHUP,
+                libc::SIGINT,
+                libc::SIGQUIT,
+                libc::SIGTERM,
+                libc::SIGALRM,
+                libc::SIGPIPE,
+                libc::SIGTSTP,
+                libc::SIGTTIN,
+                libc::SIGTTOU,
+                libc::SIGWINCH,
+            ],
+            strip_env_prefixes: vec![INTERNAL_ENV_PREFIX.to_string()],
+            passed_fds: vec![],
+            user: None,
+            cgroup_procs: None,
+            memory_limit: None,
+        }
+    }
+}
+
+impl SpawnPolicy {
+    /// Returns true when the variable should not be visible to the child.
+    pub fn is_stripped(&self, key: &OsStr) -> bool {
+        self.strip_env_prefixes
+            .iter()
+            .any(|prefix| key.as_bytes().starts_with(prefix.as_bytes()))
+    }
+
+    /// Makes `source` available to the child as descriptor number `target`.
+    pub fn pass_fd(&mut self, source: RawFd, target: RawFd) -> &mut Self {
+        self.passed_fds.push((source, target));
+        self
+    }
+
+    /// Refuses passed fds the child can't be given: a target among the
+    /// stdio streams, which are the pty, two sources for one target, or
+    /// more than `MAX_PASSED_FDS` of them.
+    pub fn check_passed_fds(&self) -> io::Result<()> {
+        if self.passed_fds.len() > MAX_PASSED_FDS {
+            let message = format!("can't pass more than {} fds", MAX_PASSED_FDS);
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, message));
+        }
+        for (i, &(source, target)) in self.passed_fds.iter().enumerate() {
+            let invalid = |reason: &str| {
+                let message = format!("can't pass fd {} as {}: {}", source, target, reason);
+                Err(io::Error::new(io::ErrorKind::InvalidInput, message))
+            };
+            if source < 0 {
+                return invalid("not a descriptor");
+            }
+            if target <= 2 {
+                return invalid("stdio is the pty");
+            }
+            if self.passed_fds[..i].iter().any(|&(_, t)| t == target) {
+                return invalid("already passed");
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the passed fds and lays them out for `prepare_for_spawn`, in
+    /// the parent since the child can't allocate.
+    pub(crate) fn plan_passed_fds(&self) -> io::Result<PassedFds> {
+        self.check_passed_fds()?;
+        let mut plan = PassedFds {
+            pairs: [(-1, -1); MAX_PASSED_FDS],
+            targets: [-1; MAX_PASSED_FDS],
+            len: self.passed_fds.len(),
+            highest: 2,
+        };
+        for (i, &(source, target)) in self.passed_fds.iter().enumerate() {
+            plan.pairs[i] = (source, target);
+            plan.targets[i] = target;
+            plan.highest = plan.highest.max(source).max(target);
+        }
+        plan.targets[..plan.len].sort_unstable();
+        Ok(plan)
+    }
+
+    /// Removes stripped variables from the environment the command inherits
+    /// as well as from any variables explicitly set on the command.
+    pub fn apply_env(&self, cmd: &mut Command) {
+        let stripped: Vec<_> = std::env::vars_os()
+            .map(|(key, _)| key)
+            .chain(cmd.get_envs().map(|(key, _)| key.to_os_string()))
+            .filter(|key| self.is_stripped(key))
+            .collect();
+        for key in stripped {
+            cmd.env_remove(key);
+        }
+    }
+
+    /// Runs in the forked child right before exec, with `fds` planned by
+    /// `plan_passed_fds`.
+    pub(crate) fn prepare_for_spawn(&self, fds: &PassedFds) -> io::Result<()> {
+        unsafe {
+            // reset signal handlers to default behavior
+            for signo in &self.reset_signals {
+                libc::signal(*signo, libc::SIG_DFL);
+            }
+
+            // unmask all signals, unblocking them
+            let empty_set: libc::sigset_t = std::mem::zeroed();
+            libc::sigprocmask(libc::SIG_SETMASK, &empty_set, std::ptr::null_mut());
+
+            // don't leak the server's umask into the child
+            libc::umask(self.umask);
+
+            // establish ourselves as a session leader.
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // set the pty as the controlling terminal
+            if libc::ioctl(0, libc::TIOCSCTTY as _, 0) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+
+            // while still allowed to move itself
+            if let Some(fd) = self.cgroup_procs {
+                if libc::write(fd, b"0".as_ptr().cast(), 1) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+            if let Some(bytes) = self.memory_limit {
+                let limit = libc::rlimit {
+                    rlim_cur: bytes as libc::rlim_t,
+                    rlim_max: bytes as libc::rlim_t,
+                };
+                if libc::setrlimit(libc::RLIMIT_AS, &limit) == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            if let Some(user) = &self.user {
+                user.switch_to()?;
+            }
+
+            // move the passed fds into place, then close everything else
+            fds.install()?;
+            close_fds_except(fds.targets());
+        }
+
+        Ok(())
+    }
+}
+
+/// The passed fds of a policy, laid out in fixed-size buffers so they can
+/// be installed in the child without allocating.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct PassedFds {
+    pairs: [(RawFd, RawFd); MAX_PASSED_FDS],
+    /// The targets of `pairs`, sorted.
+    targets: [RawFd; MAX_PASSED_FDS],
+    len: usize,
+    /// The highest source or target, staged fds go above it.
+    highest: RawFd,
+}
+
+impl PassedFds {
+    /// The sorted target fds that must stay open in the child.
+    fn targets(&self) -> &[RawFd] {
+        &self.targets[..self.len]
+    }
+
+    /// Runs in the forked child, moves every source onto its target.
+    unsafe fn install(&self) -> io::Result<()> {
+        // first move every source out of the way so that installing one
+        // target can't clobber a source that is still needed
+        let mut staged = [-1; MAX_PASSED_FDS];
+        for (i, &(source, _)) in self.pairs[..self.len].iter().enumerate() {
+            let temp = libc::fcntl(source, libc::F_DUPFD_CLOEXEC, self.highest + 1);
+            if temp == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            staged[i] = temp;
+        }
+
+        // dup2 clears close-on-exec on the target
+        for (i, &(_, target)) in self.pairs[..self.len].iter().enumerate() {
+            if libc::dup2(staged[i], target) == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            libc::close(staged[i]);
+        }
+        Ok(())
+    }
+}
+
+/// A user from the passwd database that a child can run as.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct User {
+    pub name: String,
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+    /// The supplementary groups, looked up before the fork since that
+    /// isn't safe to do in the child.
+    pub groups: Vec<libc::gid_t>,
+    pub home: String,
+    pub shell: String,
+}
+
+impl User {
+    pub fn lookup(name: &str) -> io::Result<User> {
+        let unknown = || io::Error::new(io::ErrorKind::NotFound, format!("no such user: {}", name));
+        let c_name = CString::new(name).map_err(|_| unknown())?;
+        let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let mut buf = vec![0 as libc::c_char; 4096];
+        let rc = unsafe {
+            libc::getpwnam_r(
+                c_name.as_ptr(),
+                &mut passwd,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        if rc != 0 {
+            return Err(io::Error::from_raw_os_error(rc));
+        }
+        if result.is_null() {
+            return Err(unknown());
+        }
+        let text = |ptr: *const libc::c_char| match ptr.is_null() {
+            true => String::new(),
+            false => unsafe { CStr::from_ptr(ptr) }
+                .to_string_lossy()
+                .into_owned(),
+        };
+
+        // ask again with more room when the user is in more groups
+        let mut groups: Vec<libc::gid_t> = vec![0; 64];
+        loop {
+            let mut count = groups.len() as libc::c_int;
+            let rc = unsafe {
+                libc::getgrouplist(
+                    c_name.as_ptr(),
+                    passwd.pw_gid,
+                    groups.as_mut_ptr(),
+                    &mut count,
+                )
+            };
+            if rc != -1 {
+                groups.truncate(count as usize);
+                break;
+            }
+            groups.resize(count.max(groups.len() as libc::c_int * 2) as usize, 0);
+        }
+
+        Ok(User {
+            name: name.to_string(),
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+            groups,
+            home: text(passwd.pw_dir),
+            shell: text(passwd.pw_shell),
+        })
+    }
+
+    /// Runs in the forked child: hands the pty on stdin to the user, so
+    /// opening /dev/tty works, and drops to its ids.
+    unsafe fn switch_to(&self) -> io::Result<()> {
+        // like login, the tty group may write to it for write and wall
+        if libc::fchown(0, self.uid, libc::gid_t::MAX) == -1 || libc::fchmod(0, 0o620) == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        if libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) == -1
+            || libc::setgid(self.gid) == -1
+            || libc::setuid(self.uid) == -1
+        {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// Closes every descriptor above the stdio streams except for `kept`, which must be sorted.
+///
+/// The descriptors are marked close-on-exec rather than closed right away, so
+/// the pipe std uses to report a failed exec back to the parent survives
+/// until the exec and spawn failures are still reported.
+unsafe fn close_fds_except(kept: &[RawFd]) {
+    let mut first: RawFd = 3;
+    for &fd in kept {
+        if fd > first {
+            close_range_on_exec(first, fd - 1);
+        }
+        if fd == RawFd::MAX {
+            return;
+        }
+        first = fd + 1;
+    }
+    close_range_on_exec(first, RawFd::MAX);
+}
+
+#[cfg(target_os = "linux")]
+unsafe fn close_range_on_exec(first: RawFd, last: RawFd) {
+    let flags = libc::CLOSE_RANGE_CLOEXEC;
+    let ret = libc::syscall(libc::SYS_close_range, first as u32, last as u32, flags);
+    if ret == -1 {
+        // kernels before 5.11 don't have close_range with CLOSE_RANGE_CLOEXEC
+        close_range_on_exec_fallback(first, last);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+unsafe fn close_range_on_exec(first: RawFd, last: RawFd) {
+    close_range_on_exec_fallback(first, last);
+}
+
+unsafe fn close_range_on_exec_fallback(first: RawFd, last: RawFd) {
+    // every fd is below the limit, walking it doesn't allocate like /dev/fd
+    let open_max = match libc::sysconf(libc::_SC_OPEN_MAX) {
+        -1 => 1024,
+        n => n.min(RawFd::MAX as libc::c_long) as RawFd,
+    };
+    for fd in first..=last.min(open_max - 1) {
+        libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_internal_prefix() {
+        let policy = SpawnPolicy::default();
+        assert!(policy.is_stripped(OsStr::new("RSTMUX_SOCKET")));
+        assert!(!policy.is_stripped(OsStr::new("TERM")));
+        assert!(!policy.is_stripped(OsStr::new("MY_RSTMUX_VAR")));
+    }
+
+    #[test]
+    fn apply_env_removes_inherited_and_explicit() {
+        std::env::set_var("RSTMUX_TEST_INHERITED", "1");
+        let policy = SpawnPolicy {
+            strip_env_prefixes: vec!["RSTMUX_".to_string(), "SECRET_".to_string()],
+            ..SpawnPolicy::default()
+        };
+        let mut cmd = Command::new("true");
+        cmd.env("RSTMUX_SESSION", "main")
+            .env("SECRET_TOKEN", "hunter2")
+            .env("TERM", "xterm");
+        policy.apply_env(&mut cmd);
+
+        let envs: Vec<_> = cmd.get_envs().collect();
+        let value = |key: &str| {
+            envs.iter()
+                .find(|(k, _)| *k == OsStr::new(key))
+                .map(|(_, v)| *v)
+        };
+        assert_eq!(value("RSTMUX_TEST_INHERITED"), Some(None));
+        assert_eq!(value("RSTMUX_SESSION"), Some(None));
+        assert_eq!(value("SECRET_TOKEN"), Some(None));
+        assert_eq!(value("TERM"), Some(Some(OsStr::new("xterm"))));
+    }
+
+    #[test]
+    fn passed_fds_are_checked() {
+        let mut policy = SpawnPolicy::default();
+        policy.pass_fd(7, 3).pass_fd(3, 4);
+        assert!(policy.check_passed_fds().is_ok());
+
+        policy.pass_fd(8, 4);
+        assert!(policy.check_passed_fds().is_err());
+
+        let mut policy = SpawnPolicy::default();
+        policy.pass_fd(7, 1);
+        assert!(policy.check_passed_fds().is_err());
+
+        let mut policy = SpawnPolicy::default();
+        policy.pass_fd(-1, 5);
+        assert!(policy.check_passed_fds().is_err());
+
+        let mut policy = SpawnPolicy::default();
+        for target in 3..(3 + MAX_PASSED_FDS as RawFd + 1) {
+            policy.pass_fd(target, target);
+        }
+        assert!(policy.check_passed_fds().is_err());
+    }
+
+    #[test]
+    fn passed_fds_are_planned_sorted() {
+        let mut policy = SpawnPolicy::default();
+        policy.pass_fd(20, 9).pass_fd(4, 3).pass_fd(30, 5);
+        let plan = policy.plan_passed_fds().unwrap();
+        assert_eq!(plan.targets(), &[3, 5, 9]);
+        assert_eq!(plan.highest, 30);
+    }
+}
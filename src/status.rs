@@ -0,0 +1,164 @@
+use std::ffi::CString;
+
+use crate::terminal::{Attributes, Color, Row};
+use crate::text;
+
+/// The clock format of the right side of the status line, same as tmux.
+pub const CLOCK_FORMAT: &str = "%H:%M %d-%b-%y";
+
+/// A window as shown in the window list.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub index: usize,
+    pub name: String,
+    pub active: bool,
+    /// Holds the marked pane, flagged with an M like in tmux.
+    pub marked: bool,
+    /// The active pane's title, as the application set it with OSC 0 or 2.
+    pub title: String,
+}
+
+/// The line drawn below the pane on every client: the session name and the
+/// window list on the left, the active window's pane title and the
+/// segments of the status-right option on the right.
+#[derive(Debug, Clone)]
+pub struct StatusLine {
+    pub session: String,
+    pub windows: Vec<Window>,
+    pub attrs: Attributes,
+    /// Whether the line is drawn at all, see the `status` option.
+    pub visible: bool,
+    /// Set while the pane reads a password, shown next to the clock.
+    pub secure_input: bool,
+    /// Set while hold-pane keeps the pane's output from clients, it still
+    /// goes into the history. Shown next to the clock too.
+    pub held: bool,
+    /// The text of the status segments, see `segments::Segments`.
+    pub right: String,
+}
+
+impl StatusLine {
+    pub fn new(session: &str, windows: Vec<Window>) -> Self {
+        Self {
+            session: session.to_string(),
+            windows,
+            // black on green
+            attrs: Attributes {
+                fg: Color::Indexed(0),
+                bg: Color::Indexed(2),
+                ..Attributes::default()
+            },
+            visible: true,
+            secure_input: false,
+            held: false,
+            right: clock(CLOCK_FORMAT),
+        }
+    }
+
+    /// The rows the line takes at the bottom of each client.
+    pub fn rows(&self) -> u16 {
+        self.visible as u16
+    }
+
+    /// Renders the line for a client `cols` wide. The title and the segments
+    /// are dropped on narrow clients and the left side is cut off with an
+    /// ellipsis.
+    pub fn render(&self, cols: usize) -> Row {
+        let windows: Vec<String> = self
+            .windows
+            .iter()
+            .map(|w| {
+                let active = if w.active { "*" } else { "" };
+                let marked = if w.marked { "M" } else { "" };
+                format!("{}:{}{}{}", w.index, w.name, active, marked)
+            })
+            .collect();
+        let left = format!("[{}] {}", self.session, windows.join(" "));
+
+        // like tmux, the title is quoted and cut at 21 cells
+        let title = match self.windows.iter().find(|w| w.active) {
+            Some(w) if !w.title.is_empty() => format!(" \"{}\"", text::ellipsize(&w.title, 21)),
+            _ => String::new(),
+        };
+        let mut right = format!("{} {}", title, self.right);
+        if text::width(&right) * 2 > cols {
+            right = format!(" {}", self.right);
+        }
+        if text::width(&right) * 2 > cols {
+            right.clear();
+        }
+        if self.secure_input {
+            right.insert_str(0, " [secure input]");
+        }
+        if self.held {
+            right.insert_str(0, " [held]");
+        }
+
+        let room = cols.saturating_sub(text::width(&right));
+        let line = text::pad(&text::ellipsize(&left, room), room) + &right;
+        Row::from_text(&line, self.attrs, cols)
+    }
+}
+
+/// The local time formatted with strftime.
+pub fn clock(format: &str) -> String {
+    format_time(format, unsafe { libc::time(std::ptr::null_mut()) })
+}
+
+/// Reads a local time as `HH:MM[:SS]` or `YYYY-MM-DD HH:MM[:SS]`, in
+/// seconds since the epoch. A time of day without a date is the last one
+/// up to `now`, yesterday's if today's is still to come.
+pub fn parse_time(text: &str, now: libc::time_t) -> Option<libc::time_t> {
+    let (date, time) = match text.trim().split_once(' ') {
+        Some((date, time)) => (Some(date), time.trim()),
+        None => (None, text.trim()),
+    };
+    let numbers = |text: &str, separator: char| -> Option<Vec<libc::c_int>> {
+        text.split(separator).map(|n| n.parse().ok()).collect()
+    };
+
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&now, &mut tm) }.is_null() {
+        return None;
+    }
+    let [hour, min, sec] = match numbers(time, ':')?[..] {
+        [hour, min] => [hour, min, 0],
+        [hour, min, sec] => [hour, min, sec],
+        _ => return None,
+    };
+    if !(0..24).contains(&hour) || !(0..60).contains(&min) || !(0..61).contains(&sec) {
+        return None;
+    }
+    (tm.tm_hour, tm.tm_min, tm.tm_sec) = (hour, min, sec);
+    if let Some(date) = date {
+        let [year, month, day] = numbers(date, '-')?[..] else {
+            return None;
+        };
+        (tm.tm_year, tm.tm_mon, tm.tm_mday) = (year - 1900, month - 1, day);
+    }
+    // whether daylight saving time is in effect is left to mktime
+    tm.tm_isdst = -1;
+    let time = unsafe { libc::mktime(&mut tm) };
+    match time {
+        -1 => None,
+        time if date.is_none() && time > now => Some(time - 24 * 60 * 60),
+        time => Some(time),
+    }
+}
+
+/// A time in seconds since the epoch formatted with strftime, in local time.
+pub fn format_time(format: &str, time: libc::time_t) -> String {
+    let Ok(format) = CString::new(format) else {
+        return String::new();
+    };
+
+    let mut buf = [0u8; 64];
+    let len = unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&time, &mut tm).is_null() {
+            return String::new();
+        }
+        libc::strftime(buf.as_mut_ptr().cast(), buf.len(), format.as_ptr(), &tm)
+    };
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
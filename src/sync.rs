@@ -0,0 +1,381 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+
+/// The most data put in a single datagram, well below common path MTUs
+/// once the headers and the tag are added.
+pub const MAX_FRAGMENT: usize = 1200;
+
+const NONCE_SIZE: usize = 12;
+
+/// How far behind the newest packet one may arrive and still be taken, in
+/// sequence numbers, the bits of `Channel::seen`.
+const REPLAY_WINDOW: u64 = 64;
+
+/// The secret both ends of a UDP connection share, given to the client out
+/// of band like mosh does, usually over ssh.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SyncKey([u8; 32]);
+
+impl SyncKey {
+    pub fn generate() -> io::Result<Self> {
+        let mut key = [0u8; 32];
+        File::open("/dev/urandom")?.read_exact(&mut key)?;
+        Ok(SyncKey(key))
+    }
+
+    /// Parses the key as printed, 64 hex digits.
+    pub fn parse(hex: &str) -> Result<Self, String> {
+        let invalid = || "invalid key".to_string();
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(invalid());
+        }
+        let mut key = [0u8; 32];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(SyncKey(key))
+    }
+}
+
+impl fmt::Display for SyncKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.iter().try_for_each(|byte| write!(f, "{:02x}", byte))
+    }
+}
+
+/// Which way a packet goes. It is part of the nonce, so the two ends never
+/// use the same one with the shared key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    ToServer,
+    ToClient,
+}
+
+/// Encrypts the packets one end sends and authenticates the ones it
+/// receives. Each packet carries its sequence number as the nonce. Packets
+/// that arrive out of order are taken like IPsec does, within a window
+/// behind the newest one, replayed and very late ones are dropped.
+pub struct Channel {
+    cipher: ChaCha20Poly1305,
+    outgoing: Direction,
+    sent: u64,
+    /// The newest sequence number received.
+    received: u64,
+    /// Which of the sequence numbers up to `received` arrived, the lowest
+    /// bit is `received` itself.
+    seen: u64,
+}
+
+impl Channel {
+    pub fn new(key: &SyncKey, outgoing: Direction) -> Self {
+        Channel {
+            cipher: ChaCha20Poly1305::new(&key.0.into()),
+            outgoing,
+            sent: 0,
+            received: 0,
+            // nothing is sent with sequence number 0
+            seen: 1,
+        }
+    }
+
+    pub fn seal(&mut self, payload: &[u8]) -> Vec<u8> {
+        self.sent += 1;
+        let nonce = nonce(self.outgoing, self.sent);
+        let mut packet = nonce.to_vec();
+        // encrypting into a Vec only fails on payloads of gigabytes
+        let sealed = self.cipher.encrypt(&Nonce::from(nonce), payload);
+        packet.extend(sealed.unwrap_or_default());
+        packet
+    }
+
+    /// The payload of a packet from the other end, None if it wasn't sent
+    /// with the key, arrived before or is too far behind the newest one.
+    pub fn open(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let (nonce, sealed) = packet.split_first_chunk::<NONCE_SIZE>()?;
+        let incoming = match self.outgoing {
+            Direction::ToServer => Direction::ToClient,
+            Direction::ToClient => Direction::ToServer,
+        };
+        let seq = u64::from_be_bytes(nonce[4..].try_into().ok()?);
+        if *nonce != self::nonce(incoming, seq) || self.was_seen(seq) {
+            return None;
+        }
+        let payload = self.cipher.decrypt(&Nonce::from(*nonce), sealed).ok()?;
+        self.mark_seen(seq);
+        Some(payload)
+    }
+
+    /// Whether the packet numbered `seq` arrived already, or would be too
+    /// late to tell.
+    fn was_seen(&self, seq: u64) -> bool {
+        match self.received.checked_sub(seq) {
+            None => false,
+            Some(behind) if behind >= REPLAY_WINDOW => true,
+            Some(behind) => self.seen & (1 << behind) != 0,
+        }
+    }
+
+    fn mark_seen(&mut self, seq: u64) {
+        match seq.checked_sub(self.received) {
+            Some(ahead) if ahead >= REPLAY_WINDOW => {
+                self.seen = 1;
+                self.received = seq;
+            }
+            Some(ahead) if ahead > 0 => {
+                self.seen = self.seen << ahead | 1;
+                self.received = seq;
+            }
+            _ => self.seen |= 1 << (self.received - seq),
+        }
+    }
+}
+
+fn nonce(direction: Direction, seq: u64) -> [u8; NONCE_SIZE] {
+    let mut nonce = [0u8; NONCE_SIZE];
+    nonce[0] = direction as u8;
+    nonce[4..].copy_from_slice(&seq.to_be_bytes());
+    nonce
+}
+
+/// What a client sends: the last state it shows and the input from
+/// `offset` on that the server hasn't acknowledged yet. Input is the
+/// client's messages to the server as written to the session socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InputPacket {
+    pub state_ack: u64,
+    /// The client's screen doesn't match the checksum of the state it
+    /// shows, it wants to be sent a redraw.
+    pub resync: bool,
+    pub offset: u64,
+    pub data: Vec<u8>,
+}
+
+impl InputPacket {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend(self.state_ack.to_be_bytes());
+        buf.push(self.resync as u8);
+        buf.extend(self.offset.to_be_bytes());
+        buf.extend(&self.data);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let mut reader = Reader(buf);
+        Some(InputPacket {
+            state_ack: reader.u64()?,
+            resync: reader.u8()? != 0,
+            offset: reader.u64()?,
+            data: reader.0.to_vec(),
+        })
+    }
+}
+
+/// A piece of what the server sends: how the client's screen goes from
+/// state `base` to state `num`, with `base` 0 for a redraw from scratch.
+/// Packets with `count` 0 only acknowledge input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StatePacket {
+    pub num: u64,
+    pub base: u64,
+    /// How much of the client's input has arrived.
+    pub input_ack: u64,
+    /// The `Frame::checksum` of state `num`, 0 before the first.
+    pub checksum: u64,
+    /// The session is over, `data` says why.
+    pub ended: bool,
+    pub index: u16,
+    pub count: u16,
+    pub data: Vec<u8>,
+}
+
+impl StatePacket {
+    /// Splits an update into packets that fit in datagrams.
+    pub fn fragments(
+        num: u64,
+        base: u64,
+        input_ack: u64,
+        checksum: u64,
+        ended: bool,
+        data: &[u8],
+    ) -> Vec<Self> {
+        let chunks: Vec<&[u8]> = match data.is_empty() {
+            true => vec![&[]],
+            false => data.chunks(MAX_FRAGMENT).collect(),
+        };
+        let count = chunks.len() as u16;
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, data)| StatePacket {
+                num,
+                base,
+                input_ack,
+                checksum,
+                ended,
+                index: index as u16,
+                count,
+                data: data.to_vec(),
+            })
+            .collect()
+    }
+
+    /// A packet that only acknowledges input, and tells the client what
+    /// the state it shows should look like.
+    pub fn ack(num: u64, input_ack: u64, checksum: u64) -> Self {
+        StatePacket {
+            num,
+            base: num,
+            input_ack,
+            checksum,
+            ended: false,
+            index: 0,
+            count: 0,
+            data: vec![],
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        buf.extend(self.num.to_be_bytes());
+        buf.extend(self.base.to_be_bytes());
+        buf.extend(self.input_ack.to_be_bytes());
+        buf.extend(self.checksum.to_be_bytes());
+        buf.push(self.ended as u8);
+        buf.extend(self.index.to_be_bytes());
+        buf.extend(self.count.to_be_bytes());
+        buf.extend(&self.data);
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let mut reader = Reader(buf);
+        Some(StatePacket {
+            num: reader.u64()?,
+            base: reader.u64()?,
+            input_ack: reader.u64()?,
+            checksum: reader.u64()?,
+            ended: reader.u8()? != 0,
+            index: reader.u16()?,
+            count: reader.u16()?,
+            data: reader.0.to_vec(),
+        })
+    }
+}
+
+/// A state update put back together from its packets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Update {
+    pub num: u64,
+    pub base: u64,
+    pub ended: bool,
+    pub data: Vec<u8>,
+}
+
+/// Collects the packets of the newest update, packets of an older one are
+/// dropped once a newer one starts arriving.
+#[derive(Debug, Default)]
+pub struct Assembler {
+    key: (u64, u64),
+    parts: Vec<Option<Vec<u8>>>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a packet, returning the update once all of it has arrived.
+    pub fn add(&mut self, packet: StatePacket) -> Option<Update> {
+        let key = (packet.num, packet.base);
+        if packet.count == 0 || key < self.key {
+            return None;
+        }
+        if key != self.key || self.parts.len() != packet.count as usize {
+            self.key = key;
+            self.parts = vec![None; packet.count as usize];
+        }
+        *self.parts.get_mut(packet.index as usize)? = Some(packet.data);
+        if self.parts.iter().any(Option::is_none) {
+            return None;
+        }
+        let data = self.parts.drain(..).flatten().flatten().collect();
+        Some(Update {
+            num: packet.num,
+            base: packet.base,
+            ended: packet.ended,
+            data,
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+    fn take<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (bytes, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.take::<1>().map(|[b]| b)
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        self.take().map(u16::from_be_bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        self.take().map(u64::from_be_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn channels() -> (Channel, Channel) {
+        let key = SyncKey::parse(&"ab".repeat(32)).unwrap();
+        let server = Channel::new(&key, Direction::ToClient);
+        let client = Channel::new(&key, Direction::ToServer);
+        (server, client)
+    }
+
+    #[test]
+    fn reordered_packets_are_taken_once() {
+        let (mut server, mut client) = channels();
+        let packets: Vec<Vec<u8>> = (0..3u8).map(|i| server.seal(&[i])).collect();
+        assert_eq!(client.open(&packets[2]), Some(vec![2]));
+        assert_eq!(client.open(&packets[0]), Some(vec![0]));
+        assert_eq!(client.open(&packets[1]), Some(vec![1]));
+        for packet in &packets {
+            assert_eq!(client.open(packet), None);
+        }
+    }
+
+    #[test]
+    fn packets_behind_the_window_are_dropped() {
+        let (mut server, mut client) = channels();
+        let late = server.seal(b"late");
+        let packets: Vec<Vec<u8>> = (0..REPLAY_WINDOW).map(|_| server.seal(b"")).collect();
+        assert!(client.open(packets.last().unwrap()).is_some());
+        assert_eq!(client.open(&late), None);
+        assert!(client.open(&packets[0]).is_some());
+    }
+
+    #[test]
+    fn packets_with_another_key_are_dropped() {
+        let (_, mut client) = channels();
+        let other = SyncKey::parse(&"cd".repeat(32)).unwrap();
+        let packet = Channel::new(&other, Direction::ToClient).seal(b"x");
+        assert_eq!(client.open(&packet), None);
+        // a forged packet doesn't take the sequence number either
+        let (mut server, _) = channels();
+        assert_eq!(client.open(&server.seal(b"y")), Some(b"y".to_vec()));
+    }
+}
@@ -0,0 +1,122 @@
+mod capture;
+mod grid;
+mod narrate;
+mod parser;
+mod render;
+mod screen;
+
+pub use capture::capture;
+pub use grid::{Attributes, Cell, Color, Grid, Row};
+pub use narrate::Narrator;
+pub use parser::{Parser, Perform};
+pub use render::{sgr, Frame};
+pub use screen::{CommandRecord, Cursor, Modes, MouseMode, Screen, DEFAULT_HISTORY_LIMIT};
+
+use regex::Regex;
+
+pub const DEFAULT_ROWS: u16 = 24;
+pub const DEFAULT_COLS: u16 = 80;
+
+/// A line of the screen or its history that matched a search.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SearchMatch {
+    /// The index of the line's first row in `Screen::lines`, oldest first.
+    pub line: usize,
+    pub text: String,
+}
+
+/// The server's model of a pty: every byte read from the pty is fed through
+/// the parser into the screen, so the current state can be inspected or
+/// redrawn at any time.
+pub struct Terminal {
+    parser: Parser,
+    screen: Screen,
+}
+
+impl Terminal {
+    pub fn new(rows: u16, cols: u16) -> Self {
+        Self {
+            parser: Parser::new(),
+            screen: Screen::new(rows as usize, cols as usize),
+        }
+    }
+
+    pub fn process(&mut self, bytes: &[u8]) {
+        self.parser.advance(&mut self.screen, bytes);
+    }
+
+    pub fn resize(&mut self, rows: u16, cols: u16) {
+        self.screen.resize(rows as usize, cols as usize);
+    }
+
+    /// What a client of the given size shows, with `footer` below the screen.
+    pub fn frame(&self, rows: u16, cols: u16, footer: &[Row]) -> Frame {
+        Frame::compose(&self.screen, rows as usize, cols as usize, footer)
+    }
+
+    /// The screen's cells and their attributes as stable text, see `capture`.
+    pub fn capture(&self) -> String {
+        capture::capture(&self.screen)
+    }
+
+    /// The history and the screen as text to print elsewhere, see `capture::replay`.
+    pub fn replay(&self) -> String {
+        capture::replay(&self.screen)
+    }
+
+    /// Answers to queries the application made, to be written to the pty.
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        self.screen.take_replies()
+    }
+
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.screen.set_history_limit(limit);
+    }
+
+    pub fn set_monitor_bell(&mut self, on: bool) {
+        self.screen.set_monitor_bell(on);
+    }
+
+    pub fn set_timestamps(&mut self, on: bool) {
+        self.screen.set_timestamps(on);
+    }
+
+    /// Searches the history and the screen, soft wrapped rows are joined
+    /// so a match can span them.
+    pub fn search(&self, pattern: &Regex) -> Vec<SearchMatch> {
+        let mut matches = vec![];
+        let mut text = String::new();
+        let mut start = None;
+        let mut rows = self.screen.lines().enumerate().peekable();
+        while let Some((i, row)) = rows.next() {
+            start.get_or_insert(i);
+
+            // the trailing blanks of a wrapped row are part of the line
+            if row.wrapped && rows.peek().is_some() {
+                text.extend(row.cells.iter().filter(|c| c.width > 0).map(|c| c.c));
+                continue;
+            }
+            text.push_str(&row.text());
+
+            let line = start.take().unwrap_or(i);
+            if pattern.is_match(&text) {
+                matches.push(SearchMatch {
+                    line,
+                    text: text.clone(),
+                });
+            }
+            text.clear();
+        }
+        matches
+    }
+
+    pub fn screen(&self) -> &Screen {
+        &self.screen
+    }
+}
+
+impl Default for Terminal {
+    fn default() -> Self {
+        Self::new(DEFAULT_ROWS, DEFAULT_COLS)
+    }
+}
@@ -0,0 +1,172 @@
+use std::fmt::Write;
+
+use super::grid::{Attributes, Cell, Color, Row};
+use super::render::sgr;
+use super::screen::Screen;
+
+/// Serializes the screen into a stable, line oriented text form that diffs
+/// well, for golden files: a header with the size and the cursor, then each
+/// row between bars followed by the runs of styled cells on it, if any.
+///
+/// ```text
+/// size 20x3 cursor 1,6
+///   0|hello world         |
+///    |0-4 bold fg=1
+///   1|$ ls                |
+///   2|                    |
+/// ```
+pub fn capture(screen: &Screen) -> String {
+    let cursor = screen.cursor();
+    let mut out = String::new();
+    let _ = write!(
+        out,
+        "size {}x{} cursor {},{}",
+        screen.cols(),
+        screen.rows(),
+        cursor.row,
+        cursor.col
+    );
+    if !screen.modes().cursor_visible {
+        out.push_str(" hidden");
+    }
+    out.push('\n');
+
+    for r in 0..screen.rows() {
+        let row = screen.grid().row(r);
+        let text: String = row
+            .cells
+            .iter()
+            .filter(|cell| cell.width > 0)
+            .map(|cell| if cell.c.is_control() { '?' } else { cell.c })
+            .collect();
+        let _ = writeln!(out, "{:>3}|{}|", r, text);
+
+        let runs = styled_runs(row);
+        if !runs.is_empty() {
+            let _ = writeln!(out, "   |{}", runs.join("; "));
+        }
+    }
+    out
+}
+
+/// The history and the screen as text with SGR sequences for the cells'
+/// attributes, each line ending in CRLF unless it was soft wrapped. Fed to
+/// a terminal of the same width it prints the same lines, with the cursor
+/// at the start of the line below the last that isn't blank.
+pub fn replay(screen: &Screen) -> String {
+    let mut rows: Vec<&Row> = screen.lines().collect();
+    while rows
+        .last()
+        .is_some_and(|row| row.cells.iter().all(Cell::is_blank))
+    {
+        rows.pop();
+    }
+
+    let mut out = String::new();
+    for row in rows {
+        // a wrapped row is printed in full so the next one wraps onto a new line
+        let end = match row.wrapped {
+            true => row.cells.len(),
+            false => row
+                .cells
+                .iter()
+                .rposition(|cell| !cell.is_blank())
+                .map_or(0, |i| i + 1),
+        };
+        let mut attrs = Attributes::default();
+        for cell in row.cells[..end].iter().filter(|cell| cell.width > 0) {
+            if cell.attrs != attrs {
+                attrs = cell.attrs;
+                out.push_str(&sgr(&attrs));
+            }
+            out.push(if cell.c.is_control() { '?' } else { cell.c });
+        }
+        if attrs != Attributes::default() {
+            out.push_str("\x1b[0m");
+        }
+        if !row.wrapped {
+            out.push_str("\r\n");
+        }
+    }
+    out
+}
+
+/// The runs of cells with the same non-default attributes, like `0-4 bold`.
+fn styled_runs(row: &Row) -> Vec<String> {
+    let mut runs = vec![];
+    let mut start = 0;
+    for (i, cell) in row.cells.iter().enumerate() {
+        let next = row.cells.get(i + 1).map(|next| next.attrs);
+        if next == Some(cell.attrs) {
+            continue;
+        }
+        if cell.attrs != Attributes::default() {
+            runs.push(format!("{}-{} {}", start, i, describe(&cell.attrs)));
+        }
+        start = i + 1;
+    }
+    runs
+}
+
+fn describe(attrs: &Attributes) -> String {
+    let flags = [
+        (attrs.bold, "bold"),
+        (attrs.dim, "dim"),
+        (attrs.italic, "italic"),
+        (attrs.underline, "underline"),
+        (attrs.blink, "blink"),
+        (attrs.reverse, "reverse"),
+        (attrs.hidden, "hidden"),
+        (attrs.strikethrough, "strike"),
+    ];
+    let mut words: Vec<String> = flags
+        .iter()
+        .filter(|(enabled, _)| *enabled)
+        .map(|(_, name)| name.to_string())
+        .collect();
+    if let Some(fg) = describe_color(attrs.fg) {
+        words.push(format!("fg={}", fg));
+    }
+    if let Some(bg) = describe_color(attrs.bg) {
+        words.push(format!("bg={}", bg));
+    }
+    words.join(" ")
+}
+
+fn describe_color(color: Color) -> Option<String> {
+    match color {
+        Color::Default => None,
+        Color::Indexed(i) => Some(i.to_string()),
+        Color::Rgb(r, g, b) => Some(format!("#{:02x}{:02x}{:02x}", r, g, b)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terminal::Terminal;
+
+    #[test]
+    fn captures_text_styles_and_cursor() {
+        let mut terminal = Terminal::new(3, 12);
+        terminal.process(b"\x1b[1;31mhello\x1b[0m world\r\n$ ls\x1b[44m \x1b[0m");
+        let expected = "\
+size 12x3 cursor 1,5
+  0|hello world |
+   |0-4 bold fg=1
+  1|$ ls        |
+   |4-4 bg=4
+  2|            |
+";
+        assert_eq!(terminal.capture(), expected);
+    }
+
+    #[test]
+    fn captures_a_hidden_cursor_after_moving_it() {
+        let mut terminal = Terminal::new(2, 4);
+        terminal.process(b"ab\x1b[2;3Hc\x1b[?25l");
+        assert_eq!(
+            terminal.capture(),
+            "size 4x2 cursor 1,3 hidden\n  0|ab  |\n  1|  c |\n"
+        );
+    }
+}
@@ -0,0 +1,232 @@
+use std::ops::Range;
+use std::time::SystemTime;
+
+use regex::Regex;
+use unicode_width::UnicodeWidthChar;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Default,
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+/// The rendition of a cell, as set by SGR sequences.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Attributes {
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub dim: bool,
+    pub italic: bool,
+    pub underline: bool,
+    pub blink: bool,
+    pub reverse: bool,
+    pub hidden: bool,
+    pub strikethrough: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cell {
+    pub c: char,
+    /// 1 for most characters, 2 for wide characters and 0 for the cell
+    /// covered by the right half of a wide character.
+    pub width: u8,
+    pub attrs: Attributes,
+}
+
+impl Cell {
+    pub fn blank(attrs: Attributes) -> Self {
+        // erased cells keep the background color only
+        let attrs = Attributes {
+            bg: attrs.bg,
+            ..Attributes::default()
+        };
+        Self {
+            c: ' ',
+            width: 1,
+            attrs,
+        }
+    }
+
+    pub fn is_blank(&self) -> bool {
+        self.c == ' ' && self.attrs == Attributes::default()
+    }
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self::blank(Attributes::default())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Row {
+    pub cells: Vec<Cell>,
+    /// The line continues on the next row because it was soft wrapped.
+    pub wrapped: bool,
+    /// When output first went to the row, if the screen records it, see
+    /// `Screen::set_timestamps`. It goes into the history with the row.
+    pub time: Option<SystemTime>,
+}
+
+impl Row {
+    pub fn new(cols: usize) -> Self {
+        Self {
+            cells: vec![Cell::default(); cols],
+            wrapped: false,
+            time: None,
+        }
+    }
+
+    pub fn resize(&mut self, cols: usize) {
+        self.cells.resize(cols, Cell::default());
+    }
+
+    pub fn clear(&mut self, attrs: Attributes) {
+        self.cells.fill(Cell::blank(attrs));
+        self.wrapped = false;
+        self.time = None;
+    }
+
+    /// The text of the row without trailing blanks.
+    pub fn text(&self) -> String {
+        let mut text: String = self
+            .cells
+            .iter()
+            .filter(|cell| cell.width > 0)
+            .map(|cell| cell.c)
+            .collect();
+        text.truncate(text.trim_end().len());
+        text
+    }
+
+    /// The columns each match of `pattern` in the row's text covers, a wide
+    /// character covers both of its columns.
+    pub fn find(&self, pattern: &Regex) -> Vec<Range<usize>> {
+        let mut text = String::new();
+        let mut columns = vec![];
+        for (col, cell) in self.cells.iter().enumerate().filter(|(_, c)| c.width > 0) {
+            columns.push((text.len(), col));
+            text.push(cell.c);
+        }
+        let column = |offset: usize| {
+            let next = columns.iter().find(|(start, _)| *start >= offset);
+            next.map_or(self.cells.len(), |(_, col)| *col)
+        };
+        pattern
+            .find_iter(&text)
+            .filter(|m| !m.is_empty())
+            .map(|m| column(m.start())..column(m.end()))
+            .collect()
+    }
+
+    /// A row of `cols` cells showing `text` in `attrs`, padded with blanks in
+    /// the same attributes. Text that doesn't fit is cut off.
+    pub fn from_text(text: &str, attrs: Attributes, cols: usize) -> Self {
+        let fill = Cell {
+            c: ' ',
+            width: 1,
+            attrs,
+        };
+        let mut cells = Vec::with_capacity(cols);
+        for c in text.chars() {
+            let width = match c.width() {
+                Some(width @ 1..=2) => width,
+                _ => continue,
+            };
+            if cells.len() + width > cols {
+                break;
+            }
+            cells.push(Cell {
+                c,
+                width: width as u8,
+                attrs,
+            });
+            if width == 2 {
+                cells.push(Cell { width: 0, ..fill });
+            }
+        }
+        cells.resize(cols, fill);
+        Self {
+            cells,
+            wrapped: false,
+            time: None,
+        }
+    }
+}
+
+/// A fixed size matrix of cells.
+#[derive(Debug, Clone)]
+pub struct Grid {
+    rows: Vec<Row>,
+    cols: usize,
+}
+
+impl Grid {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        Self {
+            rows: (0..rows).map(|_| Row::new(cols)).collect(),
+            cols,
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn row(&self, row: usize) -> &Row {
+        &self.rows[row]
+    }
+
+    pub fn row_mut(&mut self, row: usize) -> &mut Row {
+        &mut self.rows[row]
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Row> {
+        self.rows.iter()
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        for row in self.rows.iter_mut() {
+            row.resize(cols);
+        }
+        self.rows.resize_with(rows, || Row::new(cols));
+        self.cols = cols;
+    }
+
+    /// Scrolls the rows in `top..=bottom` up by `count`, returning the rows
+    /// that scrolled off the top of the region.
+    pub fn scroll_up(
+        &mut self,
+        top: usize,
+        bottom: usize,
+        count: usize,
+        attrs: Attributes,
+    ) -> Vec<Row> {
+        let count = count.min(bottom + 1 - top);
+        let mut scrolled = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut row = self.rows.remove(top);
+            scrolled.push(row.clone());
+            row.clear(attrs);
+            self.rows.insert(bottom, row);
+        }
+        scrolled
+    }
+
+    /// Scrolls the rows in `top..=bottom` down by `count`.
+    pub fn scroll_down(&mut self, top: usize, bottom: usize, count: usize, attrs: Attributes) {
+        let count = count.min(bottom + 1 - top);
+        for _ in 0..count {
+            let mut row = self.rows.remove(bottom);
+            row.clear(attrs);
+            self.rows.insert(top, row);
+        }
+    }
+}
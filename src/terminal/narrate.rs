@@ -0,0 +1,64 @@
+use std::collections::BTreeMap;
+
+use super::grid::Row;
+use super::screen::Screen;
+
+/// Turns screen updates into lines of newly printed text, for speech tools
+/// and other frontends that can't make sense of cursor addressed repaints.
+///
+/// Lines are numbered by `Screen::scrolled`, so text that scrolls up is not
+/// repeated. When text is appended to a line only the new part is told,
+/// anything else that changed is told as the whole line.
+#[derive(Debug, Default)]
+pub struct Narrator {
+    /// What was last told of each visible line, by line number.
+    told: BTreeMap<u64, String>,
+}
+
+impl Narrator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The text printed since the last call, oldest first.
+    pub fn narrate(&mut self, screen: &Screen) -> Vec<String> {
+        let top = screen.scrolled();
+        let mut lines = vec![];
+
+        // lines that scrolled off since, as far as the history still has them
+        let first_told = self.told.keys().next().copied().unwrap_or(top);
+        if top > first_told && !screen.is_alternate() {
+            let missed = ((top - first_told) as usize).min(screen.history().len());
+            let history = screen
+                .history()
+                .iter()
+                .skip(screen.history().len() - missed);
+            for (i, row) in history.enumerate() {
+                self.tell(top - missed as u64 + i as u64, row, &mut lines);
+            }
+        }
+        self.told = self.told.split_off(&top);
+
+        for r in 0..screen.rows() {
+            self.tell(top + r as u64, screen.grid().row(r), &mut lines);
+        }
+        lines
+    }
+
+    fn tell(&mut self, line: u64, row: &Row, lines: &mut Vec<String>) {
+        let text = row.text();
+        let before = self.told.get(&line).map_or("", String::as_str);
+        if text == before {
+            return;
+        }
+
+        let new = match text.strip_prefix(before) {
+            Some(appended) => appended.trim(),
+            None => text.trim(),
+        };
+        if !new.is_empty() {
+            lines.push(new.to_string());
+        }
+        self.told.insert(line, text);
+    }
+}
@@ -0,0 +1,305 @@
+/// Receives the actions recognized by the [`Parser`].
+pub trait Perform {
+    /// A printable character should be drawn at the cursor.
+    fn print(&mut self, c: char);
+    /// A C0 or C1 control function (LF, CR, BS, ...) should be executed.
+    fn execute(&mut self, byte: u8);
+    /// A complete control sequence (`CSI ... final`) was received.
+    fn csi_dispatch(&mut self, params: &[u16], intermediates: &[u8], ignore: bool, action: char);
+    /// A complete escape sequence (`ESC ... final`) was received.
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8);
+    /// A complete operating system command (`OSC ... ST`) was received,
+    /// split on `;` into its parameters.
+    fn osc_dispatch(&mut self, params: &[&[u8]]);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    CsiEntry,
+    CsiParam,
+    CsiIntermediate,
+    CsiIgnore,
+    OscString,
+    /// DCS, SOS, PM and APC strings are consumed and ignored.
+    IgnoredString,
+}
+
+const MAX_PARAMS: usize = 32;
+const MAX_INTERMEDIATES: usize = 2;
+const MAX_OSC_SIZE: usize = 4096;
+
+/// A VT500 style escape sequence parser.
+///
+/// The parser is fed raw bytes and keeps its state between calls, so a
+/// sequence (or a multibyte UTF-8 character) split across two reads is still
+/// recognized correctly.
+pub struct Parser {
+    state: State,
+    params: Vec<u16>,
+    current_param: Option<u16>,
+    intermediates: Vec<u8>,
+    ignoring: bool,
+    osc: Vec<u8>,
+    string_escape: bool,
+    utf8: Vec<u8>,
+    utf8_len: usize,
+}
+
+impl Parser {
+    pub fn new() -> Self {
+        Self {
+            state: State::Ground,
+            params: Vec::with_capacity(MAX_PARAMS),
+            current_param: None,
+            intermediates: Vec::with_capacity(MAX_INTERMEDIATES),
+            ignoring: false,
+            osc: vec![],
+            string_escape: false,
+            utf8: Vec::with_capacity(4),
+            utf8_len: 0,
+        }
+    }
+
+    pub fn advance<P: Perform>(&mut self, performer: &mut P, bytes: &[u8]) {
+        for &byte in bytes {
+            self.advance_byte(performer, byte);
+        }
+    }
+
+    fn advance_byte<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        // finish any multibyte character first
+        if self.utf8_len > 0 {
+            if byte & 0xC0 == 0x80 {
+                self.utf8.push(byte);
+                if self.utf8.len() == self.utf8_len {
+                    let c = std::str::from_utf8(&self.utf8)
+                        .ok()
+                        .and_then(|s| s.chars().next())
+                        .unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.utf8.clear();
+                    self.utf8_len = 0;
+                    performer.print(c);
+                }
+                return;
+            }
+
+            // truncated sequence, replace it and process this byte normally
+            self.utf8.clear();
+            self.utf8_len = 0;
+            performer.print(char::REPLACEMENT_CHARACTER);
+        }
+
+        // these are recognized in every state
+        match byte {
+            0x18 | 0x1A => {
+                self.state = State::Ground;
+                performer.execute(byte);
+                return;
+            }
+            0x1B if !matches!(self.state, State::OscString | State::IgnoredString) => {
+                self.enter_escape();
+                return;
+            }
+            _ => {}
+        }
+
+        match self.state {
+            State::Ground => self.ground(performer, byte),
+            State::Escape => self.escape(performer, byte),
+            State::EscapeIntermediate => self.escape_intermediate(performer, byte),
+            State::CsiEntry | State::CsiParam => self.csi_param(performer, byte),
+            State::CsiIntermediate => self.csi_intermediate(performer, byte),
+            State::CsiIgnore => self.csi_ignore(performer, byte),
+            State::OscString => self.osc_string(performer, byte),
+            State::IgnoredString => self.ignored_string(byte),
+        }
+    }
+
+    fn ground<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x1F => performer.execute(byte),
+            0x20..=0x7E => performer.print(byte as char),
+            0x7F => {} // DEL is ignored
+            0xC2..=0xDF => self.start_utf8(byte, 2),
+            0xE0..=0xEF => self.start_utf8(byte, 3),
+            0xF0..=0xF4 => self.start_utf8(byte, 4),
+            _ => performer.print(char::REPLACEMENT_CHARACTER),
+        }
+    }
+
+    fn start_utf8(&mut self, byte: u8, len: usize) {
+        self.utf8.push(byte);
+        self.utf8_len = len;
+    }
+
+    fn enter_escape(&mut self) {
+        self.state = State::Escape;
+        self.intermediates.clear();
+        self.params.clear();
+        self.current_param = None;
+        self.ignoring = false;
+    }
+
+    fn escape<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x1F => performer.execute(byte),
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::EscapeIntermediate;
+            }
+            b'[' => self.state = State::CsiEntry,
+            b']' => {
+                self.osc.clear();
+                self.string_escape = false;
+                self.state = State::OscString;
+            }
+            b'P' | b'X' | b'^' | b'_' => {
+                self.string_escape = false;
+                self.state = State::IgnoredString;
+            }
+            0x7F => {}
+            _ => {
+                performer.esc_dispatch(&self.intermediates, byte);
+                self.state = State::Ground;
+            }
+        }
+    }
+
+    fn escape_intermediate<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x1F => performer.execute(byte),
+            0x20..=0x2F => self.collect(byte),
+            0x7F => {}
+            _ => {
+                performer.esc_dispatch(&self.intermediates, byte);
+                self.state = State::Ground;
+            }
+        }
+    }
+
+    fn csi_param<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x1F => performer.execute(byte),
+            b'0'..=b'9' => {
+                let digit = (byte - b'0') as u16;
+                let param = self.current_param.unwrap_or(0);
+                self.current_param = Some(param.saturating_mul(10).saturating_add(digit));
+                self.state = State::CsiParam;
+            }
+            b';' | b':' => {
+                self.push_param();
+                self.state = State::CsiParam;
+            }
+            b'<'..=b'?' => {
+                // private markers are only valid at the start of the sequence
+                if self.state == State::CsiEntry {
+                    self.collect(byte);
+                } else {
+                    self.state = State::CsiIgnore;
+                }
+            }
+            0x20..=0x2F => {
+                self.collect(byte);
+                self.state = State::CsiIntermediate;
+            }
+            0x40..=0x7E => self.csi_dispatch(performer, byte),
+            _ => {}
+        }
+    }
+
+    fn csi_intermediate<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x1F => performer.execute(byte),
+            0x20..=0x2F => self.collect(byte),
+            0x30..=0x3F => self.state = State::CsiIgnore,
+            0x40..=0x7E => self.csi_dispatch(performer, byte),
+            _ => {}
+        }
+    }
+
+    fn csi_ignore<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            0x00..=0x1F => performer.execute(byte),
+            0x40..=0x7E => self.state = State::Ground,
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        if self.current_param.is_some() || !self.params.is_empty() {
+            self.push_param();
+        }
+        performer.csi_dispatch(
+            &self.params,
+            &self.intermediates,
+            self.ignoring,
+            byte as char,
+        );
+        self.state = State::Ground;
+    }
+
+    fn osc_string<P: Perform>(&mut self, performer: &mut P, byte: u8) {
+        match byte {
+            // BEL terminates the string as well as ST
+            0x07 => self.osc_dispatch(performer),
+            0x1B => self.string_escape = true,
+            b'\\' if self.string_escape => self.osc_dispatch(performer),
+            _ if self.string_escape => {
+                // ESC followed by anything else aborts the string
+                self.string_escape = false;
+                self.enter_escape();
+                self.advance_byte(performer, byte);
+            }
+            _ => {
+                if self.osc.len() < MAX_OSC_SIZE {
+                    self.osc.push(byte);
+                }
+            }
+        }
+    }
+
+    fn osc_dispatch<P: Perform>(&mut self, performer: &mut P) {
+        let params: Vec<&[u8]> = self.osc.split(|&b| b == b';').collect();
+        performer.osc_dispatch(&params);
+        self.string_escape = false;
+        self.state = State::Ground;
+    }
+
+    fn ignored_string(&mut self, byte: u8) {
+        match byte {
+            0x07 => self.state = State::Ground,
+            0x1B => self.string_escape = true,
+            b'\\' if self.string_escape => {
+                self.string_escape = false;
+                self.state = State::Ground;
+            }
+            _ => self.string_escape = false,
+        }
+    }
+
+    fn collect(&mut self, byte: u8) {
+        if self.intermediates.len() < MAX_INTERMEDIATES {
+            self.intermediates.push(byte);
+        } else {
+            self.ignoring = true;
+        }
+    }
+
+    fn push_param(&mut self) {
+        if self.params.len() < MAX_PARAMS {
+            self.params.push(self.current_param.unwrap_or(0));
+        } else {
+            self.ignoring = true;
+        }
+        self.current_param = None;
+    }
+}
+
+impl Default for Parser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
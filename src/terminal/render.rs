@@ -0,0 +1,411 @@
+use std::fmt::Write;
+use std::ops::Range;
+
+use crate::features::{limit_color, ColorDepth};
+
+use super::grid::{Attributes, Cell, Color, Row};
+use super::screen::{Modes, MouseMode, Screen};
+
+/// What a client's terminal shows: the screen clipped or padded to the
+/// client's size, with any rows the server draws itself, like the status
+/// line, below it. Clients are sent the difference between frames instead
+/// of the raw pty output, so the server owns every cell on their terminal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    rows: Vec<Vec<Cell>>,
+    cursor: (usize, usize),
+    cursor_visible: bool,
+    modes: Modes,
+    title: String,
+    bells: u64,
+}
+
+impl Frame {
+    /// Composes a frame of `rows` x `cols` from the screen with `footer`
+    /// taking up the bottom rows.
+    pub fn compose(screen: &Screen, rows: usize, cols: usize, footer: &[Row]) -> Frame {
+        let lines = (0..screen.rows()).map(|r| screen.grid().row(r));
+        let mut frame = Self::compose_rows(screen, lines, rows, cols, footer);
+        let pane_rows = rows.saturating_sub(footer.len());
+        let cursor = screen.cursor();
+        frame.cursor = (
+            cursor.row.min(pane_rows.saturating_sub(1)),
+            cursor.col.min(cols.saturating_sub(1)),
+        );
+        frame.cursor_visible = screen.modes().cursor_visible && pane_rows > 0;
+        frame
+    }
+
+    /// Composes a frame like `compose` from the lines of the screen and its
+    /// history starting at `top`, an index in `Screen::lines`. The cursor is
+    /// hidden until it is placed with `show_cursor`.
+    pub fn compose_history(
+        screen: &Screen,
+        top: usize,
+        rows: usize,
+        cols: usize,
+        footer: &[Row],
+    ) -> Frame {
+        let lines = (top..).map_while(|i| screen.line(i));
+        Self::compose_rows(screen, lines, rows, cols, footer)
+    }
+
+    /// Composes a frame like `compose` from `lines`, which needn't follow
+    /// each other on the screen. The cursor is hidden until it is placed.
+    pub fn compose_rows<'a>(
+        screen: &Screen,
+        lines: impl Iterator<Item = &'a Row>,
+        rows: usize,
+        cols: usize,
+        footer: &[Row],
+    ) -> Frame {
+        let pane_rows = rows.saturating_sub(footer.len());
+        let mut lines: Vec<Vec<Cell>> = lines
+            .take(pane_rows)
+            .map(|row| clip(&row.cells, cols))
+            .collect();
+        lines.resize(pane_rows, vec![Cell::default(); cols]);
+        for row in footer.iter().take(rows - pane_rows) {
+            lines.push(clip(&row.cells, cols));
+        }
+
+        Frame {
+            rows: lines,
+            cursor: (0, 0),
+            cursor_visible: false,
+            modes: *screen.modes(),
+            title: screen.title().to_string(),
+            bells: screen.bells(),
+        }
+    }
+
+    /// This frame's rows clipped or padded to `rows` x `cols` like `compose`
+    /// does with a screen, with `footer` below them. For a frame composed
+    /// without a footer, to be shown again later.
+    pub fn refit(&self, rows: usize, cols: usize, footer: &[Row]) -> Frame {
+        let pane_rows = rows.saturating_sub(footer.len());
+        let mut lines: Vec<Vec<Cell>> = self
+            .rows
+            .iter()
+            .take(pane_rows)
+            .map(|row| clip(row, cols))
+            .collect();
+        lines.resize(pane_rows, vec![Cell::default(); cols]);
+        for row in footer.iter().take(rows - pane_rows) {
+            lines.push(clip(&row.cells, cols));
+        }
+
+        Frame {
+            rows: lines,
+            cursor: (
+                self.cursor.0.min(pane_rows.saturating_sub(1)),
+                self.cursor.1.min(cols.saturating_sub(1)),
+            ),
+            cursor_visible: self.cursor_visible && pane_rows > 0,
+            ..self.clone()
+        }
+    }
+
+    /// Draws `rows` over the middle of the frame, like a message box. The
+    /// cursor is hidden while the box is shown.
+    pub fn overlay(&mut self, rows: &[Row]) {
+        let top = self.rows.len().saturating_sub(rows.len()) / 2;
+        for (i, row) in rows.iter().enumerate() {
+            let cols = self.rows.first().map_or(0, Vec::len);
+            let left = cols.saturating_sub(row.cells.len()) / 2;
+            self.place(top + i, left, row);
+        }
+        self.cursor_visible = false;
+    }
+
+    /// Draws `row` over the frame from `col` of row `r`, cut off at the
+    /// right edge.
+    pub fn place(&mut self, r: usize, col: usize, row: &Row) {
+        let Some(line) = self.rows.get_mut(r) else {
+            return;
+        };
+        let cols = line.len();
+        let left = col.min(cols);
+        let right = (left + row.cells.len()).min(cols);
+        let cells = clip(&row.cells, right - left);
+        // wide characters cut in half by the box become blanks
+        if left > 0 && line[left - 1].width == 2 {
+            line[left - 1] = Cell::blank(line[left - 1].attrs);
+        }
+        if line.get(right).is_some_and(|cell| cell.width == 0) {
+            line[right] = Cell::blank(line[right].attrs);
+        }
+        line[left..right].copy_from_slice(&cells);
+    }
+
+    /// Shows the cursor at `row` and `col`, kept within the frame.
+    pub fn show_cursor(&mut self, row: usize, col: usize) {
+        let cols = self.rows.first().map_or(0, Vec::len);
+        self.cursor = (
+            row.min(self.rows.len().saturating_sub(1)),
+            col.min(cols.saturating_sub(1)),
+        );
+        self.cursor_visible = true;
+    }
+
+    /// Has the client's terminal report the mouse in SGR encoding, buttons
+    /// and dragging at least, whatever the application asked for.
+    pub fn report_mouse(&mut self) {
+        if self.modes.mouse != MouseMode::AnyMotion {
+            self.modes.mouse = MouseMode::ButtonMotion;
+        }
+        self.modes.mouse_sgr = true;
+    }
+
+    /// Swaps the colors of the cells from `start` up to `end` on row `r`,
+    /// the way a selection is shown.
+    pub fn highlight(&mut self, r: usize, start: usize, end: usize) {
+        if let Some(line) = self.rows.get_mut(r) {
+            for cell in line.iter_mut().take(end).skip(start) {
+                cell.attrs.reverse = !cell.attrs.reverse;
+            }
+        }
+    }
+
+    /// Drops colors and every attribute but reverse video, which is often
+    /// all that marks a selection or a cursor drawn by the application.
+    /// Nothing moves, so the cursor stays where it belongs.
+    pub fn strip_style(&mut self) {
+        for cell in self.rows.iter_mut().flatten() {
+            cell.attrs = Attributes {
+                reverse: cell.attrs.reverse,
+                ..Attributes::default()
+            };
+        }
+    }
+
+    /// Replaces the colors a terminal with `depth` can't show with the
+    /// nearest it can.
+    pub fn limit_colors(&mut self, depth: ColorDepth) {
+        for cell in self.rows.iter_mut().flatten() {
+            cell.attrs.fg = limit_color(cell.attrs.fg, depth);
+            cell.attrs.bg = limit_color(cell.attrs.bg, depth);
+        }
+    }
+
+    /// A hash of the cells and the cursor, the same for equal frames on any
+    /// machine, for a replica to check it shows what it was sent. Only the
+    /// visible screen is replicated, so the history isn't part of it.
+    pub fn checksum(&self) -> u64 {
+        // FNV-1a, over the cells the way they are drawn
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut feed = |bytes: &[u8]| {
+            for byte in bytes {
+                hash = (hash ^ *byte as u64).wrapping_mul(0x100_0000_01b3);
+            }
+        };
+        for row in &self.rows {
+            feed(&(row.len() as u32).to_be_bytes());
+            for cell in row {
+                feed(&(cell.c as u32).to_be_bytes());
+                feed(&[cell.width]);
+                feed(sgr(&cell.attrs).as_bytes());
+            }
+        }
+        feed(&(self.cursor.0 as u32).to_be_bytes());
+        feed(&(self.cursor.1 as u32).to_be_bytes());
+        hash
+    }
+
+    /// The escape sequences that turn `previous` into this frame on the
+    /// client, or draw it from scratch if there is no previous frame or its
+    /// size differs. Empty when nothing changed.
+    pub fn render(&self, previous: Option<&Frame>) -> Vec<u8> {
+        let previous = previous.filter(|p| {
+            p.rows.len() == self.rows.len()
+                && p.rows.first().map(Vec::len) == self.rows.first().map(Vec::len)
+        });
+        if previous == Some(self) {
+            return vec![];
+        }
+
+        let mut out = String::new();
+        out.push_str("\x1b[?25l");
+        write_modes(&self.modes, previous.map(|p| &p.modes), &mut out);
+        if previous.is_none_or(|p| p.title != self.title) && !self.title.is_empty() {
+            let _ = write!(out, "\x1b]2;{}\x07", self.title);
+        }
+        if previous.is_none() {
+            out.push_str("\x1b[r\x1b[0m\x1b[H\x1b[2J");
+        }
+
+        for (r, row) in self.rows.iter().enumerate() {
+            match previous {
+                Some(previous) if previous.rows[r] == *row => continue,
+                Some(previous) => write_row_changes(r, row, &previous.rows[r], &mut out),
+                None if row.iter().all(Cell::is_blank) => continue,
+                None => write_row(r, row, &mut out),
+            }
+        }
+
+        if previous.is_some_and(|p| self.bells > p.bells) {
+            out.push('\x07');
+        }
+
+        let (row, col) = self.cursor;
+        let _ = write!(out, "\x1b[{};{}H", row + 1, col + 1);
+        if self.cursor_visible {
+            out.push_str("\x1b[?25h");
+        }
+
+        out.into_bytes()
+    }
+}
+
+/// Clips or pads cells to exactly `cols`, a wide character cut in half by
+/// the edge is replaced with a blank.
+fn clip(cells: &[Cell], cols: usize) -> Vec<Cell> {
+    let mut line: Vec<Cell> = cells.iter().take(cols).copied().collect();
+    if line.last().is_some_and(|cell| cell.width == 2) && cells.len() > cols {
+        let last = line.len() - 1;
+        line[last] = Cell::blank(line[last].attrs);
+    }
+    line.resize(cols, Cell::default());
+    line
+}
+
+fn write_row(r: usize, row: &[Cell], out: &mut String) {
+    write_span(r, row, 0..row.len(), out);
+}
+
+/// Unchanged cells between two changes are written over again when that
+/// is shorter than moving the cursor past them.
+const MAX_SKIP: usize = 8;
+
+/// Writes only the cells of a row that differ from what the client shows.
+fn write_row_changes(r: usize, row: &[Cell], previous: &[Cell], out: &mut String) {
+    let mut spans: Vec<Range<usize>> = vec![];
+    for c in (0..row.len()).filter(|&c| row[c] != previous[c]) {
+        // wide characters are written whole
+        let (start, end) = match row[c].width {
+            0 => (c.saturating_sub(1), c + 1),
+            2 => (c, c + 2),
+            _ => (c, c + 1),
+        };
+        match spans.last_mut() {
+            Some(last) if start <= last.end + MAX_SKIP => last.end = last.end.max(end),
+            _ => spans.push(start..end),
+        }
+    }
+    for span in spans {
+        if write_span(r, row, span.start..span.end.min(row.len()), out) {
+            break; // the rest of the row was cleared
+        }
+    }
+}
+
+/// Writes the cells of `span`, a blank end of the row is cleared rather
+/// than written. Returns whether the rest of the row was cleared.
+fn write_span(r: usize, row: &[Cell], span: Range<usize>, out: &mut String) -> bool {
+    let _ = write!(out, "\x1b[{};{}H", r + 1, span.start + 1);
+    // the right half of a wide character looks blank but isn't
+    let end = row
+        .iter()
+        .rposition(|cell| !cell.is_blank())
+        .map_or(0, |last| last + row[last].width.max(1) as usize)
+        .min(row.len());
+
+    let mut current = None;
+    for cell in &row[span.start..span.end.min(end).max(span.start)] {
+        if cell.width == 0 {
+            continue;
+        }
+        if current != Some(cell.attrs) {
+            out.push_str(&sgr(&cell.attrs));
+            current = Some(cell.attrs);
+        }
+        out.push(cell.c);
+    }
+
+    out.push_str("\x1b[0m");
+    let cleared = span.end > end && end < row.len();
+    if cleared {
+        out.push_str("\x1b[K");
+    }
+    cleared
+}
+
+/// Puts the client terminal in the same input modes as the screen, so keys,
+/// mouse events and pastes are encoded the way the application expects.
+/// Modes that affect drawing stay under the server's control.
+fn write_modes(modes: &Modes, previous: Option<&Modes>, out: &mut String) {
+    let private = [
+        (
+            1,
+            modes.application_cursor,
+            previous.map(|p| p.application_cursor),
+        ),
+        (1004, modes.focus_events, previous.map(|p| p.focus_events)),
+        (1006, modes.mouse_sgr, previous.map(|p| p.mouse_sgr)),
+        (
+            2004,
+            modes.bracketed_paste,
+            previous.map(|p| p.bracketed_paste),
+        ),
+    ];
+    for (mode, enabled, was) in private {
+        if was != Some(enabled) {
+            let _ = write!(out, "\x1b[?{}{}", mode, if enabled { 'h' } else { 'l' });
+        }
+    }
+
+    if previous.map(|p| p.mouse) != Some(modes.mouse) {
+        out.push_str("\x1b[?1000l\x1b[?1002l\x1b[?1003l");
+        match modes.mouse {
+            MouseMode::Off => {}
+            MouseMode::Press => out.push_str("\x1b[?1000h"),
+            MouseMode::ButtonMotion => out.push_str("\x1b[?1002h"),
+            MouseMode::AnyMotion => out.push_str("\x1b[?1003h"),
+        }
+    }
+
+    if previous.map(|p| p.application_keypad) != Some(modes.application_keypad) {
+        out.push_str(if modes.application_keypad {
+            "\x1b="
+        } else {
+            "\x1b>"
+        });
+    }
+}
+
+/// The SGR sequence that selects exactly `attrs`, starting from a reset.
+pub fn sgr(attrs: &Attributes) -> String {
+    let mut params = vec!["0".to_string()];
+    let flags = [
+        (attrs.bold, "1"),
+        (attrs.dim, "2"),
+        (attrs.italic, "3"),
+        (attrs.underline, "4"),
+        (attrs.blink, "5"),
+        (attrs.reverse, "7"),
+        (attrs.hidden, "8"),
+        (attrs.strikethrough, "9"),
+    ];
+    for (enabled, param) in flags {
+        if enabled {
+            params.push(param.to_string());
+        }
+    }
+    if let Some(fg) = color_params(attrs.fg, 30) {
+        params.push(fg);
+    }
+    if let Some(bg) = color_params(attrs.bg, 40) {
+        params.push(bg);
+    }
+    format!("\x1b[{}m", params.join(";"))
+}
+
+fn color_params(color: Color, base: u16) -> Option<String> {
+    match color {
+        Color::Default => None,
+        Color::Indexed(i) if i < 8 => Some((base + i as u16).to_string()),
+        Color::Indexed(i) if i < 16 => Some((base + 60 + (i - 8) as u16).to_string()),
+        Color::Indexed(i) => Some(format!("{};5;{}", base + 8, i)),
+        Color::Rgb(r, g, b) => Some(format!("{};2;{};{};{}", base + 8, r, g, b)),
+    }
+}
@@ -0,0 +1,1075 @@
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::time::SystemTime;
+
+use regex::Regex;
+use unicode_width::UnicodeWidthChar;
+
+use super::grid::{Attributes, Cell, Color, Grid, Row};
+use super::parser::Perform;
+
+const TAB_WIDTH: usize = 8;
+
+/// The number of scrolled off lines kept by default, same as tmux.
+pub const DEFAULT_HISTORY_LIMIT: usize = 2000;
+
+/// The number of commands remembered from shell integration.
+const COMMAND_LIMIT: usize = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cursor {
+    pub row: usize,
+    pub col: usize,
+    pub attrs: Attributes,
+    /// The last column was written, the next printable character wraps first.
+    pub pending_wrap: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MouseMode {
+    #[default]
+    Off,
+    /// Report button presses and releases (1000).
+    Press,
+    /// Also report motion while a button is held (1002).
+    ButtonMotion,
+    /// Report all motion (1003).
+    AnyMotion,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Modes {
+    pub insert: bool,
+    pub origin: bool,
+    pub autowrap: bool,
+    pub cursor_visible: bool,
+    pub application_cursor: bool,
+    pub application_keypad: bool,
+    pub bracketed_paste: bool,
+    pub focus_events: bool,
+    pub mouse: MouseMode,
+    pub mouse_sgr: bool,
+    pub alternate_screen: bool,
+}
+
+impl Default for Modes {
+    fn default() -> Self {
+        Self {
+            insert: false,
+            origin: false,
+            autowrap: true,
+            cursor_visible: true,
+            application_cursor: false,
+            application_keypad: false,
+            bracketed_paste: false,
+            focus_events: false,
+            mouse: MouseMode::Off,
+            mouse_sgr: false,
+            alternate_screen: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Charset {
+    #[default]
+    Ascii,
+    /// DEC special graphics, used for line drawing.
+    LineDrawing,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct SavedCursor {
+    cursor: Cursor,
+    origin: bool,
+    charsets: [Charset; 2],
+    active_charset: usize,
+}
+
+/// A command the shell ran, as marked by shell integration (OSC 133).
+/// Lines are numbered like `Screen::scrolled`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandRecord {
+    pub command: String,
+    /// The line the command was typed on.
+    pub line: u64,
+    /// The line its output starts on.
+    pub output: u64,
+    pub started: SystemTime,
+    pub finished: Option<SystemTime>,
+    /// The exit code, if the shell reported one.
+    pub exit: Option<i32>,
+}
+
+/// The visible state of a terminal: the grid of cells, the cursor and the
+/// modes applications have set. It is driven by the actions of a `Parser`.
+pub struct Screen {
+    grid: Grid,
+    /// The primary grid while the alternate screen is active.
+    primary: Option<Grid>,
+    cursor: Cursor,
+    saved_cursor: Option<SavedCursor>,
+    scroll_top: usize,
+    scroll_bottom: usize,
+    modes: Modes,
+    tabs: Vec<bool>,
+    charsets: [Charset; 2],
+    active_charset: usize,
+    title: String,
+    /// Lines scrolled off the top of the primary screen, oldest first.
+    history: VecDeque<Row>,
+    history_limit: usize,
+    /// The number of bells rung so far.
+    bells: u64,
+    /// Whether bells are counted, and so passed on to clients.
+    monitor_bell: bool,
+    /// Whether rows record when output first went to them.
+    timestamps: bool,
+    /// The number of lines scrolled off the top of the screen so far,
+    /// including those the history didn't keep.
+    scrolled: u64,
+    /// Answers to queries like DSR, waiting to be written back to the pty.
+    replies: Vec<u8>,
+    /// Where the command being typed starts, line and column.
+    command_start: Option<(u64, usize)>,
+    /// The commands run so far, oldest first.
+    commands: VecDeque<CommandRecord>,
+}
+
+impl Screen {
+    pub fn new(rows: usize, cols: usize) -> Self {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+        Self {
+            grid: Grid::new(rows, cols),
+            primary: None,
+            cursor: Cursor::default(),
+            saved_cursor: None,
+            scroll_top: 0,
+            scroll_bottom: rows - 1,
+            modes: Modes::default(),
+            tabs: default_tabs(cols),
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+            title: String::new(),
+            history: VecDeque::new(),
+            history_limit: DEFAULT_HISTORY_LIMIT,
+            bells: 0,
+            monitor_bell: true,
+            timestamps: false,
+            scrolled: 0,
+            replies: vec![],
+            command_start: None,
+            commands: VecDeque::new(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.grid.rows()
+    }
+
+    pub fn cols(&self) -> usize {
+        self.grid.cols()
+    }
+
+    pub fn grid(&self) -> &Grid {
+        &self.grid
+    }
+
+    pub fn cursor(&self) -> &Cursor {
+        &self.cursor
+    }
+
+    pub fn modes(&self) -> &Modes {
+        &self.modes
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn bells(&self) -> u64 {
+        self.bells
+    }
+
+    /// Turns bells off or back on, bells rung while off are not counted.
+    pub fn set_monitor_bell(&mut self, on: bool) {
+        self.monitor_bell = on;
+    }
+
+    /// Starts or stops recording when output first goes to each row, for
+    /// lining the history up with other logs. Rows keep what they recorded.
+    pub fn set_timestamps(&mut self, on: bool) {
+        self.timestamps = on;
+    }
+
+    /// The number of lines scrolled off the top so far, which numbers every
+    /// line ever shown: row `r` is line `scrolled() + r`.
+    pub fn scrolled(&self) -> u64 {
+        self.scrolled
+    }
+
+    /// The commands the shell reported running, oldest first.
+    pub fn commands(&self) -> &VecDeque<CommandRecord> {
+        &self.commands
+    }
+
+    /// The number, like `scrolled`, of the oldest line still in the history.
+    pub fn first_line(&self) -> u64 {
+        self.scrolled - self.history.len() as u64
+    }
+
+    /// The index in `lines` of a line numbered like `scrolled`, unless it
+    /// fell out of the history.
+    pub fn line_index(&self, line: u64) -> Option<usize> {
+        let index = line.checked_sub(self.first_line())? as usize;
+        (index < self.history.len() + self.rows()).then_some(index)
+    }
+
+    /// The row at `index` in `lines`.
+    pub fn line(&self, index: usize) -> Option<&Row> {
+        match index.checked_sub(self.history.len()) {
+            None => self.history.get(index),
+            Some(r) if r < self.rows() => Some(self.primary.as_ref().unwrap_or(&self.grid).row(r)),
+            Some(_) => None,
+        }
+    }
+
+    /// The index in `lines` of the first line output went to at or after
+    /// `time`, lines without a timestamp are skipped.
+    pub fn find_time(&self, time: SystemTime) -> Option<usize> {
+        self.lines()
+            .position(|row| row.time.is_some_and(|written| written >= time))
+    }
+
+    /// The next match of `pattern` after column `col` of the line at `index`
+    /// in `lines`, or the one before it if `backward`. The search wraps
+    /// around at either end of the history and the screen.
+    pub fn find(
+        &self,
+        pattern: &Regex,
+        index: usize,
+        col: usize,
+        backward: bool,
+    ) -> Option<(usize, Range<usize>)> {
+        let count = self.history.len() + self.rows();
+        // the starting line comes around again last, for what is on the
+        // other side of the column
+        for step in 0..=count {
+            let i = match backward {
+                true => (index + count - step % count) % count,
+                false => (index + step) % count,
+            };
+            let mut matches = self.line(i)?.find(pattern);
+            if backward {
+                matches.reverse();
+            }
+            let found = match step {
+                0 if backward => matches.into_iter().find(|m| m.start < col),
+                0 => matches.into_iter().find(|m| m.start > col),
+                _ => matches.into_iter().next(),
+            };
+            if let Some(found) = found {
+                return Some((i, found));
+            }
+        }
+        None
+    }
+
+    /// Whether the alternate screen is shown, its lines never become history.
+    pub fn is_alternate(&self) -> bool {
+        self.primary.is_some()
+    }
+
+    /// Takes the answers to queries the application made, they have to be
+    /// written to the pty since clients never see the queries.
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.replies)
+    }
+
+    pub fn history(&self) -> &VecDeque<Row> {
+        &self.history
+    }
+
+    pub fn history_limit(&self) -> usize {
+        self.history_limit
+    }
+
+    /// Changes how many scrolled off lines are kept, dropping the oldest
+    /// lines when the limit shrinks.
+    pub fn set_history_limit(&mut self, limit: usize) {
+        self.history_limit = limit;
+        self.trim_history();
+    }
+
+    /// Every line of the primary screen, history first, then the visible rows.
+    pub fn lines(&self) -> impl Iterator<Item = &Row> {
+        let visible = self.primary.as_ref().unwrap_or(&self.grid);
+        self.history.iter().chain(visible.iter())
+    }
+
+    /// The top and bottom rows of the scroll region, inclusive.
+    pub fn scroll_region(&self) -> (usize, usize) {
+        (self.scroll_top, self.scroll_bottom)
+    }
+
+    pub fn resize(&mut self, rows: usize, cols: usize) {
+        let rows = rows.max(1);
+        let cols = cols.max(1);
+
+        // keep the cursor line visible when shrinking
+        if self.cursor.row >= rows {
+            let shift = self.cursor.row + 1 - rows;
+            let bottom = self.grid.rows() - 1;
+            let scrolled = self.grid.scroll_up(0, bottom, shift, Attributes::default());
+            self.scrolled += scrolled.len() as u64;
+            if self.primary.is_none() {
+                self.push_history(scrolled);
+            }
+            self.cursor.row -= shift;
+        }
+
+        self.grid.resize(rows, cols);
+        if let Some(primary) = self.primary.as_mut() {
+            primary.resize(rows, cols);
+        }
+
+        self.cursor.row = self.cursor.row.min(rows - 1);
+        self.cursor.col = self.cursor.col.min(cols - 1);
+        self.cursor.pending_wrap = false;
+        self.scroll_top = 0;
+        self.scroll_bottom = rows - 1;
+        self.tabs = default_tabs(cols);
+    }
+
+    fn reset(&mut self) {
+        let mut screen = Screen::new(self.rows(), self.cols());
+        std::mem::swap(&mut screen.history, &mut self.history);
+        std::mem::swap(&mut screen.replies, &mut self.replies);
+        std::mem::swap(&mut screen.commands, &mut self.commands);
+        screen.history_limit = self.history_limit;
+        screen.bells = self.bells;
+        screen.monitor_bell = self.monitor_bell;
+        screen.timestamps = self.timestamps;
+        screen.scrolled = self.scrolled;
+        *self = screen;
+    }
+
+    /// The line the cursor is on, numbered like `scrolled`.
+    fn cursor_line(&self) -> u64 {
+        self.scrolled + self.cursor.row as u64
+    }
+
+    /// Handles the shell integration marks: A before the prompt, B where
+    /// the command starts, C where its output starts and D when it is done.
+    fn shell_mark(&mut self, mark: &[u8], args: &[&[u8]]) {
+        match mark {
+            b"A" => self.command_start = None,
+            b"B" => self.command_start = Some((self.cursor_line(), self.cursor.col)),
+            b"C" => {
+                let Some((line, col)) = self.command_start.take() else {
+                    return;
+                };
+                let output = self.cursor_line();
+                let record = CommandRecord {
+                    command: self.command_text(line, col, output),
+                    line,
+                    output,
+                    started: SystemTime::now(),
+                    finished: None,
+                    exit: None,
+                };
+                self.commands.push_back(record);
+                if self.commands.len() > COMMAND_LIMIT {
+                    self.commands.pop_front();
+                }
+            }
+            b"D" => {
+                let record = self.commands.back_mut();
+                let Some(record) = record.filter(|r| r.finished.is_none()) else {
+                    return;
+                };
+                record.finished = Some(SystemTime::now());
+                record.exit = args
+                    .first()
+                    .and_then(|code| std::str::from_utf8(code).ok())
+                    .and_then(|code| code.parse().ok());
+            }
+            _ => {}
+        }
+    }
+
+    /// The text typed from `col` of `line` up to the line output starts on,
+    /// soft wrapped rows are joined.
+    fn command_text(&self, line: u64, col: usize, output: u64) -> String {
+        let mut text = String::new();
+        let mut line = line;
+        let mut col = col;
+        while let Some(row) = self.line_index(line).and_then(|i| self.line(i)) {
+            let cells = row.cells.iter().skip(col).filter(|c| c.width > 0);
+            text.extend(cells.map(|c| c.c));
+            line += 1;
+            col = 0;
+            if !row.wrapped || line >= output {
+                break;
+            }
+        }
+        text.trim().to_string()
+    }
+
+    fn push_history(&mut self, rows: Vec<Row>) {
+        self.history.extend(rows);
+        self.trim_history();
+    }
+
+    fn trim_history(&mut self) {
+        let excess = self.history.len().saturating_sub(self.history_limit);
+        self.history.drain(..excess);
+    }
+
+    fn linefeed(&mut self) {
+        self.cursor.pending_wrap = false;
+        if self.cursor.row == self.scroll_bottom {
+            self.scroll_up(1);
+        } else if self.cursor.row + 1 < self.rows() {
+            self.cursor.row += 1;
+        }
+    }
+
+    fn reverse_index(&mut self) {
+        self.cursor.pending_wrap = false;
+        if self.cursor.row == self.scroll_top {
+            self.scroll_down(1);
+        } else if self.cursor.row > 0 {
+            self.cursor.row -= 1;
+        }
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        let scrolled = self.grid.scroll_up(
+            self.scroll_top,
+            self.scroll_bottom,
+            count,
+            self.cursor.attrs,
+        );
+
+        // only lines leaving the top of the primary screen become history
+        if self.scroll_top == 0 {
+            self.scrolled += scrolled.len() as u64;
+            if self.primary.is_none() {
+                self.push_history(scrolled);
+            }
+        }
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        self.grid.scroll_down(
+            self.scroll_top,
+            self.scroll_bottom,
+            count,
+            self.cursor.attrs,
+        );
+    }
+
+    fn carriage_return(&mut self) {
+        self.cursor.col = 0;
+        self.cursor.pending_wrap = false;
+    }
+
+    fn backspace(&mut self) {
+        self.cursor.col = self.cursor.col.saturating_sub(1);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn tab(&mut self, count: usize) {
+        for _ in 0..count {
+            let next = (self.cursor.col + 1..self.cols()).find(|&col| self.tabs[col]);
+            self.cursor.col = next.unwrap_or(self.cols() - 1);
+        }
+        self.cursor.pending_wrap = false;
+    }
+
+    fn back_tab(&mut self, count: usize) {
+        for _ in 0..count {
+            let prev = (0..self.cursor.col).rev().find(|&col| self.tabs[col]);
+            self.cursor.col = prev.unwrap_or(0);
+        }
+        self.cursor.pending_wrap = false;
+    }
+
+    /// Moves the cursor, `row` is relative to the scroll region in origin mode.
+    fn goto(&mut self, row: usize, col: usize) {
+        let (top, bottom) = if self.modes.origin {
+            (self.scroll_top, self.scroll_bottom)
+        } else {
+            (0, self.rows() - 1)
+        };
+        self.cursor.row = (top + row).min(bottom);
+        self.cursor.col = col.min(self.cols() - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn move_up(&mut self, count: usize) {
+        let top = if self.cursor.row >= self.scroll_top {
+            self.scroll_top
+        } else {
+            0
+        };
+        self.cursor.row = self.cursor.row.saturating_sub(count).max(top);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn move_down(&mut self, count: usize) {
+        let bottom = if self.cursor.row <= self.scroll_bottom {
+            self.scroll_bottom
+        } else {
+            self.rows() - 1
+        };
+        self.cursor.row = (self.cursor.row + count).min(bottom);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn move_right(&mut self, count: usize) {
+        self.cursor.col = (self.cursor.col + count).min(self.cols() - 1);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn move_left(&mut self, count: usize) {
+        self.cursor.col = self.cursor.col.saturating_sub(count);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn erase_cells(&mut self, row: usize, from: usize, to: usize) {
+        let blank = Cell::blank(self.cursor.attrs);
+        let cells = &mut self.grid.row_mut(row).cells;
+        let to = to.min(cells.len());
+        let from = from.min(to);
+        // don't leave half of a wide character behind
+        if from > 0 && from < to && cells[from].width == 0 {
+            cells[from - 1] = blank;
+        }
+        if from < to && to < cells.len() && cells[to].width == 0 {
+            cells[to] = blank;
+        }
+        for cell in cells[from..to].iter_mut() {
+            *cell = blank;
+        }
+    }
+
+    fn erase_in_display(&mut self, mode: u16) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        let cols = self.cols();
+        match mode {
+            0 => {
+                self.erase_cells(row, col, cols);
+                for r in row + 1..self.rows() {
+                    self.grid.row_mut(r).clear(self.cursor.attrs);
+                }
+            }
+            1 => {
+                for r in 0..row {
+                    self.grid.row_mut(r).clear(self.cursor.attrs);
+                }
+                self.erase_cells(row, 0, col + 1);
+            }
+            2 => {
+                for r in 0..self.rows() {
+                    self.grid.row_mut(r).clear(self.cursor.attrs);
+                }
+            }
+            3 => self.history.clear(),
+            _ => {}
+        }
+        self.cursor.pending_wrap = false;
+    }
+
+    fn erase_in_line(&mut self, mode: u16) {
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        match mode {
+            0 => self.erase_cells(row, col, self.cols()),
+            1 => self.erase_cells(row, 0, col + 1),
+            2 => self.erase_cells(row, 0, self.cols()),
+            _ => {}
+        }
+        self.cursor.pending_wrap = false;
+    }
+
+    fn insert_cells(&mut self, count: usize) {
+        let blank = Cell::blank(self.cursor.attrs);
+        let col = self.cursor.col;
+        let cells = &mut self.grid.row_mut(self.cursor.row).cells;
+        let count = count.min(cells.len() - col);
+        cells[col..].rotate_right(count);
+        cells[col..col + count].fill(blank);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn delete_cells(&mut self, count: usize) {
+        let blank = Cell::blank(self.cursor.attrs);
+        let col = self.cursor.col;
+        let cells = &mut self.grid.row_mut(self.cursor.row).cells;
+        let len = cells.len();
+        let count = count.min(len - col);
+        cells[col..].rotate_left(count);
+        cells[len - count..].fill(blank);
+        self.cursor.pending_wrap = false;
+    }
+
+    fn insert_lines(&mut self, count: usize) {
+        if self.cursor.row < self.scroll_top || self.cursor.row > self.scroll_bottom {
+            return;
+        }
+        self.grid.scroll_down(
+            self.cursor.row,
+            self.scroll_bottom,
+            count,
+            self.cursor.attrs,
+        );
+        self.cursor.col = 0;
+        self.cursor.pending_wrap = false;
+    }
+
+    fn delete_lines(&mut self, count: usize) {
+        if self.cursor.row < self.scroll_top || self.cursor.row > self.scroll_bottom {
+            return;
+        }
+        self.grid.scroll_up(
+            self.cursor.row,
+            self.scroll_bottom,
+            count,
+            self.cursor.attrs,
+        );
+        self.cursor.col = 0;
+        self.cursor.pending_wrap = false;
+    }
+
+    fn set_scroll_region(&mut self, top: usize, bottom: usize) {
+        let bottom = bottom.min(self.rows() - 1);
+        if top < bottom {
+            self.scroll_top = top;
+            self.scroll_bottom = bottom;
+            self.goto(0, 0);
+        }
+    }
+
+    fn save_cursor(&mut self) {
+        self.saved_cursor = Some(SavedCursor {
+            cursor: self.cursor,
+            origin: self.modes.origin,
+            charsets: self.charsets,
+            active_charset: self.active_charset,
+        });
+    }
+
+    fn restore_cursor(&mut self) {
+        let saved = self.saved_cursor.unwrap_or(SavedCursor {
+            cursor: Cursor::default(),
+            origin: false,
+            charsets: [Charset::Ascii; 2],
+            active_charset: 0,
+        });
+        self.cursor = saved.cursor;
+        self.cursor.row = self.cursor.row.min(self.rows() - 1);
+        self.cursor.col = self.cursor.col.min(self.cols() - 1);
+        self.modes.origin = saved.origin;
+        self.charsets = saved.charsets;
+        self.active_charset = saved.active_charset;
+    }
+
+    fn enter_alternate_screen(&mut self, save_cursor: bool) {
+        if self.primary.is_some() {
+            return;
+        }
+        if save_cursor {
+            self.save_cursor();
+        }
+        let alternate = Grid::new(self.rows(), self.cols());
+        self.primary = Some(std::mem::replace(&mut self.grid, alternate));
+        self.modes.alternate_screen = true;
+    }
+
+    fn leave_alternate_screen(&mut self, restore_cursor: bool) {
+        if let Some(primary) = self.primary.take() {
+            self.grid = primary;
+            self.modes.alternate_screen = false;
+            if restore_cursor {
+                self.restore_cursor();
+            }
+        }
+    }
+
+    fn set_private_mode(&mut self, mode: u16, enabled: bool) {
+        match mode {
+            1 => self.modes.application_cursor = enabled,
+            6 => {
+                self.modes.origin = enabled;
+                self.goto(0, 0);
+            }
+            7 => self.modes.autowrap = enabled,
+            25 => self.modes.cursor_visible = enabled,
+            9 | 1000 => self.set_mouse_mode(MouseMode::Press, enabled),
+            1002 => self.set_mouse_mode(MouseMode::ButtonMotion, enabled),
+            1003 => self.set_mouse_mode(MouseMode::AnyMotion, enabled),
+            1004 => self.modes.focus_events = enabled,
+            1006 => self.modes.mouse_sgr = enabled,
+            47 | 1047 => {
+                if enabled {
+                    self.enter_alternate_screen(false);
+                } else {
+                    self.leave_alternate_screen(false);
+                }
+            }
+            1049 => {
+                if enabled {
+                    self.enter_alternate_screen(true);
+                } else {
+                    self.leave_alternate_screen(true);
+                }
+            }
+            2004 => self.modes.bracketed_paste = enabled,
+            _ => {}
+        }
+    }
+
+    fn set_mouse_mode(&mut self, mode: MouseMode, enabled: bool) {
+        if enabled {
+            self.modes.mouse = mode;
+        } else if self.modes.mouse == mode {
+            self.modes.mouse = MouseMode::Off;
+        }
+    }
+
+    fn set_mode(&mut self, mode: u16, enabled: bool) {
+        if mode == 4 {
+            self.modes.insert = enabled;
+        }
+    }
+
+    fn select_graphic_rendition(&mut self, params: &[u16]) {
+        let attrs = &mut self.cursor.attrs;
+        if params.is_empty() {
+            *attrs = Attributes::default();
+            return;
+        }
+
+        let mut iter = params.iter().copied();
+        while let Some(param) = iter.next() {
+            match param {
+                0 => *attrs = Attributes::default(),
+                1 => attrs.bold = true,
+                2 => attrs.dim = true,
+                3 => attrs.italic = true,
+                4 | 21 => attrs.underline = true,
+                5 | 6 => attrs.blink = true,
+                7 => attrs.reverse = true,
+                8 => attrs.hidden = true,
+                9 => attrs.strikethrough = true,
+                22 => {
+                    attrs.bold = false;
+                    attrs.dim = false;
+                }
+                23 => attrs.italic = false,
+                24 => attrs.underline = false,
+                25 => attrs.blink = false,
+                27 => attrs.reverse = false,
+                28 => attrs.hidden = false,
+                29 => attrs.strikethrough = false,
+                30..=37 => attrs.fg = Color::Indexed((param - 30) as u8),
+                38 => attrs.fg = parse_extended_color(&mut iter).unwrap_or(attrs.fg),
+                39 => attrs.fg = Color::Default,
+                40..=47 => attrs.bg = Color::Indexed((param - 40) as u8),
+                48 => attrs.bg = parse_extended_color(&mut iter).unwrap_or(attrs.bg),
+                49 => attrs.bg = Color::Default,
+                90..=97 => attrs.fg = Color::Indexed((param - 90 + 8) as u8),
+                100..=107 => attrs.bg = Color::Indexed((param - 100 + 8) as u8),
+                _ => {}
+            }
+        }
+    }
+
+    fn put_char(&mut self, c: char, width: usize) {
+        if self.cursor.pending_wrap && self.modes.autowrap {
+            self.grid.row_mut(self.cursor.row).wrapped = true;
+            self.carriage_return();
+            self.linefeed();
+        }
+
+        // a wide character doesn't fit in the last column
+        if width == 2 && self.cursor.col + 1 >= self.cols() {
+            if !self.modes.autowrap || self.cols() < 2 {
+                return;
+            }
+            let (row, col) = (self.cursor.row, self.cursor.col);
+            self.erase_cells(row, col, col + 1);
+            self.grid.row_mut(row).wrapped = true;
+            self.carriage_return();
+            self.linefeed();
+        }
+
+        if self.modes.insert {
+            self.insert_cells(width);
+        }
+
+        let (row, col) = (self.cursor.row, self.cursor.col);
+        let attrs = self.cursor.attrs;
+        let cols = self.cols();
+        let timestamps = self.timestamps;
+        let written = self.grid.row_mut(row);
+        if timestamps && written.time.is_none() {
+            written.time = Some(SystemTime::now());
+        }
+        let cells = &mut written.cells;
+
+        // don't leave half of a wide character behind
+        if cells[col].width == 0 && col > 0 {
+            cells[col - 1] = Cell::blank(attrs);
+        }
+        let end = col + width;
+        if end < cols && cells[end].width == 0 {
+            cells[end] = Cell::blank(attrs);
+        }
+
+        cells[col] = Cell {
+            c,
+            width: width as u8,
+            attrs,
+        };
+        if width == 2 {
+            cells[col + 1] = Cell {
+                c: ' ',
+                width: 0,
+                attrs,
+            };
+        }
+
+        if end >= cols {
+            self.cursor.col = cols - 1;
+            self.cursor.pending_wrap = true;
+        } else {
+            self.cursor.col = end;
+        }
+    }
+
+    fn translate(&self, c: char) -> char {
+        match self.charsets[self.active_charset] {
+            Charset::Ascii => c,
+            Charset::LineDrawing => line_drawing(c),
+        }
+    }
+}
+
+impl Perform for Screen {
+    fn print(&mut self, c: char) {
+        let c = self.translate(c);
+        // combining and other zero width characters are not tracked
+        if let Some(width @ 1..=2) = c.width() {
+            self.put_char(c, width);
+        }
+    }
+
+    fn execute(&mut self, byte: u8) {
+        match byte {
+            0x07 if self.monitor_bell => self.bells += 1,
+            0x08 => self.backspace(),
+            0x09 => self.tab(1),
+            0x0A..=0x0C => self.linefeed(),
+            0x0D => self.carriage_return(),
+            0x0E => self.active_charset = 1,
+            0x0F => self.active_charset = 0,
+            _ => {}
+        }
+    }
+
+    fn csi_dispatch(&mut self, params: &[u16], intermediates: &[u8], ignore: bool, action: char) {
+        if ignore {
+            return;
+        }
+
+        // most parameters default to 1 when missing or zero
+        let param = |i: usize, default: u16| -> usize {
+            match params.get(i) {
+                Some(&0) | None => default as usize,
+                Some(&p) => p as usize,
+            }
+        };
+
+        match (intermediates, action) {
+            ([], '@') => self.insert_cells(param(0, 1)),
+            ([], 'A') => self.move_up(param(0, 1)),
+            ([], 'B') | ([], 'e') => self.move_down(param(0, 1)),
+            ([], 'C') | ([], 'a') => self.move_right(param(0, 1)),
+            ([], 'D') => self.move_left(param(0, 1)),
+            ([], 'E') => {
+                self.move_down(param(0, 1));
+                self.carriage_return();
+            }
+            ([], 'F') => {
+                self.move_up(param(0, 1));
+                self.carriage_return();
+            }
+            ([], 'G') | ([], '`') => {
+                self.cursor.col = (param(0, 1) - 1).min(self.cols() - 1);
+                self.cursor.pending_wrap = false;
+            }
+            ([], 'H') | ([], 'f') => self.goto(param(0, 1) - 1, param(1, 1) - 1),
+            ([], 'I') => self.tab(param(0, 1)),
+            ([], 'J') => self.erase_in_display(params.first().copied().unwrap_or(0)),
+            ([], 'K') => self.erase_in_line(params.first().copied().unwrap_or(0)),
+            ([], 'L') => self.insert_lines(param(0, 1)),
+            ([], 'M') => self.delete_lines(param(0, 1)),
+            ([], 'P') => self.delete_cells(param(0, 1)),
+            ([], 'S') => self.scroll_up(param(0, 1)),
+            ([], 'T') => self.scroll_down(param(0, 1)),
+            ([], 'X') => {
+                let (row, col) = (self.cursor.row, self.cursor.col);
+                self.erase_cells(row, col, col + param(0, 1));
+                self.cursor.pending_wrap = false;
+            }
+            ([], 'Z') => self.back_tab(param(0, 1)),
+            ([], 'd') => {
+                let col = self.cursor.col;
+                self.goto(param(0, 1) - 1, col);
+            }
+            ([], 'g') => match params.first().copied().unwrap_or(0) {
+                0 => self.tabs[self.cursor.col] = false,
+                3 => self.tabs.fill(false),
+                _ => {}
+            },
+            ([], 'h') => params.iter().for_each(|&mode| self.set_mode(mode, true)),
+            ([], 'l') => params.iter().for_each(|&mode| self.set_mode(mode, false)),
+            ([b'?'], 'h') => params
+                .iter()
+                .for_each(|&mode| self.set_private_mode(mode, true)),
+            ([b'?'], 'l') => params
+                .iter()
+                .for_each(|&mode| self.set_private_mode(mode, false)),
+            ([], 'm') => self.select_graphic_rendition(params),
+            ([], 'r') => {
+                let bottom = param(1, self.rows() as u16);
+                self.set_scroll_region(param(0, 1) - 1, bottom - 1);
+            }
+            ([], 'c') if params.first().copied().unwrap_or(0) == 0 => {
+                // primary device attributes, a VT100 with advanced video
+                self.replies.extend_from_slice(b"\x1b[?1;2c");
+            }
+            ([b'>'], 'c') => self.replies.extend_from_slice(b"\x1b[>84;0;0c"),
+            ([], 'n') => match params.first().copied().unwrap_or(0) {
+                5 => self.replies.extend_from_slice(b"\x1b[0n"),
+                6 => {
+                    let top = if self.modes.origin {
+                        self.scroll_top
+                    } else {
+                        0
+                    };
+                    let row = self.cursor.row.saturating_sub(top) + 1;
+                    let report = format!("\x1b[{};{}R", row, self.cursor.col + 1);
+                    self.replies.extend_from_slice(report.as_bytes());
+                }
+                _ => {}
+            },
+            ([], 's') => self.save_cursor(),
+            ([], 'u') => self.restore_cursor(),
+            ([b'!'], 'p') => {
+                // soft reset keeps the screen contents
+                self.modes = Modes::default();
+                self.cursor.attrs = Attributes::default();
+                self.scroll_top = 0;
+                self.scroll_bottom = self.rows() - 1;
+            }
+            _ => {}
+        }
+    }
+
+    fn esc_dispatch(&mut self, intermediates: &[u8], byte: u8) {
+        match (intermediates, byte) {
+            ([], b'7') => self.save_cursor(),
+            ([], b'8') => self.restore_cursor(),
+            ([], b'D') => self.linefeed(),
+            ([], b'E') => {
+                self.carriage_return();
+                self.linefeed();
+            }
+            ([], b'H') => self.tabs[self.cursor.col] = true,
+            ([], b'M') => self.reverse_index(),
+            ([], b'c') => self.reset(),
+            ([], b'=') => self.modes.application_keypad = true,
+            ([], b'>') => self.modes.application_keypad = false,
+            ([b'#'], b'8') => {
+                // DECALN fills the screen with E's
+                let fill = Cell {
+                    c: 'E',
+                    ..Cell::default()
+                };
+                for row in 0..self.rows() {
+                    self.grid.row_mut(row).cells.fill(fill);
+                }
+            }
+            ([b'('], b'0') => self.charsets[0] = Charset::LineDrawing,
+            ([b'('], _) => self.charsets[0] = Charset::Ascii,
+            ([b')'], b'0') => self.charsets[1] = Charset::LineDrawing,
+            ([b')'], _) => self.charsets[1] = Charset::Ascii,
+            _ => {}
+        }
+    }
+
+    fn osc_dispatch(&mut self, params: &[&[u8]]) {
+        match params {
+            // a title can have semicolons of its own
+            [b"0" | b"2", title @ ..] if !title.is_empty() => {
+                self.title = String::from_utf8_lossy(&title.join(&b';')).into_owned();
+            }
+            [b"133", mark, args @ ..] => self.shell_mark(mark, args),
+            _ => {}
+        }
+    }
+}
+
+fn default_tabs(cols: usize) -> Vec<bool> {
+    (0..cols)
+        .map(|col| col > 0 && col % TAB_WIDTH == 0)
+        .collect()
+}
+
+fn parse_extended_color(iter: &mut impl Iterator<Item = u16>) -> Option<Color> {
+    match iter.next()? {
+        5 => Some(Color::Indexed(iter.next()?.min(255) as u8)),
+        2 => {
+            let r = iter.next()?.min(255) as u8;
+            let g = iter.next()?.min(255) as u8;
+            let b = iter.next()?.min(255) as u8;
+            Some(Color::Rgb(r, g, b))
+        }
+        _ => None,
+    }
+}
+
+fn line_drawing(c: char) -> char {
+    match c {
+        '`' => '◆',
+        'a' => '▒',
+        'f' => '°',
+        'g' => '±',
+        'j' => '┘',
+        'k' => '┐',
+        'l' => '┌',
+        'm' => '└',
+        'n' => '┼',
+        'o' => '⎺',
+        'p' => '⎻',
+        'q' => '─',
+        'r' => '⎼',
+        's' => '⎽',
+        't' => '├',
+        'u' => '┤',
+        'v' => '┴',
+        'w' => '┬',
+        'x' => '│',
+        'y' => '≤',
+        'z' => '≥',
+        '{' => 'π',
+        '|' => '≠',
+        '}' => '£',
+        '~' => '·',
+        _ => c,
+    }
+}
@@ -0,0 +1,85 @@
+use unicode_width::UnicodeWidthChar;
+
+/// Marks where text was cut off.
+pub const ELLIPSIS: char = '…';
+
+/// The number of terminal cells `s` takes up. Control characters take none,
+/// they are dropped by the helpers below since they would corrupt a line.
+pub fn width(s: &str) -> usize {
+    s.chars().filter_map(UnicodeWidthChar::width).sum()
+}
+
+/// Cuts `s` down to at most `max_width` cells, never splitting a character.
+/// A wide character that doesn't fit is dropped entirely, so the result may
+/// be a cell short.
+pub fn truncate(s: &str, max_width: usize) -> String {
+    let mut out = String::new();
+    let mut used = 0;
+    for c in s.chars() {
+        let Some(w) = c.width() else {
+            continue;
+        };
+        if used + w > max_width {
+            break;
+        }
+        used += w;
+        out.push(c);
+    }
+    out
+}
+
+/// Like `truncate`, but ends with an ellipsis when anything was cut off.
+pub fn ellipsize(s: &str, max_width: usize) -> String {
+    if width(s) <= max_width {
+        return truncate(s, max_width);
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let mut out = truncate(s, max_width - 1);
+    out.push(ELLIPSIS);
+    out
+}
+
+/// Like `ellipsize`, but keeps the end of `s`, which suits paths and
+/// commands where the last part matters most.
+pub fn ellipsize_start(s: &str, max_width: usize) -> String {
+    if width(s) <= max_width {
+        return truncate(s, max_width);
+    }
+    if max_width == 0 {
+        return String::new();
+    }
+
+    let mut kept = vec![];
+    let mut used = 0;
+    for c in s.chars().rev() {
+        let Some(w) = c.width() else {
+            continue;
+        };
+        if used + w > max_width - 1 {
+            break;
+        }
+        used += w;
+        kept.push(c);
+    }
+
+    // combining marks whose base character was cut off
+    while kept.last().is_some_and(|c| c.width() == Some(0)) {
+        kept.pop();
+    }
+
+    let mut out = String::from(ELLIPSIS);
+    out.extend(kept.into_iter().rev());
+    out
+}
+
+/// Pads `s` with spaces on the right to exactly `width` cells, truncating
+/// it first if it is too wide.
+pub fn pad(s: &str, width: usize) -> String {
+    let mut out = truncate(s, width);
+    let used = self::width(&out);
+    out.extend(std::iter::repeat_n(' ', width - used));
+    out
+}
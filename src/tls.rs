@@ -0,0 +1,245 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    path::Path,
+    sync::Arc,
+    time::Duration,
+};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    server::WebPkiClientVerifier,
+    ClientConfig, ClientConnection, Connection, RootCertStore, ServerConfig, ServerConnection,
+};
+
+use crate::poll::{poll, pollfd};
+
+/// How much plaintext is read at a time, a TLS record holds at most this.
+const RECORD_SIZE: usize = 16 * 1024;
+
+/// The certificates in a PEM file, a chain is listed leaf first.
+fn certificates(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let invalid = |e: rustls::pki_types::pem::Error| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    };
+    let certificates = CertificateDer::pem_file_iter(path)
+        .map_err(invalid)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid)?;
+    if certificates.is_empty() {
+        let message = format!("{}: no certificates", path.display());
+        return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+    }
+    Ok(certificates)
+}
+
+fn private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{}: {}", path.display(), e),
+        )
+    })
+}
+
+/// The certificates in `path` as trust anchors, a CA's or a self-signed one.
+fn roots(path: &Path) -> io::Result<Arc<RootCertStore>> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates(path)? {
+        roots.add(certificate).map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}: {}", path.display(), e),
+            )
+        })?;
+    }
+    Ok(Arc::new(roots))
+}
+
+/// A server presenting the certificate chain `cert` with its key `key`,
+/// which only accepts clients with a certificate signed by `client_ca` if
+/// it is given.
+pub fn server_config(
+    cert: &Path,
+    key: &Path,
+    client_ca: Option<&Path>,
+) -> io::Result<Arc<ServerConfig>> {
+    let builder = match client_ca {
+        Some(ca) => {
+            let verifier = WebPkiClientVerifier::builder(roots(ca)?)
+                .build()
+                .map_err(io::Error::other)?;
+            ServerConfig::builder().with_client_cert_verifier(verifier)
+        }
+        None => ServerConfig::builder().with_no_client_auth(),
+    };
+    let config = builder
+        .with_single_cert(certificates(cert)?, private_key(key)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    Ok(Arc::new(config))
+}
+
+/// A client trusting servers with a certificate signed by `ca`, which
+/// presents `identity`, a certificate chain and its key, if the server asks.
+pub fn client_config(ca: &Path, identity: Option<(&Path, &Path)>) -> io::Result<Arc<ClientConfig>> {
+    let builder = ClientConfig::builder().with_root_certificates(roots(ca)?);
+    let config = match identity {
+        Some((cert, key)) => builder
+            .with_client_auth_cert(certificates(cert)?, private_key(key)?)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+/// Serves TLS to a client that connected over `tcp`. Returns the local end
+/// of a socket pair that carries the plaintext, so the rest of the server
+/// doesn't know the difference. The handshake happens in the background, a
+/// client that fails it is hung up on.
+pub fn accept(tcp: TcpStream, config: Arc<ServerConfig>) -> io::Result<UnixStream> {
+    let tls = ServerConnection::new(config).map_err(io::Error::other)?;
+    relay(tls.into(), tcp)
+}
+
+/// Connects to the TLS server on `tcp` that should have a certificate for
+/// `host`. Returns once the handshake is done, so a certificate that isn't
+/// trusted is reported here, with the local end of a socket pair that
+/// carries the plaintext.
+pub fn connect(
+    mut tcp: TcpStream,
+    host: &str,
+    config: Arc<ClientConfig>,
+) -> io::Result<UnixStream> {
+    let bad_host = || io::Error::new(io::ErrorKind::InvalidInput, format!("bad host: {}", host));
+    let name = ServerName::try_from(host.to_string()).map_err(|_| bad_host())?;
+    let mut tls = ClientConnection::new(config, name).map_err(io::Error::other)?;
+    while tls.is_handshaking() {
+        tls.complete_io(&mut tcp)?;
+    }
+    relay(tls.into(), tcp)
+}
+
+/// Moves data between the TLS connection and a socket pair in a thread of
+/// its own until either side closes.
+fn relay(tls: Connection, tcp: TcpStream) -> io::Result<UnixStream> {
+    let (local, plain) = UnixStream::pair()?;
+    tcp.set_nonblocking(true)?;
+    plain.set_nonblocking(true)?;
+    let mut relay = Relay {
+        tls,
+        tcp,
+        plain,
+        pending: vec![],
+    };
+    std::thread::spawn(move || {
+        let _ = relay.run();
+        relay.finish();
+    });
+    Ok(local)
+}
+
+struct Relay {
+    tls: Connection,
+    tcp: TcpStream,
+    /// Our end of the socket pair.
+    plain: UnixStream,
+    /// Plaintext from the peer not yet written to the socket pair.
+    pending: Vec<u8>,
+}
+
+impl Relay {
+    fn run(&mut self) -> io::Result<()> {
+        const READABLE: libc::c_short = libc::POLLIN | libc::POLLHUP | libc::POLLERR;
+        let mut buf = vec![0; RECORD_SIZE];
+        let mut plain_open = true;
+        loop {
+            if !plain_open && !self.tls.wants_write() {
+                return Ok(());
+            }
+            // each side is only read from once the other caught up
+            let mut tcp_events = 0;
+            if self.pending.is_empty() {
+                tcp_events |= libc::POLLIN;
+            }
+            if self.tls.wants_write() {
+                tcp_events |= libc::POLLOUT;
+            }
+            let mut plain_events = 0;
+            if plain_open && !self.tls.wants_write() {
+                plain_events |= libc::POLLIN;
+            }
+            if !self.pending.is_empty() {
+                plain_events |= libc::POLLOUT;
+            }
+            let mut fds = [
+                pollfd(self.tcp.as_raw_fd(), tcp_events),
+                pollfd(self.plain.as_raw_fd(), plain_events),
+            ];
+            poll(&mut fds, Duration::from_secs(60))?;
+
+            if fds[0].revents & libc::POLLOUT != 0 {
+                would_block(self.tls.write_tls(&mut self.tcp))?;
+            }
+            if fds[0].revents & READABLE != 0 && self.pending.is_empty() {
+                match would_block(self.tls.read_tls(&mut self.tcp))? {
+                    Some(0) => return Ok(()),
+                    Some(_) => {
+                        if let Err(e) = self.tls.process_new_packets() {
+                            // let the peer know why
+                            let _ = self.tls.write_tls(&mut self.tcp);
+                            return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+                        }
+                        loop {
+                            match self.tls.reader().read(&mut buf) {
+                                // the peer said it is done
+                                Ok(0) => return Ok(()),
+                                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                                Err(e) => return Err(e),
+                            }
+                        }
+                    }
+                    None => {}
+                }
+            }
+
+            if fds[1].revents & libc::POLLOUT != 0 {
+                if let Some(n) = would_block(self.plain.write(&self.pending))? {
+                    self.pending.drain(..n);
+                }
+            }
+            if fds[1].revents & READABLE != 0 && plain_open {
+                match would_block(self.plain.read(&mut buf))? {
+                    Some(0) => {
+                        self.tls.send_close_notify();
+                        plain_open = false;
+                    }
+                    Some(n) => self.tls.writer().write_all(&buf[..n])?,
+                    None => {}
+                }
+            }
+        }
+    }
+
+    /// Hands over what the peer sent last and hangs up on both sides.
+    fn finish(&mut self) {
+        if self.plain.set_nonblocking(false).is_ok() {
+            let _ = self.plain.write_all(&self.pending);
+        }
+        let _ = self.plain.shutdown(std::net::Shutdown::Both);
+        let _ = self.tcp.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+/// None where a non-blocking call would have blocked.
+fn would_block(result: io::Result<usize>) -> io::Result<Option<usize>> {
+    match result {
+        Ok(n) => Ok(Some(n)),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+        Err(e) => Err(e),
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    any::Any,
+    io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering::Relaxed},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How long a worker may go without checking in while it isn't waiting
+/// before it counts as stuck, on a lock that is never released say.
+pub const STUCK_AFTER: Duration = Duration::from_secs(10);
+
+/// How many times a worker that panicked is started again before it is
+/// given up on, a poisoned lock makes it panic every time.
+const MAX_RESTARTS: u32 = 3;
+
+/// How long a worker that panicked waits before it starts again.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+
+/// Keeps track of the server's threads, which check in as they work, so
+/// one that panicked or hangs shows in server-info and the log instead of
+/// silently leaving the session without output, commands or clients.
+#[derive(Clone)]
+pub struct Watchdog {
+    workers: Arc<Mutex<Vec<Arc<Worker>>>>,
+    /// Set when a worker that can't be restarted fails, a session nobody
+    /// can reach is worse than none.
+    stop: Arc<AtomicBool>,
+}
+
+/// A thread the watchdog keeps track of.
+pub struct Worker {
+    name: &'static str,
+    state: Mutex<State>,
+    restarts: AtomicU32,
+    /// Why it panicked last.
+    panic: Mutex<Option<String>>,
+    /// Set once it was logged as stuck, until it checks in again.
+    reported: AtomicBool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum State {
+    /// Checked in last at the time.
+    Working(Instant),
+    /// Waiting for something to do, like output or a command.
+    Waiting,
+    /// Returned, the server is stopping.
+    Done,
+    Failed(String),
+}
+
+impl Watchdog {
+    pub fn new(stop: Arc<AtomicBool>) -> Self {
+        Watchdog {
+            workers: Arc::new(Mutex::new(vec![])),
+            stop,
+        }
+    }
+
+    /// Runs `work` on a thread of its own. If it panics the server stops.
+    pub fn spawn<F>(&self, name: &'static str, work: F) -> io::Result<()>
+    where
+        F: FnOnce(&Worker) + Send + 'static,
+    {
+        let worker = self.add(name);
+        let stop = self.stop.clone();
+        let run = move || {
+            let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| work(&worker))) else {
+                return worker.set(State::Done);
+            };
+            let message = panic_message(panic);
+            eprintln!("{} failed, stopping: {}", name, message);
+            worker.set(State::Failed(message));
+            stop.store(true, Relaxed);
+        };
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(run)
+            .map(|_| ())
+    }
+
+    /// Runs `work` on a thread of its own, again from the start if it
+    /// panics, so it must pick up its state from the server each time.
+    pub fn spawn_restarting<F>(&self, name: &'static str, work: F) -> io::Result<()>
+    where
+        F: Fn(&Worker) + Send + 'static,
+    {
+        let worker = self.add(name);
+        thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || loop {
+                worker.beat();
+                let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| work(&worker))) else {
+                    return worker.set(State::Done);
+                };
+                let message = panic_message(panic);
+                if worker.restarts.load(Relaxed) >= MAX_RESTARTS {
+                    eprintln!("{} failed, giving up: {}", name, message);
+                    return worker.set(State::Failed(message));
+                }
+                eprintln!("{} failed, restarting: {}", name, message);
+                worker.restarts.fetch_add(1, Relaxed);
+                *worker.panic.lock().unwrap() = Some(message);
+                thread::sleep(RESTART_DELAY);
+            })
+            .map(|_| ())
+    }
+
+    fn add(&self, name: &'static str) -> Arc<Worker> {
+        let worker = Arc::new(Worker {
+            name,
+            state: Mutex::new(State::Working(Instant::now())),
+            restarts: AtomicU32::new(0),
+            panic: Mutex::new(None),
+            reported: AtomicBool::new(false),
+        });
+        self.workers.lock().unwrap().push(worker.clone());
+        worker
+    }
+
+    /// The workers that got stuck since the last check, with how long
+    /// they have been, for the log.
+    pub fn check(&self) -> Vec<(&'static str, Duration)> {
+        let workers = self.workers.lock().unwrap();
+        workers
+            .iter()
+            .filter_map(|worker| {
+                let stuck = worker.stuck()?;
+                (!worker.reported.swap(true, Relaxed)).then_some((worker.name, stuck))
+            })
+            .collect()
+    }
+
+    /// A line for each worker, like `output: waiting` or
+    /// `commands: stuck for 12s, restarted 1 time (index out of bounds)`.
+    pub fn report(&self) -> Vec<String> {
+        let workers = self.workers.lock().unwrap();
+        workers.iter().map(|worker| worker.report()).collect()
+    }
+}
+
+impl Worker {
+    /// Checks in, the worker is busy but not stuck.
+    pub fn beat(&self) {
+        self.set(State::Working(Instant::now()));
+        self.reported.store(false, Relaxed);
+    }
+
+    /// The worker is about to wait for work for as long as it takes.
+    pub fn wait(&self) {
+        self.set(State::Waiting);
+        self.reported.store(false, Relaxed);
+    }
+
+    fn set(&self, state: State) {
+        *self.state.lock().unwrap() = state;
+    }
+
+    /// How long it has gone without checking in, if that is too long.
+    fn stuck(&self) -> Option<Duration> {
+        match *self.state.lock().unwrap() {
+            State::Working(beat) if beat.elapsed() >= STUCK_AFTER => Some(beat.elapsed()),
+            _ => None,
+        }
+    }
+
+    fn report(&self) -> String {
+        let mut line = match &*self.state.lock().unwrap() {
+            State::Working(beat) if beat.elapsed() >= STUCK_AFTER => {
+                format!("{}: stuck for {}s", self.name, beat.elapsed().as_secs())
+            }
+            State::Working(_) => format!("{}: working", self.name),
+            State::Waiting => format!("{}: waiting", self.name),
+            State::Done => format!("{}: done", self.name),
+            State::Failed(message) => format!("{}: failed ({})", self.name, message),
+        };
+        let restarts = self.restarts.load(Relaxed);
+        if restarts > 0 {
+            let times = if restarts == 1 { "time" } else { "times" };
+            line.push_str(&format!(", restarted {} {}", restarts, times));
+            if let Some(message) = &*self.panic.lock().unwrap() {
+                line.push_str(&format!(" ({})", message));
+            }
+        }
+        line
+    }
+}
+
+/// What a thread panicked with, as `panic!` formats it.
+fn panic_message(panic: Box<dyn Any + Send>) -> String {
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => match panic.downcast::<&str>() {
+            Ok(message) => message.to_string(),
+            Err(_) => "unknown panic".to_string(),
+        },
+    }
+}
@@ -0,0 +1,221 @@
+use std::fmt::Write;
+
+/// A session as written by `export` and read by `import`, so a workspace
+/// can be versioned and recreated on another machine.
+///
+/// The file is a small subset of YAML:
+///
+/// ```yaml
+/// session: "work"
+/// windows:
+///   - name: "zsh"
+///     panes:
+///       - cwd: "/home/me/src"
+///         env: ["EDITOR=vim"]
+///         user: "root"
+///         command: ["zsh", "-l"]
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionSpec {
+    pub name: String,
+    pub windows: Vec<WindowSpec>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowSpec {
+    pub name: String,
+    pub panes: Vec<PaneSpec>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PaneSpec {
+    /// Where the pane's command starts, the importer's directory if None.
+    pub cwd: Option<String>,
+    /// Variables set for the pane's command, as `VAR=value`.
+    pub env: Vec<String>,
+    /// Who the pane's command runs as, the server's user if None.
+    pub user: Option<String>,
+    /// The pane's command and its arguments, the default shell if empty.
+    pub command: Vec<String>,
+}
+
+impl SessionSpec {
+    pub fn to_yaml(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "session: {}", quote(&self.name));
+        out.push_str("windows:\n");
+        for window in &self.windows {
+            let _ = writeln!(out, "  - name: {}", quote(&window.name));
+            out.push_str("    panes:\n");
+            for pane in &window.panes {
+                let mut entries = vec![];
+                if let Some(cwd) = &pane.cwd {
+                    entries.push(format!("cwd: {}", quote(cwd)));
+                }
+                if !pane.env.is_empty() {
+                    entries.push(format!("env: {}", quote_list(&pane.env)));
+                }
+                if let Some(user) = &pane.user {
+                    entries.push(format!("user: {}", quote(user)));
+                }
+                entries.push(format!("command: {}", quote_list(&pane.command)));
+                for (i, entry) in entries.iter().enumerate() {
+                    let bullet = if i == 0 { "- " } else { "  " };
+                    let _ = writeln!(out, "      {}{}", bullet, entry);
+                }
+            }
+        }
+        out
+    }
+
+    /// Parses what `to_yaml` writes, comments and blank lines are allowed
+    /// and strings may be left unquoted.
+    pub fn parse_yaml(yaml: &str) -> Result<SessionSpec, String> {
+        let mut name = None;
+        let mut windows: Vec<WindowSpec> = vec![];
+        let mut in_panes = false;
+
+        for (i, line) in yaml.lines().enumerate() {
+            let error = |message: &str| format!("line {}: {}", i + 1, message);
+            let trimmed = line.trim_start();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let top_level = line.len() == trimmed.len();
+            let (item, entry) = match trimmed.strip_prefix("- ") {
+                Some(entry) => (true, entry.trim_start()),
+                None => (false, trimmed),
+            };
+            let (key, value) = entry
+                .split_once(':')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| error("expected key: value"))?;
+
+            match (top_level, item, key) {
+                (true, false, "session") => {
+                    name = Some(parse_string(value).map_err(|e| error(&e))?)
+                }
+                (true, false, "windows") => {}
+                // panes have no name, so a named item is always a window
+                (_, true, "name") => {
+                    windows.push(WindowSpec {
+                        name: parse_string(value).map_err(|e| error(&e))?,
+                        panes: vec![],
+                    });
+                    in_panes = false;
+                }
+                (_, false, "panes") => in_panes = true,
+                (_, _, "cwd" | "env" | "user" | "command") if in_panes => {
+                    let window = windows
+                        .last_mut()
+                        .ok_or_else(|| error("pane outside a window"))?;
+                    if item || window.panes.is_empty() {
+                        window.panes.push(PaneSpec::default());
+                    }
+                    let pane = window.panes.last_mut().unwrap();
+                    match key {
+                        "cwd" => pane.cwd = Some(parse_string(value).map_err(|e| error(&e))?),
+                        "env" => pane.env = parse_list(value).map_err(|e| error(&e))?,
+                        "user" => pane.user = Some(parse_string(value).map_err(|e| error(&e))?),
+                        _ => pane.command = parse_list(value).map_err(|e| error(&e))?,
+                    }
+                }
+                _ => return Err(error(&format!("unexpected {}", key))),
+            }
+        }
+
+        Ok(SessionSpec {
+            name: name.ok_or("missing session")?,
+            windows,
+        })
+    }
+}
+
+/// A double quoted string, escaped the way YAML and JSON both understand.
+fn quote(s: &str) -> String {
+    let mut out = String::from('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A flow list like `["zsh", "-l"]`.
+fn quote_list(items: &[String]) -> String {
+    let items: Vec<String> = items.iter().map(|item| quote(item)).collect();
+    format!("[{}]", items.join(", "))
+}
+
+fn parse_string(value: &str) -> Result<String, String> {
+    let (s, rest) = parse_scalar(value)?;
+    if !rest.trim().is_empty() {
+        return Err(format!("unexpected {}", rest.trim()));
+    }
+    Ok(s)
+}
+
+/// Parses a flow list like `["zsh", "-l"]`.
+fn parse_list(value: &str) -> Result<Vec<String>, String> {
+    let inner = value
+        .strip_prefix('[')
+        .and_then(|v| v.strip_suffix(']'))
+        .ok_or("expected a [list]")?;
+
+    let mut items = vec![];
+    let mut rest = inner.trim();
+    while !rest.is_empty() {
+        let (item, remaining) = parse_scalar(rest)?;
+        items.push(item);
+        rest = remaining.trim_start();
+        if let Some(remaining) = rest.strip_prefix(',') {
+            rest = remaining.trim_start();
+        } else if !rest.is_empty() {
+            return Err(format!("unexpected {}", rest));
+        }
+    }
+    Ok(items)
+}
+
+/// Parses a double quoted or plain string at the start of `value`, returns
+/// it with whatever follows.
+fn parse_scalar(value: &str) -> Result<(String, &str), String> {
+    let Some(quoted) = value.strip_prefix('"') else {
+        // plain strings end at a list separator
+        let end = value.find([',', ']']).unwrap_or(value.len());
+        return Ok((value[..end].trim().to_string(), &value[end..]));
+    };
+
+    let mut out = String::new();
+    let mut chars = quoted.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((out, &quoted[i + 1..])),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => out.push('\n'),
+                Some('t') => out.push('\t'),
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    let c = u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or("bad \\u escape")?;
+                    out.push(c);
+                }
+                Some(c) => out.push(c),
+                None => break,
+            },
+            c => out.push(c),
+        }
+    }
+    Err("missing closing quote".to_string())
+}